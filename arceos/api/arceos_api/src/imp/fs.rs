@@ -1,5 +1,6 @@
-use alloc::string::String;
+use alloc::{string::String, vec::Vec};
 use axerrno::AxResult;
+use core::time::Duration;
 use axfs::fops::{Directory, File};
 
 pub use axfs::fops::DirEntry as AxDirEntry;
@@ -7,6 +8,7 @@ pub use axfs::fops::FileAttr as AxFileAttr;
 pub use axfs::fops::FilePerm as AxFilePerm;
 pub use axfs::fops::FileType as AxFileType;
 pub use axfs::fops::OpenOptions as AxOpenOptions;
+pub use axfs::fops::{MountFlags as AxMountFlags, MountInfo as AxMountInfo};
 pub use axio::SeekFrom as AxSeekFrom;
 
 #[cfg(feature = "myfs")]
@@ -78,6 +80,34 @@ pub fn ax_rename(old: &str, new: &str) -> AxResult {
     axfs::api::rename(old, new)
 }
 
+pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr> {
+    axfs::api::symlink_metadata(path).map(|md| *md.raw_metadata())
+}
+
+pub fn ax_set_perm(path: &str, perm: AxFilePerm) -> AxResult {
+    axfs::api::set_permissions(path, perm)
+}
+
+pub fn ax_chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> AxResult {
+    axfs::api::chown(path, uid, gid)
+}
+
+pub fn ax_set_times(path: &str, atime: Option<Duration>, mtime: Option<Duration>) -> AxResult {
+    axfs::api::set_times(path, atime, mtime)
+}
+
+pub fn ax_symlink(target: &str, path: &str) -> AxResult {
+    axfs::api::symlink(target, path)
+}
+
+pub fn ax_read_link(path: &str) -> AxResult<String> {
+    axfs::api::read_link(path)
+}
+
+pub fn ax_hard_link(old: &str, new: &str) -> AxResult {
+    axfs::api::hard_link(old, new)
+}
+
 pub fn ax_current_dir() -> AxResult<String> {
     axfs::api::current_dir()
 }
@@ -85,3 +115,15 @@ pub fn ax_current_dir() -> AxResult<String> {
 pub fn ax_set_current_dir(path: &str) -> AxResult {
     axfs::api::set_current_dir(path)
 }
+
+pub fn ax_mount(source: &str, target: &str, fstype: &str, flags: AxMountFlags) -> AxResult {
+    axfs::api::mount(source, target, fstype, flags)
+}
+
+pub fn ax_umount(target: &str) -> AxResult {
+    axfs::api::umount(target)
+}
+
+pub fn ax_mounts() -> Vec<AxMountInfo> {
+    axfs::api::mounts()
+}
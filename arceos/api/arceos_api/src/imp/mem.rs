@@ -10,6 +10,11 @@ cfg_alloc! {
     pub fn ax_dealloc(ptr: NonNull<u8>, layout: Layout) {
         axalloc::global_allocator().dealloc(ptr, layout)
     }
+
+    #[cfg(feature = "alloc-debug")]
+    pub fn ax_dump_allocations() {
+        axalloc::dump_allocations()
+    }
 }
 
 cfg_dma! {
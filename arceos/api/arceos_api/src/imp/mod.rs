@@ -37,6 +37,45 @@ mod time {
     pub use axhal::time::{
         monotonic_time as ax_monotonic_time, wall_time as ax_wall_time, TimeValue as AxTimeValue,
     };
+
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    pub use self::timer::*;
+
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    mod timer {
+        use super::AxTimeValue;
+        use core::time::Duration;
+
+        pub use axtask::Timer as AxTimerHandle;
+
+        pub fn ax_timer_create(callback: impl Fn() + Send + Sync + 'static) -> AxTimerHandle {
+            AxTimerHandle::new(callback)
+        }
+
+        pub fn ax_timer_create_deferred(
+            callback: impl Fn() + Send + Sync + 'static,
+        ) -> AxTimerHandle {
+            AxTimerHandle::new_deferred(callback)
+        }
+
+        pub fn ax_timer_start(
+            timer: &AxTimerHandle,
+            deadline: AxTimeValue,
+            interval: Option<Duration>,
+        ) {
+            timer.start(deadline, interval);
+        }
+
+        pub fn ax_timer_cancel(timer: &AxTimerHandle) -> bool {
+            timer.cancel()
+        }
+
+        pub fn ax_timer_get(timer: &AxTimerHandle) -> Option<(AxTimeValue, Option<Duration>)> {
+            timer
+                .deadline()
+                .map(|deadline| (deadline, timer.interval()))
+        }
+    }
 }
 
 pub use self::mem::*;
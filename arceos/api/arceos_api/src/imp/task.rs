@@ -26,6 +26,8 @@ pub fn ax_exit(_exit_code: i32) -> ! {
 cfg_task! {
     use core::time::Duration;
 
+    pub use axtask::AxCpuMask;
+
     /// A handle to a task.
     pub struct AxTaskHandle {
         inner: axtask::AxTaskRef,
@@ -56,11 +58,22 @@ cfg_task! {
         axtask::current().id().as_u64()
     }
 
-    pub fn ax_spawn<F>(f: F, name: alloc::string::String, stack_size: usize) -> AxTaskHandle
+    pub fn ax_spawn<F>(
+        f: F,
+        name: alloc::string::String,
+        stack_size: usize,
+        cpumask: Option<AxCpuMask>,
+    ) -> AxTaskHandle
     where
         F: FnOnce() + Send + 'static,
     {
-        let inner = axtask::spawn_raw(f, name, stack_size);
+        let mut builder = axtask::TaskBuilder::new()
+            .name(name)
+            .stack_size(stack_size);
+        if let Some(cpumask) = cpumask {
+            builder = builder.cpumask(cpumask);
+        }
+        let inner = builder.spawn(f);
         AxTaskHandle {
             id: inner.id().as_u64(),
             inner,
@@ -82,6 +95,25 @@ cfg_task! {
         }
     }
 
+    pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult {
+        if axtask::set_current_affinity(cpumask) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(
+                InvalidInput,
+                "ax_set_current_affinity: empty CPU mask"
+            )
+        }
+    }
+
+    pub fn ax_set_affinity(task: &AxTaskHandle, cpumask: AxCpuMask) -> crate::AxResult {
+        if axtask::set_affinity(&task.inner, cpumask) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(InvalidInput, "ax_set_affinity: empty CPU mask")
+        }
+    }
+
     pub fn ax_wait_queue_wait(
         wq: &AxWaitQueueHandle,
         until_condition: impl Fn() -> bool,
@@ -47,6 +47,43 @@ pub mod time {
         /// Returns the time elapsed since epoch, also known as realtime.
         pub fn ax_wall_time() -> AxTimeValue;
     }
+
+    define_api_type! {
+        @cfg(all(feature = "multitask", feature = "irq"));
+        pub type AxTimerHandle;
+    }
+
+    define_api! {
+        @cfg(all(feature = "multitask", feature = "irq"));
+
+        /// Creates a timer that calls `callback` every time it expires.
+        ///
+        /// The callback runs in the timer interrupt handler, so it must not
+        /// block or allocate memory. The timer is cancelled when the handle is
+        /// dropped.
+        pub fn ax_timer_create(callback: impl Fn() + Send + Sync + 'static) -> AxTimerHandle;
+        /// Creates a timer like [`ax_timer_create`], but the callback runs
+        /// later in a kernel task, where it may block.
+        pub fn ax_timer_create_deferred(
+            callback: impl Fn() + Send + Sync + 'static
+        ) -> AxTimerHandle;
+        /// Starts the timer to expire at the given `deadline` (in wall time),
+        /// and then every `interval` if it is not [`None`].
+        ///
+        /// Restarting an active timer replaces its previous setting.
+        pub fn ax_timer_start(
+            timer: &AxTimerHandle,
+            deadline: AxTimeValue,
+            interval: Option<core::time::Duration>
+        );
+        /// Cancels the timer, returns `true` if it was active.
+        pub fn ax_timer_cancel(timer: &AxTimerHandle) -> bool;
+        /// Returns the next expiration time and the interval of the timer, or
+        /// [`None`] if it is not active.
+        pub fn ax_timer_get(
+            timer: &AxTimerHandle
+        ) -> Option<(AxTimeValue, Option<core::time::Duration>)>;
+    }
 }
 
 /// Memory management.
@@ -75,6 +112,13 @@ pub mod mem {
         pub unsafe fn ax_dealloc(ptr: NonNull<u8>, layout: Layout);
     }
 
+    define_api! {
+        @cfg "alloc-debug";
+        /// Prints the live allocations of the global allocator to the log, as
+        /// a leak report.
+        pub fn ax_dump_allocations();
+    }
+
     define_api_type! {
         @cfg "dma";
         pub type DMAInfo;
@@ -121,6 +165,7 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxCpuMask;
     }
 
     define_api! {
@@ -145,16 +190,28 @@ pub mod task {
         /// Returns the current task's ID.
         pub fn ax_current_task_id() -> u64;
         /// Spawns a new task with the given entry point and other arguments.
+        ///
+        /// If `cpumask` is [`None`], the new task inherits the CPU affinity
+        /// of the current task.
         pub fn ax_spawn(
             f: impl FnOnce() + Send + 'static,
             name: alloc::string::String,
-            stack_size: usize
+            stack_size: usize,
+            cpumask: Option<AxCpuMask>
         ) -> AxTaskHandle;
         /// Waits for the given task to exit, and returns its exit code (the
         /// argument of [`ax_exit`]).
         pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32>;
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
+        /// Sets the CPU affinity of the current task, migrates it to one of
+        /// the allowed CPUs if necessary.
+        pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult;
+        /// Sets the CPU affinity of the given task.
+        ///
+        /// If it is running on a CPU outside the mask, that CPU is asked to
+        /// migrate it at once.
+        pub fn ax_set_affinity(task: &AxTaskHandle, cpumask: AxCpuMask) -> crate::AxResult;
 
         /// Blocks the current task and put it into the wait queue, until the
         /// given condition becomes true, or the the given duration has elapsed
@@ -186,6 +243,8 @@ pub mod fs {
         pub type AxFilePerm;
         pub type AxDirEntry;
         pub type AxSeekFrom;
+        pub type AxMountFlags;
+        pub type AxMountInfo;
         #[cfg(feature = "myfs")]
         pub type AxDisk;
         #[cfg(feature = "myfs")]
@@ -250,10 +309,42 @@ pub mod fs {
         /// It will delete the original file if `old` already exists.
         pub fn ax_rename(old: &str, new: &str) -> AxResult;
 
+        /// Returns attributes of the file at the path, without following the
+        /// symbolic link if it is one.
+        pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr>;
+        /// Changes the permissions of a file or directory.
+        pub fn ax_set_perm(path: &str, perm: AxFilePerm) -> AxResult;
+        /// Changes the owner and group of a file or directory. `None` leaves
+        /// it unchanged.
+        pub fn ax_chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> AxResult;
+        /// Changes the last access and modification times of a file or
+        /// directory, since the Unix epoch. `None` leaves it unchanged.
+        pub fn ax_set_times(
+            path: &str,
+            atime: Option<core::time::Duration>,
+            mtime: Option<core::time::Duration>,
+        ) -> AxResult;
+        /// Creates a symbolic link at `path` pointing to `target`.
+        pub fn ax_symlink(target: &str, path: &str) -> AxResult;
+        /// Returns the target of a symbolic link.
+        pub fn ax_read_link(path: &str) -> AxResult<alloc::string::String>;
+        /// Creates a hard link `new` to the file `old`, in the same filesystem.
+        pub fn ax_hard_link(old: &str, new: &str) -> AxResult;
+
         /// Returns the current working directory.
         pub fn ax_current_dir() -> AxResult<alloc::string::String>;
         /// Changes the current working directory to the specified path.
         pub fn ax_set_current_dir(path: &str) -> AxResult;
+
+        /// Mounts a filesystem of `fstype` from `source` on the directory
+        /// `target`.
+        pub fn ax_mount(source: &str, target: &str, fstype: &str, flags: AxMountFlags) -> AxResult;
+        /// Unmounts the filesystem mounted on `target`.
+        ///
+        /// It fails if the filesystem is in use.
+        pub fn ax_umount(target: &str) -> AxResult;
+        /// Returns the mounted filesystems, including the root filesystem.
+        pub fn ax_mounts() -> alloc::vec::Vec<AxMountInfo>;
     }
 }
 
@@ -6,13 +6,16 @@ macro_rules! define_api_type {
             $vis use $crate::imp::$name;
         )+
     };
-    ( @cfg $feature:literal; $( $(#[$attr:meta])* $vis:vis type $name:ident; )+ ) => {
+    ( @cfg $feature:literal; $($rest:tt)+ ) => {
+        define_api_type!(@cfg(feature = $feature); $($rest)+);
+    };
+    ( @cfg($cond:meta); $( $(#[$attr:meta])* $vis:vis type $name:ident; )+ ) => {
         $(
-            #[cfg(feature = $feature)]
+            #[cfg($cond)]
             $(#[$attr])*
             $vis use $crate::imp::$name;
 
-            #[cfg(all(feature = "dummy-if-not-enabled", not(feature = $feature)))]
+            #[cfg(all(feature = "dummy-if-not-enabled", not($cond)))]
             $(#[$attr])*
             $vis struct $name;
         )+
@@ -36,19 +39,22 @@ macro_rules! define_api {
             }
         )+
     };
+    ( @cfg $feature:literal; $($rest:tt)+ ) => {
+        define_api!(@cfg(feature = $feature); $($rest)+);
+    };
     (
-        @cfg $feature:literal;
+        @cfg($cond:meta);
         $( $(#[$attr:meta])* $vis:vis fn $name:ident( $($arg:ident : $type:ty),* $(,)? ) $( -> $ret:ty )? ; )+
     ) => {
         $(
-            #[cfg(feature = $feature)]
+            #[cfg($cond)]
             $(#[$attr])*
             $vis fn $name( $($arg : $type),* ) $( -> $ret )? {
                 $crate::imp::$name( $($arg),* )
             }
 
             #[allow(unused_variables)]
-            #[cfg(all(feature = "dummy-if-not-enabled", not(feature = $feature)))]
+            #[cfg(all(feature = "dummy-if-not-enabled", not($cond)))]
             $(#[$attr])*
             $vis fn $name( $($arg : $type),* ) $( -> $ret )? {
                 unimplemented!(stringify!($name))
@@ -56,18 +62,18 @@ macro_rules! define_api {
         )+
     };
     (
-        @cfg $feature:literal;
+        @cfg($cond:meta);
         $( $(#[$attr:meta])* $vis:vis unsafe fn $name:ident( $($arg:ident : $type:ty),* $(,)? ) $( -> $ret:ty )? ; )+
     ) => {
         $(
-            #[cfg(feature = $feature)]
+            #[cfg($cond)]
             $(#[$attr])*
             $vis unsafe fn $name( $($arg : $type),* ) $( -> $ret )? {
                 $crate::imp::$name( $($arg),* )
             }
 
             #[allow(unused_variables)]
-            #[cfg(all(feature = "dummy-if-not-enabled", not(feature = $feature)))]
+            #[cfg(all(feature = "dummy-if-not-enabled", not($cond)))]
             $(#[$attr])*
             $vis unsafe fn $name( $($arg : $type),* ) $( -> $ret )? {
                 unimplemented!(stringify!($name))
@@ -1,16 +1,37 @@
 fn main() {
     use std::io::Write;
 
-    fn gen_pthread_mutex(out_file: &str) -> std::io::Result<()> {
+    fn gen_pthread_types(out_file: &str) -> std::io::Result<()> {
         // TODO: generate size and initial content automatically.
-        let (mutex_size, mutex_init) = if cfg!(feature = "multitask") {
+        // (name, size, initializer), the initializers are from
+        // `core::mem::transmute::<_, [usize; N]>(T::new(..))`.
+        let types = if cfg!(feature = "multitask") {
             if cfg!(feature = "smp") {
-                (6, "{0, 0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
+                [
+                    // `axsync::Mutex` or `axsync::PiMutex`, followed by the `is_pi` flag
+                    ("mutex", 7, "{0, 0, 8, 0, 0, 0, 0}"),
+                    ("cond", 6, "{0, 0, 8, 0, 0, 0}"),
+                    ("rwlock", 7, "{0, 0, 8, 0, 0, 0, 0}"),
+                    ("barrier", 8, ""),
+                    ("sem", 6, ""),
+                ]
             } else {
-                (5, "{0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
+                [
+                    ("mutex", 6, "{0, 8, 0, 0, 0, 0}"),
+                    ("cond", 5, "{0, 8, 0, 0, 0}"),
+                    ("rwlock", 6, "{0, 8, 0, 0, 0, 0}"),
+                    ("barrier", 7, ""),
+                    ("sem", 5, ""),
+                ]
             }
         } else {
-            (1, "{0}")
+            [
+                ("mutex", 1, "{0}"),
+                ("cond", 1, "{0}"),
+                ("rwlock", 1, "{0}"),
+                ("barrier", 1, ""),
+                ("sem", 1, ""),
+            ]
         };
 
         let mut output = Vec::new();
@@ -20,14 +41,30 @@ fn main() {
         )?;
         writeln!(
             output,
-            r#"
-typedef struct {{
-    long __l[{mutex_size}];
-}} pthread_mutex_t;
-
-#define PTHREAD_MUTEX_INITIALIZER {{ .__l = {mutex_init}}}
-"#
+            "\n#ifndef _AX_PTHREAD_TYPES_H\n#define _AX_PTHREAD_TYPES_H"
         )?;
+        for (name, size, init) in types {
+            let ty = if name == "sem" {
+                "sem_t".to_string()
+            } else {
+                format!("pthread_{name}_t")
+            };
+            writeln!(
+                output,
+                r#"
+typedef struct {{
+    long __l[{size}];
+}} {ty};"#
+            )?;
+            if !init.is_empty() {
+                let upper = name.to_uppercase();
+                writeln!(
+                    output,
+                    "\n#define PTHREAD_{upper}_INITIALIZER {{ .__l = {init}}}"
+                )?;
+            }
+        }
+        writeln!(output, "\n#endif // _AX_PTHREAD_TYPES_H")?;
         std::fs::write(out_file, output)?;
         Ok(())
     }
@@ -44,14 +81,32 @@ typedef struct {{
             "sock.*",
             "fd_set",
             "timeval",
+            "itimerval",
+            "itimerspec",
+            "timer_t",
+            "sigaction",
+            "sigevent",
+            "siginfo_t",
             "pthread_t",
             "pthread_attr_t",
             "pthread_mutex_t",
             "pthread_mutexattr_t",
+            "pthread_cond_t",
+            "pthread_condattr_t",
+            "pthread_rwlock_t",
+            "pthread_rwlockattr_t",
+            "pthread_barrier_t",
+            "pthread_barrierattr_t",
+            "pthread_key_t",
+            "pthread_once_t",
+            "sem_t",
+            "cpu_set_t",
+            "sched_param",
             "epoll_event",
             "iovec",
             "clockid_t",
             "rlimit",
+            "rusage",
             "aibuf",
         ];
         let allow_vars = [
@@ -63,11 +118,31 @@ typedef struct {{
             "FD_.*",
             "F_.*",
             "_SC_.*",
+            "SCHED_.*",
+            "PTHREAD_.*",
+            "SEM_VALUE_MAX",
+            "_NSIG",
+            "SIG.*",
+            "SA_.*",
+            "SI_.*",
+            "ITIMER_.*",
+            "TIMER_.*",
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
+            "RLIM_INFINITY",
+            "RUSAGE_.*",
             "EAI_.*",
             "MAXADDRS",
+            "PROT_.*",
+            "MAP_.*",
+            "MREMAP_.*",
+            "MADV_.*",
+            "MS_.*",
+            "MNT_.*",
+            "UMOUNT_.*",
+            "AT_.*",
+            "UTIME_.*",
         ];
 
         #[derive(Debug)]
@@ -75,7 +150,7 @@ typedef struct {{
 
         impl bindgen::callbacks::ParseCallbacks for MyCallbacks {
             fn include_file(&self, fname: &str) {
-                if !fname.contains("ax_pthread_mutex.h") {
+                if !fname.contains("ax_pthread_types.h") {
                     println!("cargo:rerun-if-changed={}", fname);
                 }
             }
@@ -102,6 +177,6 @@ typedef struct {{
             .expect("Couldn't write bindings!");
     }
 
-    gen_pthread_mutex("../../ulib/axlibc/include/ax_pthread_mutex.h").unwrap();
+    gen_pthread_types("../../ulib/axlibc/include/ax_pthread_types.h").unwrap();
     gen_c_to_rust_bindings("ctypes.h", "src/ctypes_gen.rs");
 }
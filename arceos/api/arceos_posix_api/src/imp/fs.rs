@@ -1,8 +1,9 @@
 use alloc::sync::Arc;
-use core::ffi::{c_char, c_int};
+use core::ffi::{c_char, c_int, c_long};
+use core::time::Duration;
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs::fops::OpenOptions;
+use axfs::fops::{FileAttr, FilePerm, OpenOptions};
 use axio::{PollState, SeekFrom};
 use axsync::Mutex;
 
@@ -42,21 +43,7 @@ impl FileLike for File {
     }
 
     fn stat(&self) -> LinuxResult<ctypes::stat> {
-        let metadata = self.inner.lock().get_attr()?;
-        let ty = metadata.file_type() as u8;
-        let perm = metadata.perm().bits() as u32;
-        let st_mode = ((ty as u32) << 12) | perm;
-        Ok(ctypes::stat {
-            st_ino: 1,
-            st_nlink: 1,
-            st_mode,
-            st_uid: 1000,
-            st_gid: 1000,
-            st_size: metadata.size() as _,
-            st_blocks: metadata.blocks() as _,
-            st_blksize: 512,
-            ..Default::default()
-        })
+        Ok(attr_to_stat(&self.inner.lock().get_attr()?))
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
@@ -75,6 +62,39 @@ impl FileLike for File {
     }
 }
 
+fn attr_to_stat(attr: &FileAttr) -> ctypes::stat {
+    let ty = attr.file_type() as u8;
+    let perm = attr.perm().bits() as u32;
+    let st_mode = ((ty as u32) << 12) | perm;
+    ctypes::stat {
+        st_ino: 1,
+        st_nlink: attr.nlink(),
+        st_mode,
+        st_uid: attr.uid(),
+        st_gid: attr.gid(),
+        st_size: attr.size() as _,
+        st_blocks: attr.blocks() as _,
+        st_blksize: 512,
+        st_atim: attr.atime().into(),
+        st_mtim: attr.mtime().into(),
+        st_ctim: attr.ctime().into(),
+        ..Default::default()
+    }
+}
+
+/// Returns the node of the file `fd` to be mapped into memory.
+///
+/// The file should be opened for reading, and also for writing if `write` is
+/// `true`.
+pub fn get_mmap_node(fd: c_int, write: bool) -> LinuxResult<axfs::fops::FileNodeRef> {
+    let file = File::from_fd(fd).map_err(|e| match e {
+        LinuxError::EINVAL => LinuxError::ENODEV,
+        e => e,
+    })?;
+    let node = file.inner.lock().mmap_node(write)?;
+    Ok(node)
+}
+
 /// Convert open flags to [`OpenOptions`].
 fn flags_to_options(flags: c_int, _mode: ctypes::mode_t) -> OpenOptions {
     let flags = flags as u32;
@@ -99,6 +119,9 @@ fn flags_to_options(flags: c_int, _mode: ctypes::mode_t) -> OpenOptions {
     if flags & ctypes::O_EXEC != 0 {
         options.create_new(true);
     }
+    if flags & ctypes::O_DIRECT != 0 {
+        options.direct(true);
+    }
     options
 }
 
@@ -177,7 +200,8 @@ pub unsafe fn sys_lstat(path: *const c_char, buf: *mut ctypes::stat) -> ctypes::
         if buf.is_null() {
             return Err(LinuxError::EFAULT);
         }
-        unsafe { *buf = Default::default() }; // TODO
+        let metadata = axfs::api::symlink_metadata(path?)?;
+        unsafe { *buf = attr_to_stat(metadata.raw_metadata()) };
         Ok(0)
     })
 }
@@ -215,3 +239,195 @@ pub fn sys_rename(old: *const c_char, new: *const c_char) -> c_int {
         Ok(0)
     })
 }
+
+/// Create a symbolic link `linkpath` pointing to `target`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    syscall_body!(sys_symlink, {
+        let target = char_ptr_to_str(target)?;
+        let linkpath = char_ptr_to_str(linkpath)?;
+        debug!(
+            "sys_symlink <= target: {:?}, linkpath: {:?}",
+            target, linkpath
+        );
+        axfs::api::symlink(target, linkpath)?;
+        Ok(0)
+    })
+}
+
+/// Read the target of the symbolic link `path` into `buf`, without a trailing
+/// null byte. It is truncated if `buf` is too small.
+///
+/// Return the number of bytes placed in `buf`.
+pub unsafe fn sys_readlink(
+    path: *const c_char,
+    buf: *mut c_char,
+    bufsiz: usize,
+) -> ctypes::ssize_t {
+    let path = char_ptr_to_str(path);
+    debug!("sys_readlink <= {:?} {:#x} {}", path, buf as usize, bufsiz);
+    syscall_body!(sys_readlink, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let target = axfs::api::read_link(path?)?;
+        let len = target.len().min(bufsiz);
+        let dst = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len) };
+        dst.copy_from_slice(&target.as_bytes()[..len]);
+        Ok(len as ctypes::ssize_t)
+    })
+}
+
+/// Create a hard link `new` to the file `old`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_link(old: *const c_char, new: *const c_char) -> c_int {
+    syscall_body!(sys_link, {
+        let old_path = char_ptr_to_str(old)?;
+        let new_path = char_ptr_to_str(new)?;
+        debug!("sys_link <= old: {:?}, new: {:?}", old_path, new_path);
+        axfs::api::hard_link(old_path, new_path)?;
+        Ok(0)
+    })
+}
+
+/// Change the permission bits of the file `path`. Other bits of `mode`, such
+/// as setuid and sticky, are ignored.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_chmod(path: *const c_char, mode: ctypes::mode_t) -> c_int {
+    syscall_body!(sys_chmod, {
+        let path = char_ptr_to_str(path)?;
+        debug!("sys_chmod <= {:?} {:#o}", path, mode);
+        let perm = FilePerm::from_bits_truncate((mode & 0o777) as u16);
+        axfs::api::set_permissions(path, perm)?;
+        Ok(0)
+    })
+}
+
+/// Change the owner and group of the file `path`. If `uid` or `gid` is -1, it
+/// is not changed.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_chown(path: *const c_char, uid: ctypes::uid_t, gid: ctypes::gid_t) -> c_int {
+    syscall_body!(sys_chown, {
+        let path = char_ptr_to_str(path)?;
+        debug!("sys_chown <= {:?} {} {}", path, uid as i32, gid as i32);
+        let id = |id: u32| (id != u32::MAX).then_some(id);
+        axfs::api::chown(path, id(uid), id(gid))?;
+        Ok(0)
+    })
+}
+
+/// Change the last access and modification times of the file `path`, with
+/// `times[0]` and `times[1]`. `UTIME_NOW` sets it to the current time, and
+/// `UTIME_OMIT` leaves it unchanged. Both are set to the current time if
+/// `times` is null.
+///
+/// Only `AT_FDCWD` is supported as `dirfd` for relative paths, and symbolic
+/// links are always followed.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub unsafe fn sys_utimensat(
+    dirfd: c_int,
+    path: *const c_char,
+    times: *const ctypes::timespec,
+    flags: c_int,
+) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!(
+        "sys_utimensat <= {} {:?} {:#x} {:#x}",
+        dirfd, path, times as usize, flags
+    );
+    syscall_body!(sys_utimensat, {
+        let path = path?;
+        if dirfd != ctypes::AT_FDCWD && !path.starts_with('/') {
+            return Err(LinuxError::EBADF);
+        }
+        if flags as u32 & ctypes::AT_SYMLINK_NOFOLLOW != 0 {
+            return Err(LinuxError::EOPNOTSUPP);
+        } else if flags != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let now = axhal::time::wall_time();
+        let time = |ts: ctypes::timespec| -> LinuxResult<Option<Duration>> {
+            if ts.tv_nsec == ctypes::UTIME_NOW as c_long {
+                Ok(Some(now))
+            } else if ts.tv_nsec == ctypes::UTIME_OMIT as c_long {
+                Ok(None)
+            } else if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+                Err(LinuxError::EINVAL)
+            } else {
+                Ok(Some(ts.into()))
+            }
+        };
+        let (atime, mtime) = if times.is_null() {
+            (Some(now), Some(now))
+        } else {
+            let times = unsafe { core::slice::from_raw_parts(times, 2) };
+            (time(times[0])?, time(times[1])?)
+        };
+        if atime.is_some() || mtime.is_some() {
+            axfs::api::set_times(path, atime, mtime)?;
+        }
+        Ok(0)
+    })
+}
+
+/// Mount the filesystem of `fstype` from `source` on the directory `target`.
+///
+/// `data` is ignored. Only `MS_RDONLY` of `flags` takes effect, and remounting,
+/// bind mounts and moving mount points are not supported.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_mount(
+    source: *const c_char,
+    target: *const c_char,
+    fstype: *const c_char,
+    flags: core::ffi::c_ulong,
+    _data: *const core::ffi::c_void,
+) -> c_int {
+    syscall_body!(sys_mount, {
+        let source = if source.is_null() {
+            "none"
+        } else {
+            char_ptr_to_str(source)?
+        };
+        let target = char_ptr_to_str(target)?;
+        let fstype = char_ptr_to_str(fstype)?;
+        debug!(
+            "sys_mount <= source: {:?}, target: {:?}, fstype: {:?}, flags: {:#x}",
+            source, target, fstype, flags
+        );
+        let flags = flags as u32;
+        if flags & (ctypes::MS_REMOUNT | ctypes::MS_BIND | ctypes::MS_MOVE) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut mount_flags = axfs::api::MountFlags::empty();
+        if flags & ctypes::MS_RDONLY != 0 {
+            mount_flags |= axfs::api::MountFlags::READ_ONLY;
+        }
+        axfs::api::mount(source, target, fstype, mount_flags)?;
+        Ok(0)
+    })
+}
+
+/// Unmount the filesystem mounted on `target`.
+///
+/// Forced and lazy unmounts (`MNT_FORCE`, `MNT_DETACH`) are not supported, so
+/// it fails with `EBUSY` if the filesystem is in use.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_umount2(target: *const c_char, flags: c_int) -> c_int {
+    syscall_body!(sys_umount2, {
+        let target = char_ptr_to_str(target)?;
+        debug!("sys_umount2 <= target: {:?}, flags: {:#x}", target, flags);
+        let flags = flags as u32;
+        if flags & !ctypes::UMOUNT_NOFOLLOW != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        axfs::api::umount(target)?;
+        Ok(0)
+    })
+}
@@ -0,0 +1,114 @@
+use core::ffi::c_int;
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
+
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{FutexKey, FUTEX_BITSET_MATCH_ANY};
+
+use crate::ctypes;
+use crate::utils::check_null_ptr;
+
+const FUTEX_WAIT: c_int = 0;
+const FUTEX_WAKE: c_int = 1;
+const FUTEX_REQUEUE: c_int = 3;
+const FUTEX_CMP_REQUEUE: c_int = 4;
+const FUTEX_WAIT_BITSET: c_int = 9;
+const FUTEX_WAKE_BITSET: c_int = 10;
+
+const FUTEX_PRIVATE_FLAG: c_int = 128;
+const FUTEX_CLOCK_REALTIME: c_int = 256;
+const FUTEX_CMD_MASK: c_int = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+/// Returns the key of the futex at `uaddr` in the current address space,
+/// which is identified by the root of the page table.
+///
+/// Shared futexes are keyed the same as private ones, so they do not work
+/// across address spaces.
+///
+/// Returns `EFAULT` if `uaddr` is null, or `EINVAL` if it is not aligned to
+/// 4 bytes.
+fn futex_key(uaddr: *const u32) -> LinuxResult<FutexKey> {
+    check_null_ptr(uaddr)?;
+    if uaddr as usize % core::mem::align_of::<u32>() != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    #[cfg(target_arch = "aarch64")]
+    let aspace = axhal::arch::read_page_table_root0();
+    #[cfg(not(target_arch = "aarch64"))]
+    let aspace = axhal::arch::read_page_table_root();
+    Ok(FutexKey::new(aspace.as_usize(), uaddr as usize))
+}
+
+/// Converts the relative timeout of `FUTEX_WAIT`.
+fn relative_timeout(timeout: &ctypes::timespec) -> LinuxResult<Duration> {
+    if timeout.tv_sec < 0 || !(0..1_000_000_000).contains(&timeout.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(Duration::from(*timeout))
+}
+
+/// Fast user-space locking.
+///
+/// Supports `FUTEX_WAIT`, `FUTEX_WAKE`, `FUTEX_REQUEUE`, `FUTEX_CMP_REQUEUE`,
+/// `FUTEX_WAIT_BITSET` and `FUTEX_WAKE_BITSET`. For the requeue operations,
+/// `timeout` is the maximum number of waiters to requeue.
+///
+/// The timeouts are ignored without the `irq` feature.
+pub unsafe fn sys_futex(
+    uaddr: *mut u32,
+    futex_op: c_int,
+    val: u32,
+    timeout: *const ctypes::timespec,
+    uaddr2: *mut u32,
+    val3: u32,
+) -> c_int {
+    debug!(
+        "sys_futex <= {:#x}, {:#x}, {}",
+        uaddr as usize, futex_op, val
+    );
+    syscall_body!(sys_futex, {
+        // `uaddr` must be checked before being used as an atomic.
+        let key = futex_key(uaddr)?;
+        let futex = unsafe { AtomicU32::from_ptr(uaddr) };
+        match futex_op & FUTEX_CMD_MASK {
+            FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+                let (bitset, timeout) = if futex_op & FUTEX_CMD_MASK == FUTEX_WAIT {
+                    let timeout = unsafe { timeout.as_ref() }.map(relative_timeout);
+                    (FUTEX_BITSET_MATCH_ANY, timeout.transpose()?)
+                } else {
+                    if val3 == 0 {
+                        return Err(LinuxError::EINVAL);
+                    }
+                    let clock = if futex_op & FUTEX_CLOCK_REALTIME != 0 {
+                        ctypes::CLOCK_REALTIME
+                    } else {
+                        ctypes::CLOCK_MONOTONIC
+                    };
+                    let timeout = (!timeout.is_null()).then(|| unsafe {
+                        crate::imp::pthread::timeout_from_abstime(clock as _, timeout)
+                    });
+                    (val3, timeout.transpose()?)
+                };
+                axtask::futex_wait(key, futex, val, bitset, timeout)?;
+                Ok(0)
+            }
+            FUTEX_WAKE => {
+                Ok(axtask::futex_wake(key, val as usize, FUTEX_BITSET_MATCH_ANY) as c_int)
+            }
+            FUTEX_WAKE_BITSET => {
+                if val3 == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                Ok(axtask::futex_wake(key, val as usize, val3) as c_int)
+            }
+            FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+                let new_key = futex_key(uaddr2)?;
+                let nr_requeue = timeout as usize as u32 as usize;
+                let cmp = (futex_op & FUTEX_CMD_MASK == FUTEX_CMP_REQUEUE).then_some((futex, val3));
+                let n = axtask::futex_requeue(key, val as usize, new_key, nr_requeue, cmp)?;
+                Ok(n as c_int)
+            }
+            _ => Err(LinuxError::ENOSYS),
+        }
+    })
+}
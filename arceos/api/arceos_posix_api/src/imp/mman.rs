@@ -0,0 +1,228 @@
+use core::ffi::{c_int, c_void};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::AddrSpaceLock;
+use memory_addr::VirtAddrRange;
+
+use crate::ctypes;
+
+fn prot_to_flags(prot: c_int) -> LinuxResult<MappingFlags> {
+    let prot = prot as u32;
+    if prot & !(ctypes::PROT_READ | ctypes::PROT_WRITE | ctypes::PROT_EXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut flags = MappingFlags::empty();
+    if prot & ctypes::PROT_READ != 0 {
+        flags |= MappingFlags::READ;
+    }
+    if prot & ctypes::PROT_WRITE != 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    if prot & ctypes::PROT_EXEC != 0 {
+        flags |= MappingFlags::EXECUTE;
+    }
+    Ok(flags)
+}
+
+/// Checks the alignment of `addr`, and returns the range aligned up to pages,
+/// which should be within [`axmm::mmap_range`].
+fn page_range(addr: *mut c_void, len: usize) -> LinuxResult<(VirtAddr, usize)> {
+    let start = VirtAddr::from(addr as usize);
+    if !start.is_aligned_4k() || len == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let size = len.align_up_4k();
+    let in_range = (addr as usize).checked_add(size).is_some_and(|end| {
+        axmm::mmap_range().contains_range(VirtAddrRange::new(start, end.into()))
+    });
+    if !in_range {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok((start, size))
+}
+
+/// Map files or anonymous memory into the kernel address space.
+///
+/// Only `MAP_SHARED`, `MAP_PRIVATE`, `MAP_FIXED`, `MAP_ANONYMOUS`,
+/// `MAP_POPULATE` and `MAP_HUGETLB` are supported. Return the start address of
+/// the mapping.
+pub fn sys_mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    off: ctypes::off_t,
+) -> isize {
+    debug!(
+        "sys_mmap <= {:#x} {:#x} {:#x} {:#x} {} {:#x}",
+        addr as usize, len, prot, flags, fd, off
+    );
+    syscall_body!(sys_mmap, {
+        let map_flags = flags as u32;
+        let shared = match map_flags & ctypes::MAP_TYPE {
+            ctypes::MAP_SHARED | ctypes::MAP_SHARED_VALIDATE => true,
+            ctypes::MAP_PRIVATE => false,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let mapping_flags = prot_to_flags(prot)?;
+        if len == 0 || off < 0 || off as usize % PAGE_SIZE_4K != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let size = len.align_up_4k();
+        let anonymous = map_flags & ctypes::MAP_ANONYMOUS != 0;
+        // Get the file before locking the address space, which may sleep.
+        #[cfg(feature = "fs")]
+        let node = if anonymous {
+            None
+        } else {
+            let write = shared && mapping_flags.contains(MappingFlags::WRITE);
+            Some(super::fs::get_mmap_node(fd, write)?)
+        };
+        #[cfg(not(feature = "fs"))]
+        if !anonymous {
+            let _ = shared;
+            return Err(LinuxError::ENODEV);
+        }
+
+        let fixed = map_flags & ctypes::MAP_FIXED != 0;
+        if fixed {
+            let (start, size) = page_range(addr, size)?;
+            // The dirty pages of the replaced file mappings are written back
+            // without the lock.
+            axmm::kernel_aspace().unmap(start, size)?;
+        }
+        let mut aspace = axmm::kernel_aspace().lock();
+        let start = if fixed {
+            VirtAddr::from(addr as usize)
+        } else {
+            let hint = VirtAddr::from(addr as usize).align_down_4k();
+            let limit = axmm::mmap_range();
+            aspace
+                .find_free_area(hint.max(limit.start), size, limit)
+                .or_else(|| aspace.find_free_area(limit.start, size, limit))
+                .ok_or(LinuxError::ENOMEM)?
+        };
+
+        #[cfg(feature = "fs")]
+        if let Some(node) = node {
+            aspace.map_file(start, size, mapping_flags, node, off as usize, shared)?;
+            return Ok(start.as_usize());
+        }
+        let populate = map_flags & ctypes::MAP_POPULATE != 0;
+        let page_size = if map_flags & ctypes::MAP_HUGETLB != 0 {
+            PageSize::Size2M
+        } else {
+            PageSize::Size4K
+        };
+        aspace.map_alloc_huge(start, size, mapping_flags, populate, page_size)?;
+        Ok(start.as_usize())
+    })
+}
+
+/// Unmap the pages within the specified address range.
+pub fn sys_munmap(addr: *mut c_void, len: usize) -> c_int {
+    debug!("sys_munmap <= {:#x} {:#x}", addr as usize, len);
+    syscall_body!(sys_munmap, {
+        let (start, size) = page_range(addr, len)?;
+        axmm::kernel_aspace().unmap(start, size)?;
+        Ok(0)
+    })
+}
+
+/// Expand or shrink a mapping, and possibly move it if `MREMAP_MAYMOVE` is
+/// set. Return the new start address of the mapping.
+///
+/// `MREMAP_FIXED` and `MREMAP_DONTUNMAP` are not supported.
+pub fn sys_mremap(old_addr: *mut c_void, old_size: usize, new_size: usize, flags: c_int) -> isize {
+    debug!(
+        "sys_mremap <= {:#x} {:#x} {:#x} {:#x}",
+        old_addr as usize, old_size, new_size, flags
+    );
+    syscall_body!(sys_mremap, {
+        if flags as u32 & !ctypes::MREMAP_MAYMOVE != 0 || new_size == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let (start, old_size) = page_range(old_addr, old_size)?;
+        let may_move = flags as u32 & ctypes::MREMAP_MAYMOVE != 0;
+        let new_start =
+            axmm::kernel_aspace().mremap(start, old_size, new_size.align_up_4k(), may_move)?;
+        Ok(new_start.as_usize())
+    })
+}
+
+/// Change the access protections of the pages within the specified address
+/// range.
+pub fn sys_mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int {
+    debug!(
+        "sys_mprotect <= {:#x} {:#x} {:#x}",
+        addr as usize, len, prot
+    );
+    syscall_body!(sys_mprotect, {
+        let (start, size) = page_range(addr, len)?;
+        let flags = prot_to_flags(prot)?;
+        axmm::kernel_aspace().protect(start, size, flags)?;
+        Ok(0)
+    })
+}
+
+/// Give advice about the use of memory.
+///
+/// Only `MADV_DONTNEED` takes effect, which drops the pages within the range.
+/// The other advice is accepted and ignored.
+pub fn sys_madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int {
+    debug!("sys_madvise <= {:#x} {:#x} {}", addr as usize, len, advice);
+    syscall_body!(sys_madvise, {
+        let (start, size) = page_range(addr, len)?;
+        match advice as u32 {
+            ctypes::MADV_DONTNEED => axmm::kernel_aspace().discard(start, size)?,
+            ctypes::MADV_NORMAL
+            | ctypes::MADV_RANDOM
+            | ctypes::MADV_SEQUENTIAL
+            | ctypes::MADV_WILLNEED => {}
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+/// Lock the pages within the specified address range in memory.
+pub fn sys_mlock(addr: *const c_void, len: usize) -> c_int {
+    debug!("sys_mlock <= {:#x} {:#x}", addr as usize, len);
+    syscall_body!(sys_mlock, {
+        let start = VirtAddr::from(addr as usize).align_down_4k();
+        let size = (addr as usize + len).align_up_4k() - start.as_usize();
+        axmm::kernel_aspace().mlock(start, size)?;
+        Ok(0)
+    })
+}
+
+/// Unlock the pages within the specified address range.
+pub fn sys_munlock(addr: *const c_void, len: usize) -> c_int {
+    debug!("sys_munlock <= {:#x} {:#x}", addr as usize, len);
+    syscall_body!(sys_munlock, {
+        let start = VirtAddr::from(addr as usize).align_down_4k();
+        let size = (addr as usize + len).align_up_4k() - start.as_usize();
+        axmm::kernel_aspace().lock().munlock(start, size)?;
+        Ok(0)
+    })
+}
+
+/// Write the changes of the shared file mappings within the specified address
+/// range back to the files.
+pub fn sys_msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int {
+    debug!("sys_msync <= {:#x} {:#x} {:#x}", addr as usize, len, flags);
+    syscall_body!(sys_msync, {
+        let flags = flags as u32;
+        if flags & !(ctypes::MS_ASYNC | ctypes::MS_INVALIDATE | ctypes::MS_SYNC) != 0
+            || flags & ctypes::MS_ASYNC != 0 && flags & ctypes::MS_SYNC != 0
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let (start, size) = page_range(addr, len)?;
+        axmm::kernel_aspace().msync(start, size)?;
+        Ok(0)
+    })
+}
@@ -2,6 +2,7 @@ mod stdio;
 
 pub mod io;
 pub mod resources;
+pub mod signal;
 pub mod sys;
 pub mod task;
 pub mod time;
@@ -10,11 +11,17 @@ pub mod time;
 pub mod fd_ops;
 #[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "multitask")]
+pub mod futex;
 #[cfg(any(feature = "select", feature = "epoll"))]
 pub mod io_mpx;
+#[cfg(feature = "paging")]
+pub mod mman;
 #[cfg(feature = "net")]
 pub mod net;
 #[cfg(feature = "pipe")]
 pub mod pipe;
 #[cfg(feature = "multitask")]
 pub mod pthread;
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub mod timer;
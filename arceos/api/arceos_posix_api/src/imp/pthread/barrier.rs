@@ -0,0 +1,67 @@
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+use axerrno::LinuxError;
+use axsync::Barrier;
+
+use core::ffi::{c_int, c_uint};
+use core::mem::size_of;
+
+static_assertions::const_assert_eq!(
+    size_of::<ctypes::pthread_barrier_t>(),
+    size_of::<PthreadBarrier>()
+);
+
+/// A `pthread_barrier_t`.
+#[repr(transparent)]
+pub struct PthreadBarrier(Barrier);
+
+/// Initialize a barrier that blocks until `count` threads have waited on it,
+/// `attr` is ignored.
+pub unsafe fn sys_pthread_barrier_init(
+    barrier: *mut ctypes::pthread_barrier_t,
+    _attr: *const ctypes::pthread_barrierattr_t,
+    count: c_uint,
+) -> c_int {
+    debug!(
+        "sys_pthread_barrier_init <= {:#x}, {}",
+        barrier as usize, count
+    );
+    syscall_body!(sys_pthread_barrier_init, {
+        check_null_mut_ptr(barrier)?;
+        if count == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        unsafe {
+            barrier
+                .cast::<PthreadBarrier>()
+                .write(PthreadBarrier(Barrier::new(count as usize)))
+        };
+        Ok(0)
+    })
+}
+
+/// Destroy a barrier.
+pub unsafe fn sys_pthread_barrier_destroy(barrier: *mut ctypes::pthread_barrier_t) -> c_int {
+    debug!("sys_pthread_barrier_destroy <= {:#x}", barrier as usize);
+    syscall_body!(sys_pthread_barrier_destroy, {
+        check_null_mut_ptr(barrier)?;
+        Ok(0)
+    })
+}
+
+/// Wait until enough threads have waited on the barrier.
+///
+/// Returns [`ctypes::PTHREAD_BARRIER_SERIAL_THREAD`] in one of the threads,
+/// and 0 in the others.
+pub unsafe fn sys_pthread_barrier_wait(barrier: *mut ctypes::pthread_barrier_t) -> c_int {
+    debug!("sys_pthread_barrier_wait <= {:#x}", barrier as usize);
+    syscall_body!(sys_pthread_barrier_wait, {
+        check_null_mut_ptr(barrier)?;
+        if unsafe { (*barrier.cast::<PthreadBarrier>()).0.wait() }.is_leader() {
+            Ok(ctypes::PTHREAD_BARRIER_SERIAL_THREAD)
+        } else {
+            Ok(0)
+        }
+    })
+}
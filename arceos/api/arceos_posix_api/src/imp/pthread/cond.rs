@@ -0,0 +1,160 @@
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+use axerrno::LinuxResult;
+use axtask::WaitQueue;
+
+use core::ffi::c_int;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::mutex::PthreadMutex;
+
+static_assertions::const_assert_eq!(
+    size_of::<ctypes::pthread_cond_t>(),
+    size_of::<PthreadCond>()
+);
+
+/// The clock bits of `pthread_condattr_t::__attr`, same as musl.
+const CONDATTR_CLOCK_MASK: u32 = 0x7fff_ffff;
+
+/// A `pthread_cond_t`.
+#[repr(C)]
+pub struct PthreadCond {
+    wq: WaitQueue,
+    /// Increased on each signal, so that the signals between unlocking the
+    /// mutex and blocking are not missed.
+    seq: AtomicU32,
+    /// The clock of `pthread_cond_timedwait`.
+    clock: ctypes::clockid_t,
+}
+
+impl PthreadCond {
+    const fn new(clock: ctypes::clockid_t) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            seq: AtomicU32::new(0),
+            clock,
+        }
+    }
+
+    fn wait(&self, mutex: &PthreadMutex) -> LinuxResult {
+        let seq = self.seq.load(Ordering::Acquire);
+        mutex.unlock()?;
+        self.wq
+            .wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+
+    #[cfg(feature = "irq")]
+    fn wait_timeout(&self, mutex: &PthreadMutex, dur: core::time::Duration) -> LinuxResult {
+        let seq = self.seq.load(Ordering::Acquire);
+        mutex.unlock()?;
+        let timed_out = self
+            .wq
+            .wait_timeout_until(dur, || self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()?;
+        if timed_out {
+            Err(axerrno::LinuxError::ETIMEDOUT)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn notify(&self, all: bool) {
+        self.seq.fetch_add(1, Ordering::Release);
+        if all {
+            self.wq.notify_all(true);
+        } else {
+            self.wq.notify_one(true);
+        }
+    }
+}
+
+/// Initialize a condition variable.
+///
+/// The clock of `attr` is used by [`sys_pthread_cond_timedwait`].
+pub unsafe fn sys_pthread_cond_init(
+    cond: *mut ctypes::pthread_cond_t,
+    attr: *const ctypes::pthread_condattr_t,
+) -> c_int {
+    debug!("sys_pthread_cond_init <= {:#x}", cond as usize);
+    syscall_body!(sys_pthread_cond_init, {
+        check_null_mut_ptr(cond)?;
+        let clock = match unsafe { attr.as_ref() } {
+            Some(attr) => (attr.__attr & CONDATTR_CLOCK_MASK) as _,
+            None => ctypes::CLOCK_REALTIME as _,
+        };
+        unsafe { cond.cast::<PthreadCond>().write(PthreadCond::new(clock)) };
+        Ok(0)
+    })
+}
+
+/// Destroy a condition variable.
+pub unsafe fn sys_pthread_cond_destroy(cond: *mut ctypes::pthread_cond_t) -> c_int {
+    debug!("sys_pthread_cond_destroy <= {:#x}", cond as usize);
+    syscall_body!(sys_pthread_cond_destroy, {
+        check_null_mut_ptr(cond)?;
+        Ok(0)
+    })
+}
+
+/// Unlock `mutex` and wait on the condition variable, `mutex` is locked again
+/// before return.
+pub unsafe fn sys_pthread_cond_wait(
+    cond: *mut ctypes::pthread_cond_t,
+    mutex: *mut ctypes::pthread_mutex_t,
+) -> c_int {
+    debug!(
+        "sys_pthread_cond_wait <= {:#x}, {:#x}",
+        cond as usize, mutex as usize
+    );
+    syscall_body!(sys_pthread_cond_wait, {
+        check_null_mut_ptr(cond)?;
+        check_null_mut_ptr(mutex)?;
+        unsafe { (*cond.cast::<PthreadCond>()).wait(&*mutex.cast::<PthreadMutex>())? };
+        Ok(0)
+    })
+}
+
+/// Same as [`sys_pthread_cond_wait`], but returns `ETIMEDOUT` if the condition
+/// variable is not signaled before the absolute time `abstime` of its clock.
+#[cfg(feature = "irq")]
+pub unsafe fn sys_pthread_cond_timedwait(
+    cond: *mut ctypes::pthread_cond_t,
+    mutex: *mut ctypes::pthread_mutex_t,
+    abstime: *const ctypes::timespec,
+) -> c_int {
+    debug!(
+        "sys_pthread_cond_timedwait <= {:#x}, {:#x}",
+        cond as usize, mutex as usize
+    );
+    syscall_body!(sys_pthread_cond_timedwait, {
+        check_null_mut_ptr(cond)?;
+        check_null_mut_ptr(mutex)?;
+        let cond = unsafe { &*cond.cast::<PthreadCond>() };
+        let dur = unsafe { super::timeout_from_abstime(cond.clock, abstime)? };
+        unsafe { cond.wait_timeout(&*mutex.cast::<PthreadMutex>(), dur)? };
+        Ok(0)
+    })
+}
+
+/// Wake up one of the threads waiting on the condition variable.
+pub unsafe fn sys_pthread_cond_signal(cond: *mut ctypes::pthread_cond_t) -> c_int {
+    debug!("sys_pthread_cond_signal <= {:#x}", cond as usize);
+    syscall_body!(sys_pthread_cond_signal, {
+        check_null_mut_ptr(cond)?;
+        unsafe { (*cond.cast::<PthreadCond>()).notify(false) };
+        Ok(0)
+    })
+}
+
+/// Wake up all threads waiting on the condition variable.
+pub unsafe fn sys_pthread_cond_broadcast(cond: *mut ctypes::pthread_cond_t) -> c_int {
+    debug!("sys_pthread_cond_broadcast <= {:#x}", cond as usize);
+    syscall_body!(sys_pthread_cond_broadcast, {
+        check_null_mut_ptr(cond)?;
+        unsafe { (*cond.cast::<PthreadCond>()).notify(true) };
+        Ok(0)
+    })
+}
@@ -0,0 +1,136 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ffi::{c_int, c_void};
+
+use axerrno::{LinuxError, LinuxResult};
+use spin::Mutex;
+
+use super::Pthread;
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+const KEYS_MAX: usize = ctypes::PTHREAD_KEYS_MAX as usize;
+
+type Destructor = unsafe extern "C" fn(*mut c_void);
+
+/// The values of the keys in a thread, with the sequence number of the key
+/// when the value is set.
+pub(super) type ThreadSpecific = BTreeMap<ctypes::pthread_key_t, (u64, *mut c_void)>;
+
+#[derive(Clone, Copy)]
+struct Key {
+    in_use: bool,
+    /// Increased on each creation, so that the values of a deleted key are
+    /// not seen by the key created later in the same slot.
+    seq: u64,
+    destructor: Option<Destructor>,
+}
+
+static KEYS: Mutex<[Key; KEYS_MAX]> = Mutex::new(
+    [Key {
+        in_use: false,
+        seq: 0,
+        destructor: None,
+    }; KEYS_MAX],
+);
+
+/// Returns the sequence number of `key` if it is in use.
+fn key_seq(key: ctypes::pthread_key_t) -> LinuxResult<u64> {
+    match KEYS.lock().get(key as usize) {
+        Some(k) if k.in_use => Ok(k.seq),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+fn current_specific() -> LinuxResult<&'static mut ThreadSpecific> {
+    let thread = Pthread::current().ok_or(LinuxError::ESRCH)?;
+    Ok(unsafe { &mut *thread.specific.get() })
+}
+
+/// Calls the destructors of the non-null values in `specific`, until there
+/// are no such values or after `PTHREAD_DESTRUCTOR_ITERATIONS` times.
+pub(super) fn run_destructors(specific: &mut ThreadSpecific) {
+    for _ in 0..ctypes::PTHREAD_DESTRUCTOR_ITERATIONS {
+        let pending: Vec<_> = {
+            let keys = KEYS.lock();
+            specific
+                .iter_mut()
+                .filter_map(|(&key, (seq, value))| {
+                    let k = &keys[key as usize];
+                    let destructor = k.destructor.filter(|_| k.in_use && k.seq == *seq)?;
+                    let value = core::mem::replace(value, core::ptr::null_mut());
+                    (!value.is_null()).then_some((destructor, value))
+                })
+                .collect()
+        };
+        if pending.is_empty() {
+            break;
+        }
+        for (destructor, value) in pending {
+            unsafe { destructor(value) };
+        }
+    }
+    specific.clear();
+}
+
+/// Create a thread-specific data key, visible to all threads.
+///
+/// When a thread exits, `destructor` is called with its value of the key if
+/// the value is not null.
+pub unsafe fn sys_pthread_key_create(
+    key: *mut ctypes::pthread_key_t,
+    destructor: Option<Destructor>,
+) -> c_int {
+    debug!("sys_pthread_key_create <= {:#x}", key as usize);
+    syscall_body!(sys_pthread_key_create, {
+        check_null_mut_ptr(key)?;
+        let mut keys = KEYS.lock();
+        let (idx, k) = keys
+            .iter_mut()
+            .enumerate()
+            .find(|(_, k)| !k.in_use)
+            .ok_or(LinuxError::EAGAIN)?;
+        k.in_use = true;
+        k.seq += 1;
+        k.destructor = destructor;
+        unsafe { key.write(idx as _) };
+        Ok(0)
+    })
+}
+
+/// Delete a thread-specific data key, the destructor is not called.
+pub fn sys_pthread_key_delete(key: ctypes::pthread_key_t) -> c_int {
+    debug!("sys_pthread_key_delete <= {}", key);
+    syscall_body!(sys_pthread_key_delete, {
+        match KEYS.lock().get_mut(key as usize) {
+            Some(k) if k.in_use => k.in_use = false,
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+/// Returns the value of the key in the current thread, or null if it is not
+/// set.
+pub fn sys_pthread_getspecific(key: ctypes::pthread_key_t) -> *mut c_void {
+    let Ok(seq) = key_seq(key) else {
+        return core::ptr::null_mut();
+    };
+    match current_specific()
+        .ok()
+        .and_then(|specific| specific.get(&key))
+    {
+        Some(&(s, value)) if s == seq => value,
+        _ => core::ptr::null_mut(),
+    }
+}
+
+/// Set the value of the key in the current thread.
+pub fn sys_pthread_setspecific(key: ctypes::pthread_key_t, value: *const c_void) -> c_int {
+    debug!("sys_pthread_setspecific <= {}, {:#x}", key, value as usize);
+    syscall_body!(sys_pthread_setspecific, {
+        let seq = key_seq(key)?;
+        current_specific()?.insert(key, (seq, value as *mut c_void));
+        Ok(0)
+    })
+}
@@ -1,6 +1,7 @@
 use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
 use core::cell::UnsafeCell;
 use core::ffi::{c_int, c_void};
+use core::sync::atomic::{AtomicU8, Ordering};
 
 use axerrno::{LinuxError, LinuxResult};
 use axtask::AxTaskRef;
@@ -8,7 +9,18 @@ use spin::RwLock;
 
 use crate::ctypes;
 
+pub mod barrier;
+pub mod cond;
+pub mod key;
 pub mod mutex;
+pub mod once;
+pub mod rwlock;
+pub mod sem;
+
+/// The thread has exited.
+const EXITED: u8 = 1;
+/// The thread is detached, it is freed on exit instead of being joined.
+const DETACHED: u8 = 2;
 
 lazy_static::lazy_static! {
     static ref TID_TO_PTHREAD: RwLock<BTreeMap<u64, ForceSendSync<ctypes::pthread_t>>> = {
@@ -20,6 +32,8 @@ lazy_static::lazy_static! {
             retval: Arc::new(Packet {
                 result: UnsafeCell::new(core::ptr::null_mut()),
             }),
+            state: AtomicU8::new(0),
+            specific: UnsafeCell::new(key::ThreadSpecific::new()),
         };
         let ptr = Box::into_raw(Box::new(main_thread)) as *mut c_void;
         map.insert(main_tid, ForceSendSync(ptr));
@@ -37,36 +51,59 @@ unsafe impl<T> Sync for Packet<T> {}
 pub struct Pthread {
     inner: AxTaskRef,
     retval: Arc<Packet<*mut c_void>>,
+    /// [`EXITED`] and [`DETACHED`] flags.
+    state: AtomicU8,
+    /// The thread-specific data, only accessed by the thread itself.
+    specific: UnsafeCell<key::ThreadSpecific>,
 }
 
 impl Pthread {
     fn create(
-        _attr: *const ctypes::pthread_attr_t,
+        attr: *const ctypes::pthread_attr_t,
         start_routine: extern "C" fn(arg: *mut c_void) -> *mut c_void,
         arg: *mut c_void,
     ) -> LinuxResult<ctypes::pthread_t> {
         let arg_wrapper = ForceSendSync(arg);
+        let (stack_size, detached) = match unsafe { attr.as_ref() } {
+            Some(attr) => attr_stack_size_and_detached(attr),
+            None => (0, false),
+        };
 
         let my_packet: Arc<Packet<*mut c_void>> = Arc::new(Packet {
             result: UnsafeCell::new(core::ptr::null_mut()),
         });
         let their_packet = my_packet.clone();
+        // Locked until the thread is inserted, in case it looks up itself
+        // before that. The map itself is not locked during spawning, as the
+        // new thread may run first on this CPU and spin on it.
+        let inserted = Arc::new(axsync::Mutex::new(()));
+        let their_inserted = inserted.clone();
 
         let main = move || {
+            drop(their_inserted.lock());
             let arg = arg_wrapper;
             let ret = start_routine(arg.0);
             unsafe { *their_packet.result.get() = ret };
             drop(their_packet);
+            Self::on_exit();
         };
 
-        let task_inner = axtask::spawn(main);
+        let guard = inserted.lock();
+        let mut builder = axtask::TaskBuilder::new();
+        if stack_size != 0 {
+            builder = builder.stack_size(stack_size);
+        }
+        let task_inner = builder.spawn(main);
         let tid = task_inner.id().as_u64();
         let thread = Pthread {
             inner: task_inner,
             retval: my_packet,
+            state: AtomicU8::new(if detached { DETACHED } else { 0 }),
+            specific: UnsafeCell::new(key::ThreadSpecific::new()),
         };
         let ptr = Box::into_raw(Box::new(thread)) as *mut c_void;
         TID_TO_PTHREAD.write().insert(tid, ForceSendSync(ptr));
+        drop(guard);
         Ok(ptr)
     }
 
@@ -85,13 +122,53 @@ impl Pthread {
     fn exit_current(retval: *mut c_void) -> ! {
         let thread = Self::current().expect("fail to get current thread");
         unsafe { *thread.retval.result.get() = retval };
+        Self::on_exit();
         axtask::exit(0);
     }
 
+    /// Runs the destructors of the thread-specific data, and frees the
+    /// current thread if it is detached.
+    fn on_exit() {
+        let ptr = Self::current_ptr();
+        let Some(thread) = (unsafe { ptr.as_ref() }) else {
+            return;
+        };
+        key::run_destructors(unsafe { &mut *thread.specific.get() });
+        if thread.state.fetch_or(EXITED, Ordering::AcqRel) & DETACHED != 0 {
+            unsafe { Self::free(ptr) };
+        }
+    }
+
+    /// Frees an exited or detached thread.
+    unsafe fn free(ptr: *mut Pthread) {
+        let thread = unsafe { Box::from_raw(ptr) };
+        TID_TO_PTHREAD.write().remove(&thread.inner.id().as_u64());
+    }
+
+    fn detach(ptr: ctypes::pthread_t) -> LinuxResult {
+        let thread = unsafe { &*(ptr as *const Pthread) };
+        let state = thread.state.fetch_or(DETACHED, Ordering::AcqRel);
+        if state & DETACHED != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if state & EXITED != 0 {
+            unsafe { Self::free(ptr as *mut Pthread) };
+        }
+        Ok(())
+    }
+
     fn join(ptr: ctypes::pthread_t) -> LinuxResult<*mut c_void> {
         if core::ptr::eq(ptr, Self::current_ptr() as _) {
             return Err(LinuxError::EDEADLK);
         }
+        if unsafe { &*(ptr as *const Pthread) }
+            .state
+            .load(Ordering::Acquire)
+            & DETACHED
+            != 0
+        {
+            return Err(LinuxError::EINVAL);
+        }
 
         let thread = unsafe { Box::from_raw(ptr as *mut Pthread) };
         thread.inner.join();
@@ -103,6 +180,43 @@ impl Pthread {
     }
 }
 
+/// Returns the stack size (0 for the default) and whether the thread is
+/// created detached, same layout as musl.
+fn attr_stack_size_and_detached(attr: &ctypes::pthread_attr_t) -> (usize, bool) {
+    const SU: usize = core::mem::size_of::<usize>() / core::mem::size_of::<c_int>();
+    unsafe {
+        (
+            attr.__u.__s[0] as usize,
+            attr.__u.__i[3 * SU] == ctypes::PTHREAD_CREATE_DETACHED as c_int,
+        )
+    }
+}
+
+/// Converts an absolute timeout of the given clock to the duration from now.
+pub(crate) unsafe fn timeout_from_abstime(
+    clock: ctypes::clockid_t,
+    abstime: *const ctypes::timespec,
+) -> LinuxResult<core::time::Duration> {
+    let abstime = unsafe { abstime.as_ref() }.ok_or(LinuxError::EINVAL)?;
+    if !(0..1_000_000_000).contains(&abstime.tv_nsec) || abstime.tv_sec < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let now = match clock as u32 {
+        ctypes::CLOCK_REALTIME => axhal::time::wall_time(),
+        ctypes::CLOCK_MONOTONIC => axhal::time::monotonic_time(),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    Ok(core::time::Duration::from(*abstime).saturating_sub(now))
+}
+
+/// Returns the task of the thread whose ID is `tid`.
+pub(crate) fn find_task(tid: u64) -> Option<AxTaskRef> {
+    TID_TO_PTHREAD
+        .read()
+        .get(&tid)
+        .map(|ptr| unsafe { &*(ptr.0 as *const Pthread) }.inner.clone())
+}
+
 /// Returns the `pthread` struct of current thread.
 pub fn sys_pthread_self() -> ctypes::pthread_t {
     Pthread::current().expect("fail to get current thread") as *const Pthread as _
@@ -135,6 +249,41 @@ pub fn sys_pthread_exit(retval: *mut c_void) -> ! {
     Pthread::exit_current(retval);
 }
 
+/// Set the CPU affinity mask of the given thread.
+///
+/// If it is the calling thread and it is not allowed to run on the current CPU
+/// anymore, it is migrated immediately.
+pub unsafe fn sys_pthread_setaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_pthread_setaffinity_np <= {:#x} {:#x}",
+        thread as usize, cpuset as usize
+    );
+    syscall_body!(sys_pthread_setaffinity_np, {
+        let mask = unsafe { crate::imp::task::cpu_set_to_mask(cpusetsize, cpuset)? };
+        if core::ptr::eq(thread, Pthread::current_ptr() as _) {
+            axtask::set_current_affinity(mask);
+        } else {
+            let thread = unsafe { &*(thread as *const Pthread) };
+            thread.inner.set_cpumask(mask);
+        }
+        Ok(0)
+    })
+}
+
+/// Detach the given thread, its resources are released on exit without
+/// being joined.
+pub unsafe fn sys_pthread_detach(thread: ctypes::pthread_t) -> c_int {
+    debug!("sys_pthread_detach <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_detach, {
+        Pthread::detach(thread)?;
+        Ok(0)
+    })
+}
+
 /// Waits for the given thread to exit, and stores the return value in `retval`.
 pub unsafe fn sys_pthread_join(thread: ctypes::pthread_t, retval: *mut *mut c_void) -> c_int {
     debug!("sys_pthread_join <= {:#x}", retval as usize);
@@ -1,7 +1,8 @@
-use crate::{ctypes, utils::check_null_mut_ptr};
+use crate::ctypes;
+use crate::utils::{check_null_mut_ptr, check_null_ptr};
 
-use axerrno::LinuxResult;
-use axsync::Mutex;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::{Mutex, PiMutex};
 
 use core::ffi::c_int;
 use core::mem::{size_of, ManuallyDrop};
@@ -11,35 +12,127 @@ static_assertions::const_assert_eq!(
     size_of::<PthreadMutex>()
 );
 
+/// The bit of `pthread_mutexattr_t::__attr` for [`ctypes::PTHREAD_PRIO_INHERIT`],
+/// same as musl.
+const MUTEXATTR_PRIO_INHERIT: u32 = 8;
+
+#[repr(C)]
+union RawMutex {
+    normal: ManuallyDrop<Mutex<()>>,
+    pi: ManuallyDrop<PiMutex<()>>,
+}
+
+/// A `pthread_mutex_t`, which is a normal mutex or a mutex with priority
+/// inheritance depending on the protocol in its attributes.
+///
+/// A zeroed `is_pi` is required by `PTHREAD_MUTEX_INITIALIZER`.
 #[repr(C)]
-pub struct PthreadMutex(Mutex<()>);
+pub struct PthreadMutex {
+    raw: RawMutex,
+    is_pi: bool,
+}
 
 impl PthreadMutex {
-    const fn new() -> Self {
-        Self(Mutex::new(()))
+    const fn new(is_pi: bool) -> Self {
+        let raw = if is_pi {
+            RawMutex {
+                pi: ManuallyDrop::new(PiMutex::new(())),
+            }
+        } else {
+            RawMutex {
+                normal: ManuallyDrop::new(Mutex::new(())),
+            }
+        };
+        Self { raw, is_pi }
     }
 
-    fn lock(&self) -> LinuxResult {
-        let _guard = ManuallyDrop::new(self.0.lock());
+    pub(crate) fn lock(&self) -> LinuxResult {
+        unsafe {
+            if self.is_pi {
+                let _guard = ManuallyDrop::new(self.raw.pi.lock());
+            } else {
+                let _guard = ManuallyDrop::new(self.raw.normal.lock());
+            }
+        }
         Ok(())
     }
 
-    fn unlock(&self) -> LinuxResult {
-        unsafe { self.0.force_unlock() };
+    fn try_lock(&self) -> LinuxResult {
+        let locked = unsafe {
+            if self.is_pi {
+                self.raw.pi.try_lock().map(ManuallyDrop::new).is_some()
+            } else {
+                self.raw.normal.try_lock().map(ManuallyDrop::new).is_some()
+            }
+        };
+        if locked {
+            Ok(())
+        } else {
+            Err(LinuxError::EBUSY)
+        }
+    }
+
+    #[cfg(feature = "irq")]
+    fn lock_timeout(&self, dur: core::time::Duration) -> LinuxResult {
+        let locked = unsafe {
+            if self.is_pi {
+                self.raw
+                    .pi
+                    .lock_timeout(dur)
+                    .map(ManuallyDrop::new)
+                    .is_some()
+            } else {
+                self.raw
+                    .normal
+                    .lock_timeout(dur)
+                    .map(ManuallyDrop::new)
+                    .is_some()
+            }
+        };
+        if locked {
+            Ok(())
+        } else {
+            Err(LinuxError::ETIMEDOUT)
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        unsafe {
+            if self.is_pi {
+                self.raw.pi.is_locked()
+            } else {
+                self.raw.normal.is_locked()
+            }
+        }
+    }
+
+    pub(crate) fn unlock(&self) -> LinuxResult {
+        unsafe {
+            if self.is_pi {
+                self.raw.pi.force_unlock();
+            } else {
+                self.raw.normal.force_unlock();
+            }
+        }
         Ok(())
     }
 }
 
 /// Initialize a mutex.
-pub fn sys_pthread_mutex_init(
+///
+/// With the [`ctypes::PTHREAD_PRIO_INHERIT`] protocol in `attr`, the owner
+/// inherits the scheduling policy of the blocked threads if that is more
+/// urgent.
+pub unsafe fn sys_pthread_mutex_init(
     mutex: *mut ctypes::pthread_mutex_t,
-    _attr: *const ctypes::pthread_mutexattr_t,
+    attr: *const ctypes::pthread_mutexattr_t,
 ) -> c_int {
     debug!("sys_pthread_mutex_init <= {:#x}", mutex as usize);
     syscall_body!(sys_pthread_mutex_init, {
         check_null_mut_ptr(mutex)?;
+        let is_pi = !attr.is_null() && unsafe { (*attr).__attr } & MUTEXATTR_PRIO_INHERIT != 0;
         unsafe {
-            mutex.cast::<PthreadMutex>().write(PthreadMutex::new());
+            mutex.cast::<PthreadMutex>().write(PthreadMutex::new(is_pi));
         }
         Ok(0)
     })
@@ -57,6 +150,37 @@ pub fn sys_pthread_mutex_lock(mutex: *mut ctypes::pthread_mutex_t) -> c_int {
     })
 }
 
+/// Try to lock the given mutex, returns `EBUSY` if it is held.
+pub fn sys_pthread_mutex_trylock(mutex: *mut ctypes::pthread_mutex_t) -> c_int {
+    debug!("sys_pthread_mutex_trylock <= {:#x}", mutex as usize);
+    syscall_body!(sys_pthread_mutex_trylock, {
+        check_null_mut_ptr(mutex)?;
+        unsafe {
+            (*mutex.cast::<PthreadMutex>()).try_lock()?;
+        }
+        Ok(0)
+    })
+}
+
+/// Lock the given mutex, returns `ETIMEDOUT` if it is not locked before the
+/// absolute time `abstime` of `CLOCK_REALTIME`.
+#[cfg(feature = "irq")]
+pub unsafe fn sys_pthread_mutex_timedlock(
+    mutex: *mut ctypes::pthread_mutex_t,
+    abstime: *const ctypes::timespec,
+) -> c_int {
+    debug!("sys_pthread_mutex_timedlock <= {:#x}", mutex as usize);
+    syscall_body!(sys_pthread_mutex_timedlock, {
+        check_null_mut_ptr(mutex)?;
+        let mutex = unsafe { &*mutex.cast::<PthreadMutex>() };
+        if mutex.try_lock().is_err() {
+            let dur = unsafe { super::timeout_from_abstime(ctypes::CLOCK_REALTIME as _, abstime)? };
+            mutex.lock_timeout(dur)?;
+        }
+        Ok(0)
+    })
+}
+
 /// Unlock the given mutex.
 pub fn sys_pthread_mutex_unlock(mutex: *mut ctypes::pthread_mutex_t) -> c_int {
     debug!("sys_pthread_mutex_unlock <= {:#x}", mutex as usize);
@@ -68,3 +192,77 @@ pub fn sys_pthread_mutex_unlock(mutex: *mut ctypes::pthread_mutex_t) -> c_int {
         Ok(0)
     })
 }
+
+/// Destroy the given mutex.
+pub fn sys_pthread_mutex_destroy(mutex: *mut ctypes::pthread_mutex_t) -> c_int {
+    debug!("sys_pthread_mutex_destroy <= {:#x}", mutex as usize);
+    syscall_body!(sys_pthread_mutex_destroy, {
+        check_null_mut_ptr(mutex)?;
+        if unsafe { (*mutex.cast::<PthreadMutex>()).is_locked() } {
+            return Err(LinuxError::EBUSY);
+        }
+        Ok(0)
+    })
+}
+
+/// Initialize the mutex attributes with the default values.
+pub unsafe fn sys_pthread_mutexattr_init(attr: *mut ctypes::pthread_mutexattr_t) -> c_int {
+    debug!("sys_pthread_mutexattr_init <= {:#x}", attr as usize);
+    syscall_body!(sys_pthread_mutexattr_init, {
+        check_null_mut_ptr(attr)?;
+        unsafe { attr.write(ctypes::pthread_mutexattr_t::default()) };
+        Ok(0)
+    })
+}
+
+/// Destroy the mutex attributes.
+pub unsafe fn sys_pthread_mutexattr_destroy(attr: *mut ctypes::pthread_mutexattr_t) -> c_int {
+    debug!("sys_pthread_mutexattr_destroy <= {:#x}", attr as usize);
+    syscall_body!(sys_pthread_mutexattr_destroy, {
+        check_null_mut_ptr(attr)?;
+        Ok(0)
+    })
+}
+
+/// Set the protocol of the mutex attributes.
+///
+/// Only `PTHREAD_PRIO_NONE` and `PTHREAD_PRIO_INHERIT` are supported.
+pub unsafe fn sys_pthread_mutexattr_setprotocol(
+    attr: *mut ctypes::pthread_mutexattr_t,
+    protocol: c_int,
+) -> c_int {
+    debug!(
+        "sys_pthread_mutexattr_setprotocol <= {:#x}, {}",
+        attr as usize, protocol
+    );
+    syscall_body!(sys_pthread_mutexattr_setprotocol, {
+        check_null_mut_ptr(attr)?;
+        let attr = unsafe { &mut *attr };
+        match protocol as u32 {
+            ctypes::PTHREAD_PRIO_NONE => attr.__attr &= !MUTEXATTR_PRIO_INHERIT,
+            ctypes::PTHREAD_PRIO_INHERIT => attr.__attr |= MUTEXATTR_PRIO_INHERIT,
+            ctypes::PTHREAD_PRIO_PROTECT => return Err(LinuxError::EOPNOTSUPP),
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+/// Get the protocol of the mutex attributes.
+pub unsafe fn sys_pthread_mutexattr_getprotocol(
+    attr: *const ctypes::pthread_mutexattr_t,
+    protocol: *mut c_int,
+) -> c_int {
+    debug!("sys_pthread_mutexattr_getprotocol <= {:#x}", attr as usize);
+    syscall_body!(sys_pthread_mutexattr_getprotocol, {
+        check_null_ptr(attr)?;
+        check_null_mut_ptr(protocol)?;
+        let res = if unsafe { (*attr).__attr } & MUTEXATTR_PRIO_INHERIT != 0 {
+            ctypes::PTHREAD_PRIO_INHERIT
+        } else {
+            ctypes::PTHREAD_PRIO_NONE
+        };
+        unsafe { protocol.write(res as c_int) };
+        Ok(0)
+    })
+}
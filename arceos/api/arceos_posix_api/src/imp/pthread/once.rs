@@ -0,0 +1,41 @@
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+use axtask::WaitQueue;
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicI32, Ordering};
+
+const INCOMPLETE: i32 = 0;
+const RUNNING: i32 = 1;
+const COMPLETE: i32 = 2;
+
+/// The threads waiting for the `init_routine` of any `pthread_once_t` to
+/// complete.
+static ONCE_WQ: WaitQueue = WaitQueue::new();
+
+/// Call `init_routine` only once for all threads with the same `once_control`,
+/// other threads wait until it completes.
+pub unsafe fn sys_pthread_once(
+    once_control: *mut ctypes::pthread_once_t,
+    init_routine: extern "C" fn(),
+) -> c_int {
+    debug!("sys_pthread_once <= {:#x}", once_control as usize);
+    syscall_body!(sys_pthread_once, {
+        check_null_mut_ptr(once_control)?;
+        let state = unsafe { &*(once_control as *const AtomicI32) };
+        loop {
+            match state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    init_routine();
+                    state.store(COMPLETE, Ordering::Release);
+                    ONCE_WQ.notify_all(true);
+                    return Ok(0);
+                }
+                Err(COMPLETE) => return Ok(0),
+                Err(_) => ONCE_WQ.wait_until(|| state.load(Ordering::Acquire) != RUNNING),
+            }
+        }
+    })
+}
@@ -0,0 +1,128 @@
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+use axerrno::{LinuxError, LinuxResult};
+use axsync::RwLock;
+
+use core::ffi::c_int;
+use core::mem::{forget, size_of};
+
+static_assertions::const_assert_eq!(
+    size_of::<ctypes::pthread_rwlock_t>(),
+    size_of::<PthreadRwLock>()
+);
+
+/// A `pthread_rwlock_t`.
+///
+/// It prefers readers, so a thread can acquire the read lock recursively even
+/// if a writer is waiting.
+#[repr(transparent)]
+pub struct PthreadRwLock(RwLock<()>);
+
+impl PthreadRwLock {
+    const fn new() -> Self {
+        Self(RwLock::new(()))
+    }
+
+    fn try_read(&self) -> LinuxResult {
+        self.0
+            .try_read_recursive()
+            .map(forget)
+            .ok_or(LinuxError::EBUSY)
+    }
+
+    fn try_write(&self) -> LinuxResult {
+        self.0.try_write().map(forget).ok_or(LinuxError::EBUSY)
+    }
+
+    fn unlock(&self) -> LinuxResult {
+        unsafe {
+            if self.0.is_locked_exclusive() {
+                self.0.force_write_unlock();
+            } else if self.0.reader_count() > 0 {
+                self.0.force_read_decrement();
+            } else {
+                return Err(LinuxError::EPERM);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Initialize a read-write lock, `attr` is ignored.
+pub unsafe fn sys_pthread_rwlock_init(
+    rwlock: *mut ctypes::pthread_rwlock_t,
+    _attr: *const ctypes::pthread_rwlockattr_t,
+) -> c_int {
+    debug!("sys_pthread_rwlock_init <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_init, {
+        check_null_mut_ptr(rwlock)?;
+        unsafe { rwlock.cast::<PthreadRwLock>().write(PthreadRwLock::new()) };
+        Ok(0)
+    })
+}
+
+/// Destroy a read-write lock.
+pub unsafe fn sys_pthread_rwlock_destroy(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_destroy <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_destroy, {
+        check_null_mut_ptr(rwlock)?;
+        let rwlock = unsafe { &*rwlock.cast::<PthreadRwLock>() };
+        if rwlock.0.is_locked_exclusive() || rwlock.0.reader_count() > 0 {
+            return Err(LinuxError::EBUSY);
+        }
+        Ok(0)
+    })
+}
+
+/// Lock the read-write lock for reading.
+pub unsafe fn sys_pthread_rwlock_rdlock(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_rdlock <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_rdlock, {
+        check_null_mut_ptr(rwlock)?;
+        forget(unsafe { (*rwlock.cast::<PthreadRwLock>()).0.read_recursive() });
+        Ok(0)
+    })
+}
+
+/// Try to lock the read-write lock for reading, returns `EBUSY` if it is
+/// held by a writer.
+pub unsafe fn sys_pthread_rwlock_tryrdlock(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_tryrdlock <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_tryrdlock, {
+        check_null_mut_ptr(rwlock)?;
+        unsafe { (*rwlock.cast::<PthreadRwLock>()).try_read()? };
+        Ok(0)
+    })
+}
+
+/// Lock the read-write lock for writing.
+pub unsafe fn sys_pthread_rwlock_wrlock(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_wrlock <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_wrlock, {
+        check_null_mut_ptr(rwlock)?;
+        forget(unsafe { (*rwlock.cast::<PthreadRwLock>()).0.write() });
+        Ok(0)
+    })
+}
+
+/// Try to lock the read-write lock for writing, returns `EBUSY` if it is
+/// held.
+pub unsafe fn sys_pthread_rwlock_trywrlock(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_trywrlock <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_trywrlock, {
+        check_null_mut_ptr(rwlock)?;
+        unsafe { (*rwlock.cast::<PthreadRwLock>()).try_write()? };
+        Ok(0)
+    })
+}
+
+/// Unlock the read-write lock held by the current thread.
+pub unsafe fn sys_pthread_rwlock_unlock(rwlock: *mut ctypes::pthread_rwlock_t) -> c_int {
+    debug!("sys_pthread_rwlock_unlock <= {:#x}", rwlock as usize);
+    syscall_body!(sys_pthread_rwlock_unlock, {
+        check_null_mut_ptr(rwlock)?;
+        unsafe { (*rwlock.cast::<PthreadRwLock>()).unlock()? };
+        Ok(0)
+    })
+}
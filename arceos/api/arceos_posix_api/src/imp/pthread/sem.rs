@@ -0,0 +1,107 @@
+use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
+
+use axerrno::LinuxError;
+use axsync::Semaphore;
+
+use core::ffi::{c_int, c_uint};
+use core::mem::size_of;
+
+static_assertions::const_assert_eq!(size_of::<ctypes::sem_t>(), size_of::<PthreadSem>());
+
+/// A `sem_t`, only unnamed semaphores are supported.
+#[repr(transparent)]
+pub struct PthreadSem(Semaphore);
+
+/// Initialize an unnamed semaphore with the initial value `value`.
+pub unsafe fn sys_sem_init(sem: *mut ctypes::sem_t, _pshared: c_int, value: c_uint) -> c_int {
+    debug!("sys_sem_init <= {:#x}, {}", sem as usize, value);
+    syscall_body!(sys_sem_init, {
+        check_null_mut_ptr(sem)?;
+        if value > ctypes::SEM_VALUE_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        unsafe {
+            sem.cast::<PthreadSem>()
+                .write(PthreadSem(Semaphore::new(value as usize)))
+        };
+        Ok(0)
+    })
+}
+
+/// Destroy an unnamed semaphore.
+pub unsafe fn sys_sem_destroy(sem: *mut ctypes::sem_t) -> c_int {
+    debug!("sys_sem_destroy <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_destroy, {
+        check_null_mut_ptr(sem)?;
+        Ok(0)
+    })
+}
+
+/// Decrement the semaphore, blocking until its value is greater than zero.
+pub unsafe fn sys_sem_wait(sem: *mut ctypes::sem_t) -> c_int {
+    debug!("sys_sem_wait <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_wait, {
+        check_null_mut_ptr(sem)?;
+        unsafe { (*sem.cast::<PthreadSem>()).0.acquire() };
+        Ok(0)
+    })
+}
+
+/// Decrement the semaphore, returns `EAGAIN` if its value is zero.
+pub unsafe fn sys_sem_trywait(sem: *mut ctypes::sem_t) -> c_int {
+    debug!("sys_sem_trywait <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_trywait, {
+        check_null_mut_ptr(sem)?;
+        if !unsafe { (*sem.cast::<PthreadSem>()).0.try_acquire() } {
+            return Err(LinuxError::EAGAIN);
+        }
+        Ok(0)
+    })
+}
+
+/// Same as [`sys_sem_wait`], but returns `ETIMEDOUT` if the semaphore is not
+/// decremented before the absolute time `abstime` of `CLOCK_REALTIME`.
+#[cfg(feature = "irq")]
+pub unsafe fn sys_sem_timedwait(
+    sem: *mut ctypes::sem_t,
+    abstime: *const ctypes::timespec,
+) -> c_int {
+    debug!("sys_sem_timedwait <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_timedwait, {
+        check_null_mut_ptr(sem)?;
+        let sem = unsafe { &(*sem.cast::<PthreadSem>()).0 };
+        if !sem.try_acquire() {
+            let dur = unsafe { super::timeout_from_abstime(ctypes::CLOCK_REALTIME as _, abstime)? };
+            if !sem.acquire_timeout(dur) {
+                return Err(LinuxError::ETIMEDOUT);
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Increment the semaphore, waking up one of the waiting threads.
+pub unsafe fn sys_sem_post(sem: *mut ctypes::sem_t) -> c_int {
+    debug!("sys_sem_post <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_post, {
+        check_null_mut_ptr(sem)?;
+        let sem = unsafe { &(*sem.cast::<PthreadSem>()).0 };
+        if sem.available() >= ctypes::SEM_VALUE_MAX as usize {
+            return Err(LinuxError::EOVERFLOW);
+        }
+        sem.release();
+        Ok(0)
+    })
+}
+
+/// Get the current value of the semaphore.
+pub unsafe fn sys_sem_getvalue(sem: *mut ctypes::sem_t, sval: *mut c_int) -> c_int {
+    debug!("sys_sem_getvalue <= {:#x}", sem as usize);
+    syscall_body!(sys_sem_getvalue, {
+        check_null_mut_ptr(sem)?;
+        check_null_mut_ptr(sval)?;
+        unsafe { sval.write((*sem.cast::<PthreadSem>()).0.available() as c_int) };
+        Ok(0)
+    })
+}
@@ -2,8 +2,32 @@ use crate::ctypes;
 use axerrno::LinuxError;
 use core::ffi::c_int;
 
+/// Converts a limit in bytes to `rlim_t`, `usize::MAX` means unlimited.
+#[cfg(any(feature = "multitask", feature = "paging"))]
+fn to_rlim(limit: usize) -> ctypes::rlim_t {
+    if limit == usize::MAX {
+        ctypes::RLIM_INFINITY as _
+    } else {
+        limit as _
+    }
+}
+
+/// Converts `rlim_t` to a limit in bytes, `usize::MAX` means unlimited.
+#[cfg(any(feature = "multitask", feature = "paging"))]
+fn from_rlim(rlim: ctypes::rlim_t) -> usize {
+    if rlim == ctypes::RLIM_INFINITY as ctypes::rlim_t {
+        usize::MAX
+    } else {
+        rlim.try_into().unwrap_or(usize::MAX)
+    }
+}
+
 /// Get resource limitations
 ///
+/// `RLIMIT_DATA` is the heap limit of the current task group, while
+/// `RLIMIT_AS` and `RLIMIT_RSS` are the limits of the mappings of the
+/// application in the kernel address space (see [`axmm::mmap_range`]).
+///
 /// TODO: support more resource types
 pub unsafe fn sys_getrlimit(resource: c_int, rlimits: *mut ctypes::rlimit) -> c_int {
     debug!("sys_getrlimit <= {} {:#x}", resource, rlimits as usize);
@@ -12,22 +36,28 @@ pub unsafe fn sys_getrlimit(resource: c_int, rlimits: *mut ctypes::rlimit) -> c_
             ctypes::RLIMIT_DATA => {}
             ctypes::RLIMIT_STACK => {}
             ctypes::RLIMIT_NOFILE => {}
+            ctypes::RLIMIT_AS => {}
+            ctypes::RLIMIT_RSS => {}
             _ => return Err(LinuxError::EINVAL),
         }
         if rlimits.is_null() {
             return Ok(0);
         }
-        match resource as u32 {
-            ctypes::RLIMIT_STACK => unsafe {
-                (*rlimits).rlim_cur = axconfig::TASK_STACK_SIZE as _;
-                (*rlimits).rlim_max = axconfig::TASK_STACK_SIZE as _;
-            },
+        let limit = match resource as u32 {
+            ctypes::RLIMIT_STACK => axconfig::TASK_STACK_SIZE as _,
             #[cfg(feature = "fd")]
-            ctypes::RLIMIT_NOFILE => unsafe {
-                (*rlimits).rlim_cur = super::fd_ops::AX_FILE_LIMIT as _;
-                (*rlimits).rlim_max = super::fd_ops::AX_FILE_LIMIT as _;
-            },
-            _ => {}
+            ctypes::RLIMIT_NOFILE => super::fd_ops::AX_FILE_LIMIT as _,
+            #[cfg(feature = "multitask")]
+            ctypes::RLIMIT_DATA => to_rlim(axtask::current().mem_account().limit()),
+            #[cfg(feature = "paging")]
+            ctypes::RLIMIT_AS => to_rlim(axmm::kernel_aspace().lock().vm_limit()),
+            #[cfg(feature = "paging")]
+            ctypes::RLIMIT_RSS => to_rlim(axmm::kernel_aspace().lock().rss_limit()),
+            _ => ctypes::RLIM_INFINITY as _,
+        };
+        unsafe {
+            (*rlimits).rlim_cur = limit;
+            (*rlimits).rlim_max = limit;
         }
         Ok(0)
     })
@@ -35,6 +65,10 @@ pub unsafe fn sys_getrlimit(resource: c_int, rlimits: *mut ctypes::rlimit) -> c_
 
 /// Set resource limitations
 ///
+/// Only `RLIMIT_DATA`, `RLIMIT_AS` and `RLIMIT_RSS` can be set, and the soft
+/// limit `rlim_cur` is enforced as the hard one. `RLIMIT_AS` and `RLIMIT_RSS`
+/// below the current usage fail with `EINVAL`.
+///
 /// TODO: support more resource types
 pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit) -> c_int {
     debug!("sys_setrlimit <= {} {:#x}", resource, rlimits as usize);
@@ -43,9 +77,67 @@ pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit
             crate::ctypes::RLIMIT_DATA => {}
             crate::ctypes::RLIMIT_STACK => {}
             crate::ctypes::RLIMIT_NOFILE => {}
+            crate::ctypes::RLIMIT_AS => {}
+            crate::ctypes::RLIMIT_RSS => {}
             _ => return Err(LinuxError::EINVAL),
         }
-        // Currently do not support set resources
+        if rlimits.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let rlimits = unsafe { &*rlimits };
+        if rlimits.rlim_cur > rlimits.rlim_max {
+            return Err(LinuxError::EINVAL);
+        }
+        match resource as u32 {
+            #[cfg(feature = "multitask")]
+            ctypes::RLIMIT_DATA => axtask::current()
+                .mem_account()
+                .set_limit(from_rlim(rlimits.rlim_cur)),
+            #[cfg(feature = "paging")]
+            ctypes::RLIMIT_AS => axmm::kernel_aspace()
+                .lock()
+                .set_vm_limit(from_rlim(rlimits.rlim_cur))?,
+            #[cfg(feature = "paging")]
+            ctypes::RLIMIT_RSS => axmm::kernel_aspace()
+                .lock()
+                .set_rss_limit(from_rlim(rlimits.rlim_cur))?,
+            // Currently do not support set other resources
+            _ => {}
+        }
+        Ok(0)
+    })
+}
+
+/// Get resource usage
+///
+/// Only `ru_maxrss` is reported, which is the maximum heap usage of the
+/// current task group plus the maximum resident set size of the kernel
+/// address space.
+pub unsafe fn sys_getrusage(who: c_int, usage: *mut ctypes::rusage) -> c_int {
+    debug!("sys_getrusage <= {} {:#x}", who, usage as usize);
+    syscall_body!(sys_getrusage, {
+        if who != ctypes::RUSAGE_SELF as c_int && who != ctypes::RUSAGE_CHILDREN {
+            return Err(LinuxError::EINVAL);
+        }
+        if usage.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        #[allow(unused_mut)]
+        let mut maxrss = 0;
+        if who == ctypes::RUSAGE_SELF as c_int {
+            #[cfg(feature = "multitask")]
+            {
+                maxrss += axtask::current().mem_account().peak();
+            }
+            #[cfg(feature = "paging")]
+            {
+                maxrss += axmm::kernel_aspace().lock().max_rss();
+            }
+        }
+        unsafe {
+            *usage = core::mem::zeroed();
+            (*usage).ru_maxrss = (maxrss / 1024) as _;
+        }
         Ok(0)
     })
 }
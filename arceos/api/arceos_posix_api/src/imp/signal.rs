@@ -0,0 +1,136 @@
+//! Signal actions.
+//!
+//! ArceOS can not interrupt a running thread to run a signal handler, so the
+//! signals raised asynchronously (e.g., by timers) are handled in new threads.
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+
+use crate::ctypes;
+
+const NSIG: usize = ctypes::_NSIG as usize;
+const SIG_DFL: usize = 0;
+#[cfg(all(feature = "multitask", feature = "irq"))]
+const SIG_IGN: usize = 1;
+
+/// The handler (`sa_handler` or `sa_sigaction`) of each signal.
+static HANDLERS: [AtomicUsize; NSIG] = [const { AtomicUsize::new(SIG_DFL) }; NSIG];
+/// The `sa_flags` of each signal.
+static FLAGS: [AtomicUsize; NSIG] = [const { AtomicUsize::new(0) }; NSIG];
+
+pub(crate) fn check_signo(signum: c_int) -> LinuxResult<usize> {
+    if signum <= 0 || signum as usize >= NSIG {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(signum as usize)
+}
+
+/// Examine and change a signal action
+///
+/// Only the handler and flags are recorded, `sa_mask` is ignored.
+pub unsafe fn sys_sigaction(
+    signum: c_int,
+    act: *const ctypes::sigaction,
+    oldact: *mut ctypes::sigaction,
+) -> c_int {
+    debug!("sys_sigaction <= {}", signum);
+    syscall_body!(sys_sigaction, {
+        let signo = check_signo(signum)?;
+        if !act.is_null()
+            && (signo == ctypes::SIGKILL as usize || signo == ctypes::SIGSTOP as usize)
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        if !oldact.is_null() {
+            let mut old = ctypes::sigaction::default();
+            old.__sa_handler.sa_handler = unsafe {
+                core::mem::transmute::<usize, Option<unsafe extern "C" fn(c_int)>>(
+                    HANDLERS[signo].load(Ordering::Acquire),
+                )
+            };
+            old.sa_flags = FLAGS[signo].load(Ordering::Acquire) as c_int;
+            unsafe { *oldact = old };
+        }
+        if !act.is_null() {
+            let act = unsafe { &*act };
+            let handler = unsafe { act.__sa_handler.sa_handler }.map_or(SIG_DFL, |f| f as usize);
+            FLAGS[signo].store(act.sa_flags as usize, Ordering::Release);
+            HANDLERS[signo].store(handler, Ordering::Release);
+        }
+        Ok(0)
+    })
+}
+
+/// Returns whether the default action of the signal is to ignore it.
+#[cfg(all(feature = "multitask", feature = "irq"))]
+fn is_ignored_by_default(signo: usize) -> bool {
+    matches!(
+        signo as u32,
+        ctypes::SIGCHLD | ctypes::SIGCONT | ctypes::SIGURG | ctypes::SIGWINCH
+    )
+}
+
+/// Raises a signal asynchronously, the handler runs in a new thread.
+///
+/// If the signal has the default action, the whole system terminates (unless
+/// it is ignored by default).
+///
+/// It spawns the thread, so it can not be called in IRQ context (e.g., from the
+/// callback of a timer not created by [`axtask::Timer::new_deferred`]).
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub(crate) fn raise_async(signo: usize, code: c_int, value: ctypes::sigval) {
+    let handler = HANDLERS[signo].load(Ordering::Acquire);
+    let flags = FLAGS[signo].load(Ordering::Acquire) as u32;
+    match handler {
+        SIG_IGN => {}
+        SIG_DFL if is_ignored_by_default(signo) => {}
+        SIG_DFL => {
+            warn!("terminated by signal {}", signo);
+            axhal::misc::terminate();
+        }
+        _ => {
+            if flags & ctypes::SA_RESETHAND != 0 {
+                HANDLERS[signo].store(SIG_DFL, Ordering::Release);
+            }
+            let mut info = ctypes::siginfo_t {
+                si_signo: signo as c_int,
+                si_code: code,
+                ..Default::default()
+            };
+            info.__si_fields.__si_common.__second.si_value = value;
+            let info = ForceSend(info);
+            axtask::spawn(move || {
+                let mut info = info;
+                if flags & ctypes::SA_SIGINFO != 0 {
+                    let handler: unsafe extern "C" fn(
+                        c_int,
+                        *mut ctypes::siginfo_t,
+                        *mut core::ffi::c_void,
+                    ) = unsafe { core::mem::transmute(handler) };
+                    unsafe { handler(signo as c_int, &mut info.0, core::ptr::null_mut()) };
+                } else {
+                    let handler: unsafe extern "C" fn(c_int) =
+                        unsafe { core::mem::transmute(handler) };
+                    unsafe { handler(signo as c_int) };
+                }
+            });
+        }
+    }
+}
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub(crate) struct ForceSend<T>(pub T);
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+impl<T: Copy> ForceSend<T> {
+    pub fn get(&self) -> T {
+        self.0
+    }
+}
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+unsafe impl<T> Send for ForceSend<T> {}
+#[cfg(all(feature = "multitask", feature = "irq"))]
+unsafe impl<T> Sync for ForceSend<T> {}
@@ -1,5 +1,10 @@
 use core::ffi::c_int;
 
+#[cfg(feature = "multitask")]
+use crate::ctypes;
+#[cfg(feature = "multitask")]
+use axerrno::{LinuxError, LinuxResult};
+
 /// Relinquish the CPU, and switches to another task.
 ///
 /// For single-threaded configuration (`multitask` feature is disabled), we just
@@ -30,6 +35,177 @@ pub fn sys_getpid() -> c_int {
     )
 }
 
+/// Converts a `cpu_set_t` of `cpusetsize` bytes to the CPU mask of tasks.
+///
+/// CPUs beyond [`axconfig::SMP`] are ignored. Returns `EINVAL` if no valid CPU
+/// is in the set.
+#[cfg(feature = "multitask")]
+pub(crate) unsafe fn cpu_set_to_mask(
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> LinuxResult<axtask::AxCpuMask> {
+    if cpuset.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    let bits = unsafe { &(*cpuset).__bits };
+    let bits_per_word = 8 * core::mem::size_of_val(&bits[0]);
+    let mut mask = axtask::AxCpuMask::new();
+    for cpu_id in 0..axconfig::SMP.min(cpusetsize * 8) {
+        if bits[cpu_id / bits_per_word] & (1 << (cpu_id % bits_per_word)) != 0 {
+            mask.set(cpu_id, true);
+        }
+    }
+    if mask.is_empty() {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(mask)
+}
+
+/// Set the CPU affinity mask of the thread whose ID is `pid`.
+///
+/// If `pid` is zero, the calling thread is used. If the calling thread is not
+/// allowed to run on the current CPU anymore, it is migrated immediately.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_sched_setaffinity <= {} {:#x}", pid, cpuset as usize);
+    syscall_body!(sys_sched_setaffinity, {
+        let mask = unsafe { cpu_set_to_mask(cpusetsize, cpuset)? };
+        if pid == 0 || pid as u64 == axtask::current().id().as_u64() {
+            axtask::set_current_affinity(mask);
+        } else {
+            let task = crate::imp::pthread::find_task(pid as u64).ok_or(LinuxError::ESRCH)?;
+            task.set_cpumask(mask);
+        }
+        Ok(0)
+    })
+}
+
+/// Returns the thread whose ID is `pid`, or the calling thread if `pid` is
+/// zero.
+#[cfg(feature = "multitask")]
+fn find_task(pid: c_int) -> LinuxResult<axtask::AxTaskRef> {
+    let curr = axtask::current();
+    if pid == 0 || pid as u64 == curr.id().as_u64() {
+        Ok(curr.as_task_ref().clone())
+    } else {
+        crate::imp::pthread::find_task(pid as u64).ok_or(LinuxError::ESRCH)
+    }
+}
+
+/// Converts a POSIX scheduling policy and priority to the policy of tasks.
+#[cfg(feature = "multitask")]
+fn to_sched_policy(policy: c_int, prio: c_int) -> LinuxResult<axtask::SchedPolicy> {
+    let rt_prio = || {
+        u8::try_from(prio)
+            .ok()
+            .filter(|prio| (axtask::MIN_RT_PRIO..=axtask::MAX_RT_PRIO).contains(prio))
+            .ok_or(LinuxError::EINVAL)
+    };
+    match policy as u32 {
+        ctypes::SCHED_OTHER if prio == 0 => Ok(axtask::SchedPolicy::Normal),
+        ctypes::SCHED_BATCH if prio == 0 => Ok(axtask::SchedPolicy::Batch),
+        ctypes::SCHED_FIFO => Ok(axtask::SchedPolicy::Fifo(rt_prio()?)),
+        ctypes::SCHED_RR => Ok(axtask::SchedPolicy::RoundRobin(rt_prio()?)),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Converts the policy of tasks to a POSIX scheduling policy and priority.
+#[cfg(feature = "multitask")]
+fn from_sched_policy(policy: axtask::SchedPolicy) -> (c_int, c_int) {
+    match policy {
+        axtask::SchedPolicy::Normal => (ctypes::SCHED_OTHER as _, 0),
+        axtask::SchedPolicy::Batch => (ctypes::SCHED_BATCH as _, 0),
+        axtask::SchedPolicy::Fifo(prio) => (ctypes::SCHED_FIFO as _, prio as _),
+        axtask::SchedPolicy::RoundRobin(prio) => (ctypes::SCHED_RR as _, prio as _),
+        axtask::SchedPolicy::Deadline(_) => (ctypes::SCHED_DEADLINE as _, 0),
+    }
+}
+
+/// Set the scheduling policy and priority of the thread whose ID is `pid`.
+///
+/// `SCHED_OTHER`, `SCHED_BATCH`, `SCHED_FIFO` and `SCHED_RR` are supported.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_setscheduler(
+    pid: c_int,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    debug!("sys_sched_setscheduler <= {} {}", pid, policy);
+    syscall_body!(sys_sched_setscheduler, {
+        let param = unsafe { param.as_ref() }.ok_or(LinuxError::EINVAL)?;
+        let policy = to_sched_policy(policy, param.sched_priority)?;
+        axtask::set_sched_policy(&find_task(pid)?, policy)?;
+        Ok(0)
+    })
+}
+
+/// Get the scheduling policy of the thread whose ID is `pid`.
+#[cfg(feature = "multitask")]
+pub fn sys_sched_getscheduler(pid: c_int) -> c_int {
+    debug!("sys_sched_getscheduler <= {}", pid);
+    syscall_body!(sys_sched_getscheduler, {
+        Ok(from_sched_policy(find_task(pid)?.sched_policy()).0)
+    })
+}
+
+/// Set the scheduling priority of the thread whose ID is `pid`, without
+/// changing its policy.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_setparam(pid: c_int, param: *const ctypes::sched_param) -> c_int {
+    debug!("sys_sched_setparam <= {}", pid);
+    syscall_body!(sys_sched_setparam, {
+        let param = unsafe { param.as_ref() }.ok_or(LinuxError::EINVAL)?;
+        let task = find_task(pid)?;
+        let (policy, _) = from_sched_policy(task.sched_policy());
+        let policy = to_sched_policy(policy, param.sched_priority)?;
+        axtask::set_sched_policy(&task, policy)?;
+        Ok(0)
+    })
+}
+
+/// Get the scheduling priority of the thread whose ID is `pid`.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_getparam(pid: c_int, param: *mut ctypes::sched_param) -> c_int {
+    debug!("sys_sched_getparam <= {}", pid);
+    syscall_body!(sys_sched_getparam, {
+        if param.is_null() {
+            return Err(LinuxError::EINVAL);
+        }
+        let (_, prio) = from_sched_policy(find_task(pid)?.sched_policy());
+        unsafe { (*param).sched_priority = prio };
+        Ok(0)
+    })
+}
+
+/// Get the maximum scheduling priority of the policy.
+#[cfg(feature = "multitask")]
+pub fn sys_sched_get_priority_max(policy: c_int) -> c_int {
+    syscall_body!(sys_sched_get_priority_max, {
+        match policy as u32 {
+            ctypes::SCHED_FIFO | ctypes::SCHED_RR => Ok(axtask::MAX_RT_PRIO),
+            ctypes::SCHED_OTHER | ctypes::SCHED_BATCH => Ok(0),
+            _ => Err(LinuxError::EINVAL),
+        }
+    })
+}
+
+/// Get the minimum scheduling priority of the policy.
+#[cfg(feature = "multitask")]
+pub fn sys_sched_get_priority_min(policy: c_int) -> c_int {
+    syscall_body!(sys_sched_get_priority_min, {
+        match policy as u32 {
+            ctypes::SCHED_FIFO | ctypes::SCHED_RR => Ok(axtask::MIN_RT_PRIO),
+            ctypes::SCHED_OTHER | ctypes::SCHED_BATCH => Ok(0),
+            _ => Err(LinuxError::EINVAL),
+        }
+    })
+}
+
 /// Exit current task
 pub fn sys_exit(exit_code: c_int) -> ! {
     debug!("sys_exit <= {}", exit_code);
@@ -0,0 +1,283 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::ffi::{c_int, c_uint};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{epochoffset_nanos, wall_time};
+use axtask::Timer;
+use spin::Mutex;
+
+use super::signal::{check_signo, raise_async, ForceSend};
+use crate::ctypes;
+
+struct PosixTimer {
+    clock: ctypes::clockid_t,
+    timer: Timer,
+}
+
+static TIMERS: Mutex<BTreeMap<usize, PosixTimer>> = Mutex::new(BTreeMap::new());
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+lazy_static::lazy_static! {
+    /// The timer shared by `setitimer(ITIMER_REAL)` and `alarm`.
+    static ref REAL_TIMER: Timer = Timer::new_deferred(|| {
+        raise_async(
+            ctypes::SIGALRM as usize,
+            ctypes::SI_KERNEL as c_int,
+            ctypes::sigval::default(),
+        )
+    });
+}
+
+fn timespec_to_duration(ts: ctypes::timespec) -> LinuxResult<Duration> {
+    if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(ts.into())
+}
+
+fn timeval_to_duration(tv: ctypes::timeval) -> LinuxResult<Duration> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(tv.into())
+}
+
+/// Returns the time until the next expiration and the interval of the timer.
+fn timer_value(timer: &Timer) -> (Duration, Duration) {
+    let value = timer.deadline().map_or(Duration::ZERO, |deadline| {
+        deadline.saturating_sub(wall_time())
+    });
+    (value, timer.interval().unwrap_or_default())
+}
+
+/// Starts the timer to expire after `value`, or stops it if `value` is zero.
+fn set_timer(timer: &Timer, value: Duration, interval: Duration) {
+    if value.is_zero() {
+        timer.cancel();
+    } else {
+        timer.start(wall_time() + value, Some(interval));
+    }
+}
+
+/// Builds the callback of a timer that notifies as `sevp` specifies.
+fn notify_callback(
+    timer_id: usize,
+    sevp: *const ctypes::sigevent,
+) -> LinuxResult<Box<dyn Fn() + Send + Sync>> {
+    let sev = match unsafe { sevp.as_ref() } {
+        Some(sev) => *sev,
+        None => {
+            // Defaults to sending `SIGALRM` with the timer ID.
+            let mut sev = ctypes::sigevent {
+                sigev_signo: ctypes::SIGALRM as c_int,
+                sigev_notify: ctypes::SIGEV_SIGNAL as c_int,
+                ..Default::default()
+            };
+            sev.sigev_value.sival_int = timer_id as c_int;
+            sev
+        }
+    };
+    let value = ForceSend(sev.sigev_value);
+    match sev.sigev_notify as u32 {
+        ctypes::SIGEV_NONE => Ok(Box::new(|| {})),
+        ctypes::SIGEV_SIGNAL => {
+            let signo = check_signo(sev.sigev_signo)?;
+            Ok(Box::new(move || {
+                raise_async(signo, ctypes::SI_TIMER, value.get())
+            }))
+        }
+        ctypes::SIGEV_THREAD => {
+            let func = unsafe { sev.__sev_fields.__sev_thread.sigev_notify_function }
+                .ok_or(LinuxError::EINVAL)?;
+            Ok(Box::new(move || {
+                let value = ForceSend(value.get());
+                axtask::spawn(move || unsafe { func(value.get()) });
+            }))
+        }
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Create a POSIX per-process timer
+///
+/// `SIGEV_THREAD_ID` is not supported.
+pub unsafe fn sys_timer_create(
+    clockid: ctypes::clockid_t,
+    sevp: *mut ctypes::sigevent,
+    timerid: *mut ctypes::timer_t,
+) -> c_int {
+    debug!("sys_timer_create <= {} {:#x}", clockid, sevp as usize);
+    syscall_body!(sys_timer_create, {
+        if timerid.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        if !matches!(
+            clockid as u32,
+            ctypes::CLOCK_REALTIME | ctypes::CLOCK_MONOTONIC
+        ) {
+            return Err(LinuxError::EINVAL);
+        }
+        let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+        // The callbacks spawn threads, which is not allowed in IRQ context.
+        let timer = Timer::new_deferred(notify_callback(id, sevp)?);
+        TIMERS.lock().insert(
+            id,
+            PosixTimer {
+                clock: clockid,
+                timer,
+            },
+        );
+        unsafe { *timerid = id as ctypes::timer_t };
+        Ok(0)
+    })
+}
+
+/// Delete a POSIX per-process timer
+pub fn sys_timer_delete(timerid: ctypes::timer_t) -> c_int {
+    debug!("sys_timer_delete <= {:#x}", timerid as usize);
+    syscall_body!(sys_timer_delete, {
+        // The timer is cancelled when dropped.
+        TIMERS
+            .lock()
+            .remove(&(timerid as usize))
+            .ok_or(LinuxError::EINVAL)?;
+        Ok(0)
+    })
+}
+
+/// Arm or disarm a POSIX per-process timer
+pub unsafe fn sys_timer_settime(
+    timerid: ctypes::timer_t,
+    flags: c_int,
+    new_value: *const ctypes::itimerspec,
+    old_value: *mut ctypes::itimerspec,
+) -> c_int {
+    debug!("sys_timer_settime <= {:#x} {}", timerid as usize, flags);
+    syscall_body!(sys_timer_settime, {
+        if new_value.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let new_value = unsafe { *new_value };
+        let value = timespec_to_duration(new_value.it_value)?;
+        let interval = timespec_to_duration(new_value.it_interval)?;
+
+        let timers = TIMERS.lock();
+        let posix_timer = timers.get(&(timerid as usize)).ok_or(LinuxError::EINVAL)?;
+        let timer = &posix_timer.timer;
+        if !old_value.is_null() {
+            let (value, interval) = timer_value(timer);
+            unsafe {
+                (*old_value).it_value = value.into();
+                (*old_value).it_interval = interval.into();
+            }
+        }
+        if value.is_zero() || flags as u32 & ctypes::TIMER_ABSTIME == 0 {
+            set_timer(timer, value, interval);
+        } else {
+            // Timers expire in wall time.
+            let deadline = if posix_timer.clock as u32 == ctypes::CLOCK_MONOTONIC {
+                value + Duration::from_nanos(epochoffset_nanos())
+            } else {
+                value
+            };
+            timer.start(deadline, Some(interval));
+        }
+        Ok(0)
+    })
+}
+
+/// Fetch the state of a POSIX per-process timer
+pub unsafe fn sys_timer_gettime(
+    timerid: ctypes::timer_t,
+    curr_value: *mut ctypes::itimerspec,
+) -> c_int {
+    debug!("sys_timer_gettime <= {:#x}", timerid as usize);
+    syscall_body!(sys_timer_gettime, {
+        if curr_value.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let timers = TIMERS.lock();
+        let posix_timer = timers.get(&(timerid as usize)).ok_or(LinuxError::EINVAL)?;
+        let (value, interval) = timer_value(&posix_timer.timer);
+        unsafe {
+            (*curr_value).it_value = value.into();
+            (*curr_value).it_interval = interval.into();
+        }
+        Ok(0)
+    })
+}
+
+/// Set the value of an interval timer
+///
+/// Only `ITIMER_REAL` is supported, which sends `SIGALRM` on expiration.
+pub unsafe fn sys_setitimer(
+    which: c_int,
+    new_value: *const ctypes::itimerval,
+    old_value: *mut ctypes::itimerval,
+) -> c_int {
+    debug!("sys_setitimer <= {}", which);
+    syscall_body!(sys_setitimer, {
+        if which as u32 != ctypes::ITIMER_REAL {
+            return Err(LinuxError::EINVAL);
+        }
+        if new_value.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let new_value = unsafe { *new_value };
+        let value = timeval_to_duration(new_value.it_value)?;
+        let interval = timeval_to_duration(new_value.it_interval)?;
+        if !old_value.is_null() {
+            let (value, interval) = timer_value(&REAL_TIMER);
+            unsafe {
+                (*old_value).it_value = value.into();
+                (*old_value).it_interval = interval.into();
+            }
+        }
+        set_timer(&REAL_TIMER, value, interval);
+        Ok(0)
+    })
+}
+
+/// Get the value of an interval timer
+///
+/// Only `ITIMER_REAL` is supported.
+pub unsafe fn sys_getitimer(which: c_int, curr_value: *mut ctypes::itimerval) -> c_int {
+    debug!("sys_getitimer <= {}", which);
+    syscall_body!(sys_getitimer, {
+        if which as u32 != ctypes::ITIMER_REAL {
+            return Err(LinuxError::EINVAL);
+        }
+        if curr_value.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let (value, interval) = timer_value(&REAL_TIMER);
+        unsafe {
+            (*curr_value).it_value = value.into();
+            (*curr_value).it_interval = interval.into();
+        }
+        Ok(0)
+    })
+}
+
+/// Set an alarm clock for delivery of `SIGALRM`
+///
+/// Returns the number of seconds remaining until the previous alarm, or zero
+/// if there was no previous alarm.
+pub fn sys_alarm(seconds: c_uint) -> c_uint {
+    debug!("sys_alarm <= {}", seconds);
+    let (remaining, _) = timer_value(&REAL_TIMER);
+    set_timer(
+        &REAL_TIMER,
+        Duration::from_secs(seconds as u64),
+        Duration::ZERO,
+    );
+    if remaining.is_zero() {
+        0
+    } else {
+        // Rounds to the nearest second, but never returns zero for an
+        // active alarm.
+        (remaining + Duration::from_millis(500)).as_secs().max(1) as c_uint
+    }
+}
@@ -31,19 +31,30 @@ pub mod config {
 pub mod ctypes;
 
 pub use imp::io::{sys_read, sys_write, sys_writev};
-pub use imp::resources::{sys_getrlimit, sys_setrlimit};
+pub use imp::resources::{sys_getrlimit, sys_getrusage, sys_setrlimit};
+pub use imp::signal::sys_sigaction;
 pub use imp::sys::sys_sysconf;
 pub use imp::task::{sys_exit, sys_getpid, sys_sched_yield};
 pub use imp::time::{sys_clock_gettime, sys_nanosleep};
 
 #[cfg(feature = "fd")]
-pub use imp::fd_ops::{sys_close, sys_dup, sys_dup2, sys_fcntl, get_file_like};
+pub use imp::fd_ops::{get_file_like, sys_close, sys_dup, sys_dup2, sys_fcntl};
 #[cfg(feature = "fs")]
-pub use imp::fs::{sys_fstat, sys_getcwd, sys_lseek, sys_lstat, sys_open, sys_rename, sys_stat};
+pub use imp::fs::{
+    get_mmap_node, sys_chmod, sys_chown, sys_fstat, sys_getcwd, sys_link, sys_lseek, sys_lstat,
+    sys_mount, sys_open, sys_readlink, sys_rename, sys_stat, sys_symlink, sys_umount2,
+    sys_utimensat,
+};
+#[cfg(feature = "multitask")]
+pub use imp::futex::sys_futex;
 #[cfg(feature = "select")]
 pub use imp::io_mpx::sys_select;
 #[cfg(feature = "epoll")]
 pub use imp::io_mpx::{sys_epoll_create, sys_epoll_ctl, sys_epoll_wait};
+#[cfg(feature = "paging")]
+pub use imp::mman::{
+    sys_madvise, sys_mlock, sys_mmap, sys_mprotect, sys_mremap, sys_msync, sys_munlock, sys_munmap,
+};
 #[cfg(feature = "net")]
 pub use imp::net::{
     sys_accept, sys_bind, sys_connect, sys_freeaddrinfo, sys_getaddrinfo, sys_getpeername,
@@ -53,8 +64,54 @@ pub use imp::net::{
 #[cfg(feature = "pipe")]
 pub use imp::pipe::sys_pipe;
 #[cfg(feature = "multitask")]
+pub use imp::pthread::barrier::{
+    sys_pthread_barrier_destroy, sys_pthread_barrier_init, sys_pthread_barrier_wait,
+};
+#[cfg(feature = "multitask")]
+pub use imp::pthread::cond::{
+    sys_pthread_cond_broadcast, sys_pthread_cond_destroy, sys_pthread_cond_init,
+    sys_pthread_cond_signal, sys_pthread_cond_wait,
+};
+#[cfg(feature = "multitask")]
+pub use imp::pthread::key::{
+    sys_pthread_getspecific, sys_pthread_key_create, sys_pthread_key_delete,
+    sys_pthread_setspecific,
+};
+#[cfg(feature = "multitask")]
 pub use imp::pthread::mutex::{
-    sys_pthread_mutex_init, sys_pthread_mutex_lock, sys_pthread_mutex_unlock,
+    sys_pthread_mutex_destroy, sys_pthread_mutex_init, sys_pthread_mutex_lock,
+    sys_pthread_mutex_trylock, sys_pthread_mutex_unlock, sys_pthread_mutexattr_destroy,
+    sys_pthread_mutexattr_getprotocol, sys_pthread_mutexattr_init,
+    sys_pthread_mutexattr_setprotocol,
 };
 #[cfg(feature = "multitask")]
-pub use imp::pthread::{sys_pthread_create, sys_pthread_exit, sys_pthread_join, sys_pthread_self};
+pub use imp::pthread::once::sys_pthread_once;
+#[cfg(feature = "multitask")]
+pub use imp::pthread::rwlock::{
+    sys_pthread_rwlock_destroy, sys_pthread_rwlock_init, sys_pthread_rwlock_rdlock,
+    sys_pthread_rwlock_tryrdlock, sys_pthread_rwlock_trywrlock, sys_pthread_rwlock_unlock,
+    sys_pthread_rwlock_wrlock,
+};
+#[cfg(feature = "multitask")]
+pub use imp::pthread::sem::{
+    sys_sem_destroy, sys_sem_getvalue, sys_sem_init, sys_sem_post, sys_sem_trywait, sys_sem_wait,
+};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use imp::pthread::{
+    cond::sys_pthread_cond_timedwait, mutex::sys_pthread_mutex_timedlock, sem::sys_sem_timedwait,
+};
+#[cfg(feature = "multitask")]
+pub use imp::pthread::{
+    sys_pthread_create, sys_pthread_detach, sys_pthread_exit, sys_pthread_join, sys_pthread_self,
+    sys_pthread_setaffinity_np,
+};
+#[cfg(feature = "multitask")]
+pub use imp::task::{
+    sys_sched_get_priority_max, sys_sched_get_priority_min, sys_sched_getparam,
+    sys_sched_getscheduler, sys_sched_setaffinity, sys_sched_setparam, sys_sched_setscheduler,
+};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use imp::timer::{
+    sys_alarm, sys_getitimer, sys_setitimer, sys_timer_create, sys_timer_delete, sys_timer_gettime,
+    sys_timer_settime,
+};
@@ -12,6 +12,8 @@
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `alloc-debug`: Enable the heap debugging (redzones, poisoning and the
+//!       leak report).
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
@@ -19,9 +21,12 @@
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `tickless`: Stop the periodic scheduler tick on idle CPUs.
 //! - Upperlayer stacks (fs, net, display)
 //!     - `fs`: Enable file system support.
+//!     - `swap`: Swap to the file `/swapfile` if it exists.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
+//!     - `ext4fs`: Support ext2/ext3/ext4 as the main filesystem.
 //!     - `net`: Enable networking support.
 //!     - `display`: Enable graphics support.
 //! - Device drivers
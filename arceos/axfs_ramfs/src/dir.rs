@@ -1,12 +1,16 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::{string::String, vec::Vec};
+use core::time::Duration;
 
-use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
 use axfs_vfs::{VfsError, VfsResult};
 use spin::RwLock;
 
 use crate::file::FileNode;
+use crate::meta::Metadata;
+use crate::symlink::SymlinkNode;
+use crate::Clock;
 
 /// The directory node in the RAM filesystem.
 ///
@@ -15,14 +19,16 @@ pub struct DirNode {
     this: Weak<DirNode>,
     parent: RwLock<Weak<dyn VfsNodeOps>>,
     children: RwLock<BTreeMap<String, VfsNodeRef>>,
+    meta: Metadata,
 }
 
 impl DirNode {
-    pub(super) fn new(parent: Option<Weak<dyn VfsNodeOps>>) -> Arc<Self> {
+    pub(super) fn new(parent: Option<Weak<dyn VfsNodeOps>>, clock: Clock) -> Arc<Self> {
         Arc::new_cyclic(|this| Self {
             this: this.clone(),
             parent: RwLock::new(parent.unwrap_or_else(|| Weak::<Self>::new())),
             children: RwLock::new(BTreeMap::new()),
+            meta: Metadata::new(clock, VfsNodePerm::default_dir()),
         })
     }
 
@@ -47,11 +53,46 @@ impl DirNode {
             return Err(VfsError::AlreadyExists);
         }
         let node: VfsNodeRef = match ty {
-            VfsNodeType::File => Arc::new(FileNode::new()),
-            VfsNodeType::Dir => Self::new(Some(self.this.clone())),
+            VfsNodeType::File => Arc::new(FileNode::new(self.meta.clock())),
+            VfsNodeType::Dir => Self::new(Some(self.this.clone()), self.meta.clock()),
             _ => return Err(VfsError::Unsupported),
         };
         self.children.write().insert(name.into(), node);
+        self.meta.modified();
+        Ok(())
+    }
+
+    /// Creates a symbolic link with the given name in this directory.
+    pub fn create_symlink(&self, name: &str, target: &str) -> VfsResult {
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let node = Arc::new(SymlinkNode::new(target, self.meta.clock()));
+        children.insert(name.into(), node);
+        self.meta.modified();
+        Ok(())
+    }
+
+    /// Adds a hard link with the given name to the file `node` in this
+    /// directory.
+    pub fn create_link(&self, name: &str, node: &VfsNodeRef) -> VfsResult {
+        let Some(file) = node.as_any().downcast_ref::<FileNode>() else {
+            return Err(if node.get_attr()?.is_dir() {
+                VfsError::OperationNotPermitted
+            } else if node.as_any().is::<SymlinkNode>() {
+                VfsError::Unsupported
+            } else {
+                VfsError::CrossesDevices // not in the RAM filesystem
+            });
+        };
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        file.link();
+        children.insert(name.into(), node.clone());
+        self.meta.modified();
         Ok(())
     }
 
@@ -64,14 +105,46 @@ impl DirNode {
                 return Err(VfsError::DirectoryNotEmpty);
             }
         }
+        if let Some(file) = node.as_any().downcast_ref::<FileNode>() {
+            file.unlink();
+        }
         children.remove(name);
+        self.meta.modified();
         Ok(())
     }
+
+    /// Returns the node of the path component `name`.
+    fn child(&self, name: &str) -> VfsResult<VfsNodeRef> {
+        match name {
+            "" | "." => self.this.upgrade().map(|this| this as _),
+            ".." => self.parent(),
+            _ => self.children.read().get(name).cloned(),
+        }
+        .ok_or(VfsError::NotFound)
+    }
 }
 
 impl VfsNodeOps for DirNode {
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        Ok(VfsNodeAttr::new_dir(4096, 0))
+        let subdirs = self
+            .children
+            .read()
+            .values()
+            .filter(|node| node.as_any().is::<DirNode>())
+            .count();
+        Ok(self.meta.attr(VfsNodeType::Dir, 4096, 2 + subdirs as u32))
+    }
+
+    fn set_perm(&self, perm: VfsNodePerm) -> VfsResult {
+        self.meta.set_perm(perm)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        self.meta.set_owner(uid, gid)
+    }
+
+    fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        self.meta.set_times(atime, mtime)
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
@@ -198,15 +271,36 @@ impl VfsNodeOps for DirNode {
             let mut children = self.children.write();
             let node = children.remove(src_name).ok_or(VfsError::NotFound)?;
 
-            if children.contains_key(dst_name) {
-                children.remove(dst_name);
+            if let Some(old) = children.remove(dst_name) {
+                if let Some(file) = old.as_any().downcast_ref::<FileNode>() {
+                    file.unlink();
+                }
             }
 
             children.insert(dst_name.into(), node);
+            self.meta.modified();
             Ok(())
         }
     }
 
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        log::debug!("symlink at ramfs: {} -> {}", path, target);
+        match split_path(path) {
+            (name, Some(rest)) => self.child(name)?.symlink(rest, target),
+            ("" | "." | "..", None) => Err(VfsError::AlreadyExists),
+            (name, None) => self.create_symlink(name, target),
+        }
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        log::debug!("link at ramfs: {}", path);
+        match split_path(path) {
+            (name, Some(rest)) => self.child(name)?.link(rest, node),
+            ("" | "." | "..", None) => Err(VfsError::AlreadyExists),
+            (name, None) => self.create_link(name, node),
+        }
+    }
+
     axfs_vfs::impl_vfs_dir_default! {}
 }
 
@@ -1,25 +1,63 @@
 use alloc::vec::Vec;
-use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsResult};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use axfs_vfs::VfsResult;
+use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeType};
 use spin::RwLock;
 
+use crate::meta::Metadata;
+use crate::Clock;
+
 /// The file node in the RAM filesystem.
 ///
 /// It implements [`axfs_vfs::VfsNodeOps`].
 pub struct FileNode {
     content: RwLock<Vec<u8>>,
+    meta: Metadata,
+    /// The number of hard links to the file.
+    nlink: AtomicU32,
 }
 
 impl FileNode {
-    pub(super) const fn new() -> Self {
+    pub(super) fn new(clock: Clock) -> Self {
         Self {
             content: RwLock::new(Vec::new()),
+            meta: Metadata::new(clock, VfsNodePerm::default_file()),
+            nlink: AtomicU32::new(1),
         }
     }
+
+    /// Called when a hard link to the file is added.
+    pub(super) fn link(&self) {
+        self.nlink.fetch_add(1, Ordering::Relaxed);
+        self.meta.changed();
+    }
+
+    /// Called when a hard link to the file is removed.
+    pub(super) fn unlink(&self) {
+        self.nlink.fetch_sub(1, Ordering::Relaxed);
+        self.meta.changed();
+    }
 }
 
 impl VfsNodeOps for FileNode {
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        Ok(VfsNodeAttr::new_file(self.content.read().len() as _, 0))
+        let size = self.content.read().len() as u64;
+        let nlink = self.nlink.load(Ordering::Relaxed);
+        Ok(self.meta.attr(VfsNodeType::File, size, nlink))
+    }
+
+    fn set_perm(&self, perm: VfsNodePerm) -> VfsResult {
+        self.meta.set_perm(perm)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        self.meta.set_owner(uid, gid)
+    }
+
+    fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        self.meta.set_times(atime, mtime)
     }
 
     fn truncate(&self, size: u64) -> VfsResult {
@@ -29,6 +67,7 @@ impl VfsNodeOps for FileNode {
         } else {
             content.resize(size as _, 0);
         }
+        self.meta.modified();
         Ok(())
     }
 
@@ -38,6 +77,7 @@ impl VfsNodeOps for FileNode {
         let end = content.len().min(offset as usize + buf.len());
         let src = &content[start..end];
         buf[..src.len()].copy_from_slice(src);
+        self.meta.accessed();
         Ok(src.len())
     }
 
@@ -49,6 +89,7 @@ impl VfsNodeOps for FileNode {
         }
         let dst = &mut content[offset..offset + buf.len()];
         dst.copy_from_slice(&buf[..dst.len()]);
+        self.meta.modified();
         Ok(buf.len())
     }
 
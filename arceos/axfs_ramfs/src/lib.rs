@@ -8,17 +8,24 @@ extern crate alloc;
 
 mod dir;
 mod file;
+mod meta;
+mod symlink;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::dir::DirNode;
 pub use self::file::FileNode;
+pub use self::symlink::SymlinkNode;
 
 use alloc::sync::Arc;
 use axfs_vfs::{VfsNodeRef, VfsOps, VfsResult};
+use core::time::Duration;
 use spin::once::Once;
 
+/// Returns the current time since the Unix epoch, for the timestamps of nodes.
+pub type Clock = fn() -> Duration;
+
 /// A RAM filesystem that implements [`axfs_vfs::VfsOps`].
 pub struct RamFileSystem {
     parent: Once<VfsNodeRef>,
@@ -26,11 +33,16 @@ pub struct RamFileSystem {
 }
 
 impl RamFileSystem {
-    /// Create a new instance.
+    /// Create a new instance, whose nodes all have zero timestamps.
     pub fn new() -> Self {
+        Self::with_clock(|| Duration::ZERO)
+    }
+
+    /// Create a new instance, with the timestamps of nodes from `clock`.
+    pub fn with_clock(clock: Clock) -> Self {
         Self {
             parent: Once::new(),
-            root: DirNode::new(None),
+            root: DirNode::new(None, clock),
         }
     }
 
@@ -0,0 +1,100 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use axfs_vfs::{VfsNodeAttr, VfsNodePerm, VfsNodeType, VfsResult};
+use spin::RwLock;
+
+use crate::Clock;
+
+/// The next inode number. They are unique across all the RAM filesystems.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// The inode number, permission, owner and timestamps of a node.
+pub(crate) struct Metadata {
+    ino: u64,
+    clock: Clock,
+    inner: RwLock<MetadataInner>,
+}
+
+struct MetadataInner {
+    perm: VfsNodePerm,
+    uid: u32,
+    gid: u32,
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+impl Metadata {
+    pub fn new(clock: Clock, perm: VfsNodePerm) -> Self {
+        let now = clock();
+        Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            clock,
+            inner: RwLock::new(MetadataInner {
+                perm,
+                uid: 0,
+                gid: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+        }
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Returns the attributes of the node with the given type, size and number
+    /// of links.
+    pub fn attr(&self, ty: VfsNodeType, size: u64, nlink: u32) -> VfsNodeAttr {
+        let inner = self.inner.read();
+        VfsNodeAttr::new(inner.perm, ty, size, 0)
+            .with_owner(inner.uid, inner.gid)
+            .with_nlink(nlink)
+            .with_ino(self.ino)
+            .with_times(inner.atime, inner.mtime, inner.ctime)
+    }
+
+    /// Updates the access time.
+    pub fn accessed(&self) {
+        self.inner.write().atime = (self.clock)();
+    }
+
+    /// Updates the modification and status change times.
+    pub fn modified(&self) {
+        let now = (self.clock)();
+        let mut inner = self.inner.write();
+        inner.mtime = now;
+        inner.ctime = now;
+    }
+
+    /// Updates the status change time.
+    pub fn changed(&self) {
+        self.inner.write().ctime = (self.clock)();
+    }
+
+    pub fn set_perm(&self, perm: VfsNodePerm) -> VfsResult {
+        let mut inner = self.inner.write();
+        inner.perm = perm;
+        inner.ctime = (self.clock)();
+        Ok(())
+    }
+
+    pub fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        let mut inner = self.inner.write();
+        inner.uid = uid.unwrap_or(inner.uid);
+        inner.gid = gid.unwrap_or(inner.gid);
+        inner.ctime = (self.clock)();
+        Ok(())
+    }
+
+    pub fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        let mut inner = self.inner.write();
+        inner.atime = atime.unwrap_or(inner.atime);
+        inner.mtime = mtime.unwrap_or(inner.mtime);
+        inner.ctime = (self.clock)();
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use alloc::string::String;
+use core::time::Duration;
+
+use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeType};
+use axfs_vfs::{VfsError, VfsResult};
+
+use crate::meta::Metadata;
+use crate::Clock;
+
+/// The symbolic link node in the RAM filesystem.
+///
+/// It implements [`axfs_vfs::VfsNodeOps`], and the target is read by
+/// [`read_at`](VfsNodeOps::read_at).
+pub struct SymlinkNode {
+    target: String,
+    meta: Metadata,
+}
+
+impl SymlinkNode {
+    pub(super) fn new(target: &str, clock: Clock) -> Self {
+        Self {
+            target: target.into(),
+            meta: Metadata::new(clock, VfsNodePerm::from_bits_truncate(0o777)),
+        }
+    }
+
+    /// Returns the target of the link.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+}
+
+impl VfsNodeOps for SymlinkNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = self.target.len() as u64;
+        Ok(self.meta.attr(VfsNodeType::SymLink, size, 1))
+    }
+
+    fn set_perm(&self, _perm: VfsNodePerm) -> VfsResult {
+        Err(VfsError::Unsupported) // always 0o777
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        self.meta.set_owner(uid, gid)
+    }
+
+    fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        self.meta.set_times(atime, mtime)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let target = self.target.as_bytes();
+        let start = target.len().min(offset as usize);
+        let len = buf.len().min(target.len() - start);
+        buf[..len].copy_from_slice(&target[start..start + len]);
+        Ok(len)
+    }
+
+    impl_vfs_non_dir_default! {}
+}
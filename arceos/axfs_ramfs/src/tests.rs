@@ -134,3 +134,74 @@ fn test_ramfs() {
     assert_eq!(root.remove("./foo"), Ok(()));
     assert!(ramfs.root_dir_node().get_entries().is_empty());
 }
+
+#[test]
+fn test_links_and_attrs() {
+    use core::time::Duration;
+
+    use axfs_vfs::VfsNodePerm;
+
+    let ramfs = RamFileSystem::with_clock(|| Duration::from_secs(1000));
+    let root = ramfs.root_dir();
+    root.create("foo", VfsNodeType::Dir).unwrap();
+    root.create("foo/f1", VfsNodeType::File).unwrap();
+    let f1 = root.clone().lookup("foo/f1").unwrap();
+    f1.write_at(0, b"hello").unwrap();
+
+    // symbolic links
+    root.symlink("foo/l1", "f1").unwrap();
+    root.symlink("l2", "/no/such/file").unwrap();
+    assert_eq!(
+        root.symlink("foo/f1", "x").err(),
+        Some(VfsError::AlreadyExists)
+    );
+    let l1 = root.clone().lookup("foo/l1").unwrap();
+    let attr = l1.get_attr().unwrap();
+    assert!(attr.is_symlink());
+    assert_eq!(attr.size(), 2);
+    let mut buf = [0; 16];
+    assert_eq!(l1.read_at(0, &mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"f1");
+    assert_eq!(
+        root.clone().lookup("foo/l1/x").err(),
+        Some(VfsError::NotADirectory)
+    );
+    assert_eq!(l1.write_at(0, b"x").err(), Some(VfsError::InvalidInput));
+
+    // hard links
+    assert_eq!(f1.get_attr().unwrap().nlink(), 1);
+    root.link("f2", &f1).unwrap();
+    assert_eq!(f1.get_attr().unwrap().nlink(), 2);
+    assert!(Arc::ptr_eq(&f1, &root.clone().lookup("f2").unwrap()));
+    assert_eq!(
+        root.link("f3", &root.clone().lookup("foo").unwrap()).err(),
+        Some(VfsError::OperationNotPermitted)
+    );
+    assert_eq!(
+        root.link("foo/f1", &f1).err(),
+        Some(VfsError::AlreadyExists)
+    );
+    root.remove("foo/f1").unwrap();
+    assert_eq!(f1.get_attr().unwrap().nlink(), 1);
+    let mut buf = [0; 5];
+    root.clone()
+        .lookup("f2")
+        .unwrap()
+        .read_at(0, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // attributes
+    let dir_attr = root.get_attr().unwrap();
+    assert_eq!(dir_attr.nlink(), 3);
+    assert_eq!(dir_attr.mtime(), Duration::from_secs(1000));
+    f1.set_perm(VfsNodePerm::from_bits_truncate(0o600)).unwrap();
+    f1.set_owner(Some(1000), None).unwrap();
+    f1.set_times(None, Some(Duration::from_secs(5))).unwrap();
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.perm().bits(), 0o600);
+    assert_eq!((attr.uid(), attr.gid()), (1000, 0));
+    assert_eq!(attr.atime(), Duration::from_secs(1000));
+    assert_eq!(attr.mtime(), Duration::from_secs(5));
+    assert_eq!(attr.ctime(), Duration::from_secs(1000));
+}
@@ -0,0 +1,388 @@
+use core::time::Duration;
+
+/// Filesystem attributes.
+///
+/// Currently not used.
+#[non_exhaustive]
+pub struct FileSystemInfo;
+
+/// Node (file/directory) attributes.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct VfsNodeAttr {
+    /// File permission mode.
+    mode: VfsNodePerm,
+    /// File type.
+    ty: VfsNodeType,
+    /// Total size, in bytes.
+    size: u64,
+    /// Number of 512B blocks allocated.
+    blocks: u64,
+    /// User ID of the owner.
+    uid: u32,
+    /// Group ID of the owner.
+    gid: u32,
+    /// Number of hard links.
+    nlink: u32,
+    /// Inode number, unique in the filesystem, or `0` if unknown.
+    ino: u64,
+    /// Time of the last access, since the Unix epoch.
+    atime: Duration,
+    /// Time of the last modification of the content.
+    mtime: Duration,
+    /// Time of the last status change.
+    ctime: Duration,
+}
+
+bitflags::bitflags! {
+    /// Node (file/directory) permission mode.
+    #[derive(Debug, Clone, Copy)]
+    pub struct VfsNodePerm: u16 {
+        /// Owner has read permission.
+        const OWNER_READ = 0o400;
+        /// Owner has write permission.
+        const OWNER_WRITE = 0o200;
+        /// Owner has execute permission.
+        const OWNER_EXEC = 0o100;
+
+        /// Group has read permission.
+        const GROUP_READ = 0o40;
+        /// Group has write permission.
+        const GROUP_WRITE = 0o20;
+        /// Group has execute permission.
+        const GROUP_EXEC = 0o10;
+
+        /// Others have read permission.
+        const OTHER_READ = 0o4;
+        /// Others have write permission.
+        const OTHER_WRITE = 0o2;
+        /// Others have execute permission.
+        const OTHER_EXEC = 0o1;
+    }
+}
+
+/// Node (file/directory) type.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VfsNodeType {
+    /// FIFO (named pipe)
+    Fifo = 0o1,
+    /// Character device
+    CharDevice = 0o2,
+    /// Directory
+    Dir = 0o4,
+    /// Block device
+    BlockDevice = 0o6,
+    /// Regular file
+    File = 0o10,
+    /// Symbolic link
+    SymLink = 0o12,
+    /// Socket
+    Socket = 0o14,
+}
+
+/// Directory entry.
+pub struct VfsDirEntry {
+    d_type: VfsNodeType,
+    d_name: [u8; 63],
+}
+
+impl VfsNodePerm {
+    /// Returns the default permission for a file.
+    ///
+    /// The default permission is `0o666` (owner/group/others can read and write).
+    pub const fn default_file() -> Self {
+        Self::from_bits_truncate(0o666)
+    }
+
+    /// Returns the default permission for a directory.
+    ///
+    /// The default permission is `0o755` (owner can read, write and execute,
+    /// group/others can read and execute).
+    pub const fn default_dir() -> Self {
+        Self::from_bits_truncate(0o755)
+    }
+
+    /// Returns the underlying raw `st_mode` bits that contain the standard
+    /// Unix permissions for this file.
+    pub const fn mode(&self) -> u32 {
+        self.bits() as u32
+    }
+
+    /// Returns a 9-bytes string representation of the permission.
+    ///
+    /// For example, `0o755` is represented as `rwxr-xr-x`.
+    pub const fn rwx_buf(&self) -> [u8; 9] {
+        let mut perm = [b'-'; 9];
+        if self.contains(Self::OWNER_READ) {
+            perm[0] = b'r';
+        }
+        if self.contains(Self::OWNER_WRITE) {
+            perm[1] = b'w';
+        }
+        if self.contains(Self::OWNER_EXEC) {
+            perm[2] = b'x';
+        }
+        if self.contains(Self::GROUP_READ) {
+            perm[3] = b'r';
+        }
+        if self.contains(Self::GROUP_WRITE) {
+            perm[4] = b'w';
+        }
+        if self.contains(Self::GROUP_EXEC) {
+            perm[5] = b'x';
+        }
+        if self.contains(Self::OTHER_READ) {
+            perm[6] = b'r';
+        }
+        if self.contains(Self::OTHER_WRITE) {
+            perm[7] = b'w';
+        }
+        if self.contains(Self::OTHER_EXEC) {
+            perm[8] = b'x';
+        }
+        perm
+    }
+
+    /// Whether the owner has read permission.
+    pub const fn owner_readable(&self) -> bool {
+        self.contains(Self::OWNER_READ)
+    }
+
+    /// Whether the owner has write permission.
+    pub const fn owner_writable(&self) -> bool {
+        self.contains(Self::OWNER_WRITE)
+    }
+
+    /// Whether the owner has execute permission.
+    pub const fn owner_executable(&self) -> bool {
+        self.contains(Self::OWNER_EXEC)
+    }
+}
+
+impl VfsNodeType {
+    /// Tests whether this node type represents a regular file.
+    pub const fn is_file(self) -> bool {
+        matches!(self, Self::File)
+    }
+
+    /// Tests whether this node type represents a directory.
+    pub const fn is_dir(self) -> bool {
+        matches!(self, Self::Dir)
+    }
+
+    /// Tests whether this node type represents a symbolic link.
+    pub const fn is_symlink(self) -> bool {
+        matches!(self, Self::SymLink)
+    }
+
+    /// Returns `true` if this node type is a block device.
+    pub const fn is_block_device(self) -> bool {
+        matches!(self, Self::BlockDevice)
+    }
+
+    /// Returns `true` if this node type is a char device.
+    pub const fn is_char_device(self) -> bool {
+        matches!(self, Self::CharDevice)
+    }
+
+    /// Returns `true` if this node type is a fifo.
+    pub const fn is_fifo(self) -> bool {
+        matches!(self, Self::Fifo)
+    }
+
+    /// Returns `true` if this node type is a socket.
+    pub const fn is_socket(self) -> bool {
+        matches!(self, Self::Socket)
+    }
+
+    /// Returns a character representation of the node type.
+    ///
+    /// For example, `d` for directory, `-` for regular file, etc.
+    pub const fn as_char(self) -> char {
+        match self {
+            Self::Fifo => 'p',
+            Self::CharDevice => 'c',
+            Self::Dir => 'd',
+            Self::BlockDevice => 'b',
+            Self::File => '-',
+            Self::SymLink => 'l',
+            Self::Socket => 's',
+        }
+    }
+}
+
+impl VfsNodeAttr {
+    /// Creates a new `VfsNodeAttr` with the given permission mode, type, size
+    /// and number of blocks.
+    pub const fn new(mode: VfsNodePerm, ty: VfsNodeType, size: u64, blocks: u64) -> Self {
+        Self {
+            mode,
+            ty,
+            size,
+            blocks,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            ino: 0,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
+        }
+    }
+
+    /// Creates a new `VfsNodeAttr` for a file, with the default file permission.
+    pub const fn new_file(size: u64, blocks: u64) -> Self {
+        Self::new(VfsNodePerm::default_file(), VfsNodeType::File, size, blocks)
+    }
+
+    /// Creates a new `VfsNodeAttr` for a directory, with the default directory
+    /// permission.
+    pub const fn new_dir(size: u64, blocks: u64) -> Self {
+        Self::new(VfsNodePerm::default_dir(), VfsNodeType::Dir, size, blocks)
+    }
+
+    /// Sets the owner of the node, which is root (`0`) by default.
+    pub const fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the number of hard links to the node, which is `1` by default.
+    pub const fn with_nlink(mut self, nlink: u32) -> Self {
+        self.nlink = nlink;
+        self
+    }
+
+    /// Sets the inode number of the node, which is `0` (unknown) by default.
+    pub const fn with_ino(mut self, ino: u64) -> Self {
+        self.ino = ino;
+        self
+    }
+
+    /// Sets the access, modification and status change times of the node,
+    /// since the Unix epoch. They are all zero by default.
+    pub const fn with_times(mut self, atime: Duration, mtime: Duration, ctime: Duration) -> Self {
+        self.atime = atime;
+        self.mtime = mtime;
+        self.ctime = ctime;
+        self
+    }
+
+    /// Returns the size of the node.
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the number of blocks the node occupies on the disk.
+    pub const fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Returns the permission of the node.
+    pub const fn perm(&self) -> VfsNodePerm {
+        self.mode
+    }
+
+    /// Sets the permission of the node.
+    pub fn set_perm(&mut self, perm: VfsNodePerm) {
+        self.mode = perm
+    }
+
+    /// Returns the type of the node.
+    pub const fn file_type(&self) -> VfsNodeType {
+        self.ty
+    }
+
+    /// Whether the node is a file.
+    pub const fn is_file(&self) -> bool {
+        self.ty.is_file()
+    }
+
+    /// Whether the node is a directory.
+    pub const fn is_dir(&self) -> bool {
+        self.ty.is_dir()
+    }
+
+    /// Whether the node is a symbolic link.
+    pub const fn is_symlink(&self) -> bool {
+        self.ty.is_symlink()
+    }
+
+    /// Returns the user ID of the owner.
+    pub const fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the group ID of the owner.
+    pub const fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Returns the number of hard links to the node.
+    pub const fn nlink(&self) -> u32 {
+        self.nlink
+    }
+
+    /// Returns the inode number, which identifies the node in its filesystem,
+    /// or `0` if unknown.
+    pub const fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Returns the time of the last access.
+    pub const fn atime(&self) -> Duration {
+        self.atime
+    }
+
+    /// Returns the time of the last modification of the content.
+    pub const fn mtime(&self) -> Duration {
+        self.mtime
+    }
+
+    /// Returns the time of the last status change, i.e. the content or the
+    /// attributes.
+    pub const fn ctime(&self) -> Duration {
+        self.ctime
+    }
+}
+
+impl VfsDirEntry {
+    /// Creates an empty `VfsDirEntry`.
+    pub const fn default() -> Self {
+        Self {
+            d_type: VfsNodeType::File,
+            d_name: [0; 63],
+        }
+    }
+
+    /// Creates a new `VfsDirEntry` with the given name and type.
+    pub fn new(name: &str, ty: VfsNodeType) -> Self {
+        let mut d_name = [0; 63];
+        if name.len() > d_name.len() {
+            log::warn!(
+                "directory entry name too long: {} > {}",
+                name.len(),
+                d_name.len()
+            );
+        }
+        d_name[..name.len()].copy_from_slice(name.as_bytes());
+        Self { d_type: ty, d_name }
+    }
+
+    /// Returns the type of the entry.
+    pub fn entry_type(&self) -> VfsNodeType {
+        self.d_type
+    }
+
+    /// Converts the name of the entry to a byte slice.
+    pub fn name_as_bytes(&self) -> &[u8] {
+        let len = self
+            .d_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.d_name.len());
+        &self.d_name[..len]
+    }
+}
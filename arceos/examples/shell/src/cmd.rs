@@ -21,11 +21,17 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("cd", do_cd),
     ("echo", do_echo),
     ("exit", do_exit),
+    #[cfg(feature = "alloc-debug")]
+    ("heap", do_heap),
     ("help", do_help),
     ("ls", do_ls),
     ("mkdir", do_mkdir),
+    #[cfg(feature = "axstd")]
+    ("mount", do_mount),
     ("pwd", do_pwd),
     ("rm", do_rm),
+    #[cfg(feature = "axstd")]
+    ("umount", do_umount),
     ("uname", do_uname),
 ];
 
@@ -66,6 +72,32 @@ const fn file_perm_to_rwx(mode: u32) -> [u8; 9] {
     perm
 }
 
+/// The modification time of the file, in seconds since the Unix epoch.
+fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Splits the seconds since the Unix epoch into `(year, month, day, hour,
+/// minute)` in UTC.
+fn date_time(secs: u64) -> (u64, u64, u64, u64, u64) {
+    let days = secs / 86400 + 719468; // since 0000-03-01
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    let secs_of_day = secs % 86400;
+    (year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60)
+}
+
 fn do_ls(args: &str) {
     let current_dir = std::env::current_dir().unwrap();
     let args = if args.is_empty() {
@@ -82,7 +114,11 @@ fn do_ls(args: &str) {
         let file_type_char = file_type_to_char(file_type);
         let rwx = file_perm_to_rwx(metadata.permissions().mode());
         let rwx = unsafe { core::str::from_utf8_unchecked(&rwx) };
-        println!("{}{} {:>8} {}", file_type_char, rwx, size, entry);
+        let (year, month, day, hour, min) = date_time(modified_secs(&metadata));
+        println!(
+            "{}{} {:>8} {}-{:02}-{:02} {:02}:{:02} {}",
+            file_type_char, rwx, size, year, month, day, hour, min, entry
+        );
         Ok(())
     }
 
@@ -225,6 +261,70 @@ fn do_rm(args: &str) {
     }
 }
 
+#[cfg(feature = "axstd")]
+fn do_mount(args: &str) {
+    use std::os::arceos::api::fs::{ax_mount, ax_mounts, AxMountFlags};
+
+    if args.is_empty() {
+        for m in ax_mounts() {
+            let mode = if m.flags.contains(AxMountFlags::READ_ONLY) {
+                "ro"
+            } else {
+                "rw"
+            };
+            println!("{} on {} type {} ({})", m.source, m.target, m.fstype, mode);
+        }
+        return;
+    }
+
+    let mut fstype = None;
+    let mut flags = AxMountFlags::empty();
+    let mut paths = Vec::new();
+    let mut iter = args.split_whitespace();
+    while let Some(arg) = iter.next() {
+        match arg {
+            "-t" => fstype = iter.next(),
+            "-r" => flags |= AxMountFlags::READ_ONLY,
+            "-o" => match iter.next() {
+                Some("ro") => flags |= AxMountFlags::READ_ONLY,
+                Some("rw") => flags.remove(AxMountFlags::READ_ONLY),
+                Some(opt) => {
+                    print_err!("mount", format_args!("unsupported option '{opt}'"));
+                    return;
+                }
+                None => {
+                    print_err!("mount", "option requires an argument -- 'o'");
+                    return;
+                }
+            },
+            _ => paths.push(arg),
+        }
+    }
+    let (Some(fstype), [source, target]) = (fstype, paths.as_slice()) else {
+        print_err!(
+            "mount",
+            "usage: mount [-r] [-o ro|rw] -t fstype source target"
+        );
+        return;
+    };
+    if let Err(e) = ax_mount(source, target, fstype, flags) {
+        print_err!("mount", target, e);
+    }
+}
+
+#[cfg(feature = "axstd")]
+fn do_umount(args: &str) {
+    if args.is_empty() {
+        print_err!("umount", "missing operand");
+        return;
+    }
+    for target in args.split_whitespace() {
+        if let Err(e) = std::os::arceos::api::fs::ax_umount(target) {
+            print_err!("umount", target, e);
+        }
+    }
+}
+
 fn do_cd(mut args: &str) {
     if args.is_empty() {
         args = "/";
@@ -260,6 +360,12 @@ fn do_uname(_args: &str) {
     );
 }
 
+#[cfg(feature = "alloc-debug")]
+fn do_heap(_args: &str) {
+    // Print to the log, as the allocation table is locked while printing.
+    std::os::arceos::api::mem::ax_dump_allocations();
+}
+
 fn do_help(_args: &str) {
     println!("Available commands:");
     for (name, _) in CMD_TABLE {
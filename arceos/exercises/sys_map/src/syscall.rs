@@ -20,7 +20,9 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_MUNMAP: usize = 215;
 const SYS_MMAP: usize = 222;
+const SYS_MSYNC: usize = 227;
 
 const AT_FDCWD: i32 = -100;
 
@@ -125,6 +127,8 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             tf.arg4() as _,
             tf.arg5() as _,
         ),
+        SYS_MUNMAP => sys_munmap(tf.arg0() as _, tf.arg1() as _),
+        SYS_MSYNC => sys_msync(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         _ => {
             ax_println!("Unimplemented syscall: {}", syscall_num);
             -LinuxError::ENOSYS.code() as _
@@ -181,34 +185,54 @@ fn sys_mmap(
             aspace.map_alloc(vaddr, aligned_length, mapping_flags, true)?;
             Ok(vaddr.as_usize())
         } else {
-            aspace.map_alloc(vaddr, aligned_length, mapping_flags, true)?;
-
-            if offset != 0 {
-                let seek_result = api::sys_lseek(fd, offset as i64, 0);
-                if seek_result < 0 {
-                    return Err(LinuxError::EIO);
-                }
-            }
-            
-            let read_len = length.min(1024 * 1024);
-            let mut file_buf = alloc::vec![0u8; read_len];
-            
-            let read_size = api::sys_read(fd, file_buf.as_mut_ptr() as *mut c_void, read_len);
-            if read_size < 0 {
-                return Err(LinuxError::EIO);
-            }
-            
-            let read_size = read_size as usize;
-            if read_size > 0 {
-                let write_len = read_size.min(length);
-                aspace.write(vaddr, &file_buf[..write_len])?;
+            // File mapping: the pages are read from the file on demand.
+            if offset < 0 || !is_aligned_4k(offset as usize) {
+                return Err(LinuxError::EINVAL);
             }
-
+            let shared = flags.contains(MmapFlags::MAP_SHARED);
+            let writable = mapping_flags.contains(MappingFlags::WRITE);
+            let file = api::get_mmap_node(fd, shared && writable)?;
+            aspace.map_file(
+                vaddr,
+                aligned_length,
+                mapping_flags,
+                file,
+                offset as usize,
+                shared,
+            )?;
             Ok(vaddr.as_usize())
         }
     })
 }
 
+fn sys_munmap(addr: *mut usize, length: usize) -> isize {
+    syscall_body!(sys_munmap, {
+        let addr = addr as usize;
+        if !is_aligned_4k(addr) || length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let aligned_length = (length + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
+        let task = current();
+        let mut aspace = task.task_ext().aspace.lock();
+        aspace.unmap(VirtAddr::from(addr), aligned_length)?;
+        Ok(0)
+    })
+}
+
+fn sys_msync(addr: *mut usize, length: usize, _flags: i32) -> isize {
+    syscall_body!(sys_msync, {
+        let addr = addr as usize;
+        if !is_aligned_4k(addr) {
+            return Err(LinuxError::EINVAL);
+        }
+        let aligned_length = (length + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
+        let task = current();
+        let mut aspace = task.task_ext().aspace.lock();
+        aspace.msync(VirtAddr::from(addr), aligned_length)?;
+        Ok(0)
+    })
+}
+
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
     assert_eq!(dfd, AT_FDCWD);
     api::sys_open(fname, flags, mode) as isize
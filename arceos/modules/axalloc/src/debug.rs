@@ -0,0 +1,330 @@
+//! Heap debugging: redzones, poisoning and the allocation table.
+//!
+//! Each allocation is extended with a header and redzones:
+//!
+//! ```text
+//! | AllocHeader | front redzone | tag | user memory | back redzone |
+//! ```
+//!
+//! The tag points to the header. The live allocations are linked in a list
+//! (the allocation table) through their headers, so tracking them needs no
+//! extra memory.
+//!
+//! The redzones are checked on deallocation, and the freed memory is
+//! poisoned. Double frees are detected until the freed memory is reused.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+use allocator::{AllocError, AllocResult};
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+
+/// The number of return addresses recorded as the call site.
+pub const CALL_SITE_DEPTH: usize = 8;
+
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xbb;
+const POISON_FREE: u8 = 0x6b;
+
+const MAGIC_LIVE: usize = 0xa110_c0de_a110_c0de;
+const MAGIC_FREED: usize = 0xf4ee_dead_f4ee_dead;
+
+/// The maximum distance from the header to the user memory, to check the tags.
+const MAX_FRONT_SIZE: usize = 0x10_0000;
+
+#[repr(C)]
+struct AllocHeader {
+    magic: usize,
+    size: usize,
+    align: usize,
+    ptr: *mut u8,
+    prev: *mut AllocHeader,
+    next: *mut AllocHeader,
+    call_site: [usize; CALL_SITE_DEPTH],
+}
+
+/// The live allocations, linked through their headers.
+struct AllocTable {
+    head: *mut AllocHeader,
+    count: usize,
+    bytes: usize,
+}
+
+unsafe impl Send for AllocTable {}
+
+impl AllocTable {
+    unsafe fn insert(&mut self, header: *mut AllocHeader) {
+        (*header).prev = ptr::null_mut();
+        (*header).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = header;
+        }
+        self.head = header;
+        self.count += 1;
+        self.bytes += (*header).size;
+    }
+
+    unsafe fn remove(&mut self, header: *mut AllocHeader) {
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        self.count -= 1;
+        self.bytes -= (*header).size;
+    }
+}
+
+static TABLE: SpinNoIrq<AllocTable> = SpinNoIrq::new(AllocTable {
+    head: ptr::null_mut(),
+    count: 0,
+    bytes: 0,
+});
+
+static CALL_SITE_TRACER: LazyInit<fn(&mut [usize]) -> usize> = LazyInit::new();
+
+/// A live allocation in the allocation table.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocInfo {
+    /// The address of the allocated memory.
+    pub ptr: usize,
+    /// The size of the allocated memory.
+    pub size: usize,
+    /// The alignment of the allocated memory.
+    pub align: usize,
+    /// The return addresses of the allocating call stack, the innermost
+    /// first. Unused entries are zero.
+    pub call_site: [usize; CALL_SITE_DEPTH],
+}
+
+/// Returns the offset of the user memory from the header.
+fn front_size(align: usize) -> usize {
+    (size_of::<AllocHeader>() + REDZONE_SIZE + size_of::<usize>())
+        .next_multiple_of(align.max(align_of::<AllocHeader>()))
+}
+
+/// Returns the layout including the header and redzones.
+fn inner_layout(layout: Layout) -> AllocResult<Layout> {
+    let size = front_size(layout.align()) + layout.size() + REDZONE_SIZE;
+    Layout::from_size_align(size, layout.align().max(align_of::<AllocHeader>()))
+        .map_err(|_| AllocError::InvalidParam)
+}
+
+/// Allocates `layout` with the header and redzones by `inner_alloc`.
+pub(crate) fn alloc(
+    layout: Layout,
+    inner_alloc: impl FnOnce(Layout) -> AllocResult<NonNull<u8>>,
+) -> AllocResult<NonNull<u8>> {
+    let base = inner_alloc(inner_layout(layout)?)?.as_ptr();
+    let mut call_site = [0; CALL_SITE_DEPTH];
+    if let Some(tracer) = CALL_SITE_TRACER.get() {
+        tracer(&mut call_site);
+    }
+    unsafe {
+        let ptr = base.add(front_size(layout.align()));
+        let header = base as *mut AllocHeader;
+        let front_redzone = base.add(size_of::<AllocHeader>());
+        let tag = ptr.sub(size_of::<usize>());
+        ptr::write_bytes(
+            front_redzone,
+            REDZONE_BYTE,
+            tag as usize - front_redzone as usize,
+        );
+        ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+        (tag as *mut usize).write_unaligned(header as usize);
+        header.write(AllocHeader {
+            magic: MAGIC_LIVE,
+            size: layout.size(),
+            align: layout.align(),
+            ptr,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            call_site,
+        });
+        TABLE.lock().insert(header);
+        Ok(NonNull::new_unchecked(ptr))
+    }
+}
+
+/// Checks the deallocation of `pos` with `layout`, then frees it by
+/// `inner_dealloc` with the layout including the header and redzones.
+///
+/// Panics on double frees, invalid deallocations and corrupted redzones.
+pub(crate) fn dealloc(
+    pos: NonNull<u8>,
+    layout: Layout,
+    inner_dealloc: impl FnOnce(NonNull<u8>, Layout),
+) {
+    let ptr = pos.as_ptr();
+    let header = unsafe { (ptr.sub(size_of::<usize>()) as *const usize).read_unaligned() };
+    let offset = (ptr as usize).wrapping_sub(header);
+    if header % align_of::<AllocHeader>() != 0 || offset == 0 || offset > MAX_FRONT_SIZE {
+        panic!("invalid dealloc of {:#x}: not allocated", ptr as usize);
+    }
+    let header = header as *mut AllocHeader;
+    let info = unsafe { header_info(header) };
+    match unsafe { (*header).magic } {
+        MAGIC_LIVE if info.ptr == ptr as usize => {}
+        MAGIC_FREED if info.ptr == ptr as usize => {
+            panic!("double free of {:#x}: {:#x?}", ptr as usize, info)
+        }
+        _ => panic!("invalid dealloc of {:#x}: not allocated", ptr as usize),
+    }
+    if info.size != layout.size() || info.align != layout.align() {
+        panic!(
+            "invalid dealloc of {:#x} with {:?}: {:#x?}",
+            ptr as usize, layout, info
+        );
+    }
+    unsafe {
+        let front_redzone = (header as *const u8).add(size_of::<AllocHeader>());
+        let front_len = ptr.sub(size_of::<usize>()) as usize - front_redzone as usize;
+        if !is_filled(front_redzone, front_len, REDZONE_BYTE) {
+            panic!("heap underflow of {:#x}: {:#x?}", ptr as usize, info);
+        }
+        if !is_filled(ptr.add(layout.size()), REDZONE_SIZE, REDZONE_BYTE) {
+            panic!("heap overflow of {:#x}: {:#x?}", ptr as usize, info);
+        }
+
+        TABLE.lock().remove(header);
+        (*header).magic = MAGIC_FREED;
+        ptr::write_bytes(ptr, POISON_FREE, layout.size());
+        let inner = inner_layout(layout).unwrap();
+        inner_dealloc(NonNull::new_unchecked(header as *mut u8), inner);
+    }
+}
+
+unsafe fn header_info(header: *const AllocHeader) -> AllocInfo {
+    AllocInfo {
+        ptr: (*header).ptr as usize,
+        size: (*header).size,
+        align: (*header).align,
+        call_site: (*header).call_site,
+    }
+}
+
+unsafe fn is_filled(start: *const u8, len: usize, byte: u8) -> bool {
+    core::slice::from_raw_parts(start, len)
+        .iter()
+        .all(|&b| b == byte)
+}
+
+/// Sets the function to record the call sites of the allocations.
+///
+/// It fills the return addresses of the current call stack into the buffer
+/// and returns the number of them. It must not allocate memory. It can be set
+/// only once.
+pub fn set_call_site_tracer(tracer: fn(&mut [usize]) -> usize) {
+    CALL_SITE_TRACER.init_once(tracer);
+}
+
+/// Calls `f` on each live allocation, the latest first.
+///
+/// The allocation table is locked during the iteration, so `f` must not
+/// allocate or free memory.
+pub fn for_each_allocation(mut f: impl FnMut(&AllocInfo)) {
+    let table = TABLE.lock();
+    let mut header = table.head;
+    while !header.is_null() {
+        unsafe {
+            f(&header_info(header));
+            header = (*header).next;
+        }
+    }
+}
+
+/// Prints the live allocations to the log as a leak report.
+pub fn dump_allocations() {
+    let (count, bytes) = {
+        let table = TABLE.lock();
+        (table.count, table.bytes)
+    };
+    warn!("{} live heap allocations, {} bytes:", count, bytes);
+    for_each_allocation(|info| {
+        let depth = info.call_site.iter().take_while(|&&addr| addr != 0).count();
+        warn!(
+            "  {:#x}: size={}, align={}, call site: {:x?}",
+            info.ptr,
+            info.size,
+            info.align,
+            &info.call_site[..depth],
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::catch_unwind;
+
+    fn std_alloc(layout: Layout) -> AllocResult<NonNull<u8>> {
+        NonNull::new(unsafe { std::alloc::alloc(layout) }).ok_or(AllocError::NoMemory)
+    }
+
+    fn std_dealloc(pos: NonNull<u8>, layout: Layout) {
+        unsafe { std::alloc::dealloc(pos.as_ptr(), layout) }
+    }
+
+    fn is_live(ptr: NonNull<u8>) -> bool {
+        let mut live = false;
+        for_each_allocation(|info| live |= info.ptr == ptr.as_ptr() as usize);
+        live
+    }
+
+    /// Frees `ptr` without giving the memory back, so that it can be checked
+    /// (or freed again) afterwards. Returns the panic message if it panics.
+    fn leaky_dealloc(ptr: NonNull<u8>, layout: Layout) -> Option<String> {
+        catch_unwind(|| dealloc(ptr, layout, |_, _| {}))
+            .err()
+            .map(|err| *err.downcast::<String>().unwrap())
+    }
+
+    #[test]
+    fn alloc_dealloc() {
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let ptr = alloc(layout, std_alloc).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % 64, 0);
+        assert!(is_live(ptr));
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 1, layout.size()) };
+        dealloc(ptr, layout, std_dealloc);
+        assert!(!is_live(ptr));
+    }
+
+    #[test]
+    fn poison_and_double_free() {
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc(layout, std_alloc).unwrap();
+        assert_eq!(leaky_dealloc(ptr, layout), None);
+        let freed = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+        assert!(freed.iter().all(|&b| b == POISON_FREE));
+        let msg = leaky_dealloc(ptr, layout).unwrap();
+        assert!(msg.starts_with("double free"), "{}", msg);
+    }
+
+    #[test]
+    fn redzones() {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = alloc(layout, std_alloc).unwrap();
+        unsafe { ptr.as_ptr().add(layout.size()).write(0) };
+        let msg = leaky_dealloc(ptr, layout).unwrap();
+        assert!(msg.starts_with("heap overflow"), "{}", msg);
+
+        // The byte below the tag, which points to the header.
+        let ptr = alloc(layout, std_alloc).unwrap();
+        unsafe { ptr.as_ptr().sub(size_of::<usize>() + 1).write(0) };
+        let msg = leaky_dealloc(ptr, layout).unwrap();
+        assert!(msg.starts_with("heap underflow"), "{}", msg);
+
+        let ptr = alloc(layout, std_alloc).unwrap();
+        let wrong = Layout::from_size_align(16, 8).unwrap();
+        let msg = leaky_dealloc(ptr, wrong).unwrap();
+        assert!(msg.starts_with("invalid dealloc"), "{}", msg);
+        dealloc(ptr, layout, std_dealloc);
+    }
+}
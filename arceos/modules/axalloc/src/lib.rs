@@ -5,7 +5,7 @@
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -13,16 +13,27 @@ extern crate alloc;
 
 mod page;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+#[cfg(feature = "debug")]
+mod debug;
+
+use allocator::{
+    AllocError, AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator,
+};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
 
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
 pub use page::GlobalPage;
 
+#[cfg(feature = "debug")]
+pub use debug::{
+    dump_allocations, for_each_allocation, set_call_site_tracer, AllocInfo, CALL_SITE_DEPTH,
+};
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
         /// The default byte allocator.
@@ -36,6 +47,19 @@ cfg_if::cfg_if! {
     }
 }
 
+/// Accounts the heap memory to its users, e.g., the task groups.
+pub trait HeapAccounting: Sync {
+    /// Charges `size` bytes to the current user.
+    ///
+    /// Returns `false` to fail the allocation, e.g., if it exceeds the limit.
+    fn charge(&self, size: usize) -> bool;
+
+    /// Uncharges `size` bytes from the current user.
+    fn uncharge(&self, size: usize);
+}
+
+static ACCOUNTING: LazyInit<&'static dyn HeapAccounting> = LazyInit::new();
+
 /// The global allocator used by ArceOS.
 ///
 /// It combines a [`ByteAllocator`] and a [`PageAllocator`] into a simple
@@ -46,6 +70,10 @@ cfg_if::cfg_if! {
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
 /// [`BitmapPageAllocator`] is used as the page allocator.
 ///
+/// With the `debug` feature, the allocations of the byte allocator have
+/// redzones and are recorded in an allocation table, and the deallocations
+/// are checked (see `dump_allocations`).
+///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
@@ -102,7 +130,27 @@ impl GlobalAllocator {
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
     /// byte allocator.
+    ///
+    /// The allocation fails if the [`HeapAccounting`] refuses it.
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        if let Some(accounting) = ACCOUNTING.get() {
+            if !accounting.charge(layout.size()) {
+                return Err(AllocError::NoMemory);
+            }
+        }
+        #[cfg(feature = "debug")]
+        let res = debug::alloc(layout, |layout| self.alloc_uncharged(layout));
+        #[cfg(not(feature = "debug"))]
+        let res = self.alloc_uncharged(layout);
+        if res.is_err() {
+            if let Some(accounting) = ACCOUNTING.get() {
+                accounting.uncharge(layout.size());
+            }
+        }
+        res
+    }
+
+    fn alloc_uncharged(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
         let mut balloc = self.balloc.lock();
         loop {
@@ -133,7 +181,15 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
-        self.balloc.lock().dealloc(pos, layout)
+        #[cfg(feature = "debug")]
+        debug::dealloc(pos, layout, |pos, layout| {
+            self.balloc.lock().dealloc(pos, layout)
+        });
+        #[cfg(not(feature = "debug"))]
+        self.balloc.lock().dealloc(pos, layout);
+        if let Some(accounting) = ACCOUNTING.get() {
+            accounting.uncharge(layout.size());
+        }
     }
 
     /// Allocates contiguous pages.
@@ -231,3 +287,11 @@ pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     );
     GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
 }
+
+/// Sets the accounting of the heap memory.
+///
+/// The allocations and deallocations of the byte allocator (but not the page
+/// allocator) are reported to it afterwards. It can be set only once.
+pub fn set_heap_accounting(accounting: &'static dyn HeapAccounting) {
+    ACCOUNTING.init_once(accounting);
+}
@@ -94,7 +94,7 @@ impl DmaAllocator {
         let expand_size = num_pages * PAGE_SIZE_4K;
         axmm::kernel_aspace()
             .lock()
-            .protect(vaddr, expand_size, flags)
+            .protect_linear(vaddr, expand_size, flags)
             .map_err(|e| {
                 error!("change table flag fail: {e:?}");
                 AllocError::NoMemory
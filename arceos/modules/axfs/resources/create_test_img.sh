@@ -26,5 +26,24 @@ create_test_img() {
 	sudo umount mnt
 }
 
+# ext4 images are populated from a directory, without mounting
+create_ext4_img() {
+	local name=$1
+	local blkcount=$2
+	rm -rf staging "$name"
+	mkdir -p staging
+	for i in $(seq 1 1000); do
+	  echo "Rust is cool!" >>"staging/long.txt"
+	done
+	echo "Rust is cool!" >>"staging/short.txt"
+	mkdir -p "staging/very/long/path"
+	echo "Rust is cool!" >>"staging/very/long/path/test.txt"
+	mkdir -p "staging/very-long-dir-name"
+	echo "Rust is cool!" >>"staging/very-long-dir-name/very-long-file-name.txt"
+	mke2fs -t ext4 -L "Test!" -U 12345678-0000-0000-0000-000000000000 -d staging "$name" "${blkcount}k"
+	rm -rf staging
+}
+
 create_test_img "$CUR_DIR/fat16.img" 2500 16
 create_test_img "$CUR_DIR/fat32.img" 34000 32
+create_ext4_img "$CUR_DIR/ext4.img" 4096
@@ -5,9 +5,11 @@ mod file;
 
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
+pub use crate::fops::{MountFlags, MountInfo};
 
 use alloc::{string::String, vec::Vec};
 use axio::{self as io, prelude::*};
+use core::time::Duration;
 
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
@@ -59,6 +61,34 @@ pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Queries the metadata about a file without following symlinks.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    Ok(Metadata(
+        crate::root::lookup_nofollow(None, path)?.get_attr()?,
+    ))
+}
+
+/// Changes the permissions found on a file or a directory.
+pub fn set_permissions(path: &str, perm: Permissions) -> io::Result<()> {
+    crate::root::set_attr(path, |node| node.set_perm(perm))
+}
+
+/// Changes the owner and the group of a file or a directory. `None` leaves it
+/// unchanged.
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    crate::root::set_attr(path, |node| node.set_owner(uid, gid))
+}
+
+/// Changes the last access and modification times of a file or a directory,
+/// since the Unix epoch. `None` leaves it unchanged.
+pub fn set_times(
+    path: &str,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> io::Result<()> {
+    crate::root::set_attr(path, |node| node.set_times(accessed, modified))
+}
+
 /// Creates a new, empty directory at the provided path.
 pub fn create_dir(path: &str) -> io::Result<()> {
     DirBuilder::new().create(path)
@@ -80,6 +110,30 @@ pub fn remove_file(path: &str) -> io::Result<()> {
     crate::root::remove_file(None, path)
 }
 
+/// Creates a new symbolic link `link` pointing to `original`.
+///
+/// The target is not checked, and is resolved relative to the directory
+/// containing the link when it is followed.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    crate::root::symlink(original, link)
+}
+
+/// Reads the target of a symbolic link.
+pub fn read_link(path: &str) -> io::Result<String> {
+    crate::root::read_link(path)
+}
+
+/// Creates a new hard link `link` to the file `original`, in the same
+/// filesystem.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    crate::root::link(original, link)
+}
+
+/// Writes back all the cached file data to the filesystems.
+pub fn sync() -> io::Result<()> {
+    crate::page_cache::sync_all()
+}
+
 /// Rename a file or directory to a new name.
 /// Delete the original file if `old` already exists.
 ///
@@ -87,3 +141,28 @@ pub fn remove_file(path: &str) -> io::Result<()> {
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     crate::root::rename(old, new)
 }
+
+/// Mounts a filesystem of `fstype` from `source` on the directory `target`.
+///
+/// The mount point can be in another mounted filesystem. Supported types are
+/// `devfs`, `ramfs` (or `tmpfs`), `proc` and `sysfs` if they are enabled, and
+/// `ext4` (or `ext3`, `ext2`) from a block device or partition like
+/// `/dev/vdb1` if `ext4fs` is enabled. A block device can only be mounted
+/// once, and not while another partition overlapping it is mounted.
+pub fn mount(source: &str, target: &str, fstype: &str, flags: MountFlags) -> io::Result<()> {
+    crate::root::mount(source, target, fstype, flags)
+}
+
+/// Unmounts the filesystem mounted on `target`.
+///
+/// It fails with [`ResourceBusy`](io::Error::ResourceBusy) if the filesystem
+/// is in use, i.e., there are files opened in it, other filesystems mounted
+/// in it, or the current directory is in it.
+pub fn umount(target: &str) -> io::Result<()> {
+    crate::root::umount(target)
+}
+
+/// Returns the mounted filesystems, including the root filesystem.
+pub fn mounts() -> Vec<MountInfo> {
+    crate::root::mount_infos()
+}
@@ -1,28 +1,181 @@
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use axdriver::prelude::*;
+use axerrno::{ax_err, AxResult};
+#[cfg(feature = "devfs")]
+use axfs_vfs::{VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeType, VfsResult};
+use axsync::Mutex;
+
+use crate::partition;
 
 const BLOCK_SIZE: usize = 512;
 
+/// A block device, or a partition of it.
+pub(crate) struct BlockDevice {
+    name: &'static str,
+    dev: Arc<Mutex<AxBlockDevice>>,
+    /// The first block of the partition on the device.
+    start: u64,
+    num_blocks: u64,
+    /// Whether a [`Disk`] is opened on it.
+    in_use: AtomicBool,
+}
+
+/// Block devices and their partitions, in the order they are found.
+static BLOCK_DEVICES: Mutex<Vec<Arc<BlockDevice>>> = Mutex::new(Vec::new());
+
 /// A disk device with a cursor.
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    dev: Arc<BlockDevice>,
+}
+
+impl BlockDevice {
+    /// The device name, e.g. `vda` or `vda1`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Get the size of the device.
+    pub fn size(&self) -> u64 {
+        self.num_blocks * BLOCK_SIZE as u64
+    }
+
+    /// Whether it is a partition, which never starts at block 0.
+    fn is_partition(&self) -> bool {
+        self.start > 0
+    }
+
+    fn overlaps(&self, other: &BlockDevice) -> bool {
+        Arc::ptr_eq(&self.dev, &other.dev)
+            && self.start < other.start + other.num_blocks
+            && other.start < self.start + self.num_blocks
+    }
+
+    fn read_block(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        if block_id >= self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        self.dev.lock().read_block(self.start + block_id, buf)
+    }
+
+    fn write_block(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        if block_id >= self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        self.dev.lock().write_block(self.start + block_id, buf)
+    }
+}
+
+/// Names the `index`-th block device like Linux virtio disks: `vda`, ...,
+/// `vdz`, `vdaa`, ...
+fn device_name(mut index: usize) -> String {
+    let mut suffix = Vec::new();
+    loop {
+        suffix.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    suffix.reverse();
+    format!("vd{}", String::from_utf8(suffix).unwrap())
+}
+
+/// Adds the `index`-th block device and the partitions on it.
+pub(crate) fn add_device(index: usize, dev: AxBlockDevice) {
+    assert_eq!(BLOCK_SIZE, dev.block_size());
+    let name = device_name(index);
+    let whole = Arc::new(BlockDevice {
+        num_blocks: dev.num_blocks(),
+        name: name.clone().leak(),
+        dev: Arc::new(Mutex::new(dev)),
+        start: 0,
+        in_use: AtomicBool::new(false),
+    });
+    BLOCK_DEVICES.lock().push(whole.clone());
+
+    let parts = partition::scan(&mut Disk::open(&whole).unwrap());
+    for part in parts {
+        let dev = Arc::new(BlockDevice {
+            name: format!("{}{}", name, part.number).leak(),
+            dev: whole.dev.clone(),
+            start: part.start,
+            num_blocks: part.num_sectors,
+            in_use: AtomicBool::new(false),
+        });
+        info!(
+            "  partition {}: sectors {}..{}",
+            dev.name,
+            dev.start,
+            dev.start + dev.num_blocks
+        );
+        BLOCK_DEVICES.lock().push(dev);
+    }
+}
+
+/// Returns all block devices and partitions.
+pub(crate) fn devices() -> Vec<Arc<BlockDevice>> {
+    BLOCK_DEVICES.lock().clone()
+}
+
+/// Returns the devices that may contain filesystems: the partitions, and the
+/// whole devices without partition tables.
+pub(crate) fn volumes() -> Vec<Arc<BlockDevice>> {
+    let devices = BLOCK_DEVICES.lock();
+    let has_partitions = |dev: &BlockDevice| {
+        devices
+            .iter()
+            .any(|part| part.is_partition() && Arc::ptr_eq(&part.dev, &dev.dev))
+    };
+    devices
+        .iter()
+        .filter(|dev| dev.is_partition() || !has_partitions(dev))
+        .cloned()
+        .collect()
+}
+
+/// Finds the block device or partition by the path like `/dev/vda1`.
+pub(crate) fn find_device(path: &str) -> AxResult<Arc<BlockDevice>> {
+    let name = path.strip_prefix("/dev/").unwrap_or(path);
+    match BLOCK_DEVICES.lock().iter().find(|dev| dev.name == name) {
+        Some(dev) => Ok(dev.clone()),
+        None => ax_err!(NotFound, "no such block device"),
+    }
 }
 
 impl Disk {
-    /// Create a new disk.
-    pub fn new(dev: AxBlockDevice) -> Self {
-        assert_eq!(BLOCK_SIZE, dev.block_size());
-        Self {
+    /// Opens a disk on the block device or partition.
+    ///
+    /// It fails with [`ResourceBusy`](axerrno::AxError::ResourceBusy) if the
+    /// device, or another one overlapping it, is opened, as filesystems assume
+    /// exclusive access to their disks.
+    pub(crate) fn open(dev: &Arc<BlockDevice>) -> AxResult<Self> {
+        let devices = BLOCK_DEVICES.lock();
+        if devices
+            .iter()
+            .any(|other| other.in_use.load(Ordering::Acquire) && other.overlaps(dev))
+        {
+            return ax_err!(ResourceBusy, "block device is in use");
+        }
+        dev.in_use.store(true, Ordering::Release);
+        Ok(Self {
             block_id: 0,
             offset: 0,
-            dev,
-        }
+            dev: dev.clone(),
+        })
+    }
+
+    /// The name of the block device or partition.
+    pub(crate) fn device_name(&self) -> &'static str {
+        self.dev.name
     }
 
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        self.dev.size()
     }
 
     /// Get the position of the cursor.
@@ -90,3 +243,84 @@ impl Disk {
         Ok(write_size)
     }
 }
+
+impl Drop for Disk {
+    fn drop(&mut self) {
+        self.dev.in_use.store(false, Ordering::Release);
+    }
+}
+
+/// A block device file in devfs, which reads and writes the device directly.
+#[cfg(feature = "devfs")]
+pub(crate) struct BlockDevNode(Arc<BlockDevice>);
+
+#[cfg(feature = "devfs")]
+impl BlockDevNode {
+    pub fn new(dev: Arc<BlockDevice>) -> Self {
+        Self(dev)
+    }
+
+    /// Returns the length to access at `offset`, within the device.
+    fn len_at(&self, offset: u64, len: usize) -> usize {
+        len.min(self.0.size().saturating_sub(offset) as usize)
+    }
+}
+
+#[cfg(feature = "devfs")]
+impl VfsNodeOps for BlockDevNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o660),
+            VfsNodeType::BlockDevice,
+            self.0.size(),
+            0,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let len = self.len_at(offset, buf.len());
+        let mut data = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let start = pos as usize % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(len - done);
+            self.0
+                .read_block(pos / BLOCK_SIZE as u64, &mut data)
+                .map_err(|_| VfsError::Io)?;
+            buf[done..done + count].copy_from_slice(&data[start..start + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let len = self.len_at(offset, buf.len());
+        let mut data = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let block_id = pos / BLOCK_SIZE as u64;
+            let start = pos as usize % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(len - done);
+            let res = if count < BLOCK_SIZE {
+                // partial block
+                self.0.read_block(block_id, &mut data).and_then(|_| {
+                    data[start..start + count].copy_from_slice(&buf[done..done + count]);
+                    self.0.write_block(block_id, &data)
+                })
+            } else {
+                self.0.write_block(block_id, &buf[done..done + count])
+            };
+            res.map_err(|_| VfsError::Io)?;
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(()) // the size of devices is fixed
+    }
+}
@@ -1,11 +1,14 @@
 //! Low-level filesystem operations.
 
+use alloc::{string::String, sync::Arc};
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
-use axfs_vfs::{VfsError, VfsNodeRef};
+use axfs_vfs::{VfsError, VfsNodeOps, VfsNodeRef, VfsResult};
 use axio::SeekFrom;
 use cap_access::{Cap, WithCap};
 use core::fmt;
 
+use crate::root::MountPoint;
+
 #[cfg(feature = "myfs")]
 pub use crate::dev::Disk;
 #[cfg(feature = "myfs")]
@@ -19,12 +22,37 @@ pub type DirEntry = axfs_vfs::VfsDirEntry;
 pub type FileAttr = axfs_vfs::VfsNodeAttr;
 /// Alias of [`axfs_vfs::VfsNodePerm`].
 pub type FilePerm = axfs_vfs::VfsNodePerm;
+/// Alias of [`axfs_vfs::VfsNodeRef`].
+pub type FileNodeRef = axfs_vfs::VfsNodeRef;
+
+bitflags::bitflags! {
+    /// Flags of a mounted filesystem.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MountFlags: u32 {
+        /// Mount the filesystem read-only.
+        const READ_ONLY = 1 << 0;
+    }
+}
+
+/// Information about a mounted filesystem.
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// The device or name the filesystem is mounted from.
+    pub source: String,
+    /// The absolute path of the mount point.
+    pub target: String,
+    /// The type of the filesystem.
+    pub fstype: String,
+    /// The mount flags.
+    pub flags: MountFlags,
+}
 
 /// An opened file object, with open permissions and a cursor.
 pub struct File {
     node: WithCap<VfsNodeRef>,
     is_append: bool,
     offset: u64,
+    _mount: Option<Arc<MountPoint>>,
 }
 
 /// An opened directory object, with open permissions and a cursor for
@@ -32,6 +60,7 @@ pub struct File {
 pub struct Directory {
     node: WithCap<VfsNodeRef>,
     entry_idx: usize,
+    mount: Option<Arc<MountPoint>>,
 }
 
 /// Options and flags which can be used to configure how a file is opened.
@@ -44,6 +73,7 @@ pub struct OpenOptions {
     truncate: bool,
     create: bool,
     create_new: bool,
+    direct: bool,
     // system-specific
     _custom_flags: i32,
     _mode: u32,
@@ -60,6 +90,7 @@ impl OpenOptions {
             truncate: false,
             create: false,
             create_new: false,
+            direct: false,
             // system-specific
             _custom_flags: 0,
             _mode: 0o666,
@@ -89,6 +120,10 @@ impl OpenOptions {
     pub fn create_new(&mut self, create_new: bool) {
         self.create_new = create_new;
     }
+    /// Sets the option to bypass the page cache, like `O_DIRECT`.
+    pub fn direct(&mut self, direct: bool) {
+        self.direct = direct;
+    }
 
     const fn is_valid(&self) -> bool {
         if !self.read && !self.write && !self.append {
@@ -116,11 +151,19 @@ impl File {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_at(
+        dir: Option<&VfsNodeRef>,
+        mount: Option<Arc<MountPoint>>,
+        path: &str,
+        opts: &OpenOptions,
+    ) -> AxResult<Self> {
         debug!("open file: {} {:?}", path, opts);
         if !opts.is_valid() {
             return ax_err!(InvalidInput);
         }
+        if opts.write || opts.append {
+            crate::root::check_mount_writable(mount.as_ref())?;
+        }
 
         let node_option = crate::root::lookup(dir, path);
         let node = if opts.create || opts.create_new {
@@ -152,6 +195,11 @@ impl File {
             return ax_err!(PermissionDenied);
         }
 
+        let node = if attr.is_file() && !opts.direct {
+            crate::page_cache::cached_file(crate::root::fs_of(mount.as_ref()), node)?
+        } else {
+            node
+        };
         node.open()?;
         if opts.truncate {
             node.truncate(0)?;
@@ -160,13 +208,14 @@ impl File {
             node: WithCap::new(node, access_cap),
             is_append: opts.append,
             offset: 0,
+            _mount: mount,
         })
     }
 
     /// Opens a file at the path relative to the current directory. Returns a
     /// [`File`] object.
     pub fn open(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_at(None, path, opts)
+        Self::_open_at(None, crate::root::mount_of(path), path, opts)
     }
 
     /// Truncates the file to the specified size.
@@ -246,6 +295,65 @@ impl File {
     pub fn get_attr(&self) -> AxResult<FileAttr> {
         self.access_node(Cap::empty())?.get_attr()
     }
+
+    /// Returns the underlying node to be mapped into memory.
+    ///
+    /// The file should be opened for reading, and also for writing if `write`
+    /// is `true` (for a writable shared mapping). The returned node keeps the
+    /// filesystem of the file from being unmounted until it is dropped, i.e.,
+    /// the file is unmapped.
+    pub fn mmap_node(&self, write: bool) -> AxResult<FileNodeRef> {
+        let cap = if write {
+            Cap::READ | Cap::WRITE
+        } else {
+            Cap::READ
+        };
+        Ok(Arc::new(MappedNode {
+            node: self.access_node(cap)?.clone(),
+            _mount: self._mount.clone(),
+        }))
+    }
+}
+
+/// A file node mapped into memory, which holds the mount point of the file
+/// like an opened [`File`].
+struct MappedNode {
+    node: VfsNodeRef,
+    _mount: Option<Arc<MountPoint>>,
+}
+
+impl VfsNodeOps for MappedNode {
+    fn get_attr(&self) -> VfsResult<FileAttr> {
+        self.node.get_attr()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.node.read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.node.write_at(offset, buf)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.node.fsync()
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        self.node.truncate(size)
+    }
+
+    fn pin_page(&self, index: u64) -> VfsResult<usize> {
+        self.node.pin_page(index)
+    }
+
+    fn dirty_page(&self, index: u64) -> VfsResult {
+        self.node.dirty_page(index)
+    }
+
+    fn unpin_page(&self, index: u64) -> VfsResult {
+        self.node.unpin_page(index)
+    }
 }
 
 impl Directory {
@@ -253,7 +361,12 @@ impl Directory {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_dir_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_dir_at(
+        dir: Option<&VfsNodeRef>,
+        mount: Option<Arc<MountPoint>>,
+        path: &str,
+        opts: &OpenOptions,
+    ) -> AxResult<Self> {
         debug!("open dir: {}", path);
         if !opts.read {
             return ax_err!(InvalidInput);
@@ -276,6 +389,7 @@ impl Directory {
         Ok(Self {
             node: WithCap::new(node, access_cap),
             entry_idx: 0,
+            mount,
         })
     }
 
@@ -287,41 +401,66 @@ impl Directory {
         }
     }
 
+    /// Returns the mount point that `path` relative to this directory belongs
+    /// to.
+    fn mount_at(&self, path: &str) -> Option<Arc<MountPoint>> {
+        if path.starts_with('/') {
+            crate::root::mount_of(path)
+        } else {
+            self.mount.clone()
+        }
+    }
+
+    /// Checks that `path` relative to this directory can be modified.
+    ///
+    /// Absolute paths are checked when they are resolved from the root.
+    fn check_writable_at(&self, path: &str) -> AxResult {
+        if path.starts_with('/') {
+            Ok(())
+        } else {
+            crate::root::check_mount_writable(self.mount.as_ref())
+        }
+    }
+
     /// Opens a directory at the path relative to the current directory.
     /// Returns a [`Directory`] object.
     pub fn open_dir(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(None, path, opts)
+        Self::_open_dir_at(None, crate::root::mount_of(path), path, opts)
     }
 
     /// Opens a directory at the path relative to this directory. Returns a
     /// [`Directory`] object.
     pub fn open_dir_at(&self, path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(self.access_at(path)?, path, opts)
+        Self::_open_dir_at(self.access_at(path)?, self.mount_at(path), path, opts)
     }
 
     /// Opens a file at the path relative to this directory. Returns a [`File`]
     /// object.
     pub fn open_file_at(&self, path: &str, opts: &OpenOptions) -> AxResult<File> {
-        File::_open_at(self.access_at(path)?, path, opts)
+        File::_open_at(self.access_at(path)?, self.mount_at(path), path, opts)
     }
 
     /// Creates an empty file at the path relative to this directory.
     pub fn create_file(&self, path: &str) -> AxResult<VfsNodeRef> {
+        self.check_writable_at(path)?;
         crate::root::create_file(self.access_at(path)?, path)
     }
 
     /// Creates an empty directory at the path relative to this directory.
     pub fn create_dir(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::create_dir(self.access_at(path)?, path)
     }
 
     /// Removes a file at the path relative to this directory.
     pub fn remove_file(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::remove_file(self.access_at(path)?, path)
     }
 
     /// Removes a directory at the path relative to this directory.
     pub fn remove_dir(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::remove_dir(self.access_at(path)?, path)
     }
 
@@ -380,6 +519,7 @@ impl fmt::Debug for OpenOptions {
         fmt_opt!(truncate, "TRUNC");
         fmt_opt!(create, "CREATE");
         fmt_opt!(create_new, "CREATE_NEW");
+        fmt_opt!(direct, "DIRECT");
         Ok(())
     }
 }
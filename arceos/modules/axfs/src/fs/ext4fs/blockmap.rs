@@ -0,0 +1,508 @@
+//! Block maps of inodes: extent trees (ext4) and indirect blocks (ext2/ext3).
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+const MAX_EXTENT_DEPTH: u16 = 5;
+const DIRECT_BLOCKS: usize = 12;
+/// The number of entries in the extent tree root in the inode.
+const ROOT_EXTENTS: usize = 4;
+
+/// A leaf node of the extent tree, in the inode (`block` is `None`) or in a
+/// tree block.
+struct Leaf {
+    block: Option<u64>,
+    buf: Vec<u8>,
+}
+
+impl Leaf {
+    fn extents(&self) -> VfsResult<Vec<Extent>> {
+        let header = parse_header(&self.buf)?;
+        Ok((0..header.entries)
+            .map(|i| Extent::parse(&self.buf[12 + 12 * i..]))
+            .collect())
+    }
+}
+
+fn parse_header(buf: &[u8]) -> VfsResult<ExtentHeader> {
+    ExtentHeader::parse(buf).ok_or(VfsError::InvalidData)
+}
+
+fn uses_extents(inode: &Inode) -> bool {
+    inode.flags() & INODE_EXTENTS_FL != 0
+}
+
+impl Volume {
+    /// Maps the logical block of the inode to the physical block, or `None`
+    /// if it is a hole (or not initialized), which reads as zeros.
+    pub fn bmap(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        if uses_extents(inode) {
+            let leaf = self.find_leaf(inode, lblk)?;
+            Ok(leaf
+                .extents()?
+                .into_iter()
+                .find(|e| e.contains(lblk) && !e.uninit)
+                .map(|e| e.start + (lblk - e.block) as u64))
+        } else {
+            self.ind_bmap(inode, lblk)
+        }
+    }
+
+    /// Maps the logical block of the inode, allocating it if necessary.
+    ///
+    /// Returns the physical block and whether it is new, in which case its
+    /// content is undefined. The inode must be written back by the caller.
+    pub fn bmap_alloc(&mut self, inode: &mut Inode, lblk: u32) -> VfsResult<(u64, bool)> {
+        self.check_writable()?;
+        if uses_extents(inode) {
+            self.extent_alloc(inode, lblk)
+        } else {
+            self.ind_alloc(inode, lblk)
+        }
+    }
+
+    /// Frees the blocks of the inode from the logical block `from`.
+    ///
+    /// The inode must be written back by the caller.
+    pub fn truncate_blocks(&mut self, inode: &mut Inode, from: u32) -> VfsResult {
+        self.check_writable()?;
+        if uses_extents(inode) {
+            self.extent_truncate(inode, from)
+        } else {
+            self.ind_truncate(inode, from)
+        }
+    }
+
+    fn find_leaf(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Leaf> {
+        let mut leaf = Leaf {
+            block: None,
+            buf: inode.i_block().to_vec(),
+        };
+        let mut depth = parse_header(&leaf.buf)?.depth;
+        if depth > MAX_EXTENT_DEPTH {
+            return Err(VfsError::InvalidData);
+        }
+        while depth > 0 {
+            let header = parse_header(&leaf.buf)?;
+            if header.entries == 0 {
+                return Err(VfsError::InvalidData);
+            }
+            // the last index starting at or before `lblk`
+            let idx = (1..header.entries)
+                .take_while(|&i| parse_extent_index(&leaf.buf[12 + 12 * i..]).0 <= lblk)
+                .last()
+                .unwrap_or(0);
+            let child = parse_extent_index(&leaf.buf[12 + 12 * idx..]).1;
+            let mut buf = vec![0; self.block_size];
+            self.read_block(child, &mut buf)?;
+            depth -= 1;
+            if parse_header(&buf)?.depth != depth {
+                return Err(VfsError::InvalidData);
+            }
+            leaf = Leaf {
+                block: Some(child),
+                buf,
+            };
+        }
+        Ok(leaf)
+    }
+
+    fn write_tree_block(&mut self, inode: &Inode, block: u64, buf: &mut [u8]) -> VfsResult {
+        if self.metadata_csum {
+            let tail = 12 + 12 * parse_header(buf)?.max;
+            let csum = crc32c(self.inode_csum_seed(inode), &buf[..tail]);
+            set_le32(buf, tail, csum);
+        }
+        self.write_block(block, buf)
+    }
+
+    fn write_leaf(&mut self, inode: &mut Inode, mut leaf: Leaf) -> VfsResult {
+        match leaf.block {
+            None => {
+                inode.i_block_mut().copy_from_slice(&leaf.buf);
+                Ok(())
+            }
+            Some(block) => self.write_tree_block(inode, block, &mut leaf.buf),
+        }
+    }
+
+    fn extent_alloc(&mut self, inode: &mut Inode, lblk: u32) -> VfsResult<(u64, bool)> {
+        let mut leaf = self.find_leaf(inode, lblk)?;
+        let mut exts = leaf.extents()?;
+        // the first extent after `lblk`
+        let pos = exts.partition_point(|e| e.block <= lblk);
+        if pos > 0 && exts[pos - 1].contains(lblk) {
+            let e = exts[pos - 1];
+            let pblk = e.start + (lblk - e.block) as u64;
+            if !e.uninit {
+                return Ok((pblk, false));
+            }
+            // Split the uninitialized extent to initialize the block.
+            let mut parts = Vec::new();
+            if lblk > e.block {
+                parts.push(Extent {
+                    len: lblk - e.block,
+                    ..e
+                });
+            }
+            parts.push(Extent {
+                block: lblk,
+                len: 1,
+                start: pblk,
+                uninit: false,
+            });
+            if (lblk as u64) + 1 < e.end() {
+                parts.push(Extent {
+                    block: lblk + 1,
+                    len: (e.end() - lblk as u64 - 1) as u32,
+                    start: pblk + 1,
+                    uninit: true,
+                });
+            }
+            self.update_extents(inode, leaf, pos - 1, 1, &parts)?;
+            return Ok((pblk, true));
+        }
+
+        let goal = match pos.checked_sub(1) {
+            Some(prev) => exts[prev].start + (lblk - exts[prev].block) as u64,
+            None => self.group_goal(self.inode_group(inode.ino)),
+        };
+        let pblk = self.alloc_block(goal)?;
+        self.add_inode_blocks(inode, 1);
+        if pos > 0 {
+            let prev = &mut exts[pos - 1];
+            if !prev.uninit
+                && prev.end() == lblk as u64
+                && prev.start + prev.len as u64 == pblk
+                && prev.len < EXTENT_MAX_LEN
+            {
+                // extend the previous extent in place
+                prev.len += 1;
+                prev.write(&mut leaf.buf[12 + 12 * (pos - 1)..]);
+                self.write_leaf(inode, leaf)?;
+                return Ok((pblk, true));
+            }
+        }
+        let ext = Extent {
+            block: lblk,
+            len: 1,
+            start: pblk,
+            uninit: false,
+        };
+        if let Err(err) = self.update_extents(inode, leaf, pos, 0, &[ext]) {
+            self.free_blocks(pblk, 1)?;
+            self.add_inode_blocks(inode, -1);
+            return Err(err);
+        }
+        Ok((pblk, true))
+    }
+
+    /// Replaces `remove` extents at `pos` of the leaf with `new`, in place if
+    /// the leaf has room, otherwise by rebuilding the whole tree.
+    fn update_extents(
+        &mut self,
+        inode: &mut Inode,
+        mut leaf: Leaf,
+        pos: usize,
+        remove: usize,
+        new: &[Extent],
+    ) -> VfsResult {
+        let header = parse_header(&leaf.buf)?;
+        let entries = header.entries - remove + new.len();
+        // Inserting before the first extent of a tree block would change the
+        // key of the block in its parent.
+        let key_unchanged = pos > 0 || remove > 0 || leaf.block.is_none();
+        if entries <= header.max && key_unchanged {
+            let mut exts = leaf.extents()?;
+            exts.splice(pos..pos + remove, new.iter().copied());
+            ExtentHeader { entries, ..header }.write(&mut leaf.buf);
+            for (i, e) in exts.iter().enumerate() {
+                e.write(&mut leaf.buf[12 + 12 * i..]);
+            }
+            return self.write_leaf(inode, leaf);
+        }
+
+        let (mut exts, tree_blocks) = self.collect_extents(inode)?;
+        let at = exts.partition_point(|e| e.block < new[0].block);
+        exts.splice(at..at + remove, new.iter().copied());
+        self.rebuild_extents(inode, &exts, &tree_blocks)
+    }
+
+    /// Returns all extents of the inode and the blocks of the tree.
+    fn collect_extents(&mut self, inode: &Inode) -> VfsResult<(Vec<Extent>, Vec<u64>)> {
+        let root = inode.i_block().to_vec();
+        let depth = parse_header(&root)?.depth;
+        if depth > MAX_EXTENT_DEPTH {
+            return Err(VfsError::InvalidData);
+        }
+        let mut exts = Vec::new();
+        let mut tree_blocks = Vec::new();
+        self.walk_extents(&root, depth, &mut exts, &mut tree_blocks)?;
+        Ok((exts, tree_blocks))
+    }
+
+    fn walk_extents(
+        &mut self,
+        node: &[u8],
+        depth: u16,
+        exts: &mut Vec<Extent>,
+        tree_blocks: &mut Vec<u64>,
+    ) -> VfsResult {
+        let header = parse_header(node)?;
+        if header.depth != depth {
+            return Err(VfsError::InvalidData);
+        }
+        for i in 0..header.entries {
+            let entry = &node[12 + 12 * i..];
+            if depth == 0 {
+                exts.push(Extent::parse(entry));
+            } else {
+                let child = parse_extent_index(entry).1;
+                tree_blocks.push(child);
+                let mut buf = vec![0; self.block_size];
+                self.read_block(child, &mut buf)?;
+                self.walk_extents(&buf, depth - 1, exts, tree_blocks)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the old tree blocks and builds a new extent tree of `exts`.
+    fn rebuild_extents(
+        &mut self,
+        inode: &mut Inode,
+        exts: &[Extent],
+        old_blocks: &[u64],
+    ) -> VfsResult {
+        for &block in old_blocks {
+            self.free_blocks(block, 1)?;
+            self.add_inode_blocks(inode, -1);
+        }
+        let mut goal = match exts.first() {
+            Some(e) => e.start,
+            None => self.group_goal(self.inode_group(inode.ino)),
+        };
+
+        // the entries of the current level: (first logical block, raw entry)
+        let mut entries: Vec<(u32, [u8; 12])> = exts
+            .iter()
+            .map(|e| {
+                let mut raw = [0; 12];
+                e.write(&mut raw);
+                (e.block, raw)
+            })
+            .collect();
+        let cap = (self.block_size - 12) / 12;
+        let mut depth = 0;
+        while entries.len() > ROOT_EXTENTS {
+            let mut parents = Vec::new();
+            for chunk in entries.chunks(cap) {
+                let block = self.alloc_block(goal)?;
+                goal = block + 1;
+                self.add_inode_blocks(inode, 1);
+                let mut buf = vec![0; self.block_size];
+                ExtentHeader {
+                    entries: chunk.len(),
+                    max: cap,
+                    depth,
+                }
+                .write(&mut buf);
+                for (i, (_, raw)) in chunk.iter().enumerate() {
+                    buf[12 + 12 * i..24 + 12 * i].copy_from_slice(raw);
+                }
+                self.write_tree_block(inode, block, &mut buf)?;
+                let mut raw = [0; 12];
+                write_extent_index(&mut raw, chunk[0].0, block);
+                parents.push((chunk[0].0, raw));
+            }
+            entries = parents;
+            depth += 1;
+        }
+
+        let root = inode.i_block_mut();
+        root.fill(0);
+        ExtentHeader {
+            entries: entries.len(),
+            max: ROOT_EXTENTS,
+            depth,
+        }
+        .write(root);
+        for (i, (_, raw)) in entries.iter().enumerate() {
+            root[12 + 12 * i..24 + 12 * i].copy_from_slice(raw);
+        }
+        Ok(())
+    }
+
+    fn extent_truncate(&mut self, inode: &mut Inode, from: u32) -> VfsResult {
+        let (exts, tree_blocks) = self.collect_extents(inode)?;
+        if exts.iter().all(|e| e.end() <= from as u64) {
+            return Ok(());
+        }
+        let mut kept = Vec::new();
+        for e in exts {
+            if e.block >= from {
+                self.free_blocks(e.start, e.len as u64)?;
+                self.add_inode_blocks(inode, -(e.len as i64));
+            } else if e.end() > from as u64 {
+                let keep = from - e.block;
+                self.free_blocks(e.start + keep as u64, (e.len - keep) as u64)?;
+                self.add_inode_blocks(inode, -((e.len - keep) as i64));
+                kept.push(Extent { len: keep, ..e });
+            } else {
+                kept.push(e);
+            }
+        }
+        self.rebuild_extents(inode, &kept, &tree_blocks)
+    }
+
+    /// Returns the slot in `i_block` and the offsets in the indirect blocks
+    /// of the logical block.
+    fn ind_path(&self, lblk: u32) -> VfsResult<(usize, Vec<usize>)> {
+        let ptrs = (self.block_size / 4) as u64;
+        let mut lblk = lblk as u64;
+        if lblk < DIRECT_BLOCKS as u64 {
+            return Ok((lblk as usize, Vec::new()));
+        }
+        lblk -= DIRECT_BLOCKS as u64;
+        let mut span = ptrs;
+        for level in 1..=3 {
+            if lblk < span {
+                let mut offsets = vec![0; level];
+                for off in offsets.iter_mut().rev() {
+                    *off = (lblk % ptrs) as usize;
+                    lblk /= ptrs;
+                }
+                return Ok((DIRECT_BLOCKS - 1 + level, offsets));
+            }
+            lblk -= span;
+            span *= ptrs;
+        }
+        Err(VfsError::InvalidInput)
+    }
+
+    fn read_ptr(&mut self, block: u64, off: usize) -> VfsResult<u64> {
+        let mut ptr = [0; 4];
+        self.read_bytes(block * self.block_size as u64 + off as u64 * 4, &mut ptr)?;
+        Ok(u32::from_le_bytes(ptr) as u64)
+    }
+
+    fn write_ptr(&mut self, block: u64, off: usize, ptr: u64) -> VfsResult {
+        let pos = block * self.block_size as u64 + off as u64 * 4;
+        self.write_bytes(pos, &(ptr as u32).to_le_bytes())
+    }
+
+    fn ind_bmap(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        let (slot, offsets) = self.ind_path(lblk)?;
+        let mut block = le32(inode.i_block(), slot * 4) as u64;
+        for off in offsets {
+            if block == 0 {
+                return Ok(None);
+            }
+            block = self.read_ptr(block, off)?;
+        }
+        Ok((block != 0).then_some(block))
+    }
+
+    fn ind_alloc(&mut self, inode: &mut Inode, lblk: u32) -> VfsResult<(u64, bool)> {
+        let (slot, offsets) = self.ind_path(lblk)?;
+        let levels = offsets.len();
+        let mut block = le32(inode.i_block(), slot * 4) as u64;
+        let mut new = false;
+        if block == 0 {
+            block = self.alloc_block(self.group_goal(self.inode_group(inode.ino)))?;
+            self.add_inode_blocks(inode, 1);
+            if levels > 0 {
+                self.zero_block(block)?;
+            }
+            set_le32(inode.i_block_mut(), slot * 4, block as u32);
+            new = levels == 0;
+        }
+        for (i, off) in offsets.into_iter().enumerate() {
+            let mut ptr = self.read_ptr(block, off)?;
+            if ptr == 0 {
+                ptr = self.alloc_block(block + 1)?;
+                self.add_inode_blocks(inode, 1);
+                if i + 1 < levels {
+                    self.zero_block(ptr)?;
+                }
+                self.write_ptr(block, off, ptr)?;
+                new = i + 1 == levels;
+            }
+            block = ptr;
+        }
+        Ok((block, new))
+    }
+
+    fn ind_truncate(&mut self, inode: &mut Inode, from: u32) -> VfsResult {
+        let from = from as u64;
+        for slot in from.min(DIRECT_BLOCKS as u64) as usize..DIRECT_BLOCKS {
+            let ptr = le32(inode.i_block(), slot * 4) as u64;
+            if ptr != 0 {
+                self.free_blocks(ptr, 1)?;
+                self.add_inode_blocks(inode, -1);
+                set_le32(inode.i_block_mut(), slot * 4, 0);
+            }
+        }
+        let ptrs = (self.block_size / 4) as u64;
+        let (mut base, mut span) = (DIRECT_BLOCKS as u64, ptrs);
+        for level in 1..=3 {
+            let slot = DIRECT_BLOCKS - 1 + level as usize;
+            let ptr = le32(inode.i_block(), slot * 4) as u64;
+            if ptr != 0 && base + span > from && self.free_ind(inode, ptr, level, base, from)? {
+                set_le32(inode.i_block_mut(), slot * 4, 0);
+            }
+            base += span;
+            span *= ptrs;
+        }
+        Ok(())
+    }
+
+    /// Frees the blocks from `from` under the indirect block of `level`,
+    /// which maps the logical blocks from `base`.
+    ///
+    /// Returns whether the indirect block itself is freed.
+    fn free_ind(
+        &mut self,
+        inode: &mut Inode,
+        block: u64,
+        level: u32,
+        base: u64,
+        from: u64,
+    ) -> VfsResult<bool> {
+        let ptrs = self.block_size / 4;
+        let child_span = (ptrs as u64).pow(level - 1);
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        for i in 0..ptrs {
+            let ptr = le32(&buf, i * 4) as u64;
+            let child_base = base + i as u64 * child_span;
+            if ptr == 0 || child_base + child_span <= from {
+                continue;
+            }
+            let freed = if level == 1 {
+                self.free_blocks(ptr, 1)?;
+                self.add_inode_blocks(inode, -1);
+                true
+            } else {
+                self.free_ind(inode, ptr, level - 1, child_base, from)?
+            };
+            if freed {
+                set_le32(&mut buf, i * 4, 0);
+                changed = true;
+            }
+        }
+        if base >= from {
+            self.free_blocks(block, 1)?;
+            self.add_inode_blocks(inode, -1);
+            return Ok(true);
+        }
+        if changed {
+            self.write_block(block, &buf)?;
+        }
+        Ok(false)
+    }
+}
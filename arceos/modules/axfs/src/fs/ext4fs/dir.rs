@@ -0,0 +1,293 @@
+//! Directory entries, in linear (and hashed tree) directories.
+//!
+//! Hashed tree directories are read linearly, as their index blocks look like
+//! empty entries. The index is dropped when the directory is modified.
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+/// A directory entry.
+pub struct DirEntry {
+    pub ino: u32,
+    pub name: Vec<u8>,
+    pub file_type: u8,
+}
+
+/// The location of a directory entry.
+struct EntryPos {
+    pblk: u64,
+    block: Vec<u8>,
+    off: usize,
+    prev: Option<usize>,
+}
+
+const fn entry_size(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+impl Volume {
+    fn rec_len(&self, block: &[u8], off: usize) -> usize {
+        match le16(block, off + 4) as usize {
+            // 64KiB blocks
+            0 | 65535 if self.block_size == 65536 => 65536,
+            len => len,
+        }
+    }
+
+    fn set_rec_len(&self, block: &mut [u8], off: usize, len: usize) {
+        let len = if len == 65536 { 65535 } else { len as u16 };
+        set_le16(block, off + 4, len);
+    }
+
+    fn name_len(&self, block: &[u8], off: usize) -> usize {
+        if self.has_filetype {
+            block[off + 6] as usize
+        } else {
+            le16(block, off + 6) as usize
+        }
+    }
+
+    fn write_entry(&self, block: &mut [u8], off: usize, ino: u32, name: &[u8], ft: u8) {
+        set_le32(block, off, ino);
+        if self.has_filetype {
+            block[off + 6] = name.len() as u8;
+            block[off + 7] = ft;
+        } else {
+            set_le16(block, off + 6, name.len() as u16);
+        }
+        block[off + 8..off + 8 + name.len()].copy_from_slice(name);
+    }
+
+    /// Whether the block ends with a checksum tail.
+    fn has_dir_tail(&self, block: &[u8]) -> bool {
+        let tail = self.block_size - DIR_TAIL_SIZE;
+        le32(block, tail) == 0
+            && le16(block, tail + 4) as usize == DIR_TAIL_SIZE
+            && le16(block, tail + 6) == (FT_DIR_CSUM as u16) << 8
+    }
+
+    /// Returns the end of the entries in the block.
+    fn entries_end(&self, block: &[u8]) -> usize {
+        if self.metadata_csum && self.has_dir_tail(block) {
+            self.block_size - DIR_TAIL_SIZE
+        } else {
+            self.block_size
+        }
+    }
+
+    /// Checks an entry, returning its record length.
+    fn check_entry(&self, block: &[u8], off: usize, end: usize) -> VfsResult<usize> {
+        if off + 8 > end {
+            return Err(VfsError::InvalidData);
+        }
+        let rec_len = self.rec_len(block, off);
+        if rec_len < 8 || rec_len % 4 != 0 || off + rec_len > end {
+            return Err(VfsError::InvalidData);
+        }
+        if 8 + self.name_len(block, off) > rec_len {
+            return Err(VfsError::InvalidData);
+        }
+        Ok(rec_len)
+    }
+
+    fn write_dir_block(&mut self, dir: &Inode, pblk: u64, block: &mut [u8]) -> VfsResult {
+        if self.metadata_csum && self.has_dir_tail(block) {
+            let tail = self.block_size - DIR_TAIL_SIZE;
+            let csum = crc32c(self.inode_csum_seed(dir), &block[..tail]);
+            set_le32(block, self.block_size - 4, csum);
+        }
+        self.write_block(pblk, block)
+    }
+
+    fn dir_blocks(&self, dir: &Inode) -> u32 {
+        dir.size().div_ceil(self.block_size as u64) as u32
+    }
+
+    /// Reads the directory block, or `None` if it is a hole.
+    fn read_dir_block(&mut self, dir: &Inode, lblk: u32) -> VfsResult<Option<(u64, Vec<u8>)>> {
+        let Some(pblk) = self.bmap(dir, lblk)? else {
+            return Ok(None);
+        };
+        let mut block = vec![0; self.block_size];
+        self.read_block(pblk, &mut block)?;
+        Ok(Some((pblk, block)))
+    }
+
+    /// Calls `f` on the entries in use, until it returns `true`.
+    fn walk_dir(
+        &mut self,
+        dir: &Inode,
+        mut f: impl FnMut(&Self, &[u8], usize) -> bool,
+    ) -> VfsResult<Option<EntryPos>> {
+        for lblk in 0..self.dir_blocks(dir) {
+            let Some((pblk, block)) = self.read_dir_block(dir, lblk)? else {
+                continue;
+            };
+            let end = self.entries_end(&block);
+            let (mut off, mut prev) = (0, None);
+            while off < end {
+                let rec_len = self.check_entry(&block, off, end)?;
+                if le32(&block, off) != 0 && f(self, &block, off) {
+                    return Ok(Some(EntryPos {
+                        pblk,
+                        block,
+                        off,
+                        prev,
+                    }));
+                }
+                prev = Some(off);
+                off += rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn entry_at(&self, block: &[u8], off: usize) -> DirEntry {
+        let name_len = self.name_len(block, off);
+        DirEntry {
+            ino: le32(block, off),
+            name: block[off + 8..off + 8 + name_len].to_vec(),
+            file_type: if self.has_filetype {
+                block[off + 7]
+            } else {
+                FT_UNKNOWN
+            },
+        }
+    }
+
+    /// Returns all entries of the directory, including `.` and `..`.
+    pub fn dir_entries(&mut self, dir: &Inode) -> VfsResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        self.walk_dir(dir, |vol, block, off| {
+            entries.push(vol.entry_at(block, off));
+            false
+        })?;
+        Ok(entries)
+    }
+
+    fn find_entry(&mut self, dir: &Inode, name: &[u8]) -> VfsResult<Option<EntryPos>> {
+        self.walk_dir(dir, |vol, block, off| {
+            vol.name_len(block, off) == name.len() && &block[off + 8..off + 8 + name.len()] == name
+        })
+    }
+
+    pub fn dir_lookup(&mut self, dir: &Inode, name: &[u8]) -> VfsResult<Option<DirEntry>> {
+        Ok(self
+            .find_entry(dir, name)?
+            .map(|pos| self.entry_at(&pos.block, pos.off)))
+    }
+
+    /// Adds an entry to the directory. The inode of the directory must be
+    /// written back by the caller.
+    pub fn dir_add(&mut self, dir: &mut Inode, name: &[u8], ino: u32, ft: u8) -> VfsResult {
+        if name.is_empty() || name.len() > 255 {
+            return Err(VfsError::InvalidInput);
+        }
+        dir.set_flags(dir.flags() & !INODE_INDEX_FL);
+        let size = entry_size(name.len());
+        let nblocks = self.dir_blocks(dir);
+        for lblk in 0..nblocks {
+            let Some((pblk, mut block)) = self.read_dir_block(dir, lblk)? else {
+                continue;
+            };
+            if self.metadata_csum && !self.has_dir_tail(&block) {
+                continue; // index blocks
+            }
+            let end = self.entries_end(&block);
+            let mut off = 0;
+            while off < end {
+                let rec_len = self.check_entry(&block, off, end)?;
+                let used = match le32(&block, off) {
+                    0 => 0,
+                    _ => entry_size(self.name_len(&block, off)),
+                };
+                if rec_len - used >= size {
+                    let new_off = off + used;
+                    if used > 0 {
+                        self.set_rec_len(&mut block, off, used);
+                    }
+                    self.set_rec_len(&mut block, new_off, rec_len - used);
+                    self.write_entry(&mut block, new_off, ino, name, ft);
+                    return self.write_dir_block(dir, pblk, &mut block);
+                }
+                off += rec_len;
+            }
+        }
+
+        // Append a new block.
+        let (pblk, _) = self.bmap_alloc(dir, nblocks)?;
+        let mut block = vec![0; self.block_size];
+        let mut end = self.block_size;
+        if self.metadata_csum {
+            end -= DIR_TAIL_SIZE;
+            self.set_rec_len(&mut block, end, DIR_TAIL_SIZE);
+            block[end + 7] = FT_DIR_CSUM;
+        }
+        self.set_rec_len(&mut block, 0, end);
+        self.write_entry(&mut block, 0, ino, name, ft);
+        dir.set_size((nblocks as u64 + 1) * self.block_size as u64);
+        self.write_dir_block(dir, pblk, &mut block)
+    }
+
+    /// Writes the first block of a new directory, with `.` and `..`.
+    pub fn dir_init(&mut self, dir: &mut Inode, parent: u32) -> VfsResult {
+        let ft = if self.has_filetype {
+            FT_DIR
+        } else {
+            FT_UNKNOWN
+        };
+        let (pblk, _) = self.bmap_alloc(dir, 0)?;
+        let mut block = vec![0; self.block_size];
+        let mut end = self.block_size;
+        if self.metadata_csum {
+            end -= DIR_TAIL_SIZE;
+            self.set_rec_len(&mut block, end, DIR_TAIL_SIZE);
+            block[end + 7] = FT_DIR_CSUM;
+        }
+        self.set_rec_len(&mut block, 0, 12);
+        self.write_entry(&mut block, 0, dir.ino, b".", ft);
+        self.set_rec_len(&mut block, 12, end - 12);
+        self.write_entry(&mut block, 12, parent, b"..", ft);
+        dir.set_size(self.block_size as u64);
+        self.write_dir_block(dir, pblk, &mut block)
+    }
+
+    /// Removes the entry from the directory, returning its inode number.
+    pub fn dir_remove(&mut self, dir: &Inode, name: &[u8]) -> VfsResult<u32> {
+        let mut pos = self.find_entry(dir, name)?.ok_or(VfsError::NotFound)?;
+        let ino = le32(&pos.block, pos.off);
+        match pos.prev {
+            Some(prev) => {
+                // merge into the previous entry
+                let len = self.rec_len(&pos.block, prev) + self.rec_len(&pos.block, pos.off);
+                self.set_rec_len(&mut pos.block, prev, len);
+            }
+            None => set_le32(&mut pos.block, pos.off, 0),
+        }
+        self.write_dir_block(dir, pos.pblk, &mut pos.block)?;
+        Ok(ino)
+    }
+
+    /// Points the existing entry to another inode.
+    pub fn dir_set(&mut self, dir: &Inode, name: &[u8], ino: u32, ft: u8) -> VfsResult {
+        let mut pos = self.find_entry(dir, name)?.ok_or(VfsError::NotFound)?;
+        set_le32(&mut pos.block, pos.off, ino);
+        if self.has_filetype {
+            pos.block[pos.off + 7] = ft;
+        }
+        self.write_dir_block(dir, pos.pblk, &mut pos.block)
+    }
+
+    /// Whether the directory has no entries other than `.` and `..`.
+    pub fn dir_is_empty(&mut self, dir: &Inode) -> VfsResult<bool> {
+        let found = self.walk_dir(dir, |vol, block, off| {
+            let name_len = vol.name_len(block, off);
+            let name = &block[off + 8..off + 8 + name_len];
+            name != b"." && name != b".."
+        })?;
+        Ok(found.is_none())
+    }
+}
@@ -0,0 +1,403 @@
+//! Operations on inodes: file data, and changes of the directory tree.
+
+use axfs_vfs::{VfsError, VfsNodeType, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+/// The maximum links count of directories, beyond which it is set to 1.
+const DIR_LINK_MAX: u16 = 65000;
+/// The maximum links count of other inodes.
+const LINK_MAX: u16 = 65000;
+/// The maximum number of parents to walk up, in case of loops.
+const MAX_DEPTH: usize = 4096;
+
+impl Volume {
+    /// Whether the inode is a symlink with the target in `i_block`.
+    pub fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        inode.file_type() == S_IFLNK
+            && inode.flags() & INODE_EXTENTS_FL == 0
+            && inode.size() < inode.i_block().len() as u64
+    }
+
+    /// Returns the directory entry file type of the inode mode.
+    fn dirent_type(&self, mode: u16) -> u8 {
+        if !self.has_filetype {
+            return FT_UNKNOWN;
+        }
+        match mode & S_IFMT {
+            S_IFREG => FT_REG_FILE,
+            S_IFDIR => FT_DIR,
+            S_IFLNK => FT_SYMLINK,
+            _ => FT_UNKNOWN,
+        }
+    }
+
+    fn touch(&self, inode: &mut Inode) {
+        let (secs, nanos) = self.now();
+        inode.set_times(secs, nanos, false);
+    }
+
+    fn block_of(&self, pos: u64) -> VfsResult<(u32, usize)> {
+        let lblk = pos / self.block_size as u64;
+        let lblk = u32::try_from(lblk).map_err(|_| VfsError::InvalidInput)?;
+        Ok((lblk, (pos % self.block_size as u64) as usize))
+    }
+
+    pub fn read_data(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        if self.is_fast_symlink(inode) {
+            let start = offset as usize;
+            buf[..len].copy_from_slice(&inode.i_block()[start..start + len]);
+            return Ok(len);
+        }
+        let mut done = 0;
+        while done < len {
+            let (lblk, off) = self.block_of(offset + done as u64)?;
+            let n = (self.block_size - off).min(len - done);
+            match self.bmap(inode, lblk)? {
+                Some(pblk) => {
+                    let pos = pblk * self.block_size as u64 + off as u64;
+                    self.read_bytes(pos, &mut buf[done..done + n])?
+                }
+                None => buf[done..done + n].fill(0),
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Writes the data at `offset`, and writes back the inode.
+    pub fn write_data(&mut self, inode: &mut Inode, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.check_writable()?;
+        let mut done = 0;
+        let res = (|| -> VfsResult {
+            while done < buf.len() {
+                let (lblk, off) = self.block_of(offset + done as u64)?;
+                let n = (self.block_size - off).min(buf.len() - done);
+                let (pblk, new) = self.bmap_alloc(inode, lblk)?;
+                let data = &buf[done..done + n];
+                if new && n < self.block_size {
+                    let mut block = alloc::vec![0; self.block_size];
+                    block[off..off + n].copy_from_slice(data);
+                    self.write_block(pblk, &block)?;
+                } else {
+                    self.write_bytes(pblk * self.block_size as u64 + off as u64, data)?;
+                }
+                done += n;
+            }
+            Ok(())
+        })();
+        if done > 0 {
+            let end = offset + done as u64;
+            if end > inode.size() {
+                inode.set_size(end);
+            }
+            self.touch(inode);
+        }
+        self.write_inode(inode)?;
+        match res {
+            Err(err) if done == 0 => Err(err),
+            _ => Ok(done),
+        }
+    }
+
+    /// Sets the size of the file, and writes back the inode.
+    pub fn truncate(&mut self, inode: &mut Inode, size: u64) -> VfsResult {
+        self.check_writable()?;
+        let (lblk, off) = self.block_of(size)?;
+        if size < inode.size() {
+            let from = if off > 0 { lblk + 1 } else { lblk };
+            self.truncate_blocks(inode, from)?;
+            // zero the rest of the last block, which can be exposed by extending
+            if off > 0 {
+                if let Some(pblk) = self.bmap(inode, lblk)? {
+                    let zeros = alloc::vec![0; self.block_size - off];
+                    self.write_bytes(pblk * self.block_size as u64 + off as u64, &zeros)?;
+                }
+            }
+        }
+        inode.set_size(size);
+        self.touch(inode);
+        self.write_inode(inode)
+    }
+
+    /// Frees the inode and its blocks, which has no links any more.
+    fn release(&mut self, inode: &mut Inode) -> VfsResult {
+        if !self.is_fast_symlink(inode) {
+            self.truncate_blocks(inode, 0)?;
+        }
+        let (secs, _) = self.now();
+        inode.set_links_count(0);
+        inode.set_size(0);
+        inode.set_dtime(secs as u32);
+        self.write_inode(inode)?;
+        self.free_inode(inode.ino, inode.is_dir())
+    }
+
+    fn inc_dir_links(&self, dir: &mut Inode) {
+        match dir.links_count() {
+            1 => {}
+            n if n + 1 >= DIR_LINK_MAX => dir.set_links_count(1),
+            n => dir.set_links_count(n + 1),
+        }
+    }
+
+    fn dec_dir_links(&self, dir: &mut Inode) {
+        let n = dir.links_count();
+        if n > 2 {
+            dir.set_links_count(n - 1);
+        }
+    }
+
+    /// Creates a file or directory in the directory, if it does not exist.
+    pub fn create(&mut self, dir: &mut Inode, name: &str, ty: VfsNodeType) -> VfsResult {
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(()); // already exists
+        }
+        if self.dir_lookup(dir, name.as_bytes())?.is_some() {
+            return Ok(());
+        }
+        self.check_writable()?;
+        let mode = match ty {
+            VfsNodeType::File => S_IFREG | 0o644,
+            VfsNodeType::Dir => S_IFDIR | 0o755,
+            _ => return Err(VfsError::Unsupported),
+        };
+        let is_dir = ty == VfsNodeType::Dir;
+        let ino = self.alloc_inode(self.inode_group(dir.ino), is_dir)?;
+        let mut inode = self.new_inode(ino, mode)?;
+        let res = (|| -> VfsResult {
+            if is_dir {
+                inode.set_links_count(2);
+                self.dir_init(&mut inode, dir.ino)?;
+            } else {
+                inode.set_links_count(1);
+            }
+            self.write_inode(&mut inode)?;
+            self.dir_add(dir, name.as_bytes(), ino, self.dirent_type(mode))
+        })();
+        if let Err(err) = res {
+            self.release(&mut inode)?;
+            return Err(err);
+        }
+        if is_dir {
+            self.inc_dir_links(dir);
+        }
+        self.touch(dir);
+        self.write_inode(dir)
+    }
+
+    /// Creates a symlink to `target` in the directory. Short targets are kept
+    /// in the inode as fast symlinks.
+    pub fn symlink(&mut self, dir: &mut Inode, name: &str, target: &str) -> VfsResult {
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::AlreadyExists);
+        }
+        if target.is_empty() {
+            return Err(VfsError::NotFound);
+        }
+        if target.len() >= self.block_size {
+            return Err(VfsError::NameTooLong);
+        }
+        if self.dir_lookup(dir, name.as_bytes())?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.check_writable()?;
+        let mode = S_IFLNK | 0o777;
+        let ino = self.alloc_inode(self.inode_group(dir.ino), false)?;
+        let mut inode = self.new_inode(ino, mode)?;
+        inode.set_links_count(1);
+        let res = (|| -> VfsResult {
+            if target.len() < inode.i_block().len() {
+                inode.i_block_mut()[..target.len()].copy_from_slice(target.as_bytes());
+                inode.set_size(target.len() as u64);
+                self.write_inode(&mut inode)?;
+            } else {
+                self.write_data(&mut inode, 0, target.as_bytes())?;
+            }
+            self.dir_add(dir, name.as_bytes(), ino, self.dirent_type(mode))
+        })();
+        if let Err(err) = res {
+            self.release(&mut inode)?;
+            return Err(err);
+        }
+        self.touch(dir);
+        self.write_inode(dir)
+    }
+
+    /// Adds a hard link `name` in the directory to the inode, which is not a
+    /// directory.
+    pub fn link(&mut self, dir: &mut Inode, name: &str, inode: &mut Inode) -> VfsResult {
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::AlreadyExists);
+        }
+        if inode.is_dir() {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        if inode.links_count() >= LINK_MAX {
+            return Err(VfsError::OutOfRange);
+        }
+        if self.dir_lookup(dir, name.as_bytes())?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.check_writable()?;
+        let ft = self.dirent_type(inode.mode());
+        self.dir_add(dir, name.as_bytes(), inode.ino, ft)?;
+        let (secs, nanos) = self.now();
+        inode.set_links_count(inode.links_count() + 1);
+        inode.set_ctime(secs, nanos);
+        self.write_inode(inode)?;
+        self.touch(dir);
+        self.write_inode(dir)
+    }
+
+    /// Changes the attributes of the inode by `f`, updates the change time,
+    /// and writes it back.
+    pub fn set_attr(&mut self, inode: &mut Inode, f: impl FnOnce(&mut Inode)) -> VfsResult {
+        self.check_writable()?;
+        f(inode);
+        let (secs, nanos) = self.now();
+        inode.set_ctime(secs, nanos);
+        self.write_inode(inode)
+    }
+
+    /// Removes a link of the inode, releasing it if it is the last.
+    fn unlink(&mut self, inode: &mut Inode) -> VfsResult {
+        if inode.is_dir() || inode.links_count() <= 1 {
+            self.release(inode)
+        } else {
+            let (secs, nanos) = self.now();
+            inode.set_links_count(inode.links_count() - 1);
+            inode.set_ctime(secs, nanos);
+            self.write_inode(inode)
+        }
+    }
+
+    /// Removes the entry from the directory.
+    pub fn remove(&mut self, dir: &mut Inode, name: &str) -> VfsResult {
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        self.check_writable()?;
+        let entry = self
+            .dir_lookup(dir, name.as_bytes())?
+            .ok_or(VfsError::NotFound)?;
+        let mut inode = self.read_inode(entry.ino)?;
+        if inode.is_dir() && !self.dir_is_empty(&inode)? {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+        self.dir_remove(dir, name.as_bytes())?;
+        self.unlink(&mut inode)?;
+        if inode.is_dir() {
+            self.dec_dir_links(dir);
+        }
+        self.touch(dir);
+        self.write_inode(dir)
+    }
+
+    /// Whether the directory `ino` is `ancestor` or in it.
+    fn is_in(&mut self, mut ino: u32, ancestor: u32) -> VfsResult<bool> {
+        for _ in 0..MAX_DEPTH {
+            if ino == ancestor {
+                return Ok(true);
+            }
+            if ino == ROOT_INO {
+                return Ok(false);
+            }
+            let dir = self.read_inode(ino)?;
+            ino = self
+                .dir_lookup(&dir, b"..")?
+                .ok_or(VfsError::InvalidData)?
+                .ino;
+        }
+        Err(VfsError::InvalidData)
+    }
+
+    /// Moves the entry `src_name` in `src_dir` to `dst_name` in `dst_dir`,
+    /// replacing the existing one.
+    pub fn rename(
+        &mut self,
+        src_dir: u32,
+        src_name: &str,
+        dst_dir: u32,
+        dst_name: &str,
+    ) -> VfsResult {
+        for name in [src_name, dst_name] {
+            if name.is_empty() || name == "." || name == ".." {
+                return Err(VfsError::InvalidInput);
+            }
+        }
+        self.check_writable()?;
+        let src = self.read_inode(src_dir)?;
+        let mut dst = self.read_inode(dst_dir)?;
+        if !src.is_dir() || !dst.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        let entry = self
+            .dir_lookup(&src, src_name.as_bytes())?
+            .ok_or(VfsError::NotFound)?;
+        let mut inode = self.read_inode(entry.ino)?;
+        let moves_dir = inode.is_dir() && src_dir != dst_dir;
+        if inode.is_dir() && self.is_in(dst_dir, inode.ino)? {
+            return Err(VfsError::InvalidInput); // into its own subtree
+        }
+
+        let ft = self.dirent_type(inode.mode());
+        match self.dir_lookup(&dst, dst_name.as_bytes())? {
+            Some(old) if old.ino == inode.ino => return Ok(()),
+            Some(old) => {
+                let mut old = self.read_inode(old.ino)?;
+                match (inode.is_dir(), old.is_dir()) {
+                    (false, true) => return Err(VfsError::IsADirectory),
+                    (true, false) => return Err(VfsError::NotADirectory),
+                    (true, true) if !self.dir_is_empty(&old)? => {
+                        return Err(VfsError::DirectoryNotEmpty)
+                    }
+                    _ => {}
+                }
+                self.dir_set(&dst, dst_name.as_bytes(), inode.ino, ft)?;
+                self.unlink(&mut old)?;
+                if old.is_dir() {
+                    self.dec_dir_links(&mut dst);
+                }
+            }
+            None => self.dir_add(&mut dst, dst_name.as_bytes(), inode.ino, ft)?,
+        }
+        if moves_dir {
+            self.dir_set(&inode, b"..", dst_dir, ft)?;
+            self.inc_dir_links(&mut dst);
+        }
+        self.touch(&mut dst);
+        self.write_inode(&mut dst)?;
+
+        // reread it, in case of the same directory
+        let mut src = self.read_inode(src_dir)?;
+        self.dir_remove(&src, src_name.as_bytes())?;
+        if moves_dir {
+            self.dec_dir_links(&mut src);
+        }
+        self.touch(&mut src);
+        self.write_inode(&mut src)?;
+
+        let (secs, nanos) = self.now();
+        inode.set_ctime(secs, nanos);
+        self.write_inode(&mut inode)
+    }
+}
@@ -0,0 +1,624 @@
+//! On-disk structures of ext2/ext3/ext4.
+//!
+//! The structures are kept as raw little-endian bytes, so the fields unknown
+//! to us are preserved when they are written back.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const EXT4_MAGIC: u16 = 0xef53;
+pub const ROOT_INO: u32 = 2;
+
+pub const EXTENT_MAGIC: u16 = 0xf30a;
+/// The maximum length of an initialized extent.
+pub const EXTENT_MAX_LEN: u32 = 32768;
+
+// Superblock features.
+pub const COMPAT_SPARSE_SUPER2: u32 = 0x200;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x2;
+pub const INCOMPAT_RECOVER: u32 = 0x4;
+pub const INCOMPAT_EXTENTS: u32 = 0x40;
+pub const INCOMPAT_64BIT: u32 = 0x80;
+pub const INCOMPAT_FLEX_BG: u32 = 0x200;
+pub const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+/// The incompatible features we can read.
+pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_CSUM_SEED;
+
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+pub const RO_COMPAT_BTREE_DIR: u32 = 0x4;
+pub const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+pub const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+pub const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+pub const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+pub const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+/// The read-only compatible features we can write.
+pub const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER
+    | RO_COMPAT_LARGE_FILE
+    | RO_COMPAT_BTREE_DIR
+    | RO_COMPAT_HUGE_FILE
+    | RO_COMPAT_GDT_CSUM
+    | RO_COMPAT_DIR_NLINK
+    | RO_COMPAT_EXTRA_ISIZE
+    | RO_COMPAT_METADATA_CSUM;
+
+// Block group flags.
+pub const BG_INODE_UNINIT: u16 = 0x1;
+pub const BG_BLOCK_UNINIT: u16 = 0x2;
+
+// Inode flags.
+pub const INODE_INDEX_FL: u32 = 0x1000;
+pub const INODE_HUGE_FILE_FL: u32 = 0x40000;
+pub const INODE_EXTENTS_FL: u32 = 0x80000;
+
+// Inode modes.
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFLNK: u16 = 0o120000;
+
+// Directory entry file types.
+pub const FT_UNKNOWN: u8 = 0;
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_SYMLINK: u8 = 7;
+/// The file type of the checksum tail of directory blocks.
+pub const FT_DIR_CSUM: u8 = 0xde;
+pub const DIR_TAIL_SIZE: usize = 12;
+
+pub fn le16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([buf[off], buf[off + 1]])
+}
+
+pub fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+pub fn set_le16(buf: &mut [u8], off: usize, val: u16) {
+    buf[off..off + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+pub fn set_le32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// The superblock.
+pub struct SuperBlock(pub Vec<u8>);
+
+impl SuperBlock {
+    pub fn inodes_count(&self) -> u32 {
+        le32(&self.0, 0x0)
+    }
+    pub fn blocks_count(&self) -> u64 {
+        self.hi_lo(0x150, 0x4)
+    }
+    pub fn free_blocks_count(&self) -> u64 {
+        self.hi_lo(0x158, 0xc)
+    }
+    pub fn set_free_blocks_count(&mut self, val: u64) {
+        self.set_hi_lo(0x158, 0xc, val)
+    }
+    pub fn free_inodes_count(&self) -> u32 {
+        le32(&self.0, 0x10)
+    }
+    pub fn set_free_inodes_count(&mut self, val: u32) {
+        set_le32(&mut self.0, 0x10, val)
+    }
+    pub fn first_data_block(&self) -> u32 {
+        le32(&self.0, 0x14)
+    }
+    pub fn log_block_size(&self) -> u32 {
+        le32(&self.0, 0x18)
+    }
+    pub fn blocks_per_group(&self) -> u32 {
+        le32(&self.0, 0x20)
+    }
+    pub fn inodes_per_group(&self) -> u32 {
+        le32(&self.0, 0x28)
+    }
+    pub fn write_time(&self) -> u32 {
+        le32(&self.0, 0x30)
+    }
+    pub fn magic(&self) -> u16 {
+        le16(&self.0, 0x38)
+    }
+    pub fn rev_level(&self) -> u32 {
+        le32(&self.0, 0x4c)
+    }
+    pub fn first_ino(&self) -> u32 {
+        if self.rev_level() == 0 {
+            11
+        } else {
+            le32(&self.0, 0x54)
+        }
+    }
+    pub fn inode_size(&self) -> u16 {
+        if self.rev_level() == 0 {
+            128
+        } else {
+            le16(&self.0, 0x58)
+        }
+    }
+    pub fn feature_compat(&self) -> u32 {
+        le32(&self.0, 0x5c)
+    }
+    pub fn feature_incompat(&self) -> u32 {
+        le32(&self.0, 0x60)
+    }
+    pub fn feature_ro_compat(&self) -> u32 {
+        le32(&self.0, 0x64)
+    }
+    pub fn uuid(&self) -> &[u8] {
+        &self.0[0x68..0x78]
+    }
+    pub fn reserved_gdt_blocks(&self) -> u16 {
+        le16(&self.0, 0xce)
+    }
+    pub fn desc_size(&self) -> u16 {
+        le16(&self.0, 0xfe)
+    }
+    pub fn want_extra_isize(&self) -> u16 {
+        le16(&self.0, 0x15e)
+    }
+    pub fn backup_bgs(&self) -> [u32; 2] {
+        [le32(&self.0, 0x24c), le32(&self.0, 0x250)]
+    }
+    pub fn checksum_seed(&self) -> u32 {
+        le32(&self.0, 0x270)
+    }
+
+    /// Updates the checksum, if metadata checksums are enabled.
+    pub fn update_checksum(&mut self) {
+        if self.feature_ro_compat() & RO_COMPAT_METADATA_CSUM != 0 {
+            let csum = crc32c(!0, &self.0[..0x3fc]);
+            set_le32(&mut self.0, 0x3fc, csum);
+        }
+    }
+
+    fn hi_lo(&self, hi: usize, lo: usize) -> u64 {
+        let hi = if self.feature_incompat() & INCOMPAT_64BIT != 0 {
+            le32(&self.0, hi)
+        } else {
+            0
+        };
+        ((hi as u64) << 32) | le32(&self.0, lo) as u64
+    }
+
+    fn set_hi_lo(&mut self, hi: usize, lo: usize, val: u64) {
+        set_le32(&mut self.0, lo, val as u32);
+        if self.feature_incompat() & INCOMPAT_64BIT != 0 {
+            set_le32(&mut self.0, hi, (val >> 32) as u32);
+        }
+    }
+}
+
+/// A block group descriptor, of 32 or 64 bytes.
+pub struct GroupDesc<'a>(pub &'a mut [u8]);
+
+macro_rules! desc_field {
+    ($get: ident, $set: ident, $ty: ty, $lo: expr, $hi: expr, $shift: expr, $rd: ident, $wr: ident) => {
+        pub fn $get(&self) -> $ty {
+            let lo = $rd(self.0, $lo) as $ty;
+            if self.0.len() >= 64 {
+                lo | (($rd(self.0, $hi) as $ty) << $shift)
+            } else {
+                lo
+            }
+        }
+        pub fn $set(&mut self, val: $ty) {
+            $wr(self.0, $lo, val as _);
+            if self.0.len() >= 64 {
+                $wr(self.0, $hi, (val >> $shift) as _);
+            }
+        }
+    };
+}
+
+impl GroupDesc<'_> {
+    desc_field!(
+        block_bitmap,
+        _set_block_bitmap,
+        u64,
+        0x0,
+        0x20,
+        32,
+        le32,
+        set_le32
+    );
+    desc_field!(
+        inode_bitmap,
+        _set_inode_bitmap,
+        u64,
+        0x4,
+        0x24,
+        32,
+        le32,
+        set_le32
+    );
+    desc_field!(
+        inode_table,
+        _set_inode_table,
+        u64,
+        0x8,
+        0x28,
+        32,
+        le32,
+        set_le32
+    );
+    desc_field!(
+        free_blocks,
+        set_free_blocks,
+        u32,
+        0xc,
+        0x2c,
+        16,
+        le16,
+        set_le16
+    );
+    desc_field!(
+        free_inodes,
+        set_free_inodes,
+        u32,
+        0xe,
+        0x2e,
+        16,
+        le16,
+        set_le16
+    );
+    desc_field!(
+        used_dirs,
+        set_used_dirs,
+        u32,
+        0x10,
+        0x30,
+        16,
+        le16,
+        set_le16
+    );
+    desc_field!(
+        itable_unused,
+        set_itable_unused,
+        u32,
+        0x1c,
+        0x32,
+        16,
+        le16,
+        set_le16
+    );
+    desc_field!(
+        _block_bitmap_csum,
+        set_block_bitmap_csum,
+        u32,
+        0x18,
+        0x38,
+        16,
+        le16,
+        set_le16
+    );
+    desc_field!(
+        _inode_bitmap_csum,
+        set_inode_bitmap_csum,
+        u32,
+        0x1a,
+        0x3a,
+        16,
+        le16,
+        set_le16
+    );
+
+    pub fn flags(&self) -> u16 {
+        le16(self.0, 0x12)
+    }
+    pub fn set_flags(&mut self, flags: u16) {
+        set_le16(self.0, 0x12, flags)
+    }
+    pub fn set_checksum(&mut self, csum: u16) {
+        set_le16(self.0, 0x1e, csum)
+    }
+}
+
+/// An inode, with its number.
+#[derive(Clone)]
+pub struct Inode {
+    pub ino: u32,
+    pub raw: Vec<u8>,
+}
+
+impl Inode {
+    pub fn mode(&self) -> u16 {
+        le16(&self.raw, 0x0)
+    }
+    pub fn set_mode(&mut self, mode: u16) {
+        set_le16(&mut self.raw, 0x0, mode)
+    }
+    pub fn file_type(&self) -> u16 {
+        self.mode() & S_IFMT
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+    pub fn uid(&self) -> u32 {
+        le16(&self.raw, 0x2) as u32 | (le16(&self.raw, 0x78) as u32) << 16
+    }
+    pub fn set_uid(&mut self, uid: u32) {
+        set_le16(&mut self.raw, 0x2, uid as u16);
+        set_le16(&mut self.raw, 0x78, (uid >> 16) as u16);
+    }
+    pub fn gid(&self) -> u32 {
+        le16(&self.raw, 0x18) as u32 | (le16(&self.raw, 0x7a) as u32) << 16
+    }
+    pub fn set_gid(&mut self, gid: u32) {
+        set_le16(&mut self.raw, 0x18, gid as u16);
+        set_le16(&mut self.raw, 0x7a, (gid >> 16) as u16);
+    }
+    pub fn size(&self) -> u64 {
+        le32(&self.raw, 0x4) as u64 | (le32(&self.raw, 0x6c) as u64) << 32
+    }
+    pub fn set_size(&mut self, size: u64) {
+        set_le32(&mut self.raw, 0x4, size as u32);
+        set_le32(&mut self.raw, 0x6c, (size >> 32) as u32);
+    }
+    pub fn links_count(&self) -> u16 {
+        le16(&self.raw, 0x1a)
+    }
+    pub fn set_links_count(&mut self, links: u16) {
+        set_le16(&mut self.raw, 0x1a, links)
+    }
+    pub fn flags(&self) -> u32 {
+        le32(&self.raw, 0x20)
+    }
+    pub fn set_flags(&mut self, flags: u32) {
+        set_le32(&mut self.raw, 0x20, flags)
+    }
+    pub fn generation(&self) -> u32 {
+        le32(&self.raw, 0x64)
+    }
+    pub fn set_generation(&mut self, generation: u32) {
+        set_le32(&mut self.raw, 0x64, generation)
+    }
+    pub fn set_dtime(&mut self, time: u32) {
+        set_le32(&mut self.raw, 0x14, time)
+    }
+    pub fn extra_isize(&self) -> usize {
+        if self.raw.len() > 128 {
+            le16(&self.raw, 0x80) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The number of 512-byte sectors (or blocks for huge files) allocated.
+    pub fn blocks(&self) -> u64 {
+        le32(&self.raw, 0x1c) as u64 | (le16(&self.raw, 0x74) as u64) << 32
+    }
+    pub fn set_blocks(&mut self, blocks: u64) {
+        set_le32(&mut self.raw, 0x1c, blocks as u32);
+        set_le16(&mut self.raw, 0x74, (blocks >> 32) as u16);
+    }
+
+    /// The block map or extent tree root, or the target of fast symlinks.
+    pub fn i_block(&self) -> &[u8] {
+        &self.raw[0x28..0x64]
+    }
+    pub fn i_block_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[0x28..0x64]
+    }
+
+    /// Sets the access, change and modification times (and the creation
+    /// time if `create`) in seconds since the epoch.
+    pub fn set_times(&mut self, secs: u64, nanos: u32, create: bool) {
+        self.set_time(0x8, 0x8c, secs, nanos);
+        self.set_ctime(secs, nanos);
+        self.set_time(0x10, 0x88, secs, nanos);
+        if create && self.fits(0x94) {
+            self.set_time(0x90, 0x94, secs, nanos);
+        }
+    }
+
+    pub fn set_ctime(&mut self, secs: u64, nanos: u32) {
+        self.set_time(0xc, 0x84, secs, nanos);
+    }
+
+    pub fn atime(&self) -> Duration {
+        self.time(0x8, 0x8c)
+    }
+    pub fn set_atime(&mut self, time: Duration) {
+        self.set_time(0x8, 0x8c, time.as_secs(), time.subsec_nanos());
+    }
+    pub fn ctime(&self) -> Duration {
+        self.time(0xc, 0x84)
+    }
+    pub fn mtime(&self) -> Duration {
+        self.time(0x10, 0x88)
+    }
+    pub fn set_mtime(&mut self, time: Duration) {
+        self.set_time(0x10, 0x88, time.as_secs(), time.subsec_nanos());
+    }
+
+    /// Reads the time since the epoch, with the nanoseconds and the epoch bits
+    /// in the extra field if any. Times before the epoch are clamped to it.
+    fn time(&self, off: usize, extra_off: usize) -> Duration {
+        let mut secs = le32(&self.raw, off) as i32 as i64;
+        let mut nanos = 0;
+        if self.fits(extra_off + 4) {
+            let extra = le32(&self.raw, extra_off);
+            secs += ((extra & 0x3) as i64) << 32;
+            nanos = (extra >> 2).min(999_999_999);
+        }
+        match u64::try_from(secs) {
+            Ok(secs) => Duration::new(secs, nanos),
+            Err(_) => Duration::ZERO,
+        }
+    }
+
+    fn set_time(&mut self, off: usize, extra_off: usize, secs: u64, nanos: u32) {
+        set_le32(&mut self.raw, off, secs as u32);
+        if self.fits(extra_off + 4) {
+            // The extra field holds the nanoseconds and the epoch bits beyond
+            // the signed 32-bit seconds.
+            let epoch = ((secs as i64 - secs as i32 as i64) >> 32) as u32 & 0x3;
+            set_le32(&mut self.raw, extra_off, (nanos << 2) | epoch);
+        }
+    }
+
+    /// Whether the field ending at `end` is in the inode.
+    pub fn fits(&self, end: usize) -> bool {
+        end <= 128 || end <= 128 + self.extra_isize()
+    }
+
+    /// Updates the checksum with the checksum seed of the inode.
+    pub fn update_checksum(&mut self, seed: u32) {
+        set_le16(&mut self.raw, 0x7c, 0);
+        let has_hi = self.fits(0x84);
+        if has_hi {
+            set_le16(&mut self.raw, 0x82, 0);
+        }
+        let csum = crc32c(seed, &self.raw);
+        set_le16(&mut self.raw, 0x7c, csum as u16);
+        if has_hi {
+            set_le16(&mut self.raw, 0x82, (csum >> 16) as u16);
+        }
+    }
+}
+
+/// A leaf entry of the extent tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// The first logical block.
+    pub block: u32,
+    /// The number of blocks.
+    pub len: u32,
+    /// The first physical block.
+    pub start: u64,
+    /// Whether it is allocated but not initialized, so it reads as zeros.
+    pub uninit: bool,
+}
+
+impl Extent {
+    pub fn parse(buf: &[u8]) -> Self {
+        let len = le16(buf, 4) as u32;
+        let (len, uninit) = if len > EXTENT_MAX_LEN {
+            (len - EXTENT_MAX_LEN, true)
+        } else {
+            (len, false)
+        };
+        Self {
+            block: le32(buf, 0),
+            len,
+            start: (le16(buf, 6) as u64) << 32 | le32(buf, 8) as u64,
+            uninit,
+        }
+    }
+
+    pub fn write(&self, buf: &mut [u8]) {
+        let len = if self.uninit {
+            self.len + EXTENT_MAX_LEN
+        } else {
+            self.len
+        };
+        set_le32(buf, 0, self.block);
+        set_le16(buf, 4, len as u16);
+        set_le16(buf, 6, (self.start >> 32) as u16);
+        set_le32(buf, 8, self.start as u32);
+    }
+
+    /// The logical block after the last one.
+    pub fn end(&self) -> u64 {
+        self.block as u64 + self.len as u64
+    }
+
+    pub fn contains(&self, lblk: u32) -> bool {
+        lblk >= self.block && lblk - self.block < self.len
+    }
+}
+
+/// The header of an extent tree node.
+pub struct ExtentHeader {
+    pub entries: usize,
+    pub max: usize,
+    pub depth: u16,
+}
+
+impl ExtentHeader {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if le16(buf, 0) != EXTENT_MAGIC {
+            return None;
+        }
+        let header = Self {
+            entries: le16(buf, 2) as usize,
+            max: le16(buf, 4) as usize,
+            depth: le16(buf, 6),
+        };
+        (header.entries <= header.max && 12 + header.max * 12 <= buf.len()).then_some(header)
+    }
+
+    pub fn write(&self, buf: &mut [u8]) {
+        set_le16(buf, 0, EXTENT_MAGIC);
+        set_le16(buf, 2, self.entries as u16);
+        set_le16(buf, 4, self.max as u16);
+        set_le16(buf, 6, self.depth);
+        set_le32(buf, 8, 0);
+    }
+}
+
+/// An index entry of the extent tree: `(first logical block, child block)`.
+pub fn parse_extent_index(buf: &[u8]) -> (u32, u64) {
+    (
+        le32(buf, 0),
+        (le16(buf, 8) as u64) << 32 | le32(buf, 4) as u64,
+    )
+}
+
+pub fn write_extent_index(buf: &mut [u8], block: u32, child: u64) {
+    set_le32(buf, 0, block);
+    set_le32(buf, 4, child as u32);
+    set_le16(buf, 8, (child >> 32) as u16);
+    set_le16(buf, 10, 0);
+}
+
+const fn crc_table(poly: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = crc_table(0x82f6_3b78);
+static CRC16_TABLE: [u32; 256] = crc_table(0xa001);
+
+/// CRC32C (Castagnoli) without the final inversion, as ext4 uses.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// CRC16 (ANSI), for the group descriptors without metadata checksums.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc = CRC16_TABLE[((crc ^ b as u16) & 0xff) as usize] as u16 ^ (crc >> 8);
+    }
+    crc
+}
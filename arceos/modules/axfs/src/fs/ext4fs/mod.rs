@@ -0,0 +1,441 @@
+//! The ext2/ext3/ext4 filesystem.
+//!
+//! Files are mapped by extent trees or (on ext2/ext3) indirect blocks, and
+//! metadata checksums are maintained. The journal is not replayed nor
+//! written, so filesystems that need recovery are mounted read-only.
+
+mod blockmap;
+mod dir;
+mod inode;
+mod layout;
+mod volume;
+
+use alloc::{format, string::String, sync::Arc};
+use core::time::Duration;
+
+use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
+use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
+use axsync::Mutex;
+
+use self::layout::*;
+use self::volume::Volume;
+use crate::dev::Disk;
+
+/// Returns the current time in seconds and nanoseconds since the epoch.
+fn now() -> (u64, u32) {
+    let now = axhal::time::wall_time();
+    (now.as_secs(), now.subsec_nanos())
+}
+
+struct Ext4Inner {
+    vol: Mutex<Volume>,
+    /// The parent of the mount point, as `..` of the root directory.
+    mount_parent: Mutex<Option<VfsNodeRef>>,
+}
+
+/// The result of walking a path.
+enum Walk<'a> {
+    /// The path is in this filesystem.
+    Inode(Inode),
+    /// The path leaves this filesystem by `..` of the root directory, the
+    /// rest of it is relative to the parent of the mount point.
+    Parent(VfsNodeRef, &'a str),
+}
+
+pub struct Ext4FileSystem {
+    inner: Arc<Ext4Inner>,
+    root_generation: u32,
+}
+
+/// A directory of the ext4 filesystem.
+pub struct DirNode {
+    fs: Arc<Ext4Inner>,
+    ino: u32,
+    generation: u32,
+}
+
+/// A file or symlink of the ext4 filesystem.
+pub struct FileNode {
+    fs: Arc<Ext4Inner>,
+    ino: u32,
+    generation: u32,
+}
+
+impl Ext4FileSystem {
+    /// Returns whether the disk contains an ext2/ext3/ext4 filesystem.
+    pub fn probe(disk: &mut Disk) -> bool {
+        Volume::probe(disk)
+    }
+
+    pub fn new(disk: Disk) -> VfsResult<Self> {
+        let mut vol = Volume::open(disk)?;
+        let root = vol.read_inode(ROOT_INO)?;
+        if !root.is_dir() {
+            return Err(VfsError::InvalidData);
+        }
+        Ok(Self {
+            root_generation: root.generation(),
+            inner: Arc::new(Ext4Inner {
+                vol: Mutex::new(vol),
+                mount_parent: Mutex::new(None),
+            }),
+        })
+    }
+}
+
+impl Ext4Inner {
+    /// Reads the inode of a node, which may have been removed.
+    fn inode(&self, vol: &mut Volume, ino: u32, generation: u32) -> VfsResult<Inode> {
+        let inode = vol.read_inode(ino)?;
+        if inode.generation() != generation || inode.links_count() == 0 {
+            return Err(VfsError::NotFound);
+        }
+        Ok(inode)
+    }
+
+    fn node(self: &Arc<Self>, inode: &Inode) -> VfsNodeRef {
+        let (fs, ino, generation) = (self.clone(), inode.ino, inode.generation());
+        if inode.is_dir() {
+            Arc::new(DirNode {
+                fs,
+                ino,
+                generation,
+            })
+        } else {
+            Arc::new(FileNode {
+                fs,
+                ino,
+                generation,
+            })
+        }
+    }
+
+    fn lookup_parent(self: Arc<Self>, ino: u32, generation: u32) -> Option<VfsNodeRef> {
+        let dir = DirNode {
+            fs: self.clone(),
+            ino,
+            generation,
+        };
+        let mut vol = self.vol.lock();
+        match dir.walk(&mut vol, "..").ok()? {
+            Walk::Inode(inode) => Some(self.node(&inode)),
+            Walk::Parent(parent, _) => Some(parent),
+        }
+    }
+
+    fn attr(&self, ino: u32, generation: u32) -> VfsResult<VfsNodeAttr> {
+        let mut vol = self.vol.lock();
+        let inode = self.inode(&mut vol, ino, generation)?;
+        let ty = match inode.file_type() {
+            S_IFDIR => VfsNodeType::Dir,
+            S_IFLNK => VfsNodeType::SymLink,
+            0o010000 => VfsNodeType::Fifo,
+            0o020000 => VfsNodeType::CharDevice,
+            0o060000 => VfsNodeType::BlockDevice,
+            0o140000 => VfsNodeType::Socket,
+            _ => VfsNodeType::File,
+        };
+        let perm = VfsNodePerm::from_bits_truncate(inode.mode() & 0o777);
+        let blocks = inode.blocks() * vol.blocks_unit(&inode) / 512;
+        Ok(VfsNodeAttr::new(perm, ty, inode.size(), blocks)
+            .with_owner(inode.uid(), inode.gid())
+            .with_nlink(inode.links_count() as u32)
+            .with_ino(ino as u64)
+            .with_times(inode.atime(), inode.mtime(), inode.ctime()))
+    }
+
+    /// Changes the attributes of a node by `f`.
+    fn set_attr(&self, ino: u32, generation: u32, f: impl FnOnce(&mut Inode)) -> VfsResult {
+        let mut vol = self.vol.lock();
+        let mut inode = self.inode(&mut vol, ino, generation)?;
+        vol.set_attr(&mut inode, f)
+    }
+
+    fn set_perm(&self, ino: u32, generation: u32, perm: VfsNodePerm) -> VfsResult {
+        self.set_attr(ino, generation, |inode| {
+            inode.set_mode(inode.mode() & !0o777 | perm.bits())
+        })
+    }
+
+    fn set_owner(
+        &self,
+        ino: u32,
+        generation: u32,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> VfsResult {
+        self.set_attr(ino, generation, |inode| {
+            if let Some(uid) = uid {
+                inode.set_uid(uid);
+            }
+            if let Some(gid) = gid {
+                inode.set_gid(gid);
+            }
+        })
+    }
+
+    fn set_times(
+        &self,
+        ino: u32,
+        generation: u32,
+        atime: Option<Duration>,
+        mtime: Option<Duration>,
+    ) -> VfsResult {
+        self.set_attr(ino, generation, |inode| {
+            if let Some(atime) = atime {
+                inode.set_atime(atime);
+            }
+            if let Some(mtime) = mtime {
+                inode.set_mtime(mtime);
+            }
+        })
+    }
+}
+
+/// Splits the path into the parent and the last component.
+fn split_last(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn join(parent: &str, name: &str) -> String {
+    format!("{}/{}", parent, name)
+}
+
+impl DirNode {
+    /// Walks the path from this directory, without following symlinks.
+    fn walk<'a>(&self, vol: &mut Volume, path: &'a str) -> VfsResult<Walk<'a>> {
+        let mut inode = self.fs.inode(vol, self.ino, self.generation)?;
+        let mut rest = path;
+        loop {
+            rest = rest.trim_start_matches('/');
+            if rest.is_empty() {
+                return Ok(Walk::Inode(inode));
+            }
+            let (name, next) = rest.split_once('/').unwrap_or((rest, ""));
+            if !inode.is_dir() {
+                return Err(VfsError::NotADirectory);
+            }
+            match name {
+                "." => {}
+                ".." if inode.ino == ROOT_INO => {
+                    let parent = self.fs.mount_parent.lock().clone();
+                    return parent
+                        .map(|parent| Walk::Parent(parent, next))
+                        .ok_or(VfsError::NotFound);
+                }
+                _ => {
+                    let entry = vol
+                        .dir_lookup(&inode, name.as_bytes())?
+                        .ok_or(VfsError::NotFound)?;
+                    inode = vol.read_inode(entry.ino)?;
+                    if inode.links_count() == 0 {
+                        return Err(VfsError::InvalidData);
+                    }
+                }
+            }
+            rest = next;
+        }
+    }
+}
+
+impl VfsNodeOps for DirNode {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.fs.attr(self.ino, self.generation)
+    }
+
+    fn set_perm(&self, perm: VfsNodePerm) -> VfsResult {
+        self.fs.set_perm(self.ino, self.generation, perm)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        self.fs.set_owner(self.ino, self.generation, uid, gid)
+    }
+
+    fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        self.fs.set_times(self.ino, self.generation, atime, mtime)
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.fs.clone().lookup_parent(self.ino, self.generation)
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        debug!("lookup at ext4: {}", path);
+        let mut vol = self.fs.vol.lock();
+        match self.walk(&mut vol, path)? {
+            Walk::Inode(inode) => Ok(self.fs.node(&inode)),
+            Walk::Parent(parent, rest) => {
+                drop(vol);
+                parent.lookup(rest)
+            }
+        }
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        debug!("create {:?} at ext4: {}", ty, path);
+        let (parent, name) = split_last(path);
+        let mut vol = self.fs.vol.lock();
+        match self.walk(&mut vol, parent)? {
+            Walk::Inode(mut dir) => vol.create(&mut dir, name, ty),
+            Walk::Parent(node, rest) => {
+                drop(vol);
+                node.create(&join(rest, name), ty)
+            }
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        debug!("remove at ext4: {}", path);
+        let (parent, name) = split_last(path);
+        let mut vol = self.fs.vol.lock();
+        match self.walk(&mut vol, parent)? {
+            Walk::Inode(mut dir) => vol.remove(&mut dir, name),
+            Walk::Parent(node, rest) => {
+                drop(vol);
+                node.remove(&join(rest, name))
+            }
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let mut vol = self.fs.vol.lock();
+        let inode = self.fs.inode(&mut vol, self.ino, self.generation)?;
+        let entries = vol.dir_entries(&inode)?;
+        let mut count = 0;
+        for (entry, out_entry) in entries.iter().skip(start_idx).zip(dirents.iter_mut()) {
+            let ty = match entry.file_type {
+                FT_REG_FILE => VfsNodeType::File,
+                FT_DIR => VfsNodeType::Dir,
+                FT_SYMLINK => VfsNodeType::SymLink,
+                3 => VfsNodeType::CharDevice,
+                4 => VfsNodeType::BlockDevice,
+                5 => VfsNodeType::Fifo,
+                6 => VfsNodeType::Socket,
+                _ if vol.read_inode(entry.ino)?.is_dir() => VfsNodeType::Dir,
+                _ => VfsNodeType::File,
+            };
+            *out_entry = VfsDirEntry::new(&String::from_utf8_lossy(&entry.name), ty);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        debug!("rename at ext4: {} -> {}", src_path, dst_path);
+        let (src_parent, src_name) = split_last(src_path);
+        let (dst_parent, dst_name) = split_last(dst_path);
+        let mut vol = self.fs.vol.lock();
+        let (Walk::Inode(src_dir), Walk::Inode(dst_dir)) = (
+            self.walk(&mut vol, src_parent)?,
+            self.walk(&mut vol, dst_parent)?,
+        ) else {
+            return Err(VfsError::Unsupported); // across filesystems
+        };
+        vol.rename(src_dir.ino, src_name, dst_dir.ino, dst_name)
+    }
+
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        debug!("symlink at ext4: {} -> {}", path, target);
+        let (parent, name) = split_last(path);
+        let mut vol = self.fs.vol.lock();
+        match self.walk(&mut vol, parent)? {
+            Walk::Inode(mut dir) => vol.symlink(&mut dir, name, target),
+            Walk::Parent(node, rest) => {
+                drop(vol);
+                node.symlink(&join(rest, name), target)
+            }
+        }
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        debug!("link at ext4: {}", path);
+        let Some(file) = node.as_any().downcast_ref::<FileNode>() else {
+            return Err(if node.as_any().is::<DirNode>() {
+                VfsError::OperationNotPermitted
+            } else {
+                VfsError::CrossesDevices
+            });
+        };
+        if !Arc::ptr_eq(&file.fs, &self.fs) {
+            return Err(VfsError::CrossesDevices);
+        }
+        let (parent, name) = split_last(path);
+        let mut vol = self.fs.vol.lock();
+        let mut inode = self.fs.inode(&mut vol, file.ino, file.generation)?;
+        match self.walk(&mut vol, parent)? {
+            Walk::Inode(mut dir) => vol.link(&mut dir, name, &mut inode),
+            Walk::Parent(..) => Err(VfsError::CrossesDevices),
+        }
+    }
+}
+
+impl VfsNodeOps for FileNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.fs.attr(self.ino, self.generation)
+    }
+
+    fn set_perm(&self, perm: VfsNodePerm) -> VfsResult {
+        self.fs.set_perm(self.ino, self.generation, perm)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult {
+        self.fs.set_owner(self.ino, self.generation, uid, gid)
+    }
+
+    fn set_times(&self, atime: Option<Duration>, mtime: Option<Duration>) -> VfsResult {
+        self.fs.set_times(self.ino, self.generation, atime, mtime)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut vol = self.fs.vol.lock();
+        let inode = self.fs.inode(&mut vol, self.ino, self.generation)?;
+        vol.read_data(&inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut vol = self.fs.vol.lock();
+        let mut inode = self.fs.inode(&mut vol, self.ino, self.generation)?;
+        if inode.file_type() == S_IFLNK {
+            return Err(VfsError::InvalidInput);
+        }
+        vol.write_data(&mut inode, offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        let mut vol = self.fs.vol.lock();
+        let mut inode = self.fs.inode(&mut vol, self.ino, self.generation)?;
+        if inode.file_type() == S_IFLNK {
+            return Err(VfsError::InvalidInput);
+        }
+        vol.truncate(&mut inode, size)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        Ok(()) // written through
+    }
+}
+
+impl VfsOps for Ext4FileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.inner.mount_parent.lock() = mount_point.parent();
+        Ok(())
+    }
+
+    fn umount(&self) -> VfsResult {
+        self.inner.mount_parent.lock().take();
+        Ok(())
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        Arc::new(DirNode {
+            fs: self.inner.clone(),
+            ino: ROOT_INO,
+            generation: self.root_generation,
+        })
+    }
+}
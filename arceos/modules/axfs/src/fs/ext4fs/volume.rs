@@ -0,0 +1,563 @@
+//! The mounted volume: block I/O, group descriptors, inodes and allocation.
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use crate::dev::Disk;
+
+/// The state of a mounted ext2/ext3/ext4 filesystem.
+pub struct Volume {
+    disk: Disk,
+    pub sb: SuperBlock,
+    descs: Vec<u8>,
+    pub block_size: usize,
+    desc_size: usize,
+    inode_size: usize,
+    group_count: u32,
+    csum_seed: u32,
+    /// Whether metadata checksums (`metadata_csum`) are enabled.
+    pub metadata_csum: bool,
+    /// Whether directory entries record the file types.
+    pub has_filetype: bool,
+    /// Whether new files use extents.
+    pub use_extents: bool,
+    /// Whether it can only be read, because of unsupported features.
+    pub read_only: bool,
+}
+
+fn io_err<E>(_: E) -> VfsError {
+    VfsError::Io
+}
+
+impl Volume {
+    /// Returns whether the disk contains an ext2/ext3/ext4 filesystem.
+    pub fn probe(disk: &mut Disk) -> bool {
+        let mut magic = [0; 2];
+        disk.set_position(SUPERBLOCK_OFFSET + 0x38);
+        let found = read_exact(disk, &mut magic).is_ok() && u16::from_le_bytes(magic) == EXT4_MAGIC;
+        disk.set_position(0);
+        found
+    }
+
+    pub fn open(mut disk: Disk) -> VfsResult<Self> {
+        let mut sb = vec![0; SUPERBLOCK_SIZE];
+        disk.set_position(SUPERBLOCK_OFFSET);
+        read_exact(&mut disk, &mut sb)?;
+        let sb = SuperBlock(sb);
+        if sb.magic() != EXT4_MAGIC {
+            return Err(VfsError::InvalidData);
+        }
+
+        let incompat = sb.feature_incompat();
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            warn!(
+                "ext4: unsupported incompatible features {:#x}",
+                incompat & !INCOMPAT_SUPPORTED
+            );
+            return Err(VfsError::Unsupported);
+        }
+        let ro_compat = sb.feature_ro_compat();
+        let mut read_only = false;
+        if ro_compat & !RO_COMPAT_SUPPORTED != 0 {
+            warn!(
+                "ext4: unsupported read-only compatible features {:#x}, mounted read-only",
+                ro_compat & !RO_COMPAT_SUPPORTED
+            );
+            read_only = true;
+        }
+        if incompat & INCOMPAT_RECOVER != 0 {
+            warn!("ext4: the journal needs recovery, mounted read-only");
+            read_only = true;
+        }
+
+        let block_size = 1024usize << sb.log_block_size();
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            sb.desc_size() as usize
+        } else {
+            32
+        };
+        let inode_size = sb.inode_size() as usize;
+        if !(1024..=65536).contains(&block_size)
+            || !(32..=1024).contains(&desc_size)
+            || !(128..=block_size).contains(&inode_size)
+            || sb.blocks_per_group() == 0
+            || sb.inodes_per_group() == 0
+        {
+            return Err(VfsError::InvalidData);
+        }
+        let data_blocks = sb.blocks_count() - sb.first_data_block() as u64;
+        let group_count = data_blocks.div_ceil(sb.blocks_per_group() as u64) as u32;
+
+        let mut descs = vec![0; group_count as usize * desc_size];
+        disk.set_position((sb.first_data_block() as u64 + 1) * block_size as u64);
+        read_exact(&mut disk, &mut descs)?;
+
+        let metadata_csum = ro_compat & RO_COMPAT_METADATA_CSUM != 0;
+        let csum_seed = if incompat & INCOMPAT_CSUM_SEED != 0 {
+            sb.checksum_seed()
+        } else {
+            crc32c(!0, sb.uuid())
+        };
+        info!(
+            "ext4: block size {}, {} blocks, {} inodes, {} groups",
+            block_size,
+            sb.blocks_count(),
+            sb.inodes_count(),
+            group_count
+        );
+        Ok(Self {
+            disk,
+            has_filetype: incompat & INCOMPAT_FILETYPE != 0,
+            use_extents: incompat & INCOMPAT_EXTENTS != 0,
+            sb,
+            descs,
+            block_size,
+            desc_size,
+            inode_size,
+            group_count,
+            csum_seed,
+            metadata_csum,
+            read_only,
+        })
+    }
+
+    pub fn check_writable(&self) -> VfsResult {
+        if self.read_only {
+            Err(VfsError::ReadOnlyFilesystem)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn read_bytes(&mut self, pos: u64, buf: &mut [u8]) -> VfsResult {
+        self.disk.set_position(pos);
+        read_exact(&mut self.disk, buf)
+    }
+
+    pub fn write_bytes(&mut self, pos: u64, buf: &[u8]) -> VfsResult {
+        self.disk.set_position(pos);
+        let mut buf = buf;
+        while !buf.is_empty() {
+            match self.disk.write_one(buf).map_err(io_err)? {
+                0 => return Err(VfsError::WriteZero),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_block(&mut self, block: u64, buf: &mut [u8]) -> VfsResult {
+        self.read_bytes(block * self.block_size as u64, buf)
+    }
+
+    pub fn write_block(&mut self, block: u64, buf: &[u8]) -> VfsResult {
+        self.write_bytes(block * self.block_size as u64, buf)
+    }
+
+    pub fn zero_block(&mut self, block: u64) -> VfsResult {
+        self.write_block(block, &vec![0; self.block_size])
+    }
+
+    fn write_super(&mut self) -> VfsResult {
+        self.sb.update_checksum();
+        let sb = core::mem::take(&mut self.sb.0);
+        let res = self.write_bytes(SUPERBLOCK_OFFSET, &sb);
+        self.sb.0 = sb;
+        res
+    }
+
+    fn desc(&mut self, group: u32) -> GroupDesc {
+        let off = group as usize * self.desc_size;
+        GroupDesc(&mut self.descs[off..off + self.desc_size])
+    }
+
+    fn write_desc(&mut self, group: u32) -> VfsResult {
+        let off = group as usize * self.desc_size;
+        let ro_compat = self.sb.feature_ro_compat();
+        let csum = if ro_compat & RO_COMPAT_METADATA_CSUM != 0 {
+            let mut desc = self.descs[off..off + self.desc_size].to_vec();
+            set_le16(&mut desc, 0x1e, 0);
+            let crc = crc32c(self.csum_seed, &group.to_le_bytes());
+            Some(crc32c(crc, &desc) as u16)
+        } else if ro_compat & RO_COMPAT_GDT_CSUM != 0 {
+            let desc = &self.descs[off..off + self.desc_size];
+            let mut crc = crc16(!0, self.sb.uuid());
+            crc = crc16(crc, &group.to_le_bytes());
+            crc = crc16(crc, &desc[..0x1e]);
+            Some(crc16(crc, &desc[0x20..]))
+        } else {
+            None
+        };
+        if let Some(csum) = csum {
+            self.desc(group).set_checksum(csum);
+        }
+        let pos = (self.sb.first_data_block() as u64 + 1) * self.block_size as u64 + off as u64;
+        let desc = self.descs[off..off + self.desc_size].to_vec();
+        self.write_bytes(pos, &desc)
+    }
+
+    fn group_first_block(&self, group: u32) -> u64 {
+        self.sb.first_data_block() as u64 + group as u64 * self.sb.blocks_per_group() as u64
+    }
+
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let rest = self.sb.blocks_count() - self.group_first_block(group);
+        rest.min(self.sb.blocks_per_group() as u64) as u32
+    }
+
+    /// Returns the current time in seconds and nanoseconds since the epoch.
+    ///
+    /// It is not earlier than the last write of the superblock, in case the
+    /// clock is not set.
+    pub fn now(&self) -> (u64, u32) {
+        let (secs, nanos) = super::now();
+        match self.sb.write_time() as u64 {
+            wtime if secs < wtime => (wtime, 0),
+            _ => (secs, nanos),
+        }
+    }
+
+    /// Returns the group of the inode, to allocate blocks near it.
+    pub fn inode_group(&self, ino: u32) -> u32 {
+        (ino - 1) / self.sb.inodes_per_group()
+    }
+
+    /// Returns the first block of the group, as the goal of allocation.
+    pub fn group_goal(&self, group: u32) -> u64 {
+        self.group_first_block(group)
+    }
+
+    /// Returns the checksum seed of the inode for the metadata it owns.
+    pub fn inode_csum_seed(&self, inode: &Inode) -> u32 {
+        let crc = crc32c(self.csum_seed, &inode.ino.to_le_bytes());
+        crc32c(crc, &inode.generation().to_le_bytes())
+    }
+
+    fn inode_pos(&mut self, ino: u32) -> VfsResult<u64> {
+        if ino == 0 || ino > self.sb.inodes_count() {
+            return Err(VfsError::InvalidData);
+        }
+        let ipg = self.sb.inodes_per_group();
+        let (group, index) = ((ino - 1) / ipg, (ino - 1) % ipg);
+        let table = self.desc(group).inode_table();
+        Ok(table * self.block_size as u64 + index as u64 * self.inode_size as u64)
+    }
+
+    pub fn read_inode(&mut self, ino: u32) -> VfsResult<Inode> {
+        let pos = self.inode_pos(ino)?;
+        let mut raw = vec![0; self.inode_size];
+        self.read_bytes(pos, &mut raw)?;
+        Ok(Inode { ino, raw })
+    }
+
+    pub fn write_inode(&mut self, inode: &mut Inode) -> VfsResult {
+        if self.metadata_csum {
+            let seed = self.inode_csum_seed(inode);
+            inode.update_checksum(seed);
+        }
+        let pos = self.inode_pos(inode.ino)?;
+        self.write_bytes(pos, &inode.raw)
+    }
+
+    /// The unit of `i_blocks` of the inode, in bytes.
+    pub fn blocks_unit(&self, inode: &Inode) -> u64 {
+        if inode.flags() & INODE_HUGE_FILE_FL != 0 {
+            self.block_size as u64
+        } else {
+            512
+        }
+    }
+
+    /// Adds `n` blocks (can be negative) to the blocks count of the inode.
+    pub fn add_inode_blocks(&self, inode: &mut Inode, n: i64) {
+        let delta = n * (self.block_size as u64 / self.blocks_unit(inode)) as i64;
+        inode.set_blocks(inode.blocks().saturating_add_signed(delta));
+    }
+
+    fn has_super(&self, group: u32) -> bool {
+        if group <= 1 {
+            return true;
+        }
+        if self.sb.feature_compat() & COMPAT_SPARSE_SUPER2 != 0 {
+            return self.sb.backup_bgs().contains(&group);
+        }
+        if self.sb.feature_ro_compat() & RO_COMPAT_SPARSE_SUPER == 0 {
+            return true;
+        }
+        [3, 5, 7].iter().any(|&base| {
+            let mut n = base;
+            while n < group {
+                n *= base;
+            }
+            n == group
+        })
+    }
+
+    fn read_bitmap(&mut self, block: u64) -> VfsResult<Vec<u8>> {
+        let mut bitmap = vec![0; self.block_size];
+        self.read_block(block, &mut bitmap)?;
+        Ok(bitmap)
+    }
+
+    /// Reads the block bitmap of the group, initializing it if necessary.
+    fn block_bitmap(&mut self, group: u32) -> VfsResult<Vec<u8>> {
+        let desc = self.desc(group);
+        let block = desc.block_bitmap();
+        if desc.flags() & BG_BLOCK_UNINIT == 0 {
+            return self.read_bitmap(block);
+        }
+        // Mark the metadata of the group, as `ext4_init_block_bitmap` does.
+        let mut bitmap = vec![0; self.block_size];
+        let start = self.group_first_block(group);
+        let count = self.blocks_in_group(group) as u64;
+        let mut mark = |blk: u64| {
+            if (start..start + count).contains(&blk) {
+                set_bit(&mut bitmap, (blk - start) as usize);
+            }
+        };
+        if self.has_super(group) {
+            let gdt_blocks = (self.group_count as usize * self.desc_size).div_ceil(self.block_size);
+            let meta = 1 + gdt_blocks as u64 + self.sb.reserved_gdt_blocks() as u64;
+            (start..start + meta).for_each(&mut mark);
+        }
+        let desc = GroupDesc(&mut self.descs[group as usize * self.desc_size..][..self.desc_size]);
+        let itable = desc.inode_table();
+        mark(desc.block_bitmap());
+        mark(desc.inode_bitmap());
+        let itable_blocks = (self.sb.inodes_per_group() as usize * self.inode_size)
+            .div_ceil(self.block_size) as u64;
+        (itable..itable + itable_blocks).for_each(&mut mark);
+        (count as usize..self.block_size * 8).for_each(|i| set_bit(&mut bitmap, i));
+        Ok(bitmap)
+    }
+
+    /// Reads the inode bitmap of the group, initializing it if necessary.
+    fn inode_bitmap(&mut self, group: u32) -> VfsResult<Vec<u8>> {
+        let desc = self.desc(group);
+        let block = desc.inode_bitmap();
+        if desc.flags() & BG_INODE_UNINIT == 0 {
+            return self.read_bitmap(block);
+        }
+        let mut bitmap = vec![0; self.block_size];
+        let ipg = self.sb.inodes_per_group() as usize;
+        (ipg..self.block_size * 8).for_each(|i| set_bit(&mut bitmap, i));
+        Ok(bitmap)
+    }
+
+    fn write_block_bitmap(&mut self, group: u32, bitmap: &[u8]) -> VfsResult {
+        if self.metadata_csum {
+            let len = self.sb.blocks_per_group() as usize / 8;
+            let csum = crc32c(self.csum_seed, &bitmap[..len]);
+            self.desc(group).set_block_bitmap_csum(csum);
+        }
+        let mut desc = self.desc(group);
+        desc.set_flags(desc.flags() & !BG_BLOCK_UNINIT);
+        let block = desc.block_bitmap();
+        self.write_block(block, bitmap)
+    }
+
+    fn write_inode_bitmap(&mut self, group: u32, bitmap: &[u8]) -> VfsResult {
+        if self.metadata_csum {
+            let len = self.sb.inodes_per_group() as usize / 8;
+            let csum = crc32c(self.csum_seed, &bitmap[..len]);
+            self.desc(group).set_inode_bitmap_csum(csum);
+        }
+        let mut desc = self.desc(group);
+        desc.set_flags(desc.flags() & !BG_INODE_UNINIT);
+        let block = desc.inode_bitmap();
+        self.write_block(block, bitmap)
+    }
+
+    /// Allocates a block, preferably at or after `goal`.
+    pub fn alloc_block(&mut self, goal: u64) -> VfsResult<u64> {
+        self.check_writable()?;
+        let bpg = self.sb.blocks_per_group() as u64;
+        let fdb = self.sb.first_data_block() as u64;
+        let goal = goal.clamp(fdb, self.sb.blocks_count() - 1);
+        let goal_group = ((goal - fdb) / bpg) as u32;
+        for i in 0..self.group_count {
+            let group = (goal_group + i) % self.group_count;
+            if self.desc(group).free_blocks() == 0 {
+                continue;
+            }
+            let mut bitmap = self.block_bitmap(group)?;
+            let count = self.blocks_in_group(group) as usize;
+            let from = if i == 0 {
+                ((goal - fdb) % bpg) as usize
+            } else {
+                0
+            };
+            let Some(bit) = find_zero_bit(&bitmap, from, count)
+                .or_else(|| find_zero_bit(&bitmap, 0, from.min(count)))
+            else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            self.write_block_bitmap(group, &bitmap)?;
+            let mut desc = self.desc(group);
+            desc.set_free_blocks(desc.free_blocks() - 1);
+            self.write_desc(group)?;
+            self.sb
+                .set_free_blocks_count(self.sb.free_blocks_count().saturating_sub(1));
+            self.write_super()?;
+            return Ok(self.group_first_block(group) + bit as u64);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    /// Frees `count` contiguous blocks from `start`.
+    pub fn free_blocks(&mut self, start: u64, count: u64) -> VfsResult {
+        let bpg = self.sb.blocks_per_group() as u64;
+        let fdb = self.sb.first_data_block() as u64;
+        let mut block = start;
+        while block < start + count {
+            let group = ((block - fdb) / bpg) as u32;
+            let first = self.group_first_block(group);
+            let end = (start + count).min(first + self.blocks_in_group(group) as u64);
+            let mut bitmap = self.block_bitmap(group)?;
+            let mut freed = 0;
+            for blk in block..end {
+                let bit = (blk - first) as usize;
+                if test_bit(&bitmap, bit) {
+                    clear_bit(&mut bitmap, bit);
+                    freed += 1;
+                } else {
+                    warn!("ext4: freeing free block {}", blk);
+                }
+            }
+            self.write_block_bitmap(group, &bitmap)?;
+            let mut desc = self.desc(group);
+            desc.set_free_blocks(desc.free_blocks() + freed as u32);
+            self.write_desc(group)?;
+            self.sb
+                .set_free_blocks_count(self.sb.free_blocks_count() + freed);
+            block = end;
+        }
+        self.write_super()
+    }
+
+    /// Allocates an inode, preferably in the group `goal_group`.
+    pub fn alloc_inode(&mut self, goal_group: u32, is_dir: bool) -> VfsResult<u32> {
+        self.check_writable()?;
+        let ipg = self.sb.inodes_per_group();
+        let first_ino = self.sb.first_ino();
+        for i in 0..self.group_count {
+            let group = (goal_group + i) % self.group_count;
+            if self.desc(group).free_inodes() == 0 {
+                continue;
+            }
+            let mut bitmap = self.inode_bitmap(group)?;
+            // skip the reserved inodes
+            let from = first_ino.saturating_sub(group * ipg + 1) as usize;
+            let Some(bit) = find_zero_bit(&bitmap, from, ipg as usize) else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            self.write_inode_bitmap(group, &bitmap)?;
+
+            let csum = self.sb.feature_ro_compat() & (RO_COMPAT_GDT_CSUM | RO_COMPAT_METADATA_CSUM);
+            let mut desc = self.desc(group);
+            desc.set_free_inodes(desc.free_inodes() - 1);
+            if is_dir {
+                desc.set_used_dirs(desc.used_dirs() + 1);
+            }
+            if csum != 0 && bit as u32 + 1 > ipg - desc.itable_unused() {
+                desc.set_itable_unused(ipg - bit as u32 - 1);
+            }
+            self.write_desc(group)?;
+            self.sb
+                .set_free_inodes_count(self.sb.free_inodes_count().saturating_sub(1));
+            self.write_super()?;
+            return Ok(group * ipg + bit as u32 + 1);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    pub fn free_inode(&mut self, ino: u32, is_dir: bool) -> VfsResult {
+        let ipg = self.sb.inodes_per_group();
+        let (group, bit) = ((ino - 1) / ipg, ((ino - 1) % ipg) as usize);
+        let mut bitmap = self.inode_bitmap(group)?;
+        if !test_bit(&bitmap, bit) {
+            warn!("ext4: freeing free inode {}", ino);
+            return Ok(());
+        }
+        clear_bit(&mut bitmap, bit);
+        self.write_inode_bitmap(group, &bitmap)?;
+        let mut desc = self.desc(group);
+        desc.set_free_inodes(desc.free_inodes() + 1);
+        if is_dir {
+            desc.set_used_dirs(desc.used_dirs().saturating_sub(1));
+        }
+        self.write_desc(group)?;
+        self.sb
+            .set_free_inodes_count(self.sb.free_inodes_count() + 1);
+        self.write_super()
+    }
+
+    /// Returns a new in-memory inode `ino` with `mode`, to be written.
+    pub fn new_inode(&mut self, ino: u32, mode: u16) -> VfsResult<Inode> {
+        // Bump the generation of the old inode, so stale handles can be told.
+        let generation = self.read_inode(ino)?.generation().wrapping_add(1);
+        let mut inode = Inode {
+            ino,
+            raw: vec![0; self.inode_size],
+        };
+        if self.inode_size > 128 {
+            let extra = (self.sb.want_extra_isize() as usize).clamp(32, self.inode_size - 128);
+            set_le16(&mut inode.raw, 0x80, extra as u16);
+        }
+        inode.set_mode(mode);
+        inode.set_generation(generation);
+        let (secs, nanos) = self.now();
+        inode.set_times(secs, nanos, true);
+        if self.use_extents && mode & S_IFMT != S_IFLNK {
+            inode.set_flags(INODE_EXTENTS_FL);
+            ExtentHeader {
+                entries: 0,
+                max: 4,
+                depth: 0,
+            }
+            .write(inode.i_block_mut());
+        }
+        Ok(inode)
+    }
+}
+
+fn read_exact(disk: &mut Disk, mut buf: &mut [u8]) -> VfsResult {
+    while !buf.is_empty() {
+        match disk.read_one(buf).map_err(io_err)? {
+            0 => return Err(VfsError::UnexpectedEof),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] |= 1 << (bit % 8);
+}
+
+fn clear_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+}
+
+/// Finds the first zero bit in `from..end`.
+fn find_zero_bit(bitmap: &[u8], from: usize, end: usize) -> Option<usize> {
+    let mut bit = from;
+    while bit < end {
+        if bit % 8 == 0 && bitmap[bit / 8] == 0xff {
+            bit += 8;
+            continue;
+        }
+        if !test_bit(bitmap, bit) {
+            return Some(bit);
+        }
+        bit += 1;
+    }
+    None
+}
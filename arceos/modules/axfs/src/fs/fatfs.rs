@@ -1,22 +1,248 @@
-use alloc::sync::Arc;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use core::cell::UnsafeCell;
+use core::time::Duration;
 
 use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
 use axsync::Mutex;
-use fatfs::{Dir, File, LossyOemCpConverter, NullTimeProvider, Read, Seek, SeekFrom, Write};
+use fatfs::{Date, DirEntry, Time, TimeProvider};
+use fatfs::{Dir, File, LossyOemCpConverter, Read, Seek, SeekFrom, Write};
 
 use crate::dev::Disk;
 
 const BLOCK_SIZE: usize = 512;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "rtc")] {
+        type FatTimeProvider = WallTimeProvider;
+
+        fn time_provider() -> FatTimeProvider {
+            WallTimeProvider
+        }
+    } else {
+        /// New and modified entries are all dated 1980-01-01 without RTC.
+        type FatTimeProvider = fatfs::NullTimeProvider;
+
+        fn time_provider() -> FatTimeProvider {
+            fatfs::NullTimeProvider::new()
+        }
+    }
+}
+
+/// Provides the current time for new and modified entries from the wall
+/// clock. FAT stores local time, but there are no timezones here, so it is
+/// UTC.
+#[cfg(feature = "rtc")]
+#[derive(Debug, Clone, Copy)]
+pub struct WallTimeProvider;
+
+#[cfg(feature = "rtc")]
+impl TimeProvider for WallTimeProvider {
+    fn get_current_date(&self) -> Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> fatfs::DateTime {
+        let secs = axhal::time::wall_time().as_secs();
+        let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+        let secs = secs % SECS_PER_DAY;
+        // clamped to the years FAT can represent
+        let (date, time) = match year {
+            ..=1979 => (Date::new(1980, 1, 1), Time::new(0, 0, 0, 0)),
+            2108.. => (Date::new(2107, 12, 31), Time::new(23, 59, 59, 0)),
+            _ => (
+                Date::new(year as u16, month as u16, day as u16),
+                Time::new(
+                    (secs / 3600) as u16,
+                    (secs / 60 % 60) as u16,
+                    (secs % 60) as u16,
+                    0,
+                ),
+            ),
+        };
+        fatfs::DateTime::new(date, time)
+    }
+}
+
+/// The date of the days since 1970-01-01, as `(year, month, day)`.
+#[cfg(feature = "rtc")]
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153; // months since March
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts FAT date and time to the time since the Unix epoch.
+fn from_fat_time(date: Date, time: Time) -> Duration {
+    let days = days_from_civil(date.year as i64, date.month as i64, date.day as i64);
+    let secs = days * SECS_PER_DAY as i64
+        + time.hour as i64 * 3600
+        + time.min as i64 * 60
+        + time.sec as i64;
+    Duration::new(secs.max(0) as u64, time.millis as u32 * 1_000_000)
+}
+
+/// The time that a write records as the modification time, which has a
+/// resolution of 2 seconds.
+fn modified_now() -> Duration {
+    let now = time_provider().get_current_date_time();
+    Duration::from_secs(from_fat_time(now.date, now.time).as_secs() & !1)
+}
+
+/// The timestamps of a directory entry, since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+struct EntryTimes {
+    accessed: Duration,
+    modified: Duration,
+    created: Duration,
+}
+
+impl EntryTimes {
+    fn of(entry: &DirEntry<'_, Disk, FatTimeProvider, LossyOemCpConverter>) -> Self {
+        let (created, modified) = (entry.created(), entry.modified());
+        Self {
+            // only the date of the last access is recorded
+            accessed: from_fat_time(entry.accessed(), Time::new(0, 0, 0, 0)),
+            modified: from_fat_time(modified.date, modified.time),
+            created: from_fat_time(created.date, created.time),
+        }
+    }
+
+    /// FAT has no status change time, so the creation time is reported
+    /// instead, as Linux does.
+    const fn apply(&self, attr: VfsNodeAttr) -> VfsNodeAttr {
+        attr.with_times(self.accessed, self.modified, self.created)
+    }
+}
+
+/// The identity of an entry, shared by all the wrappers of it.
+struct NodeInfo {
+    ino: u64,
+    /// The canonical path from the root directory, in upper case since the
+    /// names are case-insensitive.
+    path: Mutex<String>,
+}
+
+/// The inode numbers of the entries that are looked up.
+///
+/// FAT has no inode numbers, so an entry gets one when it is looked up and
+/// keeps it while any node of it is alive, as Linux does.
+struct InodeTable {
+    nodes: BTreeMap<String, Weak<NodeInfo>>,
+    next_ino: u64,
+}
+
+impl InodeTable {
+    const fn new() -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            // `1` is the root directory
+            next_ino: 2,
+        }
+    }
+
+    fn get(&mut self, path: String) -> Arc<NodeInfo> {
+        if let Some(info) = self.nodes.get(&path).and_then(Weak::upgrade) {
+            return info;
+        }
+        self.nodes.retain(|_, info| info.strong_count() > 0);
+        let ino = if path.is_empty() {
+            1
+        } else {
+            self.next_ino += 1;
+            self.next_ino - 1
+        };
+        let info = Arc::new(NodeInfo {
+            ino,
+            path: Mutex::new(path.clone()),
+        });
+        self.nodes.insert(path, Arc::downgrade(&info));
+        info
+    }
+
+    /// Moves the entry at `src` and the ones under it to `dst`.
+    fn rename(&mut self, src: &str, dst: &str) {
+        if src == dst {
+            return;
+        }
+        self.remove(dst);
+        let moved = self.take_under(src);
+        for (path, info) in moved {
+            let path = String::from(dst) + &path[src.len()..];
+            if let Some(info) = info.upgrade() {
+                *info.path.lock() = path.clone();
+            }
+            self.nodes.insert(path, info);
+        }
+    }
+
+    /// Forgets the entry at `path` and the ones under it, so that a new entry
+    /// there gets a new inode number.
+    fn remove(&mut self, path: &str) {
+        self.take_under(path);
+    }
+
+    fn take_under(&mut self, path: &str) -> alloc::vec::Vec<(String, Weak<NodeInfo>)> {
+        let keys = self
+            .nodes
+            .keys()
+            .filter(|p| *p == path || (p.starts_with(path) && p[path.len()..].starts_with('/')))
+            .cloned()
+            .collect::<alloc::vec::Vec<_>>();
+        keys.into_iter()
+            .map(|p| {
+                let info = self.nodes.remove(&p).unwrap();
+                (p, info)
+            })
+            .collect()
+    }
+}
+
+/// The canonical form of `path` relative to the directory at `base`, as the
+/// key of [`InodeTable`].
+fn canonical_path(base: &str, path: &str) -> String {
+    let path = axfs_vfs::path::canonicalize(&alloc::format!("/{}/{}", base, path));
+    path.trim_start_matches('/').to_uppercase()
+}
 
 pub struct FatFileSystem {
-    inner: fatfs::FileSystem<Disk, NullTimeProvider, LossyOemCpConverter>,
+    inner: fatfs::FileSystem<Disk, FatTimeProvider, LossyOemCpConverter>,
     root_dir: UnsafeCell<Option<VfsNodeRef>>,
+    inodes: Mutex<InodeTable>,
 }
 
-pub struct FileWrapper<'a>(Mutex<File<'a, Disk, NullTimeProvider, LossyOemCpConverter>>);
-pub struct DirWrapper<'a>(Dir<'a, Disk, NullTimeProvider, LossyOemCpConverter>);
+pub struct FileWrapper<'a>(
+    Mutex<File<'a, Disk, FatTimeProvider, LossyOemCpConverter>>,
+    Mutex<EntryTimes>,
+    Arc<NodeInfo>,
+);
+pub struct DirWrapper<'a>(
+    Dir<'a, Disk, FatTimeProvider, LossyOemCpConverter>,
+    EntryTimes,
+    Arc<NodeInfo>,
+    &'a FatFileSystem,
+);
 
 unsafe impl Sync for FatFileSystem {}
 unsafe impl Send for FatFileSystem {}
@@ -30,35 +256,73 @@ impl FatFileSystem {
     pub fn new(mut disk: Disk) -> Self {
         let opts = fatfs::FormatVolumeOptions::new();
         fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
+        let opts = fatfs::FsOptions::new().time_provider(time_provider());
+        let inner =
+            fatfs::FileSystem::new(disk, opts).expect("failed to initialize FAT filesystem");
         Self {
             inner,
             root_dir: UnsafeCell::new(None),
+            inodes: Mutex::new(InodeTable::new()),
         }
     }
 
     #[cfg(not(feature = "use-ramdisk"))]
     pub fn new(disk: Disk) -> Self {
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
+        let opts = fatfs::FsOptions::new().time_provider(time_provider());
+        let inner =
+            fatfs::FileSystem::new(disk, opts).expect("failed to initialize FAT filesystem");
         Self {
             inner,
             root_dir: UnsafeCell::new(None),
+            inodes: Mutex::new(InodeTable::new()),
         }
     }
 
+    /// Returns whether the disk looks like a FAT volume, by the BIOS
+    /// parameter block in the boot sector.
+    pub fn probe(disk: &mut Disk) -> bool {
+        let mut sector = [0; BLOCK_SIZE];
+        disk.set_position(0);
+        if disk.read_one(&mut sector).is_err() || sector[510..] != [0x55, 0xaa] {
+            return false;
+        }
+        let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+        let sectors_per_cluster = sector[13];
+        let reserved_sectors = u16::from_le_bytes([sector[14], sector[15]]);
+        let num_fats = sector[16];
+        matches!(sector[0], 0xeb | 0xe9)
+            && (512..=4096).contains(&bytes_per_sector)
+            && bytes_per_sector.is_power_of_two()
+            && sectors_per_cluster.is_power_of_two()
+            && reserved_sectors > 0
+            && num_fats > 0
+    }
+
     pub fn init(&'static self) {
         // must be called before later operations
-        unsafe { *self.root_dir.get() = Some(Self::new_dir(self.inner.root_dir())) }
+        // the root directory has no entry, so no timestamps
+        let root_dir = self.new_dir(self.inner.root_dir(), EntryTimes::default(), String::new());
+        unsafe { *self.root_dir.get() = Some(root_dir) }
     }
 
-    fn new_file(file: File<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<FileWrapper> {
-        Arc::new(FileWrapper(Mutex::new(file)))
+    fn new_file(
+        &'static self,
+        file: File<'static, Disk, FatTimeProvider, LossyOemCpConverter>,
+        times: EntryTimes,
+        path: String,
+    ) -> Arc<FileWrapper<'static>> {
+        let info = self.inodes.lock().get(path);
+        Arc::new(FileWrapper(Mutex::new(file), Mutex::new(times), info))
     }
 
-    fn new_dir(dir: Dir<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<DirWrapper> {
-        Arc::new(DirWrapper(dir))
+    fn new_dir(
+        &'static self,
+        dir: Dir<'static, Disk, FatTimeProvider, LossyOemCpConverter>,
+        times: EntryTimes,
+        path: String,
+    ) -> Arc<DirWrapper<'static>> {
+        let info = self.inodes.lock().get(path);
+        Arc::new(DirWrapper(dir, times, info, self))
     }
 }
 
@@ -68,9 +332,10 @@ impl VfsNodeOps for FileWrapper<'static> {
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
         let size = self.0.lock().seek(SeekFrom::End(0)).map_err(as_vfs_err)?;
         let blocks = (size + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64;
-        // FAT fs doesn't support permissions, we just set everything to 755
-        let perm = VfsNodePerm::from_bits_truncate(0o755);
-        Ok(VfsNodeAttr::new(perm, VfsNodeType::File, size, blocks))
+        // FAT fs doesn't support permissions, files are not executable
+        let perm = VfsNodePerm::from_bits_truncate(0o644);
+        let attr = VfsNodeAttr::new(perm, VfsNodeType::File, size, blocks).with_ino(self.2.ino);
+        Ok(self.1.lock().apply(attr))
     }
 
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
@@ -82,13 +347,43 @@ impl VfsNodeOps for FileWrapper<'static> {
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
         let mut file = self.0.lock();
         file.seek(SeekFrom::Start(offset)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.write(buf).map_err(as_vfs_err)
+        let n = file.write(buf).map_err(as_vfs_err)?;
+        self.1.lock().modified = modified_now();
+        Ok(n)
     }
 
     fn truncate(&self, size: u64) -> VfsResult {
         let mut file = self.0.lock();
         file.seek(SeekFrom::Start(size)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.truncate().map_err(as_vfs_err)
+        file.truncate().map_err(as_vfs_err)?;
+        self.1.lock().modified = modified_now();
+        Ok(())
+    }
+}
+
+impl DirWrapper<'static> {
+    /// The key of `path` relative to this directory in the inode table.
+    fn canonical_path(&self, path: &str) -> String {
+        canonical_path(&self.2.path.lock(), path)
+    }
+
+    /// Returns the timestamps in the directory entry of `path`, or zeros if
+    /// it is not found, e.g. for the root directory.
+    fn entry_times(&self, path: &str) -> EntryTimes {
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => match self.0.open_dir(parent) {
+                Ok(dir) => (dir, name),
+                Err(_) => return EntryTimes::default(),
+            },
+            None => (self.0.clone(), path),
+        };
+        dir.iter()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| {
+                entry.file_name().eq_ignore_ascii_case(name)
+                    || entry.short_file_name().eq_ignore_ascii_case(name)
+            })
+            .map_or_else(EntryTimes::default, |entry| EntryTimes::of(&entry))
     }
 }
 
@@ -96,19 +391,22 @@ impl VfsNodeOps for DirWrapper<'static> {
     axfs_vfs::impl_vfs_dir_default! {}
 
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        // FAT fs doesn't support permissions, we just set everything to 755
-        Ok(VfsNodeAttr::new(
+        // FAT fs doesn't support permissions, directories are all 755
+        let attr = VfsNodeAttr::new(
             VfsNodePerm::from_bits_truncate(0o755),
             VfsNodeType::Dir,
             BLOCK_SIZE as u64,
             1,
-        ))
+        )
+        .with_ino(self.2.ino);
+        Ok(self.1.apply(attr))
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
-        self.0
-            .open_dir("..")
-            .map_or(None, |dir| Some(FatFileSystem::new_dir(dir)))
+        self.0.open_dir("..").map_or(None, |dir| {
+            let path = self.canonical_path("..");
+            Some(self.3.new_dir(dir, self.entry_times(".."), path))
+        })
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
@@ -123,9 +421,11 @@ impl VfsNodeOps for DirWrapper<'static> {
 
         // TODO: use `fatfs::Dir::find_entry`, but it's not public.
         if let Ok(file) = self.0.open_file(path) {
-            Ok(FatFileSystem::new_file(file))
+            let times = self.entry_times(path);
+            Ok(self.3.new_file(file, times, self.canonical_path(path)))
         } else if let Ok(dir) = self.0.open_dir(path) {
-            Ok(FatFileSystem::new_dir(dir))
+            let times = self.entry_times(path);
+            Ok(self.3.new_dir(dir, times, self.canonical_path(path)))
         } else {
             Err(VfsError::NotFound)
         }
@@ -161,7 +461,9 @@ impl VfsNodeOps for DirWrapper<'static> {
         if let Some(rest) = path.strip_prefix("./") {
             return self.remove(rest);
         }
-        self.0.remove(path).map_err(as_vfs_err)
+        self.0.remove(path).map_err(as_vfs_err)?;
+        self.3.inodes.lock().remove(&self.canonical_path(path));
+        Ok(())
     }
 
     fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
@@ -194,7 +496,10 @@ impl VfsNodeOps for DirWrapper<'static> {
 
         self.0
             .rename(src_path, &self.0, dst_path)
-            .map_err(as_vfs_err)
+            .map_err(as_vfs_err)?;
+        let (src, dst) = (self.canonical_path(src_path), self.canonical_path(dst_path));
+        self.3.inodes.lock().rename(&src, &dst);
+        Ok(())
     }
 }
 
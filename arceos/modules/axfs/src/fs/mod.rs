@@ -6,6 +6,9 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+pub mod ext4fs;
+
 #[cfg(feature = "devfs")]
 pub use axfs_devfs as devfs;
 
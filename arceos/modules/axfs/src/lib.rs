@@ -10,6 +10,10 @@
 //!    **enabled** by default.
 //! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
 //!    **enabled** by default.
+//! - `ext4fs`: Support [ext2/ext3/ext4][ext4]. If the disk contains one, it is
+//!    mounted on `/` instead of FAT. This feature is **disabled** by default.
+//! - `rtc`: Date new and modified FAT entries with the wall clock from the RTC,
+//!    instead of 1980-01-01. This feature is **disabled** by default.
 //! - `myfs`: Allow users to define their custom filesystems to override the
 //!    default. In this case, [`MyFileSystemIf`] is required to be implemented
 //!    to create and initialize other filesystems. This feature is **disabled** by
@@ -17,6 +21,7 @@
 //!    both are enabled.
 //!
 //! [FAT]: https://en.wikipedia.org/wiki/File_Allocation_Table
+//! [ext4]: https://en.wikipedia.org/wiki/Ext4
 //! [`MyFileSystemIf`]: fops::MyFileSystemIf
 
 #![cfg_attr(all(not(test), not(doc)), no_std)]
@@ -29,6 +34,8 @@ extern crate alloc;
 mod dev;
 mod fs;
 mod mounts;
+mod page_cache;
+mod partition;
 mod root;
 
 pub mod api;
@@ -37,10 +44,19 @@ pub mod fops;
 use axdriver::{prelude::*, AxDeviceContainer};
 
 /// Initializes filesystems by block devices.
+///
+/// The block devices are named `vda`, `vdb`, ..., and the partitions on them
+/// `vda1`, `vda2`, ..., from MBR or GPT partition tables. The root filesystem
+/// is on the one chosen by the `AX_ROOT` environment variable at build time
+/// (e.g. `/dev/vda2`), or on the first one with a supported filesystem.
 pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
     info!("Initialize filesystems...");
 
-    let dev = blk_devs.take_one().expect("No block device found!");
-    info!("  use block device 0: {:?}", dev.device_name());
-    self::root::init_rootfs(self::dev::Disk::new(dev));
+    let mut index = 0;
+    while let Some(dev) = blk_devs.take_one() {
+        info!("  use block device {}: {:?}", index, dev.device_name());
+        self::dev::add_device(index, dev);
+        index += 1;
+    }
+    self::root::init_rootfs(self::root::open_root_disk());
 }
@@ -1,6 +1,9 @@
 use alloc::sync::Arc;
+use axerrno::{ax_err, AxResult};
 use axfs_vfs::{VfsNodeType, VfsOps, VfsResult};
 
+#[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+use crate::dev::Disk;
 use crate::fs;
 
 #[cfg(feature = "devfs")]
@@ -13,17 +16,20 @@ pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
     devfs.add("null", Arc::new(null));
     devfs.add("zero", Arc::new(zero));
     foo_dir.add("bar", Arc::new(bar));
+    for dev in crate::dev::devices() {
+        devfs.add(dev.name(), Arc::new(crate::dev::BlockDevNode::new(dev)));
+    }
     Arc::new(devfs)
 }
 
 #[cfg(feature = "ramfs")]
 pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
-    Arc::new(fs::ramfs::RamFileSystem::new())
+    Arc::new(fs::ramfs::RamFileSystem::with_clock(axhal::time::wall_time))
 }
 
 #[cfg(feature = "procfs")]
 pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let procfs = fs::ramfs::RamFileSystem::new();
+    let procfs = fs::ramfs::RamFileSystem::with_clock(axhal::time::wall_time);
     let proc_root = procfs.root_dir();
 
     // Create /proc/sys/net/core/somaxconn
@@ -49,7 +55,7 @@ pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
 
 #[cfg(feature = "sysfs")]
 pub(crate) fn sysfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let sysfs = fs::ramfs::RamFileSystem::new();
+    let sysfs = fs::ramfs::RamFileSystem::with_clock(axhal::time::wall_time);
     let sys_root = sysfs.root_dir();
 
     // Create /sys/kernel/mm/transparent_hugepage/enabled
@@ -78,3 +84,28 @@ pub(crate) fn sysfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
 
     Ok(Arc::new(sysfs))
 }
+
+/// Creates a filesystem of `fstype` to be mounted, from the device `source`.
+pub(crate) fn new_fs(fstype: &str, source: &str) -> AxResult<Arc<dyn VfsOps>> {
+    debug!("new filesystem: {} from {:?}", fstype, source);
+    let fs: Arc<dyn VfsOps> = match fstype {
+        #[cfg(feature = "devfs")]
+        "devfs" => devfs(),
+        #[cfg(feature = "ramfs")]
+        "ramfs" | "tmpfs" => ramfs(),
+        #[cfg(feature = "procfs")]
+        "proc" | "procfs" => procfs()?,
+        #[cfg(feature = "sysfs")]
+        "sysfs" => sysfs()?,
+        #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+        "ext4" | "ext3" | "ext2" => {
+            let mut disk = Disk::open(&crate::dev::find_device(source)?)?;
+            if !fs::ext4fs::Ext4FileSystem::probe(&mut disk) {
+                return ax_err!(InvalidInput, "wrong filesystem type");
+            }
+            Arc::new(fs::ext4fs::Ext4FileSystem::new(disk)?)
+        }
+        _ => return ax_err!(NoSuchDevice, "unknown filesystem type"),
+    };
+    Ok(fs)
+}
@@ -0,0 +1,489 @@
+//! Page cache shared by all the opened regular files.
+//!
+//! The pages are keyed by the file and the page index, and evicted in the
+//! least recently used order. Writes inside the file are kept in the cache and
+//! written back on [`fsync`](VfsNodeOps::fsync), [`sync_all`], eviction, or
+//! when the file is no longer opened, while writes that extend the file go
+//! through to the filesystem to update the file size.
+//!
+//! The page cache is not locked during the I/O. A page read from the
+//! filesystem is dropped if the file has been written meanwhile, and read
+//! again. The pages can also be pinned and mapped by shared file mappings, see
+//! [`VfsNodeOps::pin_page`].
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axfs_vfs::{VfsError, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsOps, VfsResult};
+use axsync::Mutex;
+
+/// The size of a cached page.
+const PAGE_SIZE: usize = 4096;
+/// The maximum number of the cached pages, unless more are pinned.
+const MAX_CACHED_PAGES: usize = 1024;
+
+/// The file ID and the page index.
+type PageKey = (usize, u64);
+
+/// The content of a page, aligned to be mapped into the user space.
+#[repr(C, align(4096))]
+struct PageData([u8; PAGE_SIZE]);
+
+/// The state of a cached file, shared with its pages.
+struct FileState {
+    /// The underlying node to read from and write back to.
+    node: VfsNodeRef,
+    /// Bumped after the content of the node changes, to drop the pages read
+    /// before the change.
+    generation: AtomicU64,
+    /// Serializes the writes to the node, so that older data of a page is
+    /// never written after newer.
+    writes: Mutex<()>,
+}
+
+struct CachedPage {
+    file: Arc<FileState>,
+    data: Box<PageData>,
+    dirty: bool,
+    /// The number of the mappings of the page, it is not evicted if pinned.
+    pins: usize,
+    /// The last access time, to find the least recently used page.
+    stamp: u64,
+}
+
+struct PageCache {
+    pages: BTreeMap<PageKey, CachedPage>,
+    /// Maps the access stamps to the pages.
+    lru: BTreeMap<u64, PageKey>,
+    next_stamp: u64,
+}
+
+static PAGE_CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+
+/// The filesystem and the inode number of a file.
+type FileKey = (usize, u64);
+
+/// The cached files by their filesystems and inode numbers, so that the files
+/// opened more than once share the same pages.
+static CACHED_FILES: Mutex<BTreeMap<FileKey, Weak<CachedFile>>> = Mutex::new(BTreeMap::new());
+
+impl FileState {
+    fn id(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Called with the page cache locked after the node is written.
+    fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+fn read_page(node: &VfsNodeRef, index: u64, data: &mut PageData) -> VfsResult {
+    let mut read = 0;
+    while read < PAGE_SIZE {
+        match node.read_at(index * PAGE_SIZE as u64 + read as u64, &mut data.0[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+fn write_page(node: &VfsNodeRef, index: u64, data: &[u8]) -> VfsResult {
+    // The file may have been truncated, do not extend it.
+    let offset = index * PAGE_SIZE as u64;
+    let size = node.get_attr()?.size();
+    if offset < size {
+        let len = (size - offset).min(PAGE_SIZE as u64) as usize;
+        node.write_at(offset, &data[..len])?;
+    }
+    Ok(())
+}
+
+/// Writes back the page of `file` at `index` if it is dirty.
+fn write_back(file: &FileState, index: u64) -> VfsResult {
+    let _writes = file.writes.lock();
+    let key = (file.id(), index);
+    let data = match PAGE_CACHE.lock().pages.get_mut(&key) {
+        Some(page) if page.dirty => {
+            // Written again during the I/O if it is mapped.
+            page.dirty = false;
+            Vec::from(&page.data.0[..])
+        }
+        _ => return Ok(()),
+    };
+    let res = write_page(&file.node, index, &data);
+    let mut cache = PAGE_CACHE.lock();
+    file.bump_generation();
+    if res.is_err() {
+        if let Some(page) = cache.pages.get_mut(&key) {
+            page.dirty = true;
+        }
+    }
+    res
+}
+
+impl PageCache {
+    const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            next_stamp: 0,
+        }
+    }
+
+    /// Returns the cached page and marks it as the most recently used.
+    fn touch(&mut self, key: PageKey) -> Option<&mut CachedPage> {
+        let page = self.pages.get_mut(&key)?;
+        self.lru.remove(&page.stamp);
+        page.stamp = self.next_stamp;
+        self.next_stamp += 1;
+        self.lru.insert(page.stamp, key);
+        Some(page)
+    }
+
+    fn insert(&mut self, key: PageKey, file: &Arc<FileState>, data: Box<PageData>) {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        self.lru.insert(stamp, key);
+        let page = CachedPage {
+            file: file.clone(),
+            data,
+            dirty: false,
+            pins: 0,
+            stamp,
+        };
+        self.pages.insert(key, page);
+    }
+
+    fn remove(&mut self, key: PageKey) {
+        if let Some(page) = self.pages.remove(&key) {
+            self.lru.remove(&page.stamp);
+        }
+    }
+
+    /// The pages of the file.
+    fn keys_of(&self, id: usize) -> impl Iterator<Item = (&PageKey, &CachedPage)> {
+        self.pages.range((id, 0)..=(id, u64::MAX))
+    }
+
+    /// Evicts the least recently used clean pages that are not pinned, until
+    /// the cache is not over the limit.
+    fn evict_clean(&mut self) {
+        let mut excess = self.pages.len().saturating_sub(MAX_CACHED_PAGES);
+        let mut evicted = Vec::new();
+        for key in self.lru.values() {
+            if excess == 0 {
+                break;
+            }
+            let page = &self.pages[key];
+            if !page.dirty && page.pins == 0 {
+                evicted.push(*key);
+                excess -= 1;
+            }
+        }
+        for key in evicted {
+            self.remove(key);
+        }
+    }
+
+    /// Returns the least recently used dirty pages to write back, so that they
+    /// can be evicted to bring the cache down to the limit.
+    fn oldest_dirty(&self) -> Vec<(Arc<FileState>, u64)> {
+        let excess = self.pages.len().saturating_sub(MAX_CACHED_PAGES);
+        self.lru
+            .values()
+            .map(|key| (key, &self.pages[key]))
+            .filter(|(_, page)| page.dirty && page.pins == 0)
+            .take(excess)
+            .map(|(key, page)| (page.file.clone(), key.1))
+            .collect()
+    }
+}
+
+/// Brings the page cache down to the limit. The dirty pages are written back
+/// first, without the page cache locked.
+fn shrink() {
+    let dirty = {
+        let mut cache = PAGE_CACHE.lock();
+        cache.evict_clean();
+        cache.oldest_dirty()
+    };
+    if dirty.is_empty() {
+        return;
+    }
+    for (file, index) in dirty {
+        if let Err(e) = write_back(&file, index) {
+            warn!("failed to write back the evicted page: {:?}", e);
+        }
+    }
+    PAGE_CACHE.lock().evict_clean();
+}
+
+/// Runs `f` on the page of `file` at `index` with the page cache locked, and
+/// reads the page from the node first if it is not cached.
+fn with_page<T>(
+    file: &Arc<FileState>,
+    index: u64,
+    f: impl FnOnce(&mut CachedPage) -> T,
+) -> VfsResult<T> {
+    let key = (file.id(), index);
+    loop {
+        let generation = {
+            let mut cache = PAGE_CACHE.lock();
+            if let Some(page) = cache.touch(key) {
+                return Ok(f(page));
+            }
+            file.generation()
+        };
+        let mut data = Box::new(PageData([0; PAGE_SIZE]));
+        read_page(&file.node, index, &mut data)?;
+
+        let mut cache = PAGE_CACHE.lock();
+        // Loaded by others meanwhile.
+        if let Some(page) = cache.touch(key) {
+            return Ok(f(page));
+        }
+        // The node has been written meanwhile, the page may be stale.
+        if file.generation() != generation {
+            continue;
+        }
+        cache.insert(key, file, data);
+        let res = f(cache.pages.get_mut(&key).unwrap());
+        drop(cache);
+        shrink();
+        return Ok(res);
+    }
+}
+
+/// Writes back the dirty pages of the file.
+fn flush(file: &FileState) -> VfsResult {
+    let dirty = PAGE_CACHE
+        .lock()
+        .keys_of(file.id())
+        .filter(|(_, page)| page.dirty)
+        .map(|(key, _)| key.1)
+        .collect::<Vec<_>>();
+    for index in dirty {
+        write_back(file, index)?;
+    }
+    Ok(())
+}
+
+/// A regular file whose content is accessed through the page cache.
+pub(crate) struct CachedFile {
+    file: Arc<FileState>,
+    /// Keeps the filesystem alive, whose address identifies it in
+    /// [`CACHED_FILES`].
+    _fs: Option<Arc<dyn VfsOps>>,
+}
+
+impl CachedFile {
+    fn id(&self) -> usize {
+        self.file.id()
+    }
+
+    fn inner(&self) -> &VfsNodeRef {
+        &self.file.node
+    }
+}
+
+impl VfsNodeOps for CachedFile {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn open(&self) -> VfsResult {
+        self.inner().open()
+    }
+
+    fn release(&self) -> VfsResult {
+        self.inner().release()
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.inner().get_attr()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let size = self.inner().get_attr()?.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let mut pos = 0;
+        while pos < len {
+            let off = offset + pos as u64;
+            let page_off = off as usize % PAGE_SIZE;
+            let n = (PAGE_SIZE - page_off).min(len - pos);
+            with_page(&self.file, off / PAGE_SIZE as u64, |page| {
+                buf[pos..pos + n].copy_from_slice(&page.data.0[page_off..page_off + n]);
+            })?;
+            pos += n;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let size = self.inner().get_attr()?.size();
+        let extend = offset + buf.len() as u64 > size;
+        // Write through to extend the file, and update the cached pages.
+        let _writes = extend.then(|| self.file.writes.lock());
+        let len = if extend {
+            self.inner().write_at(offset, buf)?
+        } else {
+            buf.len()
+        };
+        let mut cache = extend.then(|| PAGE_CACHE.lock());
+        let mut pos = 0;
+        while pos < len {
+            let off = offset + pos as u64;
+            let page_off = off as usize % PAGE_SIZE;
+            let n = (PAGE_SIZE - page_off).min(len - pos);
+            let index = off / PAGE_SIZE as u64;
+            let copy = |page: &mut CachedPage| {
+                page.data.0[page_off..page_off + n].copy_from_slice(&buf[pos..pos + n]);
+                page.dirty |= !extend;
+            };
+            match &mut cache {
+                Some(cache) => cache.pages.get_mut(&(self.id(), index)).map_or((), copy),
+                None => with_page(&self.file, index, copy)?,
+            }
+            pos += n;
+        }
+        if cache.is_some() {
+            self.file.bump_generation();
+        }
+        Ok(len)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        flush(&self.file)?;
+        match self.inner().fsync() {
+            // Not implemented by the filesystems without their own buffers.
+            Err(VfsError::InvalidInput) => Ok(()),
+            res => res,
+        }
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        let _writes = self.file.writes.lock();
+        self.inner().truncate(size)?;
+        // Drop the pages beyond the end, or clear them if they are mapped.
+        let mut cache = PAGE_CACHE.lock();
+        let first = size.div_ceil(PAGE_SIZE as u64);
+        let (id, mut dropped) = (self.id(), Vec::new());
+        for (key, page) in cache
+            .pages
+            .range_mut((id, size / PAGE_SIZE as u64)..=(id, u64::MAX))
+        {
+            if key.1 < first {
+                page.data.0[size as usize % PAGE_SIZE..].fill(0);
+            } else if page.pins == 0 {
+                dropped.push(*key);
+            } else {
+                page.data.0.fill(0);
+            }
+        }
+        for key in dropped {
+            cache.remove(key);
+        }
+        self.file.bump_generation();
+        Ok(())
+    }
+
+    fn pin_page(&self, index: u64) -> VfsResult<usize> {
+        with_page(&self.file, index, |page| {
+            page.pins += 1;
+            page.data.0.as_ptr() as usize
+        })
+    }
+
+    fn dirty_page(&self, index: u64) -> VfsResult {
+        match PAGE_CACHE.lock().pages.get_mut(&(self.id(), index)) {
+            Some(page) if page.pins > 0 => {
+                page.dirty = true;
+                Ok(())
+            }
+            _ => Err(VfsError::InvalidInput),
+        }
+    }
+
+    fn unpin_page(&self, index: u64) -> VfsResult {
+        match PAGE_CACHE.lock().pages.get_mut(&(self.id(), index)) {
+            Some(page) if page.pins > 0 => page.pins -= 1,
+            _ => return Err(VfsError::InvalidInput),
+        }
+        shrink();
+        Ok(())
+    }
+}
+
+impl Drop for CachedFile {
+    fn drop(&mut self) {
+        // The pages are not kept after the file is closed and unmapped, and
+        // the node is dropped with them.
+        if let Err(e) = flush(&self.file) {
+            warn!("failed to write back the cached pages: {:?}", e);
+        }
+        let mut cache = PAGE_CACHE.lock();
+        let keys = cache
+            .keys_of(self.id())
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in keys {
+            cache.remove(key);
+        }
+    }
+}
+
+/// Wraps the regular file `node` to access it through the page cache.
+///
+/// If the file in `fs` has a known inode number and is already opened, the
+/// same cached file is returned.
+pub(crate) fn cached_file(fs: Arc<dyn VfsOps>, node: VfsNodeRef) -> VfsResult<VfsNodeRef> {
+    let ino = node.get_attr()?.ino();
+    let new_file = |fs| {
+        let file = FileState {
+            node,
+            generation: AtomicU64::new(0),
+            writes: Mutex::new(()),
+        };
+        Arc::new(CachedFile {
+            file: Arc::new(file),
+            _fs: fs,
+        })
+    };
+    if ino == 0 {
+        return Ok(new_file(None));
+    }
+    let key = (Arc::as_ptr(&fs) as *const u8 as usize, ino);
+    let mut files = CACHED_FILES.lock();
+    if let Some(file) = files.get(&key).and_then(Weak::upgrade) {
+        return Ok(file);
+    }
+    files.retain(|_, file| file.strong_count() > 0);
+    let file = new_file(Some(fs));
+    files.insert(key, Arc::downgrade(&file));
+    Ok(file)
+}
+
+/// Writes back all the dirty pages in the page cache.
+pub(crate) fn sync_all() -> VfsResult {
+    let dirty = PAGE_CACHE
+        .lock()
+        .pages
+        .iter()
+        .filter(|(_, page)| page.dirty)
+        .map(|(key, page)| (page.file.clone(), key.1))
+        .collect::<Vec<_>>();
+    for (file, index) in dirty {
+        write_back(&file, index)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! MBR and GPT partition tables.
+//!
+//! Logical partitions in the extended partition of MBR are numbered from 5,
+//! and GPT partitions by their index in the entry array from 1, as Linux does.
+//! The extended partition itself is not listed, and only the primary GPT
+//! header is read.
+
+use alloc::{vec, vec::Vec};
+use axdriver::prelude::*;
+
+use crate::dev::Disk;
+
+const SECTOR_SIZE: usize = 512;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// The maximum number of logical partitions, in case of loops in the EBR chain.
+const MAX_LOGICAL_PARTITIONS: u32 = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+/// The maximum size of the partition entry array to read.
+const GPT_ENTRIES_MAX_SIZE: usize = 1024 * 1024;
+
+/// A partition on a disk, in sectors.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Partition {
+    /// The partition number, as the suffix of the device name.
+    pub number: u32,
+    pub start: u64,
+    pub num_sectors: u64,
+}
+
+struct MbrEntry {
+    boot: u8,
+    ty: u8,
+    start: u64,
+    num_sectors: u64,
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+/// CRC32 (IEEE), for the GPT header and partition entries.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn read_sectors(disk: &mut Disk, lba: u64, buf: &mut [u8]) -> DevResult {
+    disk.set_position(lba * SECTOR_SIZE as u64);
+    for chunk in buf.chunks_mut(SECTOR_SIZE) {
+        disk.read_one(chunk)?;
+    }
+    Ok(())
+}
+
+/// Reads the partition entries of an MBR (or EBR) sector, or `None` if it
+/// does not look like one.
+fn mbr_entries(sector: &[u8]) -> Option<[MbrEntry; 4]> {
+    if sector[510..512] != MBR_SIGNATURE {
+        return None;
+    }
+    let entries = core::array::from_fn(|i| {
+        let entry = &sector[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        MbrEntry {
+            boot: entry[0],
+            ty: entry[4],
+            start: le32(entry, 8) as u64,
+            num_sectors: le32(entry, 12) as u64,
+        }
+    });
+    // FAT boot sectors also have the signature, but rarely valid boot flags
+    if entries.iter().any(|e| e.boot != 0 && e.boot != 0x80) {
+        return None;
+    }
+    Some(entries)
+}
+
+impl MbrEntry {
+    fn is_used(&self) -> bool {
+        self.ty != 0 && self.num_sectors != 0
+    }
+
+    /// Whether the entry is in `base..base + len`, with `start` relative to
+    /// `base`.
+    fn fits(&self, base: u64, len: u64) -> bool {
+        self.start > 0 && self.start + self.num_sectors <= len.saturating_sub(base)
+    }
+}
+
+/// Walks the EBR chain of the extended partition at `ext_start`.
+fn scan_logical(disk: &mut Disk, ext_start: u64, ext_len: u64, parts: &mut Vec<Partition>) {
+    let mut sector = [0; SECTOR_SIZE];
+    let mut ebr = 0;
+    let mut number = 5;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        if read_sectors(disk, ext_start + ebr, &mut sector).is_err() {
+            warn!("failed to read EBR at sector {}", ext_start + ebr);
+            return;
+        }
+        let Some([logical, next, ..]) = mbr_entries(&sector) else {
+            return;
+        };
+        if logical.is_used() && logical.fits(ebr, ext_len) {
+            parts.push(Partition {
+                number,
+                start: ext_start + ebr + logical.start,
+                num_sectors: logical.num_sectors,
+            });
+            number += 1;
+        }
+        if !next.is_used() || !next.fits(0, ext_len) {
+            return;
+        }
+        ebr = next.start;
+    }
+}
+
+fn scan_gpt(disk: &mut Disk, num_sectors: u64) -> Option<Vec<Partition>> {
+    let mut header = [0; SECTOR_SIZE];
+    read_sectors(disk, 1, &mut header).ok()?;
+    let header_size = le32(&header, 12) as usize;
+    if &header[..8] != GPT_SIGNATURE || !(92..=SECTOR_SIZE).contains(&header_size) {
+        return None;
+    }
+    let crc = le32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != crc {
+        warn!("bad GPT header checksum");
+        return None;
+    }
+
+    let entries_lba = le64(&header, 72);
+    let num_entries = le32(&header, 80) as usize;
+    let entry_size = le32(&header, 84) as usize;
+    let entries_size = num_entries.checked_mul(entry_size)?;
+    if entry_size < GPT_ENTRY_MIN_SIZE || entry_size % 8 != 0 || entries_size > GPT_ENTRIES_MAX_SIZE
+    {
+        warn!("unsupported GPT partition entries");
+        return None;
+    }
+    let mut entries = vec![0; entries_size.next_multiple_of(SECTOR_SIZE)];
+    read_sectors(disk, entries_lba, &mut entries).ok()?;
+    if crc32(&entries[..entries_size]) != le32(&header, 88) {
+        warn!("bad GPT partition entries checksum");
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    for (i, entry) in entries[..entries_size].chunks(entry_size).enumerate() {
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue; // unused
+        }
+        let (first, last) = (le64(entry, 32), le64(entry, 40));
+        if first == 0 || last < first || last >= num_sectors {
+            warn!("invalid GPT partition {}: {}..={}", i + 1, first, last);
+            continue;
+        }
+        parts.push(Partition {
+            number: i as u32 + 1,
+            start: first,
+            num_sectors: last - first + 1,
+        });
+    }
+    Some(parts)
+}
+
+/// Reads the partition table on the disk, which is empty if there is none.
+pub(crate) fn scan(disk: &mut Disk) -> Vec<Partition> {
+    let num_sectors = disk.size() / SECTOR_SIZE as u64;
+    let mut mbr = [0; SECTOR_SIZE];
+    if read_sectors(disk, 0, &mut mbr).is_err() {
+        warn!("failed to read MBR");
+        return Vec::new();
+    }
+    let Some(entries) = mbr_entries(&mbr) else {
+        return Vec::new();
+    };
+    if entries.iter().any(|e| e.ty == MBR_TYPE_GPT_PROTECTIVE) {
+        return scan_gpt(disk, num_sectors).unwrap_or_default();
+    }
+
+    // a valid MBR has at least one partition, and all of them on the disk
+    let used = || entries.iter().filter(|e| e.is_used());
+    if used().count() == 0 || used().any(|e| !e.fits(0, num_sectors)) {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if !entry.is_used() {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&entry.ty) {
+            scan_logical(disk, entry.start, entry.num_sectors, &mut parts);
+        } else {
+            parts.push(Partition {
+                number: i as u32 + 1,
+                start: entry.start,
+                num_sectors: entry.num_sectors,
+            });
+        }
+    }
+    parts
+}
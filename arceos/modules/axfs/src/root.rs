@@ -1,33 +1,59 @@
 //! Root directory of the filesystem
-//!
-//! TODO: it doesn't work very well if the mount points have containment relationships.
 
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{borrow::Cow, format, string::String, sync::Arc, vec, vec::Vec};
 use axerrno::{ax_err, AxError, AxResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
 use axsync::Mutex;
 use lazyinit::LazyInit;
 
+use crate::dev::{self, Disk};
+use crate::fops::{MountFlags, MountInfo};
 use crate::{api::FileType, fs, mounts};
 
+/// The root device or partition, e.g. `/dev/vda2`. If it is not set, the
+/// first one with a supported filesystem is used.
+const ROOT_DEVICE: Option<&str> = option_env!("AX_ROOT");
+
+/// The maximum number of symlinks to follow in a path, as Linux does.
+const MAX_SYMLINKS: usize = 40;
+
 static CURRENT_DIR_PATH: Mutex<String> = Mutex::new(String::new());
-static CURRENT_DIR: LazyInit<Mutex<VfsNodeRef>> = LazyInit::new();
 
-struct MountPoint {
-    path: &'static str,
+/// A filesystem mounted on a directory.
+///
+/// Opened files and directories hold a reference to the mount point they
+/// belong to, so that it cannot be unmounted while it is busy.
+pub(crate) struct MountPoint {
+    path: String,
+    source: String,
+    fstype: String,
+    flags: MountFlags,
     fs: Arc<dyn VfsOps>,
 }
 
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
-    mounts: Vec<MountPoint>,
+    main_source: String,
+    main_fstype: &'static str,
+    mounts: Mutex<Vec<Arc<MountPoint>>>,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    fn info(&self) -> MountInfo {
+        MountInfo {
+            source: self.source.clone(),
+            target: self.path.clone(),
+            fstype: self.fstype.clone(),
+            flags: self.flags,
+        }
+    }
+
+    /// Whether `path` (absolute and canonical) is the mount point or under it.
+    fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(self.path.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
     }
 }
 
@@ -38,91 +64,141 @@ impl Drop for MountPoint {
 }
 
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub const fn new(
+        main_fs: Arc<dyn VfsOps>,
+        main_source: String,
+        main_fstype: &'static str,
+    ) -> Self {
         Self {
             main_fs,
-            mounts: Vec::new(),
+            main_source,
+            main_fstype,
+            mounts: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn mount(&mut self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    /// Mounts `fs` on the directory `path`, which must be absolute and
+    /// canonical.
+    pub fn mount(
+        self: &Arc<Self>,
+        path: &str,
+        source: &str,
+        fstype: &str,
+        fs: Arc<dyn VfsOps>,
+        flags: MountFlags,
+    ) -> AxResult {
         if path == "/" {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
         if !path.starts_with('/') {
             return ax_err!(InvalidInput, "mount path must start with '/'");
         }
-        if self.mounts.iter().any(|mp| mp.path == path) {
-            return ax_err!(InvalidInput, "mount point already exists");
+        // the mount point is looked up in the filesystem containing it
+        let mount_point = self.clone().lookup(path)?;
+        if !mount_point.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory);
         }
-        // create the mount point in the main filesystem if it does not exist
-        self.main_fs.root_dir().create(path, FileType::Dir)?;
-        fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.push(MountPoint::new(path, fs));
+
+        let mut mounts = self.mounts.lock();
+        if mounts.iter().any(|mp| mp.path == path) {
+            return ax_err!(ResourceBusy, "mount point already exists");
+        }
+        fs.mount(path, mount_point)?;
+        mounts.push(Arc::new(MountPoint {
+            path: path.into(),
+            source: source.into(),
+            fstype: fstype.into(),
+            flags,
+            fs,
+        }));
         Ok(())
     }
 
-    pub fn _umount(&mut self, path: &str) {
-        self.mounts.retain(|mp| mp.path != path);
+    /// Unmounts the filesystem mounted on `path`, which must be absolute and
+    /// canonical.
+    ///
+    /// Fails with [`AxError::ResourceBusy`] if there are opened or mapped
+    /// files or other mount points in it, or it contains the current directory.
+    pub fn umount(&self, path: &str) -> AxResult {
+        let mut mounts = self.mounts.lock();
+        let Some(idx) = mounts.iter().position(|mp| mp.path == path) else {
+            return ax_err!(InvalidInput, "not a mount point");
+        };
+        let mp = &mounts[idx];
+        if Arc::strong_count(mp) > 1
+            || mounts.iter().any(|m| m.path != path && mp.covers(&m.path))
+            || mp.covers(CURRENT_DIR_PATH.lock().trim_end_matches('/'))
+        {
+            return ax_err!(ResourceBusy);
+        }
+        mounts.remove(idx);
+        Ok(())
     }
 
     pub fn contains(&self, path: &str) -> bool {
-        self.mounts.iter().any(|mp| mp.path == path)
+        self.mounts.lock().iter().any(|mp| mp.path == path)
     }
 
-    /// Find which filesystem a path belongs to and extract the rest path.
-    /// Returns (filesystem_index, rest_path).
-    fn find_fs_for_path<'a>(&self, path: &'a str) -> (usize, &'a str) {
-        let path = if let Some(rest) = path.strip_prefix("./") {
-            rest
-        } else {
-            path
+    fn mount_infos(&self) -> Vec<MountInfo> {
+        let root = MountInfo {
+            source: self.main_source.clone(),
+            target: "/".into(),
+            fstype: self.main_fstype.into(),
+            flags: MountFlags::empty(),
         };
+        let mounts = self.mounts.lock();
+        core::iter::once(root)
+            .chain(mounts.iter().map(|mp| mp.info()))
+            .collect()
+    }
 
-        let mut idx = usize::MAX;
-        let mut max_len = 0;
-
-        for (i, mp) in self.mounts.iter().enumerate() {
-            if path.starts_with(&mp.path[1..]) && mp.path.len() - 1 > max_len {
-                max_len = mp.path.len() - 1;
-                idx = i;
+    /// Finds the mount point that `path` belongs to, and returns it with the
+    /// rest path in its filesystem. `None` means the main filesystem.
+    ///
+    /// The path components are matched with the mount points until the first
+    /// `..`, which is left to the filesystems to resolve.
+    fn find_mount<'a>(&self, path: &'a str) -> (Option<Arc<MountPoint>>, &'a str) {
+        let mounts = self.mounts.lock();
+        let mut found = None;
+        let mut prefix = String::new();
+        let mut rest = path;
+        let mut found_rest = path;
+        loop {
+            let trimmed = rest.trim_start_matches('/');
+            let (name, next) = trimmed.split_at(trimmed.find('/').unwrap_or(trimmed.len()));
+            match name {
+                "" | ".." => break,
+                "." => {}
+                _ => {
+                    prefix.push('/');
+                    prefix.push_str(name);
+                    if let Some(mp) = mounts.iter().find(|mp| mp.path == prefix) {
+                        found = Some(mp.clone());
+                        found_rest = next;
+                    }
+                }
             }
+            rest = next;
         }
-
-        if max_len == 0 {
-            (usize::MAX, path)
+        if found.is_none() {
+            let mut path = path.trim_matches('/');
+            while let Some(rest) = path.strip_prefix("./") {
+                path = rest.trim_start_matches('/');
+            }
+            (None, path)
         } else {
-            (idx, &path[max_len..])
+            (found, found_rest.trim_matches('/'))
         }
     }
 
     fn lookup_mounted_fs<F, T>(&self, path: &str, f: F) -> AxResult<T>
     where
-        F: FnOnce(Arc<dyn VfsOps>, &str) -> AxResult<T>,
+        F: FnOnce(Arc<dyn VfsOps>, Option<&MountPoint>, &str) -> AxResult<T>,
     {
         debug!("lookup at root: {}", path);
-        let path = path.trim_matches('/');
-        if let Some(rest) = path.strip_prefix("./") {
-            return self.lookup_mounted_fs(rest, f);
-        }
-
-        let mut idx = 0;
-        let mut max_len = 0;
-
-        // Find the filesystem that has the longest mounted path match
-        // TODO: more efficient, e.g. trie
-        for (i, mp) in self.mounts.iter().enumerate() {
-            // skip the first '/'
-            if path.starts_with(&mp.path[1..]) && mp.path.len() - 1 > max_len {
-                max_len = mp.path.len() - 1;
-                idx = i;
-            }
-        }
-
-        if max_len == 0 {
-            f(self.main_fs.clone(), path) // not matched any mount point
-        } else {
-            f(self.mounts[idx].fs.clone(), &path[max_len..]) // matched at `idx`
+        match self.find_mount(path) {
+            (Some(mp), rest_path) => f(mp.fs.clone(), Some(mp.as_ref()), rest_path),
+            (None, rest_path) => f(self.main_fs.clone(), None, rest_path),
         }
     }
 }
@@ -135,37 +211,63 @@ impl VfsNodeOps for RootDirectory {
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
-        self.lookup_mounted_fs(path, |fs, rest_path| fs.root_dir().lookup(rest_path))
+        self.lookup_mounted_fs(path, |fs, _, rest_path| fs.root_dir().lookup(rest_path))
     }
 
     fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
-        self.lookup_mounted_fs(path, |fs, rest_path| {
+        self.lookup_mounted_fs(path, |fs, mp, rest_path| {
             if rest_path.is_empty() {
                 Ok(()) // already exists
             } else {
+                check_writable(mp)?;
                 fs.root_dir().create(rest_path, ty)
             }
         })
     }
 
     fn remove(&self, path: &str) -> VfsResult {
-        self.lookup_mounted_fs(path, |fs, rest_path| {
+        self.lookup_mounted_fs(path, |fs, mp, rest_path| {
             if rest_path.is_empty() {
                 ax_err!(PermissionDenied) // cannot remove mount points
             } else {
+                check_writable(mp)?;
                 fs.root_dir().remove(rest_path)
             }
         })
     }
 
-    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
-        let src_path_trimmed = src_path.trim_matches('/');
-        let dst_path_trimmed = dst_path.trim_matches('/');
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        self.lookup_mounted_fs(path, |fs, mp, rest_path| {
+            if rest_path.is_empty() {
+                ax_err!(AlreadyExists)
+            } else {
+                check_writable(mp)?;
+                fs.root_dir().symlink(rest_path, target)
+            }
+        })
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        self.lookup_mounted_fs(path, |fs, mp, rest_path| {
+            if rest_path.is_empty() {
+                ax_err!(AlreadyExists)
+            } else {
+                check_writable(mp)?;
+                fs.root_dir().link(rest_path, node)
+            }
+        })
+    }
 
-        let (src_fs_idx, src_rest) = self.find_fs_for_path(src_path_trimmed);
-        let (dst_fs_idx, dst_rest) = self.find_fs_for_path(dst_path_trimmed);
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        let (src_mp, src_rest) = self.find_mount(src_path);
+        let (dst_mp, dst_rest) = self.find_mount(dst_path);
 
-        if src_fs_idx != dst_fs_idx {
+        let same_fs = match (&src_mp, &dst_mp) {
+            (Some(src), Some(dst)) => Arc::ptr_eq(src, dst),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same_fs {
             return ax_err!(Unsupported);
         }
 
@@ -173,61 +275,301 @@ impl VfsNodeOps for RootDirectory {
             return ax_err!(PermissionDenied);
         }
 
-        if src_fs_idx == usize::MAX {
-            self.main_fs.root_dir().rename(src_rest, dst_rest)
-        } else {
-            self.mounts[src_fs_idx].fs.root_dir().rename(src_rest, dst_rest)
+        match src_mp {
+            Some(mp) => {
+                check_writable(Some(mp.as_ref()))?;
+                mp.fs.root_dir().rename(src_rest, dst_rest)
+            }
+            None => self.main_fs.root_dir().rename(src_rest, dst_rest),
         }
     }
 }
 
-pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
+/// Returns an error if the mount point is read-only.
+fn check_writable(mp: Option<&MountPoint>) -> AxResult {
+    match mp {
+        Some(mp) if mp.flags.contains(MountFlags::READ_ONLY) => ax_err!(ReadOnlyFilesystem),
+        _ => Ok(()),
+    }
+}
+
+/// Mounts `fs` at boot, creating the mount point if it does not exist.
+fn mount_at_boot(path: &str, fstype: &str, fs: Arc<dyn VfsOps>) -> AxResult {
+    ROOT_DIR.create(path, FileType::Dir)?;
+    ROOT_DIR.mount(path, fstype, fstype, fs, MountFlags::empty())
+}
+
+/// Returns the type of the filesystem on the disk, if it is supported.
+#[cfg(not(feature = "myfs"))]
+fn probe_fstype(disk: &mut Disk) -> Option<&'static str> {
+    #[cfg(feature = "ext4fs")]
+    if fs::ext4fs::Ext4FileSystem::probe(disk) {
+        return Some("ext4");
+    }
+    #[cfg(feature = "fatfs")]
+    if fs::fatfs::FatFileSystem::probe(disk) {
+        return Some("vfat");
+    }
+    None
+}
+
+/// Chooses the root device by [`ROOT_DEVICE`], or the first one with a
+/// supported filesystem, and opens it.
+pub(crate) fn open_root_disk() -> Disk {
+    let volumes = dev::volumes();
+    assert!(!volumes.is_empty(), "No block device found!");
+    let mut root = volumes[0].clone();
+    #[cfg(not(feature = "myfs"))]
+    {
+        let mut found = false;
+        for dev in volumes.iter() {
+            let mut disk = Disk::open(dev).expect("failed to open block device");
+            let fstype = probe_fstype(&mut disk);
+            info!(
+                "  /dev/{}: {} KiB, {}",
+                dev.name(),
+                dev.size() / 1024,
+                fstype.unwrap_or("unknown filesystem")
+            );
+            if fstype.is_some() && !found {
+                root = dev.clone();
+                found = true;
+            }
+        }
+    }
+    if let Some(path) = ROOT_DEVICE.filter(|path| !path.is_empty()) {
+        root =
+            dev::find_device(path).unwrap_or_else(|_| panic!("root device {:?} not found", path));
+    }
+    info!("  use /dev/{} as root", root.name());
+    Disk::open(&root).expect("failed to open root device")
+}
+
+/// Creates the main filesystem, detected by the superblock on the disk.
+#[cfg(not(feature = "myfs"))]
+fn detect_main_fs(disk: Disk) -> (Arc<dyn VfsOps>, &'static str) {
+    #[cfg(feature = "ext4fs")]
+    let disk = {
+        let mut disk = disk;
+        if fs::ext4fs::Ext4FileSystem::probe(&mut disk) {
+            let ext4_fs = fs::ext4fs::Ext4FileSystem::new(disk)
+                .expect("failed to initialize ext4 filesystem");
+            return (Arc::new(ext4_fs), "ext4");
+        }
+        disk
+    };
+
+    #[cfg(feature = "fatfs")]
+    {
+        static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
+        FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
+        FAT_FS.init();
+        (FAT_FS.clone(), "vfat")
+    }
+    #[cfg(not(feature = "fatfs"))]
+    panic!("no supported filesystem found on the disk")
+}
+
+pub(crate) fn init_rootfs(disk: Disk) {
+    let main_source = format!("/dev/{}", disk.device_name());
     cfg_if::cfg_if! {
         if #[cfg(feature = "myfs")] { // override the default filesystem
             let main_fs = fs::myfs::new_myfs(disk);
-        } else if #[cfg(feature = "fatfs")] {
-            static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
-            FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
-            FAT_FS.init();
-            let main_fs = FAT_FS.clone();
+            let main_fstype = "myfs";
+        } else {
+            let (main_fs, main_fstype) = detect_main_fs(disk);
         }
     }
 
-    let mut root_dir = RootDirectory::new(main_fs);
+    ROOT_DIR.init_once(Arc::new(RootDirectory::new(
+        main_fs,
+        main_source,
+        main_fstype,
+    )));
+    *CURRENT_DIR_PATH.lock() = "/".into();
 
     #[cfg(feature = "devfs")]
-    root_dir
-        .mount("/dev", mounts::devfs())
-        .expect("failed to mount devfs at /dev");
+    mount_at_boot("/dev", "devfs", mounts::devfs()).expect("failed to mount devfs at /dev");
 
     #[cfg(feature = "ramfs")]
-    root_dir
-        .mount("/tmp", mounts::ramfs())
-        .expect("failed to mount ramfs at /tmp");
+    mount_at_boot("/tmp", "ramfs", mounts::ramfs()).expect("failed to mount ramfs at /tmp");
 
     // Mount another ramfs as procfs
     #[cfg(feature = "procfs")]
-    root_dir // should not fail
-        .mount("/proc", mounts::procfs().unwrap())
+    mount_at_boot("/proc", "proc", mounts::procfs().unwrap()) // should not fail
         .expect("fail to mount procfs at /proc");
 
     // Mount another ramfs as sysfs
     #[cfg(feature = "sysfs")]
-    root_dir // should not fail
-        .mount("/sys", mounts::sysfs().unwrap())
+    mount_at_boot("/sys", "sysfs", mounts::sysfs().unwrap()) // should not fail
         .expect("fail to mount sysfs at /sys");
+}
 
-    ROOT_DIR.init_once(Arc::new(root_dir));
-    CURRENT_DIR.init_once(Mutex::new(ROOT_DIR.clone()));
-    *CURRENT_DIR_PATH.lock() = "/".into();
+/// Returns the directory to resolve `path` from, and the path relative to it.
+///
+/// Paths not relative to `dir` are resolved from the root directory, so that
+/// they can cross mount points.
+fn parent_node_of<'a>(dir: Option<&VfsNodeRef>, path: &'a str) -> (VfsNodeRef, Cow<'a, str>) {
+    match dir {
+        _ if path.starts_with('/') => (ROOT_DIR.clone(), Cow::Borrowed(path)),
+        Some(dir) => (dir.clone(), Cow::Borrowed(path)),
+        None => (
+            ROOT_DIR.clone(),
+            Cow::Owned(CURRENT_DIR_PATH.lock().clone() + path),
+        ),
+    }
 }
 
-fn parent_node_of(dir: Option<&VfsNodeRef>, path: &str) -> VfsNodeRef {
-    if path.starts_with('/') {
-        ROOT_DIR.clone()
-    } else {
-        dir.cloned().unwrap_or_else(|| CURRENT_DIR.lock().clone())
+/// A path with the symlinks in it resolved.
+struct Resolved {
+    /// The directory to look up the path from.
+    dir: VfsNodeRef,
+    /// The path relative to `dir`, or absolute if `dir` is the root directory.
+    path: String,
+    /// The node of the path, if it has been looked up.
+    node: Option<VfsNodeRef>,
+}
+
+impl Resolved {
+    fn node(&self) -> AxResult<VfsNodeRef> {
+        match &self.node {
+            Some(node) => Ok(node.clone()),
+            None => self.dir.clone().lookup(&self.path),
+        }
+    }
+
+    /// The canonical absolute path, if it is resolved from the root.
+    fn absolute_path(&self) -> Option<String> {
+        self.path
+            .starts_with('/')
+            .then(|| axfs_vfs::path::canonicalize(&self.path))
+    }
+
+    /// The mount point that the path belongs to, `None` for the main
+    /// filesystem or unknown.
+    fn mount(&self) -> Option<Arc<MountPoint>> {
+        ROOT_DIR.find_mount(&self.absolute_path()?).0
+    }
+}
+
+/// Reads the target of the symlink `node`.
+fn read_symlink(node: &VfsNodeRef) -> AxResult<String> {
+    let attr = node.get_attr()?;
+    if !attr.is_symlink() {
+        return ax_err!(InvalidInput, "not a symlink");
     }
+    let mut buf = vec![0; attr.size() as usize];
+    let len = node.read_at(0, &mut buf)?;
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|_| AxError::InvalidData)
+}
+
+/// Resolves the symlinks in `path` relative to `dir`, and the one at the last
+/// component if `follow` or the path ends with `/`.
+///
+/// The last component does not have to exist, so that it can be created. The
+/// `..` after a resolved symlink goes to the parent of its target, and fails
+/// with [`AxError::FilesystemLoop`] if there are too many symlinks.
+fn resolve(dir: Option<&VfsNodeRef>, path: &str, follow: bool) -> AxResult<Resolved> {
+    let (mut dir, path) = parent_node_of(dir, path);
+    let dir_only = path.ends_with('/');
+    let follow = follow || dir_only;
+    // Filesystems fail with `NotADirectory` on symlinks in the middle, so a
+    // path without symlinks is looked up at once.
+    match dir.clone().lookup(&path) {
+        Ok(node) if !follow || !node.get_attr()?.is_symlink() => {
+            if dir_only && !node.get_attr()?.is_dir() {
+                return ax_err!(NotADirectory);
+            }
+            return Ok(Resolved {
+                dir,
+                path: path.into_owned(),
+                node: Some(node),
+            });
+        }
+        Ok(_) | Err(AxError::NotADirectory) => {}
+        Err(AxError::NotFound) => {
+            return Ok(Resolved {
+                dir,
+                path: path.into_owned(),
+                node: None,
+            });
+        }
+        Err(e) => return Err(e),
+    }
+
+    // Walk it component by component. The components resolved so far have no
+    // symlinks, so `..` just removes the last one.
+    let mut from_root = path.starts_with('/');
+    let components = |path: &str| -> Vec<String> {
+        path.split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .rev()
+            .map(String::from)
+            .collect()
+    };
+    let mut rest = components(&path);
+    let mut resolved: Vec<String> = Vec::new();
+    let mut node = None;
+    let mut links = 0;
+    let joined = |from_root: bool, resolved: &[String]| {
+        let path = resolved.join("/");
+        if from_root {
+            format!("/{}", path)
+        } else {
+            path
+        }
+    };
+    while let Some(name) = rest.pop() {
+        node = None;
+        if name == ".." && resolved.last().is_some_and(|last| last != "..") {
+            resolved.pop();
+            continue;
+        } else if name == ".." && from_root {
+            continue; // `..` of the root
+        }
+        resolved.push(name);
+        let is_last = rest.is_empty();
+        if is_last && !follow {
+            break;
+        }
+        let found = match dir.clone().lookup(&joined(from_root, &resolved)) {
+            Ok(found) => found,
+            Err(AxError::NotFound) if is_last => break,
+            Err(e) => return Err(e),
+        };
+        let attr = found.get_attr()?;
+        if !attr.is_symlink() {
+            if !is_last && !attr.is_dir() {
+                return ax_err!(NotADirectory);
+            }
+            node = Some(found);
+            continue;
+        }
+
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return ax_err!(FilesystemLoop);
+        }
+        let target = read_symlink(&found)?;
+        resolved.pop();
+        if target.starts_with('/') {
+            dir = ROOT_DIR.clone();
+            from_root = true;
+            resolved.clear();
+        }
+        rest.extend(components(&target));
+    }
+    if let Some(node) = node.as_ref().filter(|_| dir_only) {
+        if !node.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+    }
+    Ok(Resolved {
+        dir,
+        path: joined(from_root, &resolved),
+        node,
+    })
 }
 
 pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
@@ -239,16 +581,38 @@ pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
     }
 }
 
-pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
+/// Returns the mount point that `path` (relative to the current directory)
+/// belongs to, or `None` for the main filesystem.
+pub(crate) fn mount_of(path: &str) -> Option<Arc<MountPoint>> {
+    resolve(None, path, true).ok()?.mount()
+}
+
+/// Returns the filesystem of the mount point, or the main filesystem for
+/// `None`.
+pub(crate) fn fs_of(mount: Option<&Arc<MountPoint>>) -> Arc<dyn VfsOps> {
+    mount.map_or_else(|| ROOT_DIR.main_fs.clone(), |mp| mp.fs.clone())
+}
+
+/// Returns [`AxError::ReadOnlyFilesystem`] if the mount point is read-only.
+pub(crate) fn check_mount_writable(mount: Option<&Arc<MountPoint>>) -> AxResult {
+    check_writable(mount.map(Arc::as_ref))
+}
+
+fn lookup_at(dir: Option<&VfsNodeRef>, path: &str, follow: bool) -> AxResult<VfsNodeRef> {
     if path.is_empty() {
         return ax_err!(NotFound);
     }
-    let node = parent_node_of(dir, path).lookup(path)?;
-    if path.ends_with('/') && !node.get_attr()?.is_dir() {
-        ax_err!(NotADirectory)
-    } else {
-        Ok(node)
-    }
+    resolve(dir, path, follow)?.node()
+}
+
+pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
+    lookup_at(dir, path, true)
+}
+
+/// Looks up `path` like [`lookup`], but returns the symlink itself if the
+/// last component is one.
+pub(crate) fn lookup_nofollow(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
+    lookup_at(dir, path, false)
 }
 
 pub(crate) fn create_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
@@ -257,28 +621,34 @@ pub(crate) fn create_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsN
     } else if path.ends_with('/') {
         return ax_err!(NotADirectory);
     }
-    let parent = parent_node_of(dir, path);
-    parent.create(path, VfsNodeType::File)?;
-    parent.lookup(path)
+    let resolved = resolve(dir, path, true)?;
+    resolved.dir.create(&resolved.path, VfsNodeType::File)?;
+    resolved.dir.lookup(&resolved.path)
 }
 
 pub(crate) fn create_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    match lookup(dir, path) {
+    match lookup_nofollow(dir, path) {
         Ok(_) => ax_err!(AlreadyExists),
-        Err(AxError::NotFound) => parent_node_of(dir, path).create(path, VfsNodeType::Dir),
+        Err(AxError::NotFound) => {
+            let resolved = resolve(dir, path, false)?;
+            resolved.dir.create(&resolved.path, VfsNodeType::Dir)
+        }
         Err(e) => Err(e),
     }
 }
 
 pub(crate) fn remove_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
+    if path.is_empty() {
+        return ax_err!(NotFound);
+    }
+    let resolved = resolve(dir, path, false)?;
+    let attr = resolved.node()?.get_attr()?;
     if attr.is_dir() {
         ax_err!(IsADirectory)
     } else if !attr.perm().owner_writable() {
         ax_err!(PermissionDenied)
     } else {
-        parent_node_of(dir, path).remove(path)
+        resolved.dir.remove(&resolved.path)
     }
 }
 
@@ -296,18 +666,22 @@ pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
     {
         return ax_err!(InvalidInput);
     }
-    if ROOT_DIR.contains(&absolute_path(path)?) {
+    let resolved = resolve(dir, path, false)?;
+    let abs_path = match resolved.absolute_path() {
+        Some(abs_path) => abs_path,
+        None => absolute_path(path)?,
+    };
+    if ROOT_DIR.contains(&abs_path) {
         return ax_err!(PermissionDenied);
     }
 
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
+    let attr = resolved.node()?.get_attr()?;
     if !attr.is_dir() {
         ax_err!(NotADirectory)
     } else if !attr.perm().owner_writable() {
         ax_err!(PermissionDenied)
     } else {
-        parent_node_of(dir, path).remove(path)
+        resolved.dir.remove(&resolved.path)
     }
 }
 
@@ -316,33 +690,88 @@ pub(crate) fn current_dir() -> AxResult<String> {
 }
 
 pub(crate) fn set_current_dir(path: &str) -> AxResult {
-    let mut abs_path = absolute_path(path)?;
+    let resolved = resolve(None, &absolute_path(path)?, true)?;
+    let mut abs_path = resolved.absolute_path().unwrap(); // from the root
     if !abs_path.ends_with('/') {
         abs_path += "/";
     }
     if abs_path == "/" {
-        *CURRENT_DIR.lock() = ROOT_DIR.clone();
         *CURRENT_DIR_PATH.lock() = "/".into();
         return Ok(());
     }
 
-    let node = lookup(None, &abs_path)?;
-    let attr = node.get_attr()?;
+    let attr = resolved.node()?.get_attr()?;
     if !attr.is_dir() {
         ax_err!(NotADirectory)
     } else if !attr.perm().owner_executable() {
         ax_err!(PermissionDenied)
     } else {
-        *CURRENT_DIR.lock() = node;
         *CURRENT_DIR_PATH.lock() = abs_path;
         Ok(())
     }
 }
 
 pub(crate) fn rename(old: &str, new: &str) -> AxResult {
-    if parent_node_of(None, new).lookup(new).is_ok() {
+    let new = resolve(None, new, false)?;
+    if new.node().is_ok() {
         warn!("dst file already exist, now remove it");
-        remove_file(None, new)?;
+        remove_file(None, &new.path)?;
     }
-    parent_node_of(None, old).rename(old, new)
+    let old = resolve(None, old, false)?;
+    old.dir.rename(&old.path, &new.path)
+}
+
+/// Creates a symlink at `path` pointing to `target`, which is not checked.
+pub(crate) fn symlink(target: &str, path: &str) -> AxResult {
+    if path.is_empty() || target.is_empty() {
+        return ax_err!(NotFound);
+    }
+    let resolved = resolve(None, path, false)?;
+    resolved.dir.symlink(&resolved.path, target)
+}
+
+/// Returns the target of the symlink at `path`.
+pub(crate) fn read_link(path: &str) -> AxResult<String> {
+    read_symlink(&lookup_nofollow(None, path)?)
+}
+
+/// Creates a hard link at `new` to the existing `old`, which is not a
+/// directory.
+pub(crate) fn link(old: &str, new: &str) -> AxResult {
+    let old = resolve(None, old, false)?;
+    let node = old.node()?;
+    if node.get_attr()?.is_dir() {
+        return ax_err!(OperationNotPermitted, "hard link to directory");
+    }
+    let new = resolve(None, new, false)?;
+    let same_fs = match (old.mount(), new.mount()) {
+        (Some(old), Some(new)) => Arc::ptr_eq(&old, &new),
+        (None, None) => true,
+        _ => false,
+    };
+    if !same_fs {
+        return ax_err!(CrossesDevices);
+    }
+    new.dir.link(&new.path, &node)
+}
+
+/// Changes the attributes of the node at `path` by `f`, following symlinks.
+pub(crate) fn set_attr(path: &str, f: impl FnOnce(&VfsNodeRef) -> AxResult) -> AxResult {
+    let resolved = resolve(None, path, true)?;
+    check_writable(resolved.mount().as_deref())?;
+    f(&resolved.node()?)
+}
+
+pub(crate) fn mount(source: &str, target: &str, fstype: &str, flags: MountFlags) -> AxResult {
+    let path = resolve(None, target, true)?.absolute_path().unwrap(); // from the root
+    let fs = mounts::new_fs(fstype, source)?;
+    ROOT_DIR.mount(&path, source, fstype, fs, flags)
+}
+
+pub(crate) fn umount(target: &str) -> AxResult {
+    ROOT_DIR.umount(&absolute_path(target)?)
+}
+
+pub(crate) fn mount_infos() -> Vec<MountInfo> {
+    ROOT_DIR.mount_infos()
 }
@@ -1,8 +1,10 @@
 use axfs::api as fs;
+use axfs_vfs::VfsNodeOps;
 use axio as io;
+use std::time::Duration;
 
-use fs::{File, FileType, OpenOptions};
-use io::{prelude::*, Error, Result};
+use fs::{File, FileType, MountFlags, OpenOptions};
+use io::{prelude::*, Error, Result, SeekFrom};
 
 macro_rules! assert_err {
     ($expr: expr) => {
@@ -252,6 +254,230 @@ fn test_devfs_ramfs() -> Result<()> {
     Ok(())
 }
 
+fn test_page_cache() -> Result<()> {
+    let fname = "/page_cache.txt";
+    println!("test page cache with {:?}:", fname);
+    let data = (0..10000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(fname, &data)?;
+
+    // the file opened twice shares the cached pages
+    let mut file1 = File::options().read(true).write(true).open(fname)?;
+    let mut file2 = File::open(".//page_cache.txt")?;
+    file1.seek(SeekFrom::Start(4090))?;
+    assert_eq!(file1.write(b"0123456789")?, 10); // across the page boundary
+    let mut buf = [0; 16];
+    file2.seek(SeekFrom::Start(4088))?;
+    file2.read_exact(&mut buf)?;
+    assert_eq!(buf[..2], data[4088..4090]);
+    assert_eq!(&buf[2..12], b"0123456789");
+    assert_eq!(buf[12..], data[4100..4104]);
+    file1.flush()?;
+
+    // still shared after renamed, as the same node
+    let renamed = "/page_cache_renamed.txt";
+    fs::rename(fname, renamed)?;
+    let mut file3 = File::open(renamed)?;
+    file1.seek(SeekFrom::Start(0))?;
+    assert_eq!(file1.write(b"renamed")?, 7);
+    file3.read_exact(&mut buf[..7])?;
+    assert_eq!(&buf[..7], b"renamed");
+    drop(file3);
+    fs::rename(renamed, fname)?;
+
+    // the mapped pages are the cached ones
+    let mut opts = axfs::fops::OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    let mapped = axfs::fops::File::open(fname, &opts)?.mmap_node(true)?;
+    let page = mapped.pin_page(1)? as *mut u8;
+    unsafe { page.add(8).copy_from_nonoverlapping(b"mapped".as_ptr(), 6) };
+    mapped.dirty_page(1)?;
+    mapped.unpin_page(1)?;
+    file2.seek(SeekFrom::Start(4104))?;
+    file2.read_exact(&mut buf[..6])?;
+    assert_eq!(&buf[..6], b"mapped");
+    drop(mapped);
+
+    // extend the file, and write back on close
+    file1.seek(SeekFrom::End(0))?;
+    assert_eq!(file1.write(b"end")?, 3);
+    assert_eq!(file2.metadata()?.len(), 10003);
+    drop(file1);
+    drop(file2);
+
+    let new_data = fs::read(fname)?;
+    assert_eq!(new_data.len(), 10003);
+    assert_eq!(&new_data[..7], b"renamed");
+    assert_eq!(&new_data[4090..4100], b"0123456789");
+    assert_eq!(&new_data[4104..4110], b"mapped");
+    assert_eq!(&new_data[10000..], b"end");
+    assert_eq!(fs::sync(), Ok(()));
+    assert_eq!(fs::remove_file(fname), Ok(()));
+
+    println!("test_page_cache() OK!");
+    Ok(())
+}
+
+fn test_mount_umount() -> Result<()> {
+    let dir = "/tmp/mnt";
+    println!("test mount and umount at {:?}:", dir);
+    fs::create_dir(dir)?;
+    fs::mount("none", dir, "ramfs", MountFlags::empty())?;
+    assert_eq!(fs::read_dir(dir)?.count(), 0);
+    fs::write("/tmp/mnt/test.txt", "mounted")?;
+    assert_err!(
+        fs::mount("none", dir, "ramfs", MountFlags::empty()),
+        ResourceBusy
+    );
+    assert_err!(
+        fs::mount("none", "/tmp/mnt/test.txt", "ramfs", MountFlags::empty()),
+        NotADirectory
+    );
+    assert_err!(
+        fs::mount("none", "/tmp/mnt2", "nofs", MountFlags::empty()),
+        NoSuchDevice
+    );
+
+    // nested and read-only mount
+    fs::create_dir("/tmp/mnt/ro")?;
+    fs::mount("none", "/tmp/mnt/ro", "ramfs", MountFlags::READ_ONLY)?;
+    assert!(fs::mounts().iter().any(|m| m.target == "/tmp/mnt/ro"));
+    assert_err!(
+        fs::write("/tmp/mnt/ro/test.txt", "test"),
+        ReadOnlyFilesystem
+    );
+    assert_err!(fs::create_dir("/tmp/mnt/ro/dir"), ReadOnlyFilesystem);
+    assert_err!(fs::umount(dir), ResourceBusy);
+    fs::umount("/tmp/mnt/ro")?;
+
+    // relative to the current directory
+    fs::set_current_dir("/tmp")?;
+    assert_eq!(fs::read_to_string("mnt/./test.txt")?, "mounted");
+    fs::set_current_dir("mnt")?;
+    assert_err!(fs::umount(dir), ResourceBusy);
+    fs::set_current_dir("/")?;
+
+    // busy with opened files
+    let file = File::open("/tmp/mnt/test.txt")?;
+    assert_err!(fs::umount(dir), ResourceBusy);
+    drop(file);
+    fs::umount(dir)?;
+    assert_err!(fs::metadata("/tmp/mnt/test.txt"), NotFound);
+    assert_err!(fs::umount(dir), InvalidInput);
+    fs::remove_dir(dir)?;
+
+    println!("test_mount_umount() OK!");
+    Ok(())
+}
+
+fn test_links_in(dir: &str, other_fs: &str) -> Result<()> {
+    println!("test symlinks and hard links in {:?}:", dir);
+    let path = |name: &str| format!("{}/{}", dir, name);
+    fs::create_dir(dir)?;
+    fs::create_dir(&path("sub"))?;
+    fs::write(&path("file.txt"), "linked")?;
+
+    // relative and absolute symlinks
+    fs::symlink("file.txt", &path("rel"))?;
+    fs::symlink(dir, &path("abs"))?;
+    let long_target = format!("{}file.txt", "./".repeat(40));
+    fs::symlink(&long_target, &path("long"))?;
+    assert_eq!(fs::read_link(&path("rel"))?, "file.txt");
+    assert_eq!(fs::read_link(&path("long"))?, long_target);
+    assert_eq!(fs::read_to_string(&path("rel"))?, "linked");
+    assert_eq!(fs::read_to_string(&path("long"))?, "linked");
+    assert_eq!(fs::read_to_string(&path("abs/abs/./rel"))?, "linked");
+    assert!(fs::symlink_metadata(&path("rel"))?.is_symlink());
+    assert_eq!(fs::symlink_metadata(&path("rel"))?.len(), 8);
+    assert!(fs::metadata(&path("rel"))?.is_file());
+    assert!(fs::metadata(&path("abs/"))?.is_dir());
+    assert_err!(fs::read_link(&path("file.txt")), InvalidInput);
+    assert_err!(fs::symlink("file.txt", &path("rel")), AlreadyExists);
+
+    // `..` after a symlink is the parent of its target
+    fs::symlink(dir, &path("sub/up"))?;
+    let name = dir.rsplit('/').next().unwrap();
+    let via_up = format!("{}/sub/up/../{}/file.txt", dir, name);
+    assert_eq!(fs::read_to_string(&via_up)?, "linked");
+
+    // loops and dangling symlinks
+    fs::symlink("loop2", &path("loop1"))?;
+    fs::symlink("loop1", &path("loop2"))?;
+    assert_err!(fs::metadata(&path("loop1")), FilesystemLoop);
+    fs::symlink("new.txt", &path("dangling"))?;
+    assert_err!(fs::metadata(&path("dangling")), NotFound);
+    fs::write(&path("dangling"), "created")?;
+    assert_eq!(fs::read_to_string(&path("new.txt"))?, "created");
+
+    // the current directory through a symlink
+    fs::set_current_dir(&path("abs/sub"))?;
+    assert_eq!(fs::current_dir()?, path("sub/"));
+    fs::set_current_dir("/")?;
+
+    // hard links
+    fs::hard_link(&path("file.txt"), &path("hard"))?;
+    assert_eq!(fs::metadata(&path("hard"))?.nlink(), 2);
+    fs::write(&path("hard"), "changed")?;
+    assert_eq!(fs::read_to_string(&path("file.txt"))?, "changed");
+    fs::remove_file(&path("file.txt"))?;
+    assert_eq!(fs::metadata(&path("hard"))?.nlink(), 1);
+    assert_eq!(fs::read_to_string(&path("hard"))?, "changed");
+    assert_err!(fs::read_to_string(&path("rel")), NotFound);
+    assert_err!(
+        fs::hard_link(&path("sub"), &path("sub2")),
+        OperationNotPermitted
+    );
+    assert_err!(fs::hard_link(&path("hard"), other_fs), CrossesDevices);
+    assert_err!(
+        fs::hard_link(&path("hard"), &path("new.txt")),
+        AlreadyExists
+    );
+
+    // permissions, owner and times
+    let fname = path("hard");
+    fs::set_permissions(&fname, fs::Permissions::from_bits_truncate(0o444))?;
+    assert_eq!(fs::metadata(&fname)?.permissions().bits(), 0o444);
+    assert_err!(File::create(&fname), PermissionDenied);
+    fs::set_permissions(&fname, fs::Permissions::from_bits_truncate(0o644))?;
+    fs::chown(&fname, Some(1000), Some(100))?;
+    fs::symlink("hard", &path("to_hard"))?;
+    fs::chown(&path("to_hard"), None, Some(200))?; // follows the symlink
+    let md = fs::metadata(&fname)?;
+    assert_eq!((md.uid(), md.gid()), (1000, 200));
+    let (atime, mtime) = (Duration::from_secs(100), Duration::new(200, 5000));
+    fs::set_times(&fname, Some(atime), Some(mtime))?;
+    let md = fs::metadata(&fname)?;
+    assert_eq!((md.accessed(), md.modified()), (atime, mtime));
+    fs::set_times(&fname, None, Some(atime))?;
+    assert_eq!(fs::metadata(&fname)?.accessed(), atime);
+    assert_eq!(fs::metadata(&fname)?.modified(), atime);
+
+    // unlink removes the symlinks, not the targets
+    for name in [
+        "rel", "abs", "long", "sub/up", "loop1", "loop2", "dangling", "to_hard",
+    ] {
+        fs::remove_file(&path(name))?;
+    }
+    assert!(fs::metadata(&path("new.txt"))?.is_file());
+    fs::remove_file(&path("new.txt"))?;
+    fs::remove_file(&path("hard"))?;
+    fs::remove_dir(&path("sub"))?;
+    fs::remove_dir(dir)?;
+
+    println!("test_links_in() OK!");
+    Ok(())
+}
+
+fn test_links() -> Result<()> {
+    test_links_in("/tmp/links", "/hard")?;
+    // FAT does not support links
+    let root = fs::mounts().into_iter().find(|m| m.target == "/").unwrap();
+    if root.fstype != "vfat" {
+        test_links_in("/links", "/tmp/hard")?;
+    }
+    Ok(())
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
@@ -259,4 +485,7 @@ pub fn test_all() {
     test_create_file_dir().expect("test_create_file_dir() failed");
     test_remove_file_dir().expect("test_remove_file_dir() failed");
     test_devfs_ramfs().expect("test_devfs_ramfs() failed");
+    test_page_cache().expect("test_page_cache() failed");
+    test_mount_umount().expect("test_mount_umount() failed");
+    test_links().expect("test_links() failed");
 }
@@ -2,10 +2,16 @@
 
 mod test_common;
 
+use std::time::Duration;
+
 use axdriver::AxDeviceContainer;
 use axdriver_block::ramdisk::RamDisk;
+use axfs::api as fs;
+use axio::Result;
 
 const IMG_PATH: &str = "resources/fat16.img";
+/// 1980-01-01 00:00:00, the earliest FAT timestamp.
+const FAT_EPOCH: Duration = Duration::from_secs(315_532_800);
 
 fn make_disk() -> std::io::Result<RamDisk> {
     let path = std::env::current_dir()?.join(IMG_PATH);
@@ -15,6 +21,27 @@ fn make_disk() -> std::io::Result<RamDisk> {
     Ok(RamDisk::from(&data))
 }
 
+fn test_fat_times() -> Result<()> {
+    println!("test FAT timestamps:");
+    // the files in the image are dated when the image is made
+    let md = fs::metadata("short.txt")?;
+    assert!(md.modified() > FAT_EPOCH);
+    assert!(md.changed() > FAT_EPOCH); // the creation time
+    assert!(md.accessed() > FAT_EPOCH);
+    assert_eq!(fs::metadata("/")?.modified(), Duration::ZERO);
+
+    // without RTC, new files are dated at the FAT epoch
+    let fname = "/times.txt";
+    fs::write(fname, "FAT")?;
+    let md = fs::metadata(fname)?;
+    assert_eq!(md.modified(), FAT_EPOCH);
+    assert_eq!(md.changed(), FAT_EPOCH);
+    fs::remove_file(fname)?;
+
+    println!("test_fat_times() OK!");
+    Ok(())
+}
+
 #[test]
 fn test_fatfs() {
     println!("Testing fatfs with ramdisk ...");
@@ -23,5 +50,6 @@ fn test_fatfs() {
     axtask::init_scheduler(); // call this to use `axsync::Mutex`.
     axfs::init_filesystems(AxDeviceContainer::from_one(disk));
 
+    test_fat_times().expect("test_fat_times() failed");
     test_common::test_all();
 }
@@ -0,0 +1,152 @@
+#![cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+
+#[macro_use]
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, File, FileType, MountFlags};
+use axio::{prelude::*, Error, Result, SeekFrom};
+
+const IMG_PATH: &str = "resources/ext4.img";
+
+const SECTOR_SIZE: usize = 512;
+/// The first sector of partitions, aligned to 1 MiB.
+const FIRST_SECTOR: usize = 2048;
+const NUM_ENTRIES: usize = 128;
+const ENTRY_SIZE: usize = 128;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Creates a disk with a GPT partition table, and two partitions with the
+/// filesystem image in each.
+fn make_disk() -> std::io::Result<RamDisk> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let img = std::fs::read(path)?;
+    let part_sectors = img.len() / SECTOR_SIZE;
+    let num_sectors = FIRST_SECTOR + part_sectors * 2 + 64;
+    let mut data = vec![0u8; num_sectors * SECTOR_SIZE];
+
+    // protective MBR
+    let mbr = &mut data[..SECTOR_SIZE];
+    mbr[446 + 4] = 0xee;
+    mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&(num_sectors as u32 - 1).to_le_bytes());
+    mbr[510..].copy_from_slice(&[0x55, 0xaa]);
+
+    let mut entries = vec![0u8; NUM_ENTRIES * ENTRY_SIZE];
+    for i in 0..2 {
+        let first = FIRST_SECTOR + i * part_sectors;
+        let entry = &mut entries[i * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..16].fill(0xaf); // any type
+        entry[16] = i as u8 + 1;
+        entry[32..40].copy_from_slice(&(first as u64).to_le_bytes());
+        entry[40..48].copy_from_slice(&((first + part_sectors - 1) as u64).to_le_bytes());
+        let pos = first * SECTOR_SIZE;
+        data[pos..pos + img.len()].copy_from_slice(&img);
+    }
+    data[2 * SECTOR_SIZE..][..entries.len()].copy_from_slice(&entries);
+
+    let header = &mut data[SECTOR_SIZE..2 * SECTOR_SIZE];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&1u64.to_le_bytes());
+    header[32..40].copy_from_slice(&(num_sectors as u64 - 1).to_le_bytes());
+    header[40..48].copy_from_slice(&(FIRST_SECTOR as u64).to_le_bytes());
+    header[48..56].copy_from_slice(&(num_sectors as u64 - 34).to_le_bytes());
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&(NUM_ENTRIES as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+    println!("size = {} bytes", data.len());
+    Ok(RamDisk::from(&data[..]))
+}
+
+fn test_partitions() -> Result<()> {
+    println!("test partitions:");
+    // the first partition with a filesystem is the root
+    let root = fs::mounts().into_iter().find(|m| m.target == "/").unwrap();
+    assert_eq!(root.source, "/dev/vda1");
+    assert_eq!(root.fstype, "ext4");
+
+    let dirents = fs::read_dir("/dev")?
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    for name in ["vda", "vda1", "vda2"] {
+        assert!(dirents.contains(&name.into()));
+    }
+    let md = fs::metadata("/dev/vda2")?;
+    assert_eq!(md.file_type(), FileType::BlockDevice);
+    assert_eq!(md.len(), fs::metadata("/dev/vda1")?.len());
+
+    // read the ext4 magic in the superblock of the partition
+    let mut file = File::open("/dev/vda2")?;
+    let mut buf = [0; 2];
+    file.seek(SeekFrom::Start(1024 + 0x38))?;
+    file.read_exact(&mut buf)?;
+    assert_eq!(buf, [0x53, 0xef]);
+    drop(file);
+
+    // mount the other partition, but only once
+    let dir = "/tmp/part";
+    fs::create_dir(dir)?;
+    assert_err!(
+        fs::mount("/dev/vda1", dir, "ext4", MountFlags::empty()),
+        ResourceBusy
+    );
+    assert_err!(
+        fs::mount("/dev/vda", dir, "ext4", MountFlags::empty()),
+        ResourceBusy
+    );
+    assert_err!(
+        fs::mount("/dev/vdb1", dir, "ext4", MountFlags::empty()),
+        NotFound
+    );
+    fs::mount("/dev/vda2", dir, "ext4", MountFlags::empty())?;
+    assert_eq!(
+        fs::read_to_string("/tmp/part/short.txt")?,
+        "Rust is cool!\n"
+    );
+    fs::write("/tmp/part/new.txt", "on vda2")?;
+    assert_err!(fs::metadata("/new.txt"), NotFound);
+    assert_err!(
+        fs::mount("/dev/vda2", "/tmp", "ext4", MountFlags::empty()),
+        ResourceBusy
+    );
+    fs::umount(dir)?;
+
+    // mount it again, and the changes are kept
+    fs::mount("/dev/vda2", dir, "ext4", MountFlags::empty())?;
+    assert_eq!(fs::read_to_string("/tmp/part/new.txt")?, "on vda2");
+    fs::umount(dir)?;
+    fs::remove_dir(dir)?;
+
+    println!("test_partitions() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_partition() {
+    println!("Testing partitions with ramdisk ...");
+
+    let disk = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    test_partitions().expect("test_partitions() failed");
+    test_common::test_all();
+}
@@ -98,6 +98,52 @@ pub fn flush_tlb(vaddr: Option<VirtAddr>) {
     }
 }
 
+/// Returns the largest address space identifier (ASID) supported by the CPU,
+/// or 0 if ASIDs are not supported.
+///
+/// On AArch64, it is always 0 for now: the ASIDs only tag the non-global
+/// mappings (with the `nG` bit), but all the mappings created by the page table
+/// are global.
+pub fn max_asid() -> usize {
+    0
+}
+
+/// Writes the `TTBR0_EL1` register to update the page table root of the lower
+/// half, and tags the TLB entries of the new address space with `asid`.
+///
+/// The TLB entries of the other ASIDs are kept. If `flush` is `true`, the
+/// entries tagged with `asid` are flushed, as they may be left by the previous
+/// address space using the ASID.
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the virtual memory address space.
+pub unsafe fn write_page_table_root_asid(root_paddr: PhysAddr, asid: usize, flush: bool) {
+    trace!("set page table root0: {:#x} (asid={})", root_paddr, asid);
+    TTBR0_EL1.set(((asid as u64) << 48) | root_paddr.as_usize() as u64);
+    asm!("isb");
+    if flush {
+        flush_tlb_asid(asid, None);
+    }
+}
+
+/// Flushes the TLB entries tagged with `asid` on the current CPU.
+///
+/// If `vaddr` is [`None`], flushes all the entries of the ASID. Otherwise,
+/// flushes the entry that maps the given virtual address.
+#[inline]
+pub fn flush_tlb_asid(asid: usize, vaddr: Option<VirtAddr>) {
+    let asid = (asid as u64) << 48;
+    unsafe {
+        if let Some(vaddr) = vaddr {
+            let page = (vaddr.as_usize() as u64 >> 12) & ((1 << 44) - 1);
+            asm!("tlbi vae1, {}; dsb nsh; isb", in(reg) asid | page)
+        } else {
+            asm!("tlbi aside1, {}; dsb nsh; isb", in(reg) asid)
+        }
+    }
+}
+
 /// Flushes the entire instruction cache.
 #[inline]
 pub fn flush_icache_all() {
@@ -6,7 +6,11 @@ use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
 
-global_asm!(include_str!("trap.S"));
+global_asm!(
+    include_str!("trap.S"),
+    overflow_stack = sym crate::trap::__PERCPU_OVERFLOW_STACK,
+    overflow_stack_size = const crate::trap::OVERFLOW_STACK_SIZE,
+);
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -47,10 +51,13 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(FAR_EL1.get() as usize);
+    if !is_user {
+        crate::trap::handle_stack_guard(vaddr);
+    }
 
-    // Only handle Translation fault and Permission fault
-    if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+    // Only handle Translation fault, Access flag fault and Permission fault
+    if !matches!(iss & 0b111100, 0b0100 | 0b1000 | 0b1100) // IFSC or DFSC bits
+        || !crate::trap::handle_page_fault(vaddr, access_flags, is_user)
     {
         panic!(
             "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ISS={:#x} ({:?}):\n{:#x?}",
@@ -76,10 +83,13 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(FAR_EL1.get() as usize);
+    if !is_user {
+        crate::trap::handle_stack_guard(vaddr);
+    }
 
-    // Only handle Translation fault and Permission fault
-    if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+    // Only handle Translation fault, Access flag fault and Permission fault
+    if !matches!(iss & 0b111100, 0b0100 | 0b1000 | 0b1100) // IFSC or DFSC bits
+        || !crate::trap::handle_page_fault(vaddr, access_flags, is_user)
     {
         panic!(
             "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}",
@@ -93,6 +103,18 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     }
 }
 
+/// Handles the data abort right below the stack pointer in EL1, on the stack
+/// for the stack overflows.
+#[no_mangle]
+fn handle_stack_overflow(tf: &TrapFrame) -> ! {
+    let vaddr = va!(FAR_EL1.get() as usize);
+    crate::trap::handle_stack_guard(vaddr);
+    panic!(
+        "Kernel stack overflow @ {:#x}, fault_vaddr={:#x}:\n{:#x?}",
+        tf.elr, vaddr, tf,
+    );
+}
+
 #[no_mangle]
 fn handle_sync_exception(tf: &mut TrapFrame) {
     let esr = ESR_EL1.extract();
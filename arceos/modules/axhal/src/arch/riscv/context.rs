@@ -1,7 +1,5 @@
 use core::arch::asm;
 use memory_addr::VirtAddr;
-#[cfg(feature = "uspace")]
-use memory_addr::PhysAddr;
 
 include_asm_marcos!();
 
@@ -120,9 +118,6 @@ pub struct TaskContext {
     pub s11: usize,
 
     pub tp: usize,
-    /// The `satp` register value, i.e., the page table root.
-    #[cfg(feature = "uspace")]
-    pub satp: PhysAddr,
     // TODO: FP states
 }
 
@@ -135,11 +130,7 @@ impl TaskContext {
     /// [`init`]: TaskContext::init
     /// [`switch_to`]: TaskContext::switch_to
     pub fn new() -> Self {
-        Self {
-            #[cfg(feature = "uspace")]
-            satp: crate::paging::kernel_page_table_root(),
-            ..Default::default()
-        }
+        Self::default()
     }
 
     /// Initializes the context for a new task, with the given entry point and
@@ -150,17 +141,6 @@ impl TaskContext {
         self.tp = tls_area.as_usize();
     }
 
-    /// Changes the page table root (`satp` register for riscv64).
-    ///
-    /// If not set, the kernel page table root is used (obtained by
-    /// [`axhal::paging::kernel_page_table_root`][1]).
-    ///
-    /// [1]: crate::paging::kernel_page_table_root
-    #[cfg(feature = "uspace")]
-    pub fn set_page_table_root(&mut self, satp: PhysAddr) {
-        self.satp = satp;
-    }
-
     /// Switches to another task.
     ///
     /// It first saves the current task's context from CPU to this place, and then
@@ -171,12 +151,6 @@ impl TaskContext {
             self.tp = super::read_thread_pointer();
             unsafe { super::write_thread_pointer(next_ctx.tp) };
         }
-        #[cfg(feature = "uspace")]
-        unsafe {
-            if self.satp != next_ctx.satp {
-                super::write_page_table_root(next_ctx.satp);
-            }
-        }
         unsafe {
             // TODO: switch FP states
             context_switch(self, next_ctx)
@@ -4,6 +4,8 @@ mod macros;
 mod context;
 mod trap;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use memory_addr::{PhysAddr, VirtAddr};
 use riscv::asm;
 use riscv::register::{satp, sstatus, stvec};
@@ -82,6 +84,60 @@ pub fn flush_tlb(vaddr: Option<VirtAddr>) {
     }
 }
 
+/// Returns the largest address space identifier (ASID) supported by the CPU,
+/// or 0 if ASIDs are not supported.
+///
+/// On RISC-V, the number of ASID bits is detected by writing ones to the ASID
+/// field of `satp` and reading it back.
+pub fn max_asid() -> usize {
+    static MAX_ASID: AtomicUsize = AtomicUsize::new(usize::MAX);
+    let mut max_asid = MAX_ASID.load(Ordering::Relaxed);
+    if max_asid == usize::MAX {
+        let satp = satp::read();
+        unsafe {
+            satp::set(satp.mode(), 0xffff, satp.ppn());
+            max_asid = satp::read().asid();
+            satp::write(satp.bits());
+            asm::sfence_vma_all();
+        }
+        MAX_ASID.store(max_asid, Ordering::Relaxed);
+    }
+    max_asid
+}
+
+/// Writes the register to update the current page table root, and tags the
+/// TLB entries of the new address space with `asid`.
+///
+/// The TLB entries of the other ASIDs are kept. If `flush` is `true`, the
+/// entries tagged with `asid` are flushed, as they may be left by the previous
+/// address space using the ASID.
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the virtual memory address space.
+pub unsafe fn write_page_table_root_asid(root_paddr: PhysAddr, asid: usize, flush: bool) {
+    trace!("set page table root: {:#x} (asid={})", root_paddr, asid);
+    satp::set(satp::Mode::Sv39, asid, root_paddr.as_usize() >> 12);
+    if flush {
+        flush_tlb_asid(asid, None);
+    }
+}
+
+/// Flushes the TLB entries tagged with `asid` on the current CPU.
+///
+/// If `vaddr` is [`None`], flushes all the entries of the ASID. Otherwise,
+/// flushes the entry that maps the given virtual address.
+#[inline]
+pub fn flush_tlb_asid(asid: usize, vaddr: Option<VirtAddr>) {
+    unsafe {
+        if let Some(vaddr) = vaddr {
+            asm::sfence_vma(asid, vaddr.as_usize())
+        } else {
+            core::arch::asm!("sfence.vma zero, {}", in(reg) asid)
+        }
+    }
+}
+
 /// Writes Supervisor Trap Vector Base Address Register (`stvec`).
 #[inline]
 pub fn set_trap_vector_base(stvec: usize) {
@@ -9,6 +9,9 @@ include_asm_marcos!();
 core::arch::global_asm!(
     include_str!("trap.S"),
     trapframe_size = const core::mem::size_of::<TrapFrame>(),
+    overflow_stack = sym crate::trap::__PERCPU_OVERFLOW_STACK,
+    overflow_stack_size = const crate::trap::OVERFLOW_STACK_SIZE,
+    stack_guard_page = sym crate::trap::__PERCPU_STACK_GUARD_PAGE,
 );
 
 fn handle_breakpoint(sepc: &mut usize) {
@@ -21,7 +24,10 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(stval::read());
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+    if !is_user {
+        crate::trap::handle_stack_guard(vaddr);
+    }
+    if !crate::trap::handle_page_fault(vaddr, access_flags, is_user) {
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
             if is_user { "User" } else { "Supervisor" },
@@ -33,6 +39,18 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
     }
 }
 
+/// Handles the page fault in the guard page of the current stack in S mode, on
+/// the stack for the stack overflows.
+#[no_mangle]
+fn riscv_stack_overflow_handler(tf: &TrapFrame) -> ! {
+    let vaddr = va!(stval::read());
+    crate::trap::handle_stack_guard(vaddr);
+    panic!(
+        "Kernel stack overflow @ {:#x}, fault_vaddr={:#x}:\n{:#x?}",
+        tf.sepc, vaddr, tf,
+    );
+}
+
 #[no_mangle]
 fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
     let scause = scause::read();
@@ -8,12 +8,15 @@ mod trap;
 use core::arch::asm;
 
 use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use raw_cpuid::CpuId;
 use x86::{controlregs, msr, tlb};
 use x86_64::instructions::interrupts;
+use x86_64::instructions::tlb::{InvPicdCommand, Pcid};
+use x86_64::registers::control::{Cr4, Cr4Flags};
 
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
 pub use self::gdt::GdtStruct;
-pub use self::idt::IdtStruct;
+pub use self::idt::{IdtStruct, DOUBLE_FAULT_IST_INDEX};
 pub use x86_64::structures::tss::TaskStateSegment;
 
 /// Allows the current CPU to respond to interrupts.
@@ -80,15 +83,117 @@ pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
 ///
 /// If `vaddr` is [`None`], flushes the entire TLB. Otherwise, flushes the TLB
 /// entry that maps the given virtual address.
+///
+/// With PCIDs enabled, the entries of all PCIDs are flushed, which flushes the
+/// entire TLB even if `vaddr` is given, as `invlpg` only flushes the current
+/// PCID.
 #[inline]
 pub fn flush_tlb(vaddr: Option<VirtAddr>) {
-    if let Some(vaddr) = vaddr {
+    if max_asid() != 0 {
+        flush_tlb_all_pcids();
+    } else if let Some(vaddr) = vaddr {
         unsafe { tlb::flush(vaddr.into()) }
     } else {
         unsafe { tlb::flush_all() }
     }
 }
 
+/// The bit of `CR3` to keep the TLB entries of the new PCID when writing it.
+const CR3_NOFLUSH: u64 = 1 << 63;
+/// The mask of the PCID in `CR3`.
+const CR3_PCID_MASK: u64 = 0xfff;
+
+fn has_invpcid() -> bool {
+    CpuId::new()
+        .get_extended_feature_info()
+        .is_some_and(|info| info.has_invpcid())
+}
+
+fn flush_tlb_all_pcids() {
+    if has_invpcid() {
+        unsafe { x86_64::instructions::tlb::flush_pcid(InvPicdCommand::All) }
+    } else {
+        // Toggling `CR4.PGE` flushes the entries of all PCIDs.
+        let cr4 = Cr4::read();
+        unsafe {
+            Cr4::write(cr4 ^ Cr4Flags::PAGE_GLOBAL);
+            Cr4::write(cr4);
+        }
+    }
+}
+
+/// Returns the largest address space identifier (ASID) supported by the CPU,
+/// or 0 if ASIDs are not supported.
+///
+/// On x86_64, the ASIDs are the process-context identifiers (PCIDs), which
+/// are available if they are enabled by [`cpu_init`].
+pub fn max_asid() -> usize {
+    if Cr4::read().contains(Cr4Flags::PCID) {
+        CR3_PCID_MASK as usize
+    } else {
+        0
+    }
+}
+
+/// Writes the register to update the current page table root, and tags the
+/// TLB entries of the new address space with `asid`.
+///
+/// The TLB entries of the other ASIDs are kept. If `flush` is `true`, the
+/// entries tagged with `asid` are flushed, as they may be left by the previous
+/// address space using the ASID.
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the virtual memory address space.
+pub unsafe fn write_page_table_root_asid(root_paddr: PhysAddr, asid: usize, flush: bool) {
+    trace!("set page table root: {:#x} (asid={})", root_paddr, asid);
+    let mut cr3 = root_paddr.as_usize() as u64 | (asid as u64 & CR3_PCID_MASK);
+    if !flush {
+        cr3 |= CR3_NOFLUSH;
+    }
+    controlregs::cr3_write(cr3)
+}
+
+/// Flushes the TLB entries tagged with `asid` on the current CPU.
+///
+/// If `vaddr` is [`None`], flushes all the entries of the ASID. Otherwise,
+/// flushes the entry that maps the given virtual address. If the ASID is not
+/// the current one and `invpcid` is not supported, the entire TLB is flushed.
+pub fn flush_tlb_asid(asid: usize, vaddr: Option<VirtAddr>) {
+    let current = unsafe { controlregs::cr3() } & CR3_PCID_MASK;
+    if current == asid as u64 {
+        if let Some(vaddr) = vaddr {
+            unsafe { tlb::flush(vaddr.into()) }
+        } else {
+            unsafe { tlb::flush_all() }
+        }
+    } else if has_invpcid() {
+        let pcid = Pcid::new(asid as u16).unwrap();
+        let cmd = match vaddr {
+            Some(vaddr) => {
+                InvPicdCommand::Address(x86_64::VirtAddr::new(vaddr.as_usize() as u64), pcid)
+            }
+            None => InvPicdCommand::Single(pcid),
+        };
+        unsafe { x86_64::instructions::tlb::flush_pcid(cmd) }
+    } else {
+        flush_tlb_all_pcids();
+    }
+}
+
+/// Initializes CPU states on the current CPU.
+///
+/// On x86_64, it enables PCIDs if they are supported.
+pub fn cpu_init() {
+    let has_pcid = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_pcid());
+    if has_pcid {
+        // The current PCID (in `CR3[11:0]`) must be 0 when enabling PCIDs.
+        unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::PCID)) };
+    }
+}
+
 /// Reads the thread pointer of the current CPU.
 ///
 /// It is used to implement TLS (Thread Local Storage).
@@ -13,7 +13,10 @@ fn handle_page_fault(tf: &TrapFrame) {
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+    if !tf.is_user() {
+        crate::trap::handle_stack_guard(vaddr);
+    }
+    if !crate::trap::handle_page_fault(vaddr, access_flags, tf.is_user()) {
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
             if tf.is_user() { "user" } else { "kernel" },
@@ -30,6 +33,12 @@ fn handle_page_fault(tf: &TrapFrame) {
 fn x86_trap_handler(tf: &TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
+        DOUBLE_FAULT_VECTOR => {
+            // Usually caused by a page fault on the overflowed kernel stack.
+            let vaddr = va!(unsafe { cr2() });
+            crate::trap::handle_stack_guard(vaddr);
+            panic!("#DF @ {:#x}, fault_vaddr={:#x}:\n{:#x?}", tf.rip, vaddr, tf);
+        }
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
@@ -0,0 +1,96 @@
+//! Stack backtraces by walking the frame pointers.
+//!
+//! The kernel should be built with `-C force-frame-pointers=yes`, otherwise
+//! some frames may be missed.
+
+use core::ops::Range;
+
+/// The maximum number of frames to walk.
+const MAX_DEPTH: usize = 32;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "riscv64")] {
+        /// The offset of the frame record (previous frame pointer and return
+        /// address) from the frame pointer.
+        const FRAME_RECORD_OFFSET: isize = -16;
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+            fp
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        const FRAME_RECORD_OFFSET: isize = 0;
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mov {}, x29", out(reg) fp) };
+            fp
+        }
+    } else if #[cfg(target_arch = "x86_64")] {
+        const FRAME_RECORD_OFFSET: isize = 0;
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mov {}, rbp", out(reg) fp) };
+            fp
+        }
+    } else {
+        const FRAME_RECORD_OFFSET: isize = 0;
+
+        fn read_fp() -> usize {
+            0
+        }
+    }
+}
+
+/// Walks the call stack of the caller, and calls `f` with the return address
+/// of each frame.
+///
+/// Only the frames in `stack` or in the stack for the kernel stack overflows
+/// are walked, so the walk stops at a corrupted frame pointer.
+pub fn unwind(stack: Range<usize>, mut f: impl FnMut(usize)) {
+    let overflow_stack = crate::trap::overflow_stack_range();
+    let is_valid = |record: usize| {
+        record % core::mem::align_of::<usize>() == 0
+            && [&stack, &overflow_stack]
+                .iter()
+                .any(|s| s.start <= record && record + 2 * core::mem::size_of::<usize>() <= s.end)
+    };
+
+    let mut fp = read_fp();
+    for _ in 0..MAX_DEPTH {
+        let record = fp.wrapping_add_signed(FRAME_RECORD_OFFSET);
+        if !is_valid(record) {
+            break;
+        }
+        // Safety: the frame record is in a valid stack.
+        let (prev_fp, ret_addr) = unsafe {
+            let record = record as *const usize;
+            (record.read(), record.add(1).read())
+        };
+        if ret_addr == 0 {
+            break;
+        }
+        f(ret_addr);
+        if prev_fp == fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// Prints the backtrace of the caller with [`error!`] logs.
+///
+/// See [`unwind`] for the meaning of `stack`.
+pub fn dump(stack: Range<usize>) {
+    error!("Backtrace:");
+    let mut depth = 0;
+    unwind(stack, |ret_addr| {
+        error!("  {:>2}: {:#x}", depth, ret_addr);
+        depth += 1;
+    });
+}
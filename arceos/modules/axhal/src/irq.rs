@@ -6,6 +6,8 @@ use crate::platform::irq::{dispatch_irq, MAX_IRQ_COUNT};
 use crate::trap::{register_trap_handler, IRQ};
 
 pub use crate::platform::irq::{register_handler, set_enable};
+#[cfg(feature = "smp")]
+pub use crate::platform::irq::{send_ipi, IPI_IRQ_NUM};
 
 /// The type if an IRQ handler.
 pub type IrqHandler = handler_table::Handler;
@@ -75,6 +75,12 @@ pub fn memory_regions() -> impl Iterator<Item = MemRegion> {
     kernel_image_regions().chain(crate::platform::mem::platform_regions())
 }
 
+/// Returns the address range of the boot stack of the primary CPU, which is
+/// also the stack of the main task.
+pub fn boot_stack_range() -> core::ops::Range<usize> {
+    boot_stack as usize..boot_stack_top as usize
+}
+
 /// Returns the memory regions of the kernel image (code and data sections).
 fn kernel_image_regions() -> impl Iterator<Item = MemRegion> {
     [
@@ -2,7 +2,9 @@
 
 use axalloc::global_allocator;
 use lazyinit::LazyInit;
-use page_table_multiarch::PagingHandler;
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, PageTable64, PagingHandler, PagingMetaData};
 
 use crate::mem::{phys_to_virt, virt_to_phys, MemRegionFlags, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
@@ -54,7 +56,15 @@ impl PagingHandler for PagingHandlerImpl {
 }
 
 cfg_if::cfg_if! {
-    if #[cfg(target_arch = "x86_64")] {
+    if #[cfg(all(target_arch = "x86_64", not(target_os = "none")))] {
+        /// The page table on the host (e.g., in unit tests), which is never
+        /// activated, so the TLB is not flushed.
+        pub type PageTable = PageTable64<
+            HostPagingMetaData,
+            page_table_entry::x86_64::X64PTE,
+            PagingHandlerImpl,
+        >;
+    } else if #[cfg(target_arch = "x86_64")] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::x86_64::X64PageTable<PagingHandlerImpl>;
     } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
@@ -66,6 +76,144 @@ cfg_if::cfg_if! {
     }
 }
 
+/// The paging metadata of the host page table, the same as x86_64 except that
+/// the TLB flushes are no-ops, as they are privileged.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[doc(hidden)]
+pub struct HostPagingMetaData;
+
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+impl PagingMetaData for HostPagingMetaData {
+    const LEVELS: usize = X64PagingMetaData::LEVELS;
+    const PA_MAX_BITS: usize = X64PagingMetaData::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = X64PagingMetaData::VA_MAX_BITS;
+
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        /// The accessed bit of the page table entries.
+        const PTE_ACCESSED: u64 = 1 << 5;
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        /// The accessed bit of the page table entries.
+        const PTE_ACCESSED: u64 = 1 << 6;
+    } else if #[cfg(target_arch = "aarch64")]{
+        /// The access flag of the page table entries.
+        const PTE_ACCESSED: u64 = 1 << 10;
+    }
+}
+
+/// Returns the leaf entry that maps `vaddr` and its level, where the root
+/// table is level 0, or `None` if it is not mapped.
+fn leaf_entry<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler>(
+    pt: &mut PageTable64<M, PTE, H>,
+    vaddr: VirtAddr,
+) -> Option<(&mut PTE, usize)> {
+    let mut table = pt.root_paddr();
+    for level in 0..M::LEVELS {
+        let entries = H::phys_to_virt(table).as_mut_ptr() as *mut PTE;
+        let index = (vaddr.as_usize() >> level_shift::<M>(level)) & 0x1ff;
+        // SAFETY: the table is owned by `pt`, which is borrowed mutably.
+        let entry = unsafe { &mut *entries.add(index) };
+        if !entry.is_present() {
+            return None;
+        }
+        if level == M::LEVELS - 1 || entry.is_huge() {
+            return Some((entry, level));
+        }
+        table = entry.paddr();
+    }
+    None
+}
+
+/// Returns the shift of the size of the pages mapped by the entries at `level`.
+const fn level_shift<M: PagingMetaData>(level: usize) -> usize {
+    12 + 9 * (M::LEVELS - 1 - level)
+}
+
+/// Returns the raw bits of the page table entry.
+fn raw_bits<PTE: GenericPTE>(entry: &mut PTE) -> &mut u64 {
+    // SAFETY: all the page table entries are 64-bit words.
+    unsafe { &mut *(entry as *mut PTE as *mut u64) }
+}
+
+fn split_huge_entry<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler>(
+    pt: &mut PageTable64<M, PTE, H>,
+    vaddr: VirtAddr,
+) -> PagingResult<Option<PageSize>> {
+    let Some((entry, level)) = leaf_entry(pt, vaddr) else {
+        return Ok(None);
+    };
+    if level == M::LEVELS - 1 {
+        return Ok(None);
+    }
+    let small = match level_shift::<M>(level + 1) {
+        12 => PageSize::Size4K,
+        21 => PageSize::Size2M,
+        _ => return Err(PagingError::MappedToHugePage),
+    };
+    let table = H::alloc_frame().ok_or(PagingError::NoMemory)?;
+    let entries = H::phys_to_virt(table).as_mut_ptr() as *mut PTE;
+    let (frame, flags) = (entry.paddr(), entry.flags());
+    for i in 0..512 {
+        let page = PTE::new_page(frame + i * small as usize, flags, small.is_huge());
+        // SAFETY: the table is just allocated, and has 512 entries.
+        unsafe { entries.add(i).write(page) };
+    }
+    // Make the new table visible to the page table walker before the entry.
+    core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    // SAFETY: `entry` is a valid entry of the page table.
+    unsafe { (entry as *mut PTE).write_volatile(PTE::new_table(table)) };
+    Ok(Some(small))
+}
+
+/// Splits the huge page that maps `vaddr` into the pages of the next level,
+/// which are mapped to the same frames with the same flags.
+///
+/// The next-level table is filled before it replaces the huge page entry in a
+/// single store, so the pages are mapped all the time. The TLB must be flushed
+/// afterwards, so that the entries of the huge page are dropped.
+///
+/// Returns the size of the new pages, or `None` if `vaddr` is not mapped by a
+/// huge page.
+pub fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr) -> PagingResult<Option<PageSize>> {
+    split_huge_entry(pt, vaddr)
+}
+
+/// Clears the accessed bit of the page that maps `vaddr`, and returns whether
+/// it was set, i.e. the page has been accessed since the bit was cleared last
+/// time. Returns `None` if `vaddr` is not mapped.
+///
+/// The TLB entry of `vaddr` must be flushed afterwards, so that the next
+/// access sets the bit again.
+pub fn test_and_clear_accessed(pt: &mut PageTable, vaddr: VirtAddr) -> Option<bool> {
+    let entry = raw_bits(leaf_entry(pt, vaddr)?.0);
+    let accessed = *entry & PTE_ACCESSED != 0;
+    *entry &= !PTE_ACCESSED;
+    Some(accessed)
+}
+
+/// Sets the accessed bit of the page that maps `vaddr`, and returns whether it
+/// was clear.
+///
+/// The CPUs that do not set the bit themselves, e.g. RISC-V without Svadu and
+/// AArch64 without FEAT_HAFDBS, raise page faults on the pages whose bit is
+/// cleared by [`test_and_clear_accessed`], which are handled by this.
+pub fn set_accessed(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    // x86 always sets it itself.
+    if cfg!(target_arch = "x86_64") {
+        return false;
+    }
+    match leaf_entry(pt, vaddr).map(|(entry, _)| raw_bits(entry)) {
+        Some(entry) if *entry & PTE_ACCESSED == 0 => {
+            *entry |= PTE_ACCESSED;
+            true
+        }
+        _ => false,
+    }
+}
+
 static KERNEL_PAGE_TABLE_ROOT: LazyInit<PhysAddr> = LazyInit::new();
 
 /// Saves the root physical address of the kernel page table, which may be used
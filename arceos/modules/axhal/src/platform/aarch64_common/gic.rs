@@ -9,6 +9,10 @@ pub const MAX_IRQ_COUNT: usize = 1024;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = translate_irq(14, InterruptType::PPI).unwrap();
 
+/// The inter-processor interrupt (IPI) number (software generated interrupt 1).
+#[cfg(feature = "smp")]
+pub const IPI_IRQ_NUM: usize = translate_irq(1, InterruptType::SGI).unwrap();
+
 /// The UART IRQ number.
 pub const UART_IRQ_NUM: usize = translate_irq(axconfig::UART_IRQ, InterruptType::SPI).unwrap();
 
@@ -45,6 +49,17 @@ pub fn dispatch_irq(_unused: usize) {
     GICC.handle_irq(|irq_num| crate::irq::dispatch_irq_common(irq_num as _));
 }
 
+/// Sends an inter-processor interrupt (IPI) to the given CPU.
+#[cfg(feature = "smp")]
+pub fn send_ipi(cpu_id: usize) {
+    // GICD_SGIR: TargetListFilter = 0b00 (use the target list),
+    // CPUTargetList = 1 << cpu_id, SGIINTID = IPI_IRQ_NUM.
+    const GICD_SGIR: usize = 0xf00;
+    let sgir = (phys_to_virt(GICD_BASE).as_usize() + GICD_SGIR) as *mut u32;
+    let _gicd = GICD.lock();
+    unsafe { sgir.write_volatile((1 << (16 + cpu_id)) | IPI_IRQ_NUM as u32) };
+}
+
 /// Initializes GICD, GICC on the primary CPU.
 pub(crate) fn init_primary() {
     info!("Initialize GICv2...");
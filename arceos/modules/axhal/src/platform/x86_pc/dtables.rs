@@ -1,6 +1,6 @@
 //! Description tables (per-CPU GDT, per-CPU ISS, IDT)
 
-use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment};
+use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment, DOUBLE_FAULT_IST_INDEX};
 use lazyinit::LazyInit;
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
@@ -16,7 +16,10 @@ fn init_percpu() {
         IDT.load();
         let tss = TSS.current_ref_mut_raw();
         let gdt = GDT.current_ref_mut_raw();
-        tss.init_once(TaskStateSegment::new());
+        let mut new_tss = TaskStateSegment::new();
+        new_tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            x86_64::VirtAddr::new(crate::trap::overflow_stack_range().end as u64);
+        tss.init_once(new_tss);
         gdt.init_once(GdtStruct::new(tss));
         gdt.load();
         gdt.load_tss();
@@ -37,6 +37,7 @@ unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
     if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
         crate::mem::clear_bss();
         crate::cpu::init_primary(current_cpu_id());
+        crate::arch::cpu_init();
         self::uart16550::init();
         self::dtables::init_primary();
         self::time::init_early();
@@ -49,6 +50,7 @@ unsafe extern "C" fn rust_entry_secondary(magic: usize) {
     #[cfg(feature = "smp")]
     if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
         crate::cpu::init_secondary(current_cpu_id());
+        crate::arch::cpu_init();
         self::dtables::init_secondary();
         rust_main_secondary(current_cpu_id());
     }
@@ -1,5 +1,7 @@
 //! Trap handling.
 
+use core::ops::Range;
+
 use linkme::distributed_slice as def_trap_handler;
 use memory_addr::VirtAddr;
 use page_table_entry::MappingFlags;
@@ -17,6 +19,14 @@ pub static IRQ: [fn(usize) -> bool];
 #[def_trap_handler]
 pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
 
+/// A slice of stack guard handler functions.
+///
+/// They are called on the kernel page faults before the page fault handlers,
+/// and do not return if the fault address is in the guard page of a stack,
+/// i.e., the stack overflows.
+#[def_trap_handler]
+pub static STACK_GUARD: [fn(VirtAddr)];
+
 /// A slice of syscall handler functions.
 #[cfg(feature = "uspace")]
 #[def_trap_handler]
@@ -38,6 +48,63 @@ macro_rules! handle_trap {
     }}
 }
 
+/// The size of the stack to handle the kernel stack overflows.
+pub(crate) const OVERFLOW_STACK_SIZE: usize = 0x4000;
+
+#[repr(align(16))]
+#[allow(dead_code)]
+pub(crate) struct OverflowStack([u8; OVERFLOW_STACK_SIZE]);
+
+/// The stack to handle the kernel stack overflows on each CPU.
+///
+/// A page fault right below the stack pointer cannot push the trap frame on
+/// the overflowed stack, so the trap entry switches to this stack instead.
+#[percpu::def_percpu]
+pub(crate) static OVERFLOW_STACK: OverflowStack = OverflowStack([0; OVERFLOW_STACK_SIZE]);
+
+/// The guard page below the stack of the current task on each CPU, or 0 if
+/// there is none. The trap entry checks it for the stack overflows on some
+/// architectures (e.g., RISC-V).
+#[percpu::def_percpu]
+pub(crate) static STACK_GUARD_PAGE: usize = 0;
+
+/// Sets the 4K guard page below the stack of the current task on this CPU, or
+/// 0 if there is none. It should be updated when switching tasks.
+pub fn set_stack_guard_page(vaddr: usize) {
+    STACK_GUARD_PAGE.write_current(vaddr);
+}
+
+/// Returns the address range of the stack to handle the kernel stack
+/// overflows on the current CPU.
+pub fn overflow_stack_range() -> Range<usize> {
+    let start = unsafe { OVERFLOW_STACK.current_ptr() } as usize;
+    start..start + OVERFLOW_STACK_SIZE
+}
+
+/// Call the external stack guard handlers on a kernel page fault.
+#[allow(dead_code)]
+pub(crate) fn handle_stack_guard(vaddr: VirtAddr) {
+    for func in STACK_GUARD.iter() {
+        func(vaddr);
+    }
+}
+
+/// Call the external page fault handlers in turn until one of them handles the
+/// fault, e.g. the kernel one and the one of the user address spaces.
+#[allow(dead_code)]
+pub(crate) fn handle_page_fault(
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+    is_user: bool,
+) -> bool {
+    if PAGE_FAULT.is_empty() {
+        warn!("No registered handler for trap PAGE_FAULT");
+    }
+    PAGE_FAULT
+        .iter()
+        .any(|func| func(vaddr, access_flags, is_user))
+}
+
 /// Call the external syscall handler.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
@@ -1,24 +1,73 @@
 use core::fmt;
 
+use crate::backend::{move_page, split_huge_page, Backend, Fault, FetchedPage, PendingIo, Reclaim};
+use crate::lock::Access;
+use crate::mapping_err_to_ax_err;
+use crate::tlb::TlbState;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use axerrno::{ax_err, AxError, AxResult};
+use axfs_vfs::VfsNodeRef;
 use axhal::{
     mem::phys_to_virt,
-    paging::{MappingFlags, PageTable},
+    paging::{MappingFlags, PageSize, PageTable},
 };
 use memory_addr::{
-    is_aligned_4k, pa, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
+    is_aligned_4k, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
 };
 use memory_set::{MemoryArea, MemorySet};
-use crate::backend::Backend;
-use crate::paging_err_to_ax_err;
-use crate::mapping_err_to_ax_err;
-use alloc::vec::Vec;
+
+/// A handle to switch to an address space, see
+/// [`AddrSpace::page_table_handle`].
+#[derive(Clone)]
+pub struct PageTableHandle {
+    root: PhysAddr,
+    tlb: Arc<TlbState>,
+}
+
+impl PageTableHandle {
+    /// Switches the current CPU to the address space.
+    ///
+    /// The TLB entries are tagged with the ASID of the address space if it is
+    /// supported, so that the entries of the other address spaces are kept.
+    ///
+    /// # Safety
+    ///
+    /// The address space should map the kernel, and should not be dropped
+    /// while it is active. The IRQs should be disabled.
+    pub unsafe fn activate(&self) {
+        self.tlb.activate(self.root);
+    }
+
+    /// Whether the two handles are of the same address space.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.tlb, &other.tlb)
+    }
+}
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    /// The pages allocated on page faults, in the order they are scanned by
+    /// [`AddrSpace::reclaim`].
+    resident: VecDeque<VirtAddr>,
+    /// The pages locked by [`AddrSpace::mlock`], which are never reclaimed.
+    locked: BTreeSet<VirtAddr>,
+    tlb: Arc<TlbState>,
+    /// The resident set size in bytes, and its maximum.
+    rss: usize,
+    max_rss: usize,
+    /// The range of the mappings that the limits apply to, and the resident
+    /// set size within it.
+    limit_range: VirtAddrRange,
+    limited_rss: usize,
+    /// The limits of the size of the mappings and the resident set size
+    /// within `limit_range`.
+    vm_limit: usize,
+    rss_limit: usize,
 }
 
 impl AddrSpace {
@@ -59,9 +108,183 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            resident: VecDeque::new(),
+            locked: BTreeSet::new(),
+            tlb: Arc::new(TlbState::new()),
+            rss: 0,
+            max_rss: 0,
+            limit_range: VirtAddrRange::from_start_size(base, size),
+            limited_rss: 0,
+            vm_limit: usize::MAX,
+            rss_limit: usize::MAX,
         })
     }
 
+    /// Marks the address space as the kernel one, whose mappings are also in
+    /// the other address spaces.
+    pub(crate) fn set_kernel(&mut self) {
+        self.tlb = Arc::new(TlbState::kernel());
+    }
+
+    /// Returns the handle to switch to the address space without locking it,
+    /// e.g. on context switches.
+    pub fn page_table_handle(&self) -> PageTableHandle {
+        PageTableHandle {
+            root: self.page_table_root(),
+            tlb: self.tlb.clone(),
+        }
+    }
+
+    /// Returns the total size of the mappings in bytes.
+    ///
+    /// The linear mappings (e.g., of the kernel image and the physical memory)
+    /// are not counted.
+    pub fn vm_size(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| !matches!(area.backend(), Backend::Linear { .. }))
+            .map(|area| area.size())
+            .sum()
+    }
+
+    /// Returns the resident set size in bytes, i.e., the size of the pages
+    /// with frames allocated (they may be shared with the cloned address
+    /// spaces). The linear mappings are not counted.
+    pub const fn rss(&self) -> usize {
+        self.rss
+    }
+
+    /// Returns the maximum resident set size in bytes.
+    pub const fn max_rss(&self) -> usize {
+        self.max_rss
+    }
+
+    /// Returns the range of the mappings that [`AddrSpace::vm_limit`] and
+    /// [`AddrSpace::rss_limit`] apply to, the whole address space by default.
+    pub const fn limit_range(&self) -> VirtAddrRange {
+        self.limit_range
+    }
+
+    /// Sets the range of the mappings that the limits apply to, e.g. to limit
+    /// the mappings of the user programs only in the kernel address space.
+    pub fn set_limit_range(&mut self, range: VirtAddrRange) {
+        self.limit_range = range;
+        self.limited_rss = self.resident_size(range.start, range.size());
+    }
+
+    /// Returns the size of the mappings within [`AddrSpace::limit_range`],
+    /// like [`AddrSpace::vm_size`].
+    pub fn limited_vm_size(&self) -> usize {
+        let range = self.limit_range;
+        self.areas
+            .iter()
+            .filter(|area| !matches!(area.backend(), Backend::Linear { .. }))
+            .filter(|area| area.va_range().overlaps(range))
+            .map(|area| area.end().min(range.end) - area.start().max(range.start))
+            .sum()
+    }
+
+    /// Returns the resident set size within [`AddrSpace::limit_range`], like
+    /// [`AddrSpace::rss`].
+    pub const fn limited_rss(&self) -> usize {
+        self.limited_rss
+    }
+
+    /// Returns the limit of [`AddrSpace::limited_vm_size`], `usize::MAX` if
+    /// unlimited.
+    pub const fn vm_limit(&self) -> usize {
+        self.vm_limit
+    }
+
+    /// Sets the limit of [`AddrSpace::limited_vm_size`], `usize::MAX` for
+    /// unlimited.
+    ///
+    /// The new mappings exceeding it fail with [`AxError::NoMemory`]. Returns
+    /// an error if it is below the current size.
+    pub fn set_vm_limit(&mut self, limit: usize) -> AxResult {
+        if limit < self.limited_vm_size() {
+            return ax_err!(InvalidInput, "below the current size");
+        }
+        self.vm_limit = limit;
+        Ok(())
+    }
+
+    /// Returns the limit of [`AddrSpace::limited_rss`], `usize::MAX` if
+    /// unlimited.
+    pub const fn rss_limit(&self) -> usize {
+        self.rss_limit
+    }
+
+    /// Sets the limit of [`AddrSpace::limited_rss`], `usize::MAX` for
+    /// unlimited.
+    ///
+    /// When it is reached, the page faults reclaim some pages first, and fail
+    /// if none can be reclaimed. The populated mappings are not limited.
+    /// Returns an error if it is below the current size.
+    pub fn set_rss_limit(&mut self, limit: usize) -> AxResult {
+        if limit < self.limited_rss {
+            return ax_err!(InvalidInput, "below the current size");
+        }
+        self.rss_limit = limit;
+        Ok(())
+    }
+
+    /// Checks if `size` more bytes can be mapped at `start`.
+    fn check_vm_limit(&self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.limit_range.contains(start) {
+            return Ok(());
+        }
+        if self.limited_vm_size().saturating_add(size) > self.vm_limit {
+            return ax_err!(NoMemory, "exceeds the address space limit");
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the resident pages in `[start, start + size)`.
+    fn resident_size(&self, start: VirtAddr, size: usize) -> usize {
+        let range = VirtAddrRange::from_start_size(start, size);
+        let mut total = 0;
+        for area in self.areas.iter() {
+            if matches!(area.backend(), Backend::Linear { .. }) || !area.va_range().overlaps(range)
+            {
+                continue;
+            }
+            let end = range.end.min(area.end());
+            let mut addr = range.start.max(area.start());
+            while addr < end {
+                let next = match self.pt.query(addr) {
+                    Ok((_, flags, page_size)) => {
+                        let next = (addr.align_down(page_size) + page_size as usize).min(end);
+                        if !flags.is_empty() {
+                            total += next - addr;
+                        }
+                        next
+                    }
+                    Err(_) => addr + PAGE_SIZE_4K,
+                };
+                addr = next;
+            }
+        }
+        total
+    }
+
+    /// Adds `size` bytes of the resident pages at `start` (in one area).
+    fn add_rss(&mut self, start: VirtAddr, size: usize) {
+        self.rss += size;
+        self.max_rss = self.max_rss.max(self.rss);
+        if self.limit_range.contains(start) {
+            self.limited_rss += size;
+        }
+    }
+
+    /// Subtracts `size` bytes of the resident pages at `start` (in one area).
+    fn sub_rss(&mut self, start: VirtAddr, size: usize) {
+        self.rss -= size;
+        if self.limit_range.contains(start) {
+            self.limited_rss -= size;
+        }
+    }
+
     /// Copies page table mappings from another address space.
     ///
     /// It copies the page table entries only rather than the memory regions,
@@ -77,6 +300,51 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Clones the address space for `fork`.
+    ///
+    /// The frames of the allocation mappings are shared by the two address
+    /// spaces rather than copied. They are mapped read-only in both, and are
+    /// copied on the first write (see [`AddrSpace::handle_page_fault`]). The
+    /// kernel mappings of a user address space are copied as well.
+    pub fn clone_cow(&mut self) -> AxResult<Self> {
+        let mut new_aspace = Self::new_empty(self.base(), self.size())?;
+        let kernel_range = VirtAddrRange::from_start_size(
+            axconfig::KERNEL_ASPACE_BASE.into(),
+            axconfig::KERNEL_ASPACE_SIZE,
+        );
+        // Do not lock the kernel address space if it is being cloned.
+        if !self.va_range.overlaps(kernel_range) {
+            new_aspace.copy_mappings_from(&crate::kernel_aspace().lock())?;
+        }
+
+        for area in self.areas.iter() {
+            let backend = area.backend();
+            let new_area = MemoryArea::new(
+                area.start(),
+                area.size(),
+                area.flags(),
+                backend.clone_for_cow(),
+            );
+            new_aspace
+                .areas
+                .map(new_area, &mut new_aspace.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+            if !backend.share_frames(area.start(), area.size(), &mut self.pt, &mut new_aspace.pt) {
+                return ax_err!(BadState, "failed to share frames");
+            }
+        }
+        // The pages shared for copy-on-write are read-only now.
+        self.tlb.flush(self.base(), self.size());
+        new_aspace.resident = self.resident.clone();
+        new_aspace.rss = self.rss;
+        new_aspace.max_rss = self.rss;
+        new_aspace.limit_range = self.limit_range;
+        new_aspace.limited_rss = self.limited_rss;
+        new_aspace.vm_limit = self.vm_limit;
+        new_aspace.rss_limit = self.rss_limit;
+        Ok(new_aspace)
+    }
+
     /// Finds a free area that can accommodate the given size.
     ///
     /// The search starts from the given hint address, and the area should be within the given limit range.
@@ -115,17 +383,10 @@ impl AddrSpace {
         }
 
         let offset = start_vaddr.as_usize() - start_paddr.as_usize();
-        self.pt
-            .map_region(
-                start_vaddr,
-                |va| pa!(va.as_usize() - offset),
-                size,
-                flags,
-                false, // allow_huge
-                false, // flush_tlb_by_page
-            )
-            .map_err(paging_err_to_ax_err)?
-            .flush_all();
+        let area = MemoryArea::new(start_vaddr, size, flags, Backend::new_linear(offset));
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
         Ok(())
     }
 
@@ -143,6 +404,25 @@ impl AddrSpace {
         size: usize,
         flags: MappingFlags,
         populate: bool,
+    ) -> AxResult {
+        self.map_alloc_huge(start, size, flags, populate, PageSize::Size4K)
+    }
+
+    /// Add a new allocation mapping that uses huge pages up to `page_size`.
+    ///
+    /// The parts of the range aligned to the huge page size are mapped by huge
+    /// pages if the contiguous frames are available, and the others are mapped
+    /// by 4K pages as [`AddrSpace::map_alloc`].
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_alloc_huge(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        populate: bool,
+        page_size: PageSize,
     ) -> AxResult {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
@@ -150,33 +430,200 @@ impl AddrSpace {
         if !start.is_aligned_4k() || !is_aligned_4k(size) {
             return ax_err!(InvalidInput, "address not aligned");
         }
+        self.check_vm_limit(start, size)?;
 
-        let area = MemoryArea::new(start, size, flags, Backend::new_alloc(populate));
+        let area = MemoryArea::new(
+            start,
+            size,
+            flags,
+            Backend::new_alloc_huge(populate, page_size),
+        );
         self.areas
             .map(area, &mut self.pt, false)
             .map_err(mapping_err_to_ax_err)?;
+        if populate {
+            self.add_rss(start, self.resident_size(start, size));
+        }
         Ok(())
     }
 
-    /// Removes mappings within the specified virtual address range.
+    /// Add a new file mapping.
+    ///
+    /// `start` is mapped to the `offset` of `file`, and the pages are read from
+    /// the file on demand. If `shared` is `true`, the changes are written back
+    /// to the file on [`AddrSpace::msync`] and unmap. Otherwise, the changes
+    /// are private to the address space.
     ///
     /// Returns an error if the address range is out of the address space or not
     /// aligned.
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        file: VfsNodeRef,
+        offset: usize,
+        shared: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        self.check_vm_limit(start, size)?;
+
+        let area = MemoryArea::new(
+            start,
+            size,
+            flags,
+            Backend::new_file(file, start, offset, shared),
+        );
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Removes mappings within the specified virtual address range.
+    ///
+    /// The dirty pages of the shared file mappings within the range are written
+    /// back to the files.
+    ///
+    /// Returns an error if the address range is out of the address space, not
+    /// aligned, or overlaps linear mappings.
     pub fn unmap(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.with_io(|aspace, io| aspace.unmap_deferred(start, size, io))
+    }
+
+    /// Removes mappings like [`AddrSpace::unmap`], and leaves the write-back
+    /// to `io`.
+    pub(crate) fn unmap_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        io: &mut PendingIo,
+    ) -> AxResult {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
         }
         if !start.is_aligned_4k() || !is_aligned_4k(size) {
             return ax_err!(InvalidInput, "address not aligned");
         }
+        self.check_not_linear(start, size)?;
 
-        self.pt
-            .unmap_region(start, size, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        self.split_huge_pages_across(start, size)?;
+        let freed = self.resident_size(start, size);
+        self.detach_pages(start, size, io);
+        self.areas
+            .unmap(start, size, &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        self.tlb.flush(start, size);
+        self.sub_rss(start, freed);
+        let range = VirtAddrRange::from_start_size(start, size);
+        self.resident.retain(|&vaddr| !range.contains(vaddr));
+        self.locked.retain(|&vaddr| !range.contains(vaddr));
+        Ok(())
+    }
+
+    /// Checks that `[start, start + size)` does not overlap the linear
+    /// mappings, whose frames are not owned by the address space.
+    fn check_not_linear(&self, start: VirtAddr, size: usize) -> AxResult {
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.areas.iter().any(|area| {
+            matches!(area.backend(), Backend::Linear { .. }) && area.va_range().overlaps(range)
+        }) {
+            return ax_err!(InvalidInput, "overlaps linear mappings");
+        }
+        Ok(())
+    }
+
+    /// Detaches the pages within `[start, start + size)` that need I/O to be
+    /// put, before they are unmapped or discarded, see [`Backend::detach_pages`].
+    fn detach_pages(&mut self, start: VirtAddr, size: usize, io: &mut PendingIo) {
+        let range = VirtAddrRange::from_start_size(start, size);
+        for area in self
+            .areas
+            .iter()
+            .filter(|area| area.va_range().overlaps(range))
+        {
+            let start = start.max(area.start());
+            let end = range.end.min(area.end());
+            area.backend()
+                .detach_pages(start, end - start, &mut self.pt, io);
+        }
+    }
+
+    /// Splits the huge pages across the boundaries of `[start, start + size)`,
+    /// so that the range can be unmapped or protected separately.
+    fn split_huge_pages_across(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        for vaddr in [start, start + size] {
+            if let Ok((_, _, page_size)) = self.pt.query(vaddr) {
+                if page_size.is_huge()
+                    && !vaddr.is_aligned(page_size)
+                    && !split_huge_page(&mut self.pt, vaddr)
+                {
+                    return ax_err!(NoMemory, "failed to split huge page");
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Writes the dirty pages of the shared file mappings within the specified
+    /// virtual address range back to the files.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn msync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.with_io(|aspace, io| aspace.sync_deferred(start, size, io))
+    }
+
+    /// Finds the dirty pages like [`AddrSpace::msync`], and leaves the
+    /// write-back to `io`.
+    pub(crate) fn sync_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        io: &mut PendingIo,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let range = VirtAddrRange::from_start_size(start, size);
+        for area in self
+            .areas
+            .iter()
+            .filter(|area| area.va_range().overlaps(range))
+        {
+            let start = start.max(area.start());
+            let end = range.end.min(area.end());
+            area.backend().sync(start, end - start, &self.pt, io);
+        }
+        Ok(())
+    }
+
+    /// Finishes the I/O left to `io` after it is run, see
+    /// [`PendingIo::finish`].
+    ///
+    /// Returns the number of the pages swapped out.
+    pub(crate) fn finish_io(&mut self, io: &mut PendingIo) -> usize {
+        let swapped = io.finish(&mut self.pt, &self.tlb, &self.locked);
+        if !swapped.is_empty() {
+            // Only 4K pages are swapped out, see `Backend::swap_out_page`.
+            for &vaddr in swapped.iter() {
+                self.sub_rss(vaddr, PAGE_SIZE_4K);
+            }
+            let pages = swapped.iter().copied().collect::<BTreeSet<_>>();
+            self.resident.retain(|vaddr| !pages.contains(vaddr));
+        }
+        swapped.len()
+    }
+
     /// To process data in this area with the given function.
     ///
     /// Now it supports reading and writing data in the given interval.
@@ -193,7 +640,11 @@ impl AddrSpace {
         for vaddr in PageIter4K::new(start.align_down_4k(), end_align_up)
             .expect("Failed to create page iterator")
         {
-            let (mut paddr, _, _) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
+            let (mut paddr, flags, _) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
+            if flags.is_empty() {
+                // Not allocated yet, or swapped out.
+                return ax_err!(BadAddress);
+            }
 
             let mut copy_size = (size - cnt).min(PAGE_SIZE_4K);
 
@@ -237,42 +688,507 @@ impl AddrSpace {
 
     /// Updates mapping within the specified virtual address range.
     ///
-    /// Returns an error if the address range is out of the address space or not
-    /// aligned.
+    /// The dirty pages of the shared file mappings are written back first if
+    /// they become read-only.
+    ///
+    /// Returns an error if the address range is out of the address space, not
+    /// aligned, or overlaps linear mappings (see [`AddrSpace::protect_linear`]).
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        self.protect_pages(start, size, flags)
+    }
+
+    /// Updates mapping like [`AddrSpace::protect`]. If the dirty pages of the
+    /// shared file mappings become read-only, they are left to `io` to be
+    /// written back instead, and it returns `false` to be called again after
+    /// that.
+    pub(crate) fn protect_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        io: &mut PendingIo,
+    ) -> AxResult<bool> {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
         }
         if !start.is_aligned_4k() || !is_aligned_4k(size) {
             return ax_err!(InvalidInput, "address not aligned");
         }
+        self.check_not_linear(start, size)?;
+        if !flags.contains(MappingFlags::WRITE) {
+            self.sync_deferred(start, size, io)?;
+            if !io.is_empty() {
+                return Ok(false);
+            }
+        }
+
+        self.split_huge_pages_across(start, size)?;
+        self.areas
+            .protect(start, size, |_| Some(flags), &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        self.tlb.flush(start, size);
+        Ok(true)
+    }
+
+    /// Updates the linear mappings within the specified virtual address range,
+    /// e.g. to map the DMA memory uncached.
+    ///
+    /// Returns an error if the address range is not aligned, or not fully
+    /// covered by linear mappings.
+    pub fn protect_linear(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> AxResult {
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            match self.areas.find(addr) {
+                Some(area) if matches!(area.backend(), Backend::Linear { .. }) => addr = area.end(),
+                _ => return ax_err!(InvalidInput, "not linear mappings"),
+            }
+        }
 
-        self.pt
-            .protect_region(start, size, flags, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        self.split_huge_pages_across(start, size)?;
+        self.areas
+            .protect(start, size, |_| Some(flags), &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        self.tlb.flush(start, size);
         Ok(())
     }
 
+    /// Resizes the mapping `[old_start, old_start + old_size)`, which should be
+    /// within one area, and returns the start address after resizing.
+    ///
+    /// Shrinking unmaps the tail. Growing maps the new tail in place if the
+    /// range after the mapping is free. Otherwise, if `may_move` is `true`, the
+    /// pages are moved to a new range with their frames (the moved allocation
+    /// mappings are populated on demand afterwards).
+    ///
+    /// Returns an error if the range is not mapped, is a linear mapping, or
+    /// cannot be grown.
+    pub fn mremap(
+        &mut self,
+        old_start: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        may_move: bool,
+    ) -> AxResult<VirtAddr> {
+        self.with_io(|aspace, io| {
+            aspace.mremap_deferred(old_start, old_size, new_size, may_move, io)
+        })
+    }
+
+    /// Resizes the mapping like [`AddrSpace::mremap`], and leaves the
+    /// write-back of the unmapped tail to `io`.
+    pub(crate) fn mremap_deferred(
+        &mut self,
+        old_start: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        may_move: bool,
+        io: &mut PendingIo,
+    ) -> AxResult<VirtAddr> {
+        if !self.contains_range(old_start, old_size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !old_start.is_aligned_4k() || !is_aligned_4k(old_size) || !is_aligned_4k(new_size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if new_size == 0 {
+            return ax_err!(InvalidInput, "zero size");
+        }
+        let old_end = old_start + old_size;
+        let area = match self.areas.find(old_start) {
+            Some(area) if area.end() >= old_end => area,
+            _ => return ax_err!(BadAddress, "not mapped in one area"),
+        };
+        let (flags, backend) = (area.flags(), area.backend().clone());
+        if let Backend::Linear { .. } = backend {
+            return ax_err!(InvalidInput, "cannot remap linear mappings");
+        }
+
+        if new_size <= old_size {
+            if new_size < old_size {
+                self.unmap_deferred(old_start + new_size, old_size - new_size, io)?;
+            }
+            return Ok(old_start);
+        }
+
+        self.check_vm_limit(old_start, new_size - old_size)?;
+        let tail = VirtAddrRange::from_start_size(old_end, new_size - old_size);
+        if self.va_range.contains_range(tail) && !self.areas.overlaps(tail) {
+            // The backends map the addresses after the area in the same way.
+            let area_start = area.start();
+            let area = MemoryArea::new(tail.start, tail.size(), flags, backend.clone());
+            self.areas
+                .map(area, &mut self.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+            self.add_rss(tail.start, self.resident_size(tail.start, tail.size()));
+            // Merge the tail into the area, whose pages have been mapped.
+            let area = MemoryArea::new(
+                area_start,
+                tail.end - area_start,
+                flags,
+                backend.relocate(area_start, area_start),
+            );
+            self.replace_areas(area)?;
+            return Ok(old_start);
+        }
+        if !may_move {
+            return ax_err!(NoMemory, "cannot grow in place");
+        }
+
+        // Keep it within the limit range if it is in.
+        let limit = if self.limit_range.contains(old_start) {
+            self.limit_range
+        } else {
+            self.va_range
+        };
+        let new_start = self
+            .find_free_area(old_end, new_size, limit)
+            .or_else(|| self.find_free_area(limit.start, new_size, limit))
+            .ok_or(AxError::NoMemory)?;
+        let area = MemoryArea::new(
+            new_start,
+            new_size,
+            flags,
+            backend.relocate(old_start, new_start),
+        );
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        for offset in (0..old_size).step_by(PAGE_SIZE_4K) {
+            if !move_page(&mut self.pt, old_start + offset, new_start + offset) {
+                // Move the pages back, whose page tables are still there.
+                for offset in (0..offset).step_by(PAGE_SIZE_4K) {
+                    move_page(&mut self.pt, new_start + offset, old_start + offset);
+                }
+                self.areas
+                    .unmap(new_start, new_size, &mut self.pt)
+                    .map_err(mapping_err_to_ax_err)?;
+                self.tlb.flush(new_start, new_size);
+                return ax_err!(NoMemory, "failed to move page");
+            }
+        }
+
+        let old_range = VirtAddrRange::from_start_size(old_start, old_size);
+        let moved = |vaddr: VirtAddr| {
+            if old_range.contains(vaddr) {
+                new_start + (vaddr - old_start)
+            } else {
+                vaddr
+            }
+        };
+        self.resident
+            .iter_mut()
+            .for_each(|vaddr| *vaddr = moved(*vaddr));
+        self.locked = self.locked.iter().map(|&vaddr| moved(vaddr)).collect();
+        // The entries have been cleared, nothing is freed.
+        self.unmap_deferred(old_start, old_size, io)?;
+        Ok(new_start)
+    }
+
+    /// Replaces the areas within the range of `area` by `area`, without
+    /// changing the mappings in the page table.
+    ///
+    /// The backend of `area` must not populate the pages, like the one from
+    /// [`Backend::relocate`].
+    fn replace_areas(&mut self, area: MemoryArea<Backend>) -> AxResult {
+        // The areas are unmapped from and mapped to an empty page table, which
+        // is dropped with the entries afterwards.
+        let mut scratch = PageTable::try_new().map_err(|_| AxError::NoMemory)?;
+        self.areas
+            .unmap(area.start(), area.size(), &mut scratch)
+            .map_err(mapping_err_to_ax_err)?;
+        self.areas
+            .map(area, &mut scratch, false)
+            .map_err(mapping_err_to_ax_err)
+    }
+
+    /// Drops the frames within the specified virtual address range like
+    /// `MADV_DONTNEED`.
+    ///
+    /// The anonymous pages are zero-filled, and the file pages are read from
+    /// the files again on the next access. The dirty pages of the shared file
+    /// mappings are written back first.
+    ///
+    /// Returns an error if the range is not fully mapped, or contains locked
+    /// pages or linear mappings.
+    pub fn discard(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.with_io(|aspace, io| aspace.discard_deferred(start, size, io))
+    }
+
+    /// Drops the frames like [`AddrSpace::discard`], and leaves the write-back
+    /// to `io`.
+    pub(crate) fn discard_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        io: &mut PendingIo,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if !self.is_mapped(start, size) {
+            return ax_err!(NoMemory, "range not mapped");
+        }
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.locked.range(range.start..range.end).next().is_some() {
+            return ax_err!(InvalidInput, "range locked");
+        }
+
+        self.split_huge_pages_across(start, size)?;
+        let freed = self.resident_size(start, size);
+        self.detach_pages(start, size, io);
+        for area in self
+            .areas
+            .iter()
+            .filter(|area| area.va_range().overlaps(range))
+        {
+            let start = start.max(area.start());
+            let end = range.end.min(area.end());
+            if !area
+                .backend()
+                .discard(start, end - start, area.flags(), &mut self.pt)
+            {
+                return ax_err!(InvalidInput, "failed to discard pages");
+            }
+        }
+        self.tlb.flush(start, size);
+        self.sub_rss(start, freed);
+        self.resident.retain(|&vaddr| !range.contains(vaddr));
+        Ok(())
+    }
+
+    /// Locks the pages within the specified virtual address range in memory.
+    ///
+    /// The pages are allocated (or read back) now, and are never reclaimed
+    /// until they are unlocked by [`AddrSpace::munlock`] or unmapped.
+    ///
+    /// Returns an error if the range is not fully mapped, or the pages cannot
+    /// be allocated.
+    pub fn mlock(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.lock_pages(start, size)
+    }
+
+    /// Locks the pages within the range if all the accessible ones are
+    /// resident. Otherwise, returns the first one that is not, searching from
+    /// `next` on, to be faulted in first.
+    pub(crate) fn lock_resident(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        next: VirtAddr,
+    ) -> AxResult<Option<VirtAddr>> {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if !self.is_mapped(start, size) {
+            return ax_err!(NoMemory, "range not mapped");
+        }
+
+        let end = start + size;
+        let missing = |from: VirtAddr, to: VirtAddr| {
+            PageIter4K::new(from, to).unwrap().find(|&vaddr| {
+                let accessible = self
+                    .areas
+                    .find(vaddr)
+                    .is_some_and(|area| !area.flags().is_empty());
+                accessible && !self.is_resident(vaddr)
+            })
+        };
+        if let Some(vaddr) = missing(next, end).or_else(|| missing(start, next)) {
+            return Ok(Some(vaddr));
+        }
+        self.locked.extend(PageIter4K::new(start, end).unwrap());
+        Ok(None)
+    }
+
+    /// Unlocks the pages within the specified virtual address range, so that
+    /// they can be reclaimed again.
+    pub fn munlock(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let range = VirtAddrRange::from_start_size(start, size);
+        self.locked.retain(|&vaddr| !range.contains(vaddr));
+        Ok(())
+    }
+
+    /// Checks if the address range is fully covered by the areas.
+    fn is_mapped(&self, start: VirtAddr, size: usize) -> bool {
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            match self.areas.find(addr) {
+                Some(area) => addr = area.end(),
+                None => return false,
+            }
+        }
+        true
+    }
+
     /// Handles a page fault at the given address.
     ///
     /// `access_flags` indicates the access type that caused the page fault.
     ///
     /// Returns `true` if the page fault is handled successfully (not a real
     /// fault).
+    ///
+    /// If it fails as a frame cannot be allocated, some pages of this and the
+    /// other address spaces are reclaimed (see [`AddrSpace::reclaim`] and
+    /// [`crate::register_aspace`]), and it is retried once. The pages within
+    /// [`AddrSpace::limit_range`] are also reclaimed first if the
+    /// [`AddrSpace::rss_limit`] is reached, and it fails if none can be
+    /// reclaimed.
     pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        self.page_fault(vaddr, access_flags)
+    }
+
+    /// Handles a page fault with the address space locked. The pages to read
+    /// are fetched without the lock by the caller, see [`Fault`].
+    pub(crate) fn try_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+        fetched: &mut Option<FetchedPage>,
+    ) -> Fault {
         if !self.va_range.contains(vaddr) {
-            return false;
+            return Fault::Done(false);
         }
-        if let Some(area) = self.areas.find(vaddr) {
-            let orig_flags = area.flags();
-            if orig_flags.contains(access_flags) {
-                return area
-                    .backend()
-                    .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+        let Some(area) = self.areas.find(vaddr) else {
+            return Fault::Done(false);
+        };
+        let orig_flags = area.flags();
+        if !orig_flags.contains(access_flags) {
+            return Fault::Done(false);
+        }
+        let backend = area.backend().clone();
+        let area_range = area.va_range();
+        let page = vaddr.align_down_4k();
+        // The accessed bit cleared by `reclaim`, on the CPUs that fault rather
+        // than set it.
+        if axhal::paging::set_accessed(&mut self.pt, page) {
+            self.tlb.flush(page, PAGE_SIZE_4K);
+            return Fault::Done(true);
+        }
+        let was_resident = self.is_resident(page);
+        if !was_resident && self.limit_range.contains(page) && self.limited_rss >= self.rss_limit {
+            return Fault::OverLimit;
+        }
+        let fault = backend.handle_page_fault(vaddr, orig_flags, area_range, &mut self.pt, fetched);
+        if let Fault::Done(true) = fault {
+            if !was_resident {
+                self.resident.push_back(page);
+                let page_size = self
+                    .pt
+                    .query(page)
+                    .map_or(PAGE_SIZE_4K, |(_, _, size)| size as _);
+                self.add_rss(page, page_size);
+            } else {
+                // Copied on write, or made writable.
+                self.tlb.flush(page, PAGE_SIZE_4K);
             }
         }
-        false
+        fault
+    }
+
+    /// Reclaims at most `count` pages allocated on page faults, which have not
+    /// been accessed recently.
+    ///
+    /// The pages are scanned in turn like a clock. A page accessed since it
+    /// was scanned last time is given a second chance, with its accessed bit
+    /// cleared, see [`axhal::paging::test_and_clear_accessed`].
+    ///
+    /// The clean pages of the file mappings are dropped, and the others are
+    /// written to the swap device (see [`crate::swap_on`]). The pages shared
+    /// with other address spaces are skipped.
+    ///
+    /// Returns the number of pages reclaimed.
+    pub fn reclaim(&mut self, count: usize) -> usize {
+        self.reclaim_pages(count, false)
+    }
+
+    /// Reclaims pages like [`AddrSpace::reclaim`] with the address space
+    /// locked. The pages to write back or swap out are left to `io`, and the
+    /// swapped out ones are unmapped by [`AddrSpace::finish_io`].
+    ///
+    /// Only the pages within [`AddrSpace::limit_range`] are reclaimed if
+    /// `limited`, e.g. when [`AddrSpace::rss_limit`] is reached.
+    ///
+    /// Returns the number of pages unmapped now.
+    pub(crate) fn reclaim_deferred(
+        &mut self,
+        count: usize,
+        limited: bool,
+        io: &mut PendingIo,
+    ) -> usize {
+        let mut reclaimed = 0;
+        let mut pending = 0;
+        // Each page is scanned at most twice, the second time not accessed.
+        for _ in 0..self.resident.len() * 2 {
+            if reclaimed + pending >= count {
+                break;
+            }
+            let Some(vaddr) = self.resident.pop_front() else {
+                break;
+            };
+            let Some(area) = self.areas.find(vaddr) else {
+                continue;
+            };
+            if self.locked.contains(&vaddr) || (limited && !self.limit_range.contains(vaddr)) {
+                self.resident.push_back(vaddr);
+                continue;
+            }
+            if axhal::paging::test_and_clear_accessed(&mut self.pt, vaddr) == Some(true) {
+                self.tlb.flush(vaddr, PAGE_SIZE_4K);
+                self.resident.push_back(vaddr);
+                continue;
+            }
+            let page_size = self
+                .pt
+                .query(vaddr)
+                .map_or(PAGE_SIZE_4K, |(_, _, size)| size as usize);
+            match area.backend().reclaim_page(vaddr, &mut self.pt, io) {
+                Reclaim::Freed => {
+                    self.tlb.flush(vaddr, page_size);
+                    self.sub_rss(vaddr, page_size);
+                    reclaimed += 1;
+                }
+                Reclaim::SwapOut => {
+                    // Read-only on all CPUs before it is written. It stays in
+                    // the list until it is unmapped, and is skipped meanwhile
+                    // as its frame is held.
+                    self.tlb.flush(vaddr, page_size);
+                    self.resident.push_back(vaddr);
+                    pending += 1;
+                }
+                // Try it again later.
+                Reclaim::Kept if self.is_resident(vaddr) => self.resident.push_back(vaddr),
+                Reclaim::Kept => {}
+            }
+        }
+        debug!("reclaimed {} pages, {} to swap out", reclaimed, pending);
+        reclaimed
+    }
+
+    fn is_resident(&self, vaddr: VirtAddr) -> bool {
+        matches!(self.pt.query(vaddr), Ok((_, flags, _)) if !flags.is_empty())
     }
 
     pub fn translated_byte_buffer(
@@ -324,6 +1240,15 @@ impl AddrSpace {
     }
 }
 
+impl Drop for AddrSpace {
+    fn drop(&mut self) {
+        // Free the allocated frames, or drop the references to the shared ones.
+        if let Err(err) = self.areas.clear(&mut self.pt) {
+            warn!("AddrSpace drop: {:?}", err);
+        }
+    }
+}
+
 impl fmt::Debug for AddrSpace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddrSpace")
@@ -1,12 +1,35 @@
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use axalloc::global_allocator;
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
-use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::io::{Fault, FetchedPage, PageFetch, PendingIo};
+use super::{huge_page_at, split_huge_page, Backend, Reclaim};
+use crate::swap;
 
-use super::Backend;
+/// Reference counts of the frames shared by copy-on-write mappings. The frames
+/// not in it have only one owner.
+static SHARED_FRAMES: SpinNoIrq<BTreeMap<PhysAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
 
-fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
-    let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
+/// The number of the failed frame allocations, to tell whether a page fault
+/// failed for lack of memory.
+static ALLOC_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of the failed frame allocations so far.
+pub(crate) fn alloc_failures() -> usize {
+    ALLOC_FAILURES.load(Ordering::Relaxed)
+}
+
+pub(super) fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
+    let Ok(vaddr) = global_allocator().alloc_pages(1, PAGE_SIZE_4K) else {
+        ALLOC_FAILURES.fetch_add(1, Ordering::Relaxed);
+        return None;
+    };
+    let vaddr = VirtAddr::from(vaddr);
     if zeroed {
         unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
     }
@@ -14,15 +37,89 @@ fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     Some(paddr)
 }
 
-fn dealloc_frame(frame: PhysAddr) {
+/// Allocates contiguous frames for a huge page.
+fn alloc_huge_frame(page_size: PageSize, zeroed: bool) -> Option<PhysAddr> {
+    let size = page_size as usize;
+    let vaddr = VirtAddr::from(
+        global_allocator()
+            .alloc_pages(size / PAGE_SIZE_4K, size)
+            .ok()?,
+    );
+    if zeroed {
+        unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, size) };
+    }
+    Some(virt_to_phys(vaddr))
+}
+
+/// Frees the frames of a huge page, which are never shared.
+pub(super) fn dealloc_huge_frame(frame: PhysAddr, page_size: PageSize) {
+    let vaddr = phys_to_virt(frame);
+    global_allocator().dealloc_pages(vaddr.as_usize(), page_size as usize / PAGE_SIZE_4K);
+}
+
+/// Maps the 4K frame (or the swap entry) at `vaddr`, the page table entry may
+/// have not been created if it is in a huge page range.
+pub(super) fn map_frame(
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    flags: MappingFlags,
+    pt: &mut PageTable,
+) -> bool {
+    pt.remap(vaddr, frame, flags)
+        .map(|(_, tlb)| tlb.flush())
+        .or_else(|_| {
+            pt.map(vaddr.align_down_4k(), frame, PageSize::Size4K, flags)
+                .map(|tlb| tlb.flush())
+        })
+        .is_ok()
+}
+
+pub(super) fn share_frame(frame: PhysAddr) {
+    *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1;
+}
+
+pub(super) fn is_frame_shared(frame: PhysAddr) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame)
+}
+
+/// Drops a reference to the frame, and frees it if it is the last one.
+pub(super) fn dealloc_frame(frame: PhysAddr) {
+    {
+        let mut shared = SHARED_FRAMES.lock();
+        if let Some(refs) = shared.get_mut(&frame) {
+            *refs -= 1;
+            if *refs == 1 {
+                shared.remove(&frame);
+            }
+            return;
+        }
+    }
     let vaddr = phys_to_virt(frame);
     global_allocator().dealloc_pages(vaddr.as_usize(), 1);
 }
 
+/// Drops the reference to the swap slot if the page at `vaddr` is swapped out.
+pub(super) fn put_swapped(vaddr: VirtAddr, pt: &PageTable) {
+    if let Ok((entry, flags, _)) = pt.query(vaddr) {
+        if let Some(slot) = swap::entry_slot(entry).filter(|_| flags.is_empty()) {
+            swap::put_slot(slot);
+        }
+    }
+}
+
 impl Backend {
     /// Creates a new allocation mapping backend.
     pub const fn new_alloc(populate: bool) -> Self {
-        Self::Alloc { populate }
+        Self::new_alloc_huge(populate, PageSize::Size4K)
+    }
+
+    /// Creates a new allocation mapping backend that uses huge pages up to
+    /// `page_size`.
+    pub const fn new_alloc_huge(populate: bool, page_size: PageSize) -> Self {
+        Self::Alloc {
+            populate,
+            page_size,
+        }
     }
 
     pub(crate) fn map_alloc(
@@ -32,17 +129,33 @@ impl Backend {
         flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
+        page_size: PageSize,
     ) -> bool {
         debug!(
-            "map_alloc: [{:#x}, {:#x}) {:?} (populate={})",
+            "map_alloc: [{:#x}, {:#x}) {:?} (populate={}, page_size={:?})",
             start,
             start + size,
             flags,
-            populate
+            populate,
+            page_size
         );
         if populate {
             // allocate all possible physical frames for populated mapping.
-            for addr in PageIter4K::new(start, start + size).unwrap() {
+            let range = VirtAddrRange::from_start_size(start, size);
+            let mut addr = start;
+            while addr < range.end {
+                if let Some((_, huge_size)) = huge_page_at(addr, range, page_size)
+                    .filter(|&(huge_start, _)| huge_start == addr)
+                {
+                    if let Some(frame) = alloc_huge_frame(huge_size, true) {
+                        if let Ok(tlb) = pt.map(addr, frame, huge_size, flags) {
+                            tlb.ignore();
+                            addr += huge_size as usize;
+                            continue;
+                        }
+                        dealloc_huge_frame(frame, huge_size);
+                    }
+                }
                 if let Some(frame) = alloc_frame(true) {
                     if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
                         tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
@@ -50,8 +163,13 @@ impl Backend {
                         return false;
                     }
                 }
+                addr += PAGE_SIZE_4K;
             }
             true
+        } else if page_size.is_huge() {
+            // Leave the entries uncreated, so that huge pages can be mapped on
+            // demand.
+            true
         } else {
             // Map to a empty entry for on-demand mapping.
             let flags = MappingFlags::empty();
@@ -69,18 +187,28 @@ impl Backend {
         _populate: bool,
     ) -> bool {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
-        for addr in PageIter4K::new(start, start + size).unwrap() {
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            put_swapped(addr, pt);
             if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                 // Deallocate the physical frame if there is a mapping in the
                 // page table.
+                tlb.flush();
                 if page_size.is_huge() {
-                    return false;
+                    // The huge pages across the boundaries have been split.
+                    if !addr.is_aligned(page_size) || addr + page_size as usize > end {
+                        return false;
+                    }
+                    dealloc_huge_frame(frame, page_size);
+                    addr += page_size as usize;
+                    continue;
                 }
-                tlb.flush();
                 dealloc_frame(frame);
             } else {
                 // Deallocation is needn't if the page is not mapped.
             }
+            addr += PAGE_SIZE_4K;
         }
         true
     }
@@ -91,18 +219,230 @@ impl Backend {
         orig_flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
+        huge_page: Option<(VirtAddr, PageSize)>,
+        fetched: &mut Option<FetchedPage>,
+    ) -> Fault {
+        let handled = match pt.query(vaddr.align_down_4k()) {
+            Ok((frame, flags, page_size)) if !flags.is_empty() => {
+                if orig_flags.contains(MappingFlags::WRITE)
+                    && !flags.contains(MappingFlags::WRITE)
+                    && !page_size.is_huge()
+                {
+                    // Write to a copy-on-write page.
+                    Self::copy_on_write(vaddr, frame, orig_flags, pt)
+                } else {
+                    false
+                }
+            }
+            Ok((entry, ..)) if swap::entry_slot(entry).is_some() => {
+                return Self::swap_in_page(vaddr, entry, orig_flags, pt, fetched);
+            }
+            _ if populate => false, // Populated mappings should not trigger page faults.
+            Err(_)
+                if huge_page.is_some_and(|(start, size)| {
+                    Self::map_huge_page(start, size, orig_flags, pt)
+                }) =>
+            {
+                true
+            }
+            _ => match alloc_frame(true) {
+                // Allocate a physical frame lazily and map it to the fault address.
+                // `vaddr` does not need to be aligned. It will be automatically
+                // aligned during `pt.remap` regardless of the page size.
+                Some(frame) => {
+                    let mapped = map_frame(vaddr, frame, orig_flags, pt);
+                    if !mapped {
+                        dealloc_frame(frame);
+                    }
+                    mapped
+                }
+                None => false,
+            },
+        };
+        Fault::Done(handled)
+    }
+
+    /// Allocates a huge page and maps it at `start`.
+    ///
+    /// Returns `false` if no contiguous frames are available, or some pages in
+    /// the range have been mapped.
+    fn map_huge_page(
+        start: VirtAddr,
+        page_size: PageSize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
     ) -> bool {
-        if populate {
-            false // Populated mappings should not trigger page faults.
-        } else if let Some(frame) = alloc_frame(true) {
-            // Allocate a physical frame lazily and map it to the fault address.
-            // `vaddr` does not need to be aligned. It will be automatically
-            // aligned during `pt.remap` regardless of the page size.
-            pt.remap(vaddr, frame, orig_flags)
+        let Some(frame) = alloc_huge_frame(page_size, false) else {
+            return false;
+        };
+        // Reserve the range with a mapping inaccessible to the user first, to
+        // avoid zeroing the frames in vain if it fails.
+        if pt.map(start, frame, page_size, MappingFlags::READ).is_err() {
+            dealloc_huge_frame(frame, page_size);
+            return false;
+        }
+        unsafe { core::ptr::write_bytes(phys_to_virt(frame).as_mut_ptr(), 0, page_size as usize) };
+        pt.protect(start, flags).map(|(_, tlb)| tlb.flush()).is_ok()
+    }
+
+    /// Makes the page at `vaddr` writable, copies the frame first if it is
+    /// still shared with other address spaces.
+    pub(super) fn copy_on_write(
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        if !is_frame_shared(frame) {
+            // The last reference, reuse the frame.
+            return pt
+                .remap(vaddr, frame, orig_flags)
                 .map(|(_, tlb)| tlb.flush())
-                .is_ok()
-        } else {
-            false
+                .is_ok();
+        }
+        let Some(new_frame) = alloc_frame(false) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(new_frame).as_mut_ptr(),
+                PAGE_SIZE_4K,
+            )
+        };
+        match pt.remap(vaddr, new_frame, orig_flags) {
+            Ok((_, tlb)) => {
+                tlb.flush();
+                dealloc_frame(frame);
+                true
+            }
+            Err(_) => {
+                dealloc_frame(new_frame);
+                false
+            }
         }
     }
+
+    /// Leaves the page at `vaddr` to `io` to be written to the swap device,
+    /// and makes it read-only until then. The entry refers to the swap slot
+    /// instead once it is written, and the page is read back on the next page
+    /// fault.
+    ///
+    /// Returns [`Reclaim::Kept`] if the page is not mapped, is shared with
+    /// other address spaces, or there is no free swap slot.
+    pub(super) fn swap_out_page(
+        vaddr: VirtAddr,
+        pt: &mut PageTable,
+        io: &mut PendingIo,
+    ) -> Reclaim {
+        let Ok((frame, flags, page_size)) = pt.query(vaddr) else {
+            return Reclaim::Kept;
+        };
+        if flags.is_empty() || page_size.is_huge() || is_frame_shared(frame) {
+            return Reclaim::Kept;
+        }
+        let Some(slot) = swap::alloc_slot() else {
+            return Reclaim::Kept;
+        };
+        // Write-protect it first, so that it is not changed while being
+        // written.
+        match pt.protect(vaddr, flags - MappingFlags::WRITE) {
+            Ok((_, tlb)) => tlb.flush(),
+            Err(_) => {
+                swap::put_slot(slot);
+                return Reclaim::Kept;
+            }
+        }
+        io.swap_out(vaddr, frame, flags, slot);
+        Reclaim::SwapOut
+    }
+
+    /// Reads the swapped out page at `vaddr` back from the swap slot that
+    /// `entry` refers to. The page is asked for by [`Fault::Fetch`] first, and
+    /// is mapped when it is in `fetched`.
+    pub(super) fn swap_in_page(
+        vaddr: VirtAddr,
+        entry: PhysAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        fetched: &mut Option<FetchedPage>,
+    ) -> Fault {
+        let Some(slot) = swap::entry_slot(entry) else {
+            return Fault::Done(false);
+        };
+        let req = PageFetch::SwapIn(slot);
+        let Some(page) = FetchedPage::take(fetched, &req) else {
+            swap::dup_slot(slot);
+            return Fault::Fetch(req);
+        };
+        match pt.remap(vaddr, page.frame(), orig_flags) {
+            Ok((_, tlb)) => {
+                tlb.flush();
+                // Drop the references of the page table and the request.
+                swap::put_slot(slot);
+                swap::put_slot(slot);
+                Fault::Done(true)
+            }
+            Err(_) => {
+                *fetched = Some(page);
+                Fault::Done(false)
+            }
+        }
+    }
+
+    /// Shares the mapped frames in `[start, start + size)` of `pt` with the
+    /// same range of `new_pt`, which should have been mapped to empty entries.
+    /// If `cow` is `true`, the pages become read-only in both page tables, and
+    /// are copied on the first write.
+    pub(crate) fn share_frames_alloc(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        new_pt: &mut PageTable,
+        cow: bool,
+    ) -> bool {
+        debug!("share_frames_alloc: [{:#x}, {:#x})", start, start + size);
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((_, _, page_size)) = pt.query(addr) else {
+                continue; // Not allocated yet.
+            };
+            // Share the huge pages by 4K pages to copy them separately.
+            if page_size.is_huge() && !split_huge_page(pt, addr) {
+                return false;
+            }
+            let Ok((frame, flags, page_size)) = pt.query(addr) else {
+                continue;
+            };
+            if flags.is_empty() {
+                if let Some(slot) = swap::entry_slot(frame) {
+                    // Swapped out, both refer to the same slot.
+                    if new_pt.remap(addr, frame, flags).is_err() {
+                        return false;
+                    }
+                    swap::dup_slot(slot);
+                }
+                continue;
+            }
+            if page_size.is_huge() {
+                return false;
+            }
+            let flags = if cow {
+                let flags = flags - MappingFlags::WRITE;
+                if let Ok((_, tlb)) = pt.protect(addr, flags) {
+                    tlb.flush();
+                } else {
+                    return false;
+                }
+                flags
+            } else {
+                flags
+            };
+            if new_pt.remap(addr, frame, flags).is_err() {
+                return false;
+            }
+            share_frame(frame);
+        }
+        true
+    }
 }
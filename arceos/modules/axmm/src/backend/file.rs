@@ -0,0 +1,353 @@
+use axfs_vfs::{VfsError, VfsNodeRef};
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::paging::{MappingFlags, PageTable};
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{dealloc_frame, dealloc_huge_frame, is_frame_shared, put_swapped};
+use super::io::{Fault, FetchedPage, PageFetch, PendingIo};
+use super::{Backend, Reclaim};
+use crate::swap;
+
+fn frame_bytes<'a>(frame: PhysAddr) -> &'a mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Reads the file at `offset` into the frame, the part beyond the end of the
+/// file is left unchanged.
+pub(super) fn read_page(file: &VfsNodeRef, offset: u64, frame: PhysAddr) -> bool {
+    let buf = frame_bytes(frame);
+    let mut read = 0;
+    while read < PAGE_SIZE_4K {
+        match file.read_at(offset + read as u64, &mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Writes the frame back to the file at `offset`, without extending the file.
+pub(super) fn write_page(file: &VfsNodeRef, offset: u64, frame: PhysAddr) -> bool {
+    let Ok(attr) = file.get_attr() else {
+        return false;
+    };
+    if offset >= attr.size() {
+        return true;
+    }
+    let len = (attr.size() - offset).min(PAGE_SIZE_4K as u64) as usize;
+    file.write_at(offset, &frame_bytes(frame)[..len]).is_ok()
+}
+
+/// Puts the page of a shared mapping at `offset` of `file` after it is
+/// unmapped from `frame`, and writes it back if `dirty`.
+///
+/// The pages of the files in the page cache are the cached pages, and are
+/// unpinned. The pages of other files are copies, and are freed.
+pub(super) fn put_shared_page(
+    file: &VfsNodeRef,
+    offset: u64,
+    frame: PhysAddr,
+    dirty: bool,
+) -> bool {
+    let index = offset / PAGE_SIZE_4K as u64;
+    let res = if dirty {
+        file.dirty_page(index).and_then(|_| file.unpin_page(index))
+    } else {
+        file.unpin_page(index)
+    };
+    match res {
+        Err(VfsError::Unsupported) => {
+            let ok = !dirty || write_page(file, offset, frame);
+            dealloc_frame(frame);
+            ok
+        }
+        res => res.is_ok(),
+    }
+}
+
+impl Backend {
+    /// Creates a new file mapping backend, `start` is mapped to the `offset`
+    /// of `file`.
+    pub const fn new_file(file: VfsNodeRef, start: VirtAddr, offset: usize, shared: bool) -> Self {
+        Self::File {
+            file,
+            start,
+            offset,
+            shared,
+        }
+    }
+
+    /// Returns the mapped file, and the file offset of `vaddr`.
+    fn file_offset(&self, vaddr: VirtAddr) -> (&VfsNodeRef, u64) {
+        match self {
+            Self::File {
+                file,
+                start,
+                offset,
+                ..
+            } => (file, (vaddr - *start + *offset) as u64),
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_shared_file(&self) -> bool {
+        matches!(self, Self::File { shared: true, .. })
+    }
+
+    /// Puts the page of a shared mapping at `vaddr` after it is unmapped from
+    /// `frame`, see [`put_shared_page`].
+    fn put_shared_page(&self, vaddr: VirtAddr, frame: PhysAddr, dirty: bool) -> bool {
+        let (file, offset) = self.file_offset(vaddr);
+        put_shared_page(file, offset, frame, dirty)
+    }
+
+    pub(crate) fn map_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!(
+            "map_file: [{:#x}, {:#x}) {:?} (shared={})",
+            start,
+            start + size,
+            flags,
+            self.is_shared_file()
+        );
+        // Map to a empty entry, the pages are read from the file on demand.
+        let flags = MappingFlags::empty();
+        pt.map_region(start, |_| 0.into(), size, flags, false, false)
+            .map(|tlb| tlb.ignore())
+            .is_ok()
+    }
+
+    /// Unmaps the pages, and puts the ones of shared mappings at once. They
+    /// are detached by [`Backend::detach_shared_pages`] first if the address
+    /// space is locked.
+    pub(crate) fn unmap_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        let mut ok = true;
+        let mut addr = start;
+        while addr < start + size {
+            put_swapped(addr, pt);
+            let dirty =
+                matches!(pt.query(addr), Ok((_, flags, _)) if flags.contains(MappingFlags::WRITE));
+            let Ok((frame, page_size, tlb)) = pt.unmap(addr) else {
+                addr += PAGE_SIZE_4K;
+                continue;
+            };
+            tlb.flush();
+            if page_size.is_huge() {
+                // File pages are never mapped as huge pages, free it anyway
+                // rather than leak it, and go on with the rest.
+                dealloc_huge_frame(frame, page_size);
+                ok = false;
+                addr = addr.align_down(page_size) + page_size as usize;
+                continue;
+            }
+            if self.is_shared_file() {
+                ok &= self.put_shared_page(addr, frame, dirty);
+            } else {
+                dealloc_frame(frame);
+            }
+            addr += PAGE_SIZE_4K;
+        }
+        ok
+    }
+
+    /// Handles a page fault. The page to read from the file is asked for by
+    /// [`Fault::Fetch`] first, and is mapped when it is in `fetched`.
+    pub(crate) fn handle_page_fault_file(
+        &self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        fetched: &mut Option<FetchedPage>,
+    ) -> Fault {
+        let vaddr = vaddr.align_down_4k();
+        let handled = match pt.query(vaddr) {
+            Ok((frame, flags, _)) if !flags.is_empty() => {
+                if !orig_flags.contains(MappingFlags::WRITE) || flags.contains(MappingFlags::WRITE)
+                {
+                    false
+                } else if self.is_shared_file() {
+                    // The first write since the page is read or synced, the
+                    // writable pages are the dirty ones.
+                    pt.remap(vaddr, frame, orig_flags)
+                        .map(|(_, tlb)| tlb.flush())
+                        .is_ok()
+                } else {
+                    Self::copy_on_write(vaddr, frame, orig_flags, pt)
+                }
+            }
+            // A private page swapped out after being written.
+            Ok((entry, ..)) if swap::entry_slot(entry).is_some() => {
+                return Self::swap_in_page(vaddr, entry, orig_flags, pt, fetched);
+            }
+            _ => {
+                let (file, offset) = self.file_offset(vaddr);
+                let req = PageFetch::File {
+                    file: file.clone(),
+                    offset,
+                    shared: self.is_shared_file(),
+                };
+                let Some(page) = FetchedPage::take(fetched, &req) else {
+                    return Fault::Fetch(req);
+                };
+                // Shared pages are read-only until they are written.
+                let flags = if self.is_shared_file() {
+                    orig_flags - MappingFlags::WRITE
+                } else {
+                    orig_flags
+                };
+                match pt.remap(vaddr, page.frame(), flags) {
+                    Ok((_, tlb)) => {
+                        tlb.flush();
+                        true
+                    }
+                    Err(_) => {
+                        *fetched = Some(page);
+                        false
+                    }
+                }
+            }
+        };
+        Fault::Done(handled)
+    }
+
+    /// Reclaims the page at `vaddr`. The clean private pages are dropped and
+    /// read from the file again on demand, while the private writable pages
+    /// may have been changed and are swapped out.
+    ///
+    /// The pages of shared mappings are detached, and left to `io` to be put
+    /// (see [`put_shared_page`]). The dirty ones are marked dirty in the page
+    /// cache, or written back if the file is not in the page cache.
+    pub(crate) fn reclaim_page_file(
+        &self,
+        vaddr: VirtAddr,
+        pt: &mut PageTable,
+        io: &mut PendingIo,
+    ) -> Reclaim {
+        let Ok((frame, flags, page_size)) = pt.query(vaddr) else {
+            return Reclaim::Kept;
+        };
+        if flags.is_empty() || page_size.is_huge() || is_frame_shared(frame) {
+            return Reclaim::Kept;
+        }
+        let shared = self.is_shared_file();
+        if !shared && flags.contains(MappingFlags::WRITE) {
+            return Self::swap_out_page(vaddr, pt, io);
+        }
+        match pt.remap(vaddr, 0.into(), MappingFlags::empty()) {
+            Ok((_, tlb)) => {
+                tlb.flush();
+                if shared {
+                    let (file, offset) = self.file_offset(vaddr);
+                    io.put_shared(
+                        file.clone(),
+                        offset,
+                        frame,
+                        flags.contains(MappingFlags::WRITE),
+                    );
+                } else {
+                    dealloc_frame(frame);
+                }
+                Reclaim::Freed
+            }
+            Err(_) => Reclaim::Kept,
+        }
+    }
+
+    /// Shares the pages of a shared file mapping with the area in the cloned
+    /// address space, by pinning the cached pages again for it.
+    pub(crate) fn share_frames_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        new_pt: &mut PageTable,
+    ) -> bool {
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, flags, _)) = pt.query(addr) else {
+                continue;
+            };
+            if flags.is_empty() {
+                continue; // Not read yet.
+            }
+            let (file, offset) = self.file_offset(addr);
+            let index = offset / PAGE_SIZE_4K as u64;
+            match file.pin_page(index) {
+                Ok(page) if virt_to_phys(page.into()) == frame => {
+                    if new_pt.remap(addr, frame, flags).is_err() {
+                        let _ = file.unpin_page(index);
+                        return false;
+                    }
+                }
+                Ok(_) => {
+                    let _ = file.unpin_page(index);
+                    return false;
+                }
+                // The pages are copies of the file, share them by references.
+                Err(VfsError::Unsupported) => {
+                    return self.share_frames_alloc(start, size, pt, new_pt, false);
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Leaves the dirty pages to `io` to be written back to the file, or
+    /// marked dirty in the page cache. They are made read-only again to find
+    /// the next writes after that.
+    pub(crate) fn sync_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &PageTable,
+        io: &mut PendingIo,
+    ) {
+        debug!("sync_file: [{:#x}, {:#x})", start, start + size);
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, flags, _)) = pt.query(addr) else {
+                continue;
+            };
+            if flags.contains(MappingFlags::WRITE) {
+                let (file, offset) = self.file_offset(addr);
+                io.sync(file.clone(), offset, addr, frame, flags);
+            }
+        }
+    }
+
+    /// Detaches the pages of a shared file mapping from the page table, and
+    /// leaves them to `io` to be put (see [`put_shared_page`]). The entries
+    /// become the empty ones of the lazy mapping.
+    pub(crate) fn detach_shared_pages(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        io: &mut PendingIo,
+    ) {
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, flags, page_size)) = pt.query(addr) else {
+                continue;
+            };
+            if flags.is_empty() || page_size.is_huge() {
+                continue;
+            }
+            if let Ok((_, tlb)) = pt.remap(addr, 0.into(), MappingFlags::empty()) {
+                tlb.flush();
+                let (file, offset) = self.file_offset(addr);
+                io.put_shared(
+                    file.clone(),
+                    offset,
+                    frame,
+                    flags.contains(MappingFlags::WRITE),
+                );
+            }
+        }
+    }
+}
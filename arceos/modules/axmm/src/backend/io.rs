@@ -0,0 +1,430 @@
+//! Page I/O done without the address space locked.
+//!
+//! The address spaces are usually locked by spinlocks, while the I/O of the
+//! files may sleep. So the backends only change the page tables with the lock
+//! held, and leave the I/O in a [`PendingIo`] to be run after the lock is
+//! released. A page fault that needs a page from a file asks for it by a
+//! [`PageFetch`] instead, and is handled again with the [`FetchedPage`].
+//!
+//! The reclaimed pages are swapped out in the same way: they are made
+//! read-only and written to the swap slots without the lock, and are unmapped
+//! with the lock held again if they have not been changed meanwhile.
+
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{VfsError, VfsNodeRef};
+use axhal::mem::virt_to_phys;
+use axhal::paging::{MappingFlags, PageTable};
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, dealloc_frame, is_frame_shared, share_frame};
+use super::file::{put_shared_page, read_page, write_page};
+use crate::swap;
+use crate::tlb::TlbState;
+
+/// The result of a page fault handled with the address space locked.
+pub(crate) enum Fault {
+    /// Handled, or `false` if it is a real fault or fails.
+    Done(bool),
+    /// The page should be fetched without the lock first, and the page fault
+    /// is handled again with it.
+    Fetch(PageFetch),
+    /// The resident set size limit is reached, some pages should be reclaimed
+    /// first.
+    OverLimit,
+}
+
+/// A page to be read for a page fault.
+pub(crate) enum PageFetch {
+    /// The page of `file` at `offset`. It is pinned in the page cache for the
+    /// shared mappings, or read into a new frame otherwise.
+    File {
+        file: VfsNodeRef,
+        offset: u64,
+        shared: bool,
+    },
+    /// The swapped out page in the swap slot. The slot is referenced by the
+    /// request until the page is mapped or released, so that it is not reused
+    /// meanwhile.
+    SwapIn(usize),
+}
+
+impl PageFetch {
+    fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::File {
+                    file,
+                    offset,
+                    shared,
+                },
+                Self::File {
+                    file: other_file,
+                    offset: other_offset,
+                    shared: other_shared,
+                },
+            ) => Arc::ptr_eq(file, other_file) && offset == other_offset && shared == other_shared,
+            (Self::SwapIn(slot), Self::SwapIn(other_slot)) => slot == other_slot,
+            _ => false,
+        }
+    }
+
+    /// Reads the page. Returns `None` if it fails.
+    pub(crate) fn fetch(self) -> Option<FetchedPage> {
+        let (frame, pinned) = match &self {
+            Self::File {
+                file,
+                offset,
+                shared,
+            } => {
+                let pinned = if *shared {
+                    match file.pin_page(offset / PAGE_SIZE_4K as u64) {
+                        Ok(page) => Some(virt_to_phys(page.into())),
+                        Err(VfsError::Unsupported) => None,
+                        Err(_) => return None,
+                    }
+                } else {
+                    None
+                };
+                match pinned {
+                    Some(frame) => (frame, true),
+                    None => {
+                        let frame = alloc_frame(true)?;
+                        if !read_page(file, *offset, frame) {
+                            dealloc_frame(frame);
+                            return None;
+                        }
+                        (frame, false)
+                    }
+                }
+            }
+            Self::SwapIn(slot) => {
+                let Some(frame) = alloc_frame(false) else {
+                    swap::put_slot(*slot);
+                    return None;
+                };
+                if !swap::read_slot(*slot, frame) {
+                    dealloc_frame(frame);
+                    swap::put_slot(*slot);
+                    return None;
+                }
+                (frame, false)
+            }
+        };
+        Some(FetchedPage {
+            req: self,
+            frame,
+            pinned,
+        })
+    }
+}
+
+/// A page read by [`PageFetch::fetch`], which is owned by the page table once
+/// it is mapped.
+pub(crate) struct FetchedPage {
+    req: PageFetch,
+    frame: PhysAddr,
+    /// Whether the frame is pinned in the page cache rather than allocated.
+    pinned: bool,
+}
+
+impl FetchedPage {
+    /// Takes the page out of `fetched` if it is the one asked for by `req`.
+    pub(super) fn take(fetched: &mut Option<Self>, req: &PageFetch) -> Option<Self> {
+        if fetched.as_ref().is_some_and(|page| page.req.is_same(req)) {
+            fetched.take()
+        } else {
+            None
+        }
+    }
+
+    pub(super) const fn frame(&self) -> PhysAddr {
+        self.frame
+    }
+
+    /// Releases the page that is not mapped, e.g. the faulting page has been
+    /// mapped by another CPU meanwhile.
+    pub(crate) fn release(self) {
+        match self.req {
+            PageFetch::File { file, offset, .. } if self.pinned => {
+                let _ = file.unpin_page(offset / PAGE_SIZE_4K as u64);
+            }
+            PageFetch::File { .. } => dealloc_frame(self.frame),
+            PageFetch::SwapIn(slot) => {
+                swap::put_slot(slot);
+                dealloc_frame(self.frame);
+            }
+        }
+    }
+}
+
+enum PageIo {
+    /// Puts a page of a shared file mapping after it is unmapped, see
+    /// [`put_shared_page`].
+    PutShared {
+        file: VfsNodeRef,
+        offset: u64,
+        frame: PhysAddr,
+        dirty: bool,
+    },
+    /// Writes back a dirty page of a shared file mapping, which is mapped at
+    /// `vaddr` with `flags`, and makes it read-only afterwards if it is still
+    /// mapped so. The frame is referenced until then.
+    Sync {
+        file: VfsNodeRef,
+        offset: u64,
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        flags: MappingFlags,
+        written: bool,
+    },
+    /// Writes a private page mapped at `vaddr` to the swap slot, and unmaps it
+    /// if it is still mapped read-only (from `flags`) afterwards. The frame is
+    /// referenced until then.
+    SwapOut {
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        flags: MappingFlags,
+        slot: usize,
+        written: bool,
+    },
+}
+
+/// The page I/O left by the changes of an address space, which is run after
+/// the address space is unlocked.
+///
+/// It should be dropped without the lock as well, as it keeps the files of the
+/// unmapped areas, whose last references may flush them.
+pub(crate) struct PendingIo {
+    ops: Vec<PageIo>,
+    files: Vec<VfsNodeRef>,
+    done: bool,
+}
+
+impl PendingIo {
+    pub(crate) const fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            files: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Keeps a reference to `file` until the I/O is done.
+    pub(super) fn keep_file(&mut self, file: VfsNodeRef) {
+        self.files.push(file);
+    }
+
+    pub(super) fn put_shared(
+        &mut self,
+        file: VfsNodeRef,
+        offset: u64,
+        frame: PhysAddr,
+        dirty: bool,
+    ) {
+        self.ops.push(PageIo::PutShared {
+            file,
+            offset,
+            frame,
+            dirty,
+        });
+    }
+
+    pub(super) fn sync(
+        &mut self,
+        file: VfsNodeRef,
+        offset: u64,
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        flags: MappingFlags,
+    ) {
+        share_frame(frame);
+        self.ops.push(PageIo::Sync {
+            file,
+            offset,
+            vaddr,
+            frame,
+            flags,
+            written: false,
+        });
+    }
+
+    /// Leaves the page to be swapped out, which should have been made
+    /// read-only.
+    pub(super) fn swap_out(
+        &mut self,
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        flags: MappingFlags,
+        slot: usize,
+    ) {
+        share_frame(frame);
+        self.ops.push(PageIo::SwapOut {
+            vaddr,
+            frame,
+            flags,
+            slot,
+            written: false,
+        });
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Runs the I/O, without the address space locked.
+    pub(crate) fn run(&mut self) -> AxResult {
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        let mut ok = true;
+        for op in self.ops.iter_mut() {
+            match op {
+                PageIo::PutShared {
+                    file,
+                    offset,
+                    frame,
+                    dirty,
+                } => ok &= put_shared_page(file, *offset, *frame, *dirty),
+                PageIo::Sync {
+                    file,
+                    offset,
+                    frame,
+                    written,
+                    ..
+                } => {
+                    *written = match file.dirty_page(*offset / PAGE_SIZE_4K as u64) {
+                        Ok(()) => true,
+                        Err(VfsError::Unsupported) => write_page(file, *offset, *frame),
+                        // Unpinned after being unmapped meanwhile, and it was
+                        // put as dirty.
+                        Err(VfsError::InvalidInput) => true,
+                        Err(_) => false,
+                    };
+                    ok &= *written;
+                }
+                // Failing to swap out is not an error, the page is kept.
+                PageIo::SwapOut {
+                    frame,
+                    slot,
+                    written,
+                    ..
+                } => *written = swap::write_slot(*slot, *frame),
+            }
+        }
+        self.ops
+            .retain(|op| !matches!(op, PageIo::PutShared { .. }));
+        if !ok {
+            return ax_err!(Io, "failed to write back");
+        }
+        Ok(())
+    }
+
+    /// Whether the address space should be locked again for
+    /// [`PendingIo::finish`].
+    pub(crate) fn needs_finish(&self) -> bool {
+        !self.ops.is_empty()
+    }
+
+    /// Finishes the I/O with the address space locked again, after
+    /// [`PendingIo::run`].
+    ///
+    /// The written back pages become read-only to find the next writes, if
+    /// they have not been changed meanwhile. The swapped out pages are
+    /// unmapped if they have not been changed or `locked` meanwhile, or get
+    /// their flags back otherwise.
+    ///
+    /// Returns the addresses of the swapped out pages.
+    pub(crate) fn finish(
+        &mut self,
+        pt: &mut PageTable,
+        tlb: &TlbState,
+        locked: &BTreeSet<VirtAddr>,
+    ) -> Vec<VirtAddr> {
+        let mut swapped = Vec::new();
+        for op in self.ops.drain(..) {
+            match op {
+                PageIo::Sync {
+                    vaddr,
+                    frame,
+                    flags,
+                    written,
+                    ..
+                } => {
+                    if written && is_mapped(pt, vaddr, frame, flags) {
+                        if let Ok((_, flush)) = pt.protect(vaddr, flags - MappingFlags::WRITE) {
+                            flush.flush();
+                            tlb.flush(vaddr, PAGE_SIZE_4K);
+                        }
+                    }
+                    dealloc_frame(frame);
+                }
+                PageIo::SwapOut {
+                    vaddr,
+                    frame,
+                    flags,
+                    slot,
+                    written,
+                } => {
+                    let unchanged = is_mapped(pt, vaddr, frame, flags - MappingFlags::WRITE);
+                    if written && unchanged && !locked.contains(&vaddr) {
+                        if let Ok((_, flush)) =
+                            pt.remap(vaddr, swap::slot_entry(slot), MappingFlags::empty())
+                        {
+                            flush.flush();
+                            tlb.flush(vaddr, PAGE_SIZE_4K);
+                            // Drop the references of the page table and the
+                            // hold.
+                            dealloc_frame(frame);
+                            dealloc_frame(frame);
+                            swapped.push(vaddr);
+                            continue;
+                        }
+                    }
+                    swap::put_slot(slot);
+                    dealloc_frame(frame);
+                    // Writable again unless it is shared by a cloned address
+                    // space meanwhile.
+                    if unchanged && !is_frame_shared(frame) {
+                        if let Ok((_, flush)) = pt.protect(vaddr, flags) {
+                            flush.flush();
+                            tlb.flush(vaddr, PAGE_SIZE_4K);
+                        }
+                    }
+                }
+                PageIo::PutShared { .. } => {}
+            }
+        }
+        swapped
+    }
+}
+
+/// Whether `vaddr` is still mapped to `frame` with `flags`.
+fn is_mapped(pt: &PageTable, vaddr: VirtAddr, frame: PhysAddr, flags: MappingFlags) -> bool {
+    pt.query(vaddr)
+        .is_ok_and(|(f, fl, _)| f == frame && fl == flags)
+}
+
+impl Drop for PendingIo {
+    fn drop(&mut self) {
+        let _ = self.run();
+        // Not finished, e.g. the address space cannot be locked again. The
+        // pages to swap out are kept, and are writable again on write faults.
+        for op in self.ops.drain(..) {
+            match op {
+                PageIo::Sync { frame, .. } => dealloc_frame(frame),
+                PageIo::SwapOut { frame, slot, .. } => {
+                    swap::put_slot(slot);
+                    dealloc_frame(frame);
+                }
+                PageIo::PutShared { .. } => {}
+            }
+        }
+        // Drop the files after their pages are put.
+        self.files.clear();
+    }
+}
@@ -1,21 +1,33 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
-use axhal::paging::{MappingFlags, PageTable};
-use memory_addr::VirtAddr;
+use axfs_vfs::VfsNodeRef;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 use memory_set::MappingBackend;
 
 mod alloc;
+mod file;
+mod io;
 mod linear;
 
+pub(crate) use self::alloc::alloc_failures;
+use self::alloc::{is_frame_shared, map_frame};
+pub(crate) use self::io::{Fault, FetchedPage, PageFetch, PendingIo};
+use crate::swap;
+
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, three backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
-///   frames are obtained from the global allocator.
+///   frames are obtained from the global allocator, and can be shared between
+///   address spaces by copy-on-write.
+/// - **File**: used for file mappings. The physical frames are allocated and
+///   filled with the file content on demand, or are the pages in the page
+///   cache for shared mappings.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -33,9 +45,31 @@ pub enum Backend {
     /// mapping is created, and no page faults are triggered during the memory
     /// access. Otherwise, the physical frames are allocated on demand (by
     /// handling page faults).
+    ///
+    /// Huge pages up to `page_size` are used where the alignment allows, and
+    /// 4K pages are used for the rest or if huge frames are not available.
     Alloc {
         /// Whether to populate the physical frames when creating the mapping.
         populate: bool,
+        /// The largest page size to use.
+        page_size: PageSize,
+    },
+    /// File mapping backend.
+    ///
+    /// The virtual address `vaddr` is mapped to the content of `file` at the
+    /// offset `vaddr - start + offset`. If `shared` is `true`, the written pages
+    /// are written back to the file on `msync` and unmap. Otherwise, the pages
+    /// are private copies of the file, and are copied on write if they are
+    /// shared by a cloned address space.
+    File {
+        /// The mapped file.
+        file: VfsNodeRef,
+        /// The start virtual address of the mapping.
+        start: VirtAddr,
+        /// The file offset mapped at `start`.
+        offset: usize,
+        /// Whether the changes are visible to the file.
+        shared: bool,
     },
 }
 
@@ -46,14 +80,19 @@ impl MappingBackend for Backend {
     fn map(&self, start: VirtAddr, size: usize, flags: MappingFlags, pt: &mut PageTable) -> bool {
         match *self {
             Self::Linear { pa_va_offset } => self.map_linear(start, size, flags, pt, pa_va_offset),
-            Self::Alloc { populate } => self.map_alloc(start, size, flags, pt, populate),
+            Self::Alloc {
+                populate,
+                page_size,
+            } => self.map_alloc(start, size, flags, pt, populate, page_size),
+            Self::File { .. } => self.map_file(start, size, flags, pt),
         }
     }
 
     fn unmap(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
         match *self {
             Self::Linear { pa_va_offset } => self.unmap_linear(start, size, pt, pa_va_offset),
-            Self::Alloc { populate } => self.unmap_alloc(start, size, pt, populate),
+            Self::Alloc { populate, .. } => self.unmap_alloc(start, size, pt, populate),
+            Self::File { .. } => self.unmap_file(start, size, pt),
         }
     }
 
@@ -64,25 +103,281 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
-        page_table
-            .protect_region(start, size, new_flags, true)
-            .map(|tlb| tlb.ignore())
-            .is_ok()
+        // The pages not allocated yet or swapped out get the new flags of the
+        // area on the next page fault.
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            let Ok((frame, flags, _)) = page_table.query(addr) else {
+                addr += PAGE_SIZE_4K;
+                continue;
+            };
+            if flags.is_empty() {
+                addr += PAGE_SIZE_4K;
+                continue;
+            }
+            let mut new_flags = new_flags;
+            match *self {
+                // Keep the clean pages read-only to find the next writes. The
+                // dirty pages have been written back before they become
+                // read-only, see `AddrSpace::protect`.
+                Self::File { shared: true, .. } => {
+                    if !flags.contains(MappingFlags::WRITE) {
+                        new_flags -= MappingFlags::WRITE;
+                    } else if !new_flags.contains(MappingFlags::WRITE) {
+                        return false;
+                    }
+                }
+                // Keep the shared frames read-only to copy them on write.
+                Self::Alloc { .. } | Self::File { .. } if is_frame_shared(frame) => {
+                    new_flags -= MappingFlags::WRITE;
+                }
+                _ => {}
+            }
+            match page_table.protect(addr, new_flags) {
+                Ok((page_size, tlb)) => {
+                    tlb.flush();
+                    addr += page_size as usize;
+                }
+                Err(_) => return false,
+            }
+        }
+        true
     }
 }
 
 impl Backend {
+    /// Handles a page fault at `vaddr` in the area `area_range` mapped by the
+    /// backend.
+    ///
+    /// The pages fetched for [`Fault::Fetch`] are passed back in `fetched`,
+    /// and are taken if they are mapped.
     pub(crate) fn handle_page_fault(
         &self,
         vaddr: VirtAddr,
         orig_flags: MappingFlags,
+        area_range: VirtAddrRange,
+        page_table: &mut PageTable,
+        fetched: &mut Option<FetchedPage>,
+    ) -> Fault {
+        match *self {
+            // Linear mappings should not trigger page faults.
+            Self::Linear { .. } => Fault::Done(false),
+            Self::Alloc {
+                populate,
+                page_size,
+            } => self.handle_page_fault_alloc(
+                vaddr,
+                orig_flags,
+                page_table,
+                populate,
+                huge_page_at(vaddr, area_range, page_size),
+                fetched,
+            ),
+            Self::File { .. } => {
+                self.handle_page_fault_file(vaddr, orig_flags, page_table, fetched)
+            }
+        }
+    }
+
+    /// Reclaims the frame of the page at `vaddr` under memory pressure, the
+    /// I/O is left to `io`.
+    pub(crate) fn reclaim_page(
+        &self,
+        vaddr: VirtAddr,
+        page_table: &mut PageTable,
+        io: &mut PendingIo,
+    ) -> Reclaim {
+        match *self {
+            Self::Linear { .. } => Reclaim::Kept,
+            Self::Alloc { .. } => Self::swap_out_page(vaddr, page_table, io),
+            Self::File { .. } => self.reclaim_page_file(vaddr, page_table, io),
+        }
+    }
+
+    /// Leaves the dirty pages in `[start, start + size)` to `io` to be written
+    /// back to the file if it is a shared file mapping.
+    pub(crate) fn sync(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        page_table: &PageTable,
+        io: &mut PendingIo,
+    ) {
+        if let Self::File { shared: true, .. } = self {
+            self.sync_file(start, size, page_table, io);
+        }
+    }
+
+    /// Detaches the pages in `[start, start + size)` that need I/O to be put
+    /// before the range is unmapped, and leaves the I/O to `io`.
+    pub(crate) fn detach_pages(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        page_table: &mut PageTable,
+        io: &mut PendingIo,
+    ) {
+        if let Self::File { file, shared, .. } = self {
+            io.keep_file(file.clone());
+            if *shared {
+                self.detach_shared_pages(start, size, page_table, io);
+            }
+        }
+    }
+
+    /// Returns the backend for the copy of an area in the cloned address
+    /// space, see [`Backend::share_frames`].
+    pub(crate) fn clone_for_cow(&self) -> Self {
+        match *self {
+            Self::Linear { .. } | Self::File { .. } => self.clone(),
+            // The frames are shared rather than allocated for the new area, and
+            // the huge pages are split to be copied on write separately.
+            Self::Alloc { .. } => Self::new_alloc(false),
+        }
+    }
+
+    /// Shares the frames in `[start, start + size)` of `page_table` with the
+    /// area mapped by the backend from [`Backend::clone_for_cow`] in
+    /// `new_page_table`.
+    pub(crate) fn share_frames(
+        &self,
+        start: VirtAddr,
+        size: usize,
         page_table: &mut PageTable,
+        new_page_table: &mut PageTable,
     ) -> bool {
         match *self {
-            Self::Linear { .. } => false, // Linear mappings should not trigger page faults.
-            Self::Alloc { populate } => {
-                self.handle_page_fault_alloc(vaddr, orig_flags, page_table, populate)
+            Self::Linear { .. } => true, // Already mapped to the same frames.
+            Self::Alloc { .. } => {
+                self.share_frames_alloc(start, size, page_table, new_page_table, true)
+            }
+            // The pages of a shared file mapping are still writable in both.
+            Self::File { shared: true, .. } => {
+                self.share_frames_file(start, size, page_table, new_page_table)
             }
+            Self::File { .. } => {
+                self.share_frames_alloc(start, size, page_table, new_page_table, true)
+            }
+        }
+    }
+
+    /// Drops the frames in `[start, start + size)` like `MADV_DONTNEED`, and
+    /// maps the range again as a new mapping with `flags`.
+    ///
+    /// Returns `false` for linear mappings, whose frames cannot be dropped.
+    pub(crate) fn discard(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        page_table: &mut PageTable,
+    ) -> bool {
+        match *self {
+            Self::Linear { .. } => false,
+            _ => self.unmap(start, size, page_table) && self.map(start, size, flags, page_table),
+        }
+    }
+
+    /// Returns the backend for the area moved from `old_start` to `new_start`,
+    /// whose pages are moved by [`move_page`] rather than mapped again.
+    pub(crate) fn relocate(&self, old_start: VirtAddr, new_start: VirtAddr) -> Self {
+        match self {
+            Self::Linear { pa_va_offset } => Self::Linear {
+                pa_va_offset: pa_va_offset.wrapping_add(new_start - old_start),
+            },
+            Self::Alloc { page_size, .. } => Self::new_alloc_huge(false, *page_size),
+            Self::File {
+                file,
+                start,
+                offset,
+                shared,
+            } => Self::new_file(
+                file.clone(),
+                new_start,
+                offset + (old_start - *start),
+                *shared,
+            ),
+        }
+    }
+}
+
+/// The result of [`Backend::reclaim_page`].
+pub(crate) enum Reclaim {
+    /// The page is unmapped, and its frame is freed or left to the
+    /// [`PendingIo`] to be put.
+    Freed,
+    /// The page is read-only, and is left to the [`PendingIo`] to be swapped
+    /// out.
+    SwapOut,
+    /// The page cannot be reclaimed for now.
+    Kept,
+}
+
+/// Moves the page at `vaddr` to `new_vaddr` with the frame or the swap slot,
+/// the huge page containing it is split first.
+///
+/// Returns `false` if the page cannot be moved, and it stays at `vaddr`.
+pub(crate) fn move_page(pt: &mut PageTable, vaddr: VirtAddr, new_vaddr: VirtAddr) -> bool {
+    if !split_huge_page(pt, vaddr) {
+        return false;
+    }
+    let Ok((entry, flags, _)) = pt.query(vaddr) else {
+        return true;
+    };
+    if flags.is_empty() && swap::entry_slot(entry).is_none() {
+        return true; // Not allocated yet.
+    }
+    // Clear the old entry without freeing the frame.
+    if let Ok((_, _, tlb)) = pt.unmap(vaddr) {
+        tlb.flush();
+    }
+    if map_frame(new_vaddr, entry, flags, pt) {
+        return true;
+    }
+    // Put the page back, whose page table is still there.
+    map_frame(vaddr, entry, flags, pt);
+    false
+}
+
+/// Returns the start address and size of the largest huge page not larger than
+/// `max_size` that contains `vaddr` and is within `range`.
+pub(crate) fn huge_page_at(
+    vaddr: VirtAddr,
+    range: VirtAddrRange,
+    max_size: PageSize,
+) -> Option<(VirtAddr, PageSize)> {
+    [PageSize::Size1G, PageSize::Size2M]
+        .into_iter()
+        .filter(|&size| size as usize <= max_size as usize)
+        .map(|size| (vaddr.align_down(size), size))
+        .find(|&(start, size)| {
+            range.contains_range(VirtAddrRange::from_start_size(start, size as usize))
+        })
+}
+
+/// Splits the huge page containing `vaddr` into smaller pages mapped to the
+/// same frames with the same flags, until `vaddr` is in a 4K page.
+///
+/// Each level is replaced at once by a filled table, so the other pages of the
+/// huge page stay mapped while it is split.
+///
+/// Returns `false` if the page table cannot be allocated.
+pub(crate) fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    let Ok((_, _, page_size)) = pt.query(vaddr) else {
+        return true;
+    };
+    if !page_size.is_huge() {
+        return true;
+    }
+    loop {
+        match axhal::paging::split_huge_page(pt, vaddr) {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(_) => return false,
         }
     }
+    // Flushing any address in the huge page drops its TLB entry.
+    axhal::arch::flush_tlb(Some(vaddr));
+    true
 }
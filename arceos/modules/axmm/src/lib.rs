@@ -1,6 +1,6 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -8,15 +8,30 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
-
-pub use self::aspace::AddrSpace;
+mod lock;
+mod reclaim;
+mod stack;
+mod swap;
+mod tlb;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::aspace::{AddrSpace, PageTableHandle};
+pub use self::lock::AddrSpaceLock;
+pub use self::reclaim::register_aspace;
+pub use self::stack::{alloc_kernel_stack, dealloc_kernel_stack, STACK_GUARD_SIZE};
+pub use self::swap::{swap_off, swap_on, swap_usage, SwapDevice};
+#[cfg(all(feature = "smp", feature = "irq"))]
+pub use self::tlb::handle_shootdown;
 
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
-use axhal::paging::PagingError;
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, PAGE_FAULT};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
-use memory_addr::{va, PhysAddr, VirtAddr};
+use memory_addr::{va, PhysAddr, VirtAddr, VirtAddrRange};
 use memory_set::MappingError;
 
 const USER_ASPACE_BASE: usize = 0x0000;
@@ -33,17 +48,6 @@ fn mapping_err_to_ax_err(err: MappingError) -> AxError {
     }
 }
 
-fn paging_err_to_ax_err(err: PagingError) -> AxError {
-    warn!("Paging error: {:?}", err);
-    match err {
-        PagingError::NoMemory => AxError::NoMemory,
-        PagingError::NotAligned => AxError::InvalidInput,
-        PagingError::NotMapped => AxError::NotFound,
-        PagingError::AlreadyMapped => AxError::AlreadyExists,
-        PagingError::MappedToHugePage => AxError::InvalidInput,
-    }
-}
-
 /// Creates a new address space for user processes.
 pub fn new_user_aspace() -> AxResult<AddrSpace> {
     let mut aspace = AddrSpace::new_empty(VirtAddr::from(USER_ASPACE_BASE), USER_ASPACE_SIZE)?;
@@ -57,6 +61,7 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
         va!(axconfig::KERNEL_ASPACE_BASE),
         axconfig::KERNEL_ASPACE_SIZE,
     )?;
+    aspace.set_kernel();
     for r in axhal::mem::memory_regions() {
         aspace.map_linear(phys_to_virt(r.paddr), r.paddr, r.size, r.flags.into())?;
     }
@@ -68,11 +73,39 @@ pub fn kernel_aspace() -> &'static SpinNoIrq<AddrSpace> {
     &KERNEL_ASPACE
 }
 
+/// Returns the range of the kernel address space for the mappings of user
+/// programs, e.g. by `mmap`. It is below the region of the kernel stacks.
+pub fn mmap_range() -> VirtAddrRange {
+    VirtAddrRange::new(
+        va!(axconfig::KERNEL_ASPACE_BASE),
+        stack::stack_region().start,
+    )
+}
+
 /// Returns the root physical address of the kernel page table.
 pub fn kernel_page_table_root() -> PhysAddr {
     KERNEL_ASPACE.lock().page_table_root()
 }
 
+/// Handles the page faults of the lazy mappings in the kernel address space.
+///
+/// The kernel address space is unlocked while the pages are read from the
+/// files, see [`AddrSpaceLock`].
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    !is_user && KERNEL_ASPACE.is_inited() && KERNEL_ASPACE.handle_page_fault(vaddr, access_flags)
+}
+
+/// Switches the current CPU back to the kernel address space, e.g. when
+/// switching from a task with its own address space to a kernel task.
+///
+/// # Safety
+///
+/// The IRQs should be disabled.
+pub unsafe fn activate_kernel() {
+    tlb::activate_kernel(axhal::paging::kernel_page_table_root());
+}
+
 /// Initializes virtual memory management.
 ///
 /// It mainly sets up the kernel virtual memory address space and recreate a
@@ -80,13 +113,19 @@ pub fn kernel_page_table_root() -> PhysAddr {
 pub fn init_memory_management() {
     info!("Initialize virtual memory management...");
 
-    let kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
+    let mut kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
+    stack::init_stack_region(&mut kernel_aspace);
+    // Only the mappings of the user programs are limited.
+    kernel_aspace.set_limit_range(mmap_range());
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
+    reclaim::register_kernel_aspace(kernel_aspace());
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
+    tlb::cpu_online();
 }
 
 /// Initializes kernel paging for secondary CPUs.
 pub fn init_memory_management_secondary() {
     unsafe { axhal::arch::write_page_table_root(kernel_page_table_root()) };
+    tlb::cpu_online();
 }
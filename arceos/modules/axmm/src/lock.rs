@@ -0,0 +1,207 @@
+//! Changes of the locked address spaces with the I/O done unlocked.
+//!
+//! The address spaces are usually locked by spinlocks (e.g., the kernel one),
+//! while the page faults and some mapping changes read or write files, which
+//! may sleep. [`AddrSpaceLock`] does them in steps: the page tables are changed
+//! with the lock held, and the I/O is done between the steps without it.
+
+use axerrno::{ax_err, AxResult};
+use axhal::paging::MappingFlags;
+use kspin::SpinNoIrq;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+use crate::backend::{alloc_failures, Fault, PendingIo};
+use crate::AddrSpace;
+
+/// The number of pages to reclaim at a time when out of memory.
+const RECLAIM_BATCH: usize = 32;
+
+/// A lock of an address space.
+///
+/// Besides running a closure on the locked address space, it provides the
+/// operations of [`AddrSpace`] that may do I/O, with the lock released during
+/// the I/O. The address spaces locked by spinlocks should use them rather than
+/// the ones of [`AddrSpace`].
+pub trait AddrSpaceLock: Send + Sync {
+    /// Runs `f` on the locked address space.
+    fn with(&self, f: &mut dyn FnMut(&mut AddrSpace));
+
+    /// Runs `f` on the address space if it can be locked without waiting.
+    fn try_with(&self, f: &mut dyn FnMut(&mut AddrSpace));
+
+    /// Handles a page fault like [`AddrSpace::handle_page_fault`].
+    fn handle_page_fault(&self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        Locked(self).page_fault(vaddr, access_flags)
+    }
+
+    /// Removes mappings like [`AddrSpace::unmap`].
+    fn unmap(&self, start: VirtAddr, size: usize) -> AxResult {
+        Locked(self).with_io(|aspace, io| aspace.unmap_deferred(start, size, io))
+    }
+
+    /// Writes back the dirty pages like [`AddrSpace::msync`].
+    fn msync(&self, start: VirtAddr, size: usize) -> AxResult {
+        Locked(self).with_io(|aspace, io| aspace.sync_deferred(start, size, io))
+    }
+
+    /// Updates mappings like [`AddrSpace::protect`].
+    fn protect(&self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        Locked(self).protect_pages(start, size, flags)
+    }
+
+    /// Resizes a mapping like [`AddrSpace::mremap`].
+    fn mremap(
+        &self,
+        old_start: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        may_move: bool,
+    ) -> AxResult<VirtAddr> {
+        Locked(self).with_io(|aspace, io| {
+            aspace.mremap_deferred(old_start, old_size, new_size, may_move, io)
+        })
+    }
+
+    /// Drops the frames like [`AddrSpace::discard`].
+    fn discard(&self, start: VirtAddr, size: usize) -> AxResult {
+        Locked(self).with_io(|aspace, io| aspace.discard_deferred(start, size, io))
+    }
+
+    /// Locks the pages in memory like [`AddrSpace::mlock`].
+    fn mlock(&self, start: VirtAddr, size: usize) -> AxResult {
+        Locked(self).lock_pages(start, size)
+    }
+}
+
+impl AddrSpaceLock for SpinNoIrq<AddrSpace> {
+    fn with(&self, f: &mut dyn FnMut(&mut AddrSpace)) {
+        f(&mut self.lock());
+    }
+
+    fn try_with(&self, f: &mut dyn FnMut(&mut AddrSpace)) {
+        if let Some(mut aspace) = self.try_lock() {
+            f(&mut aspace);
+        }
+    }
+}
+
+/// An address space changed in steps, which is locked within each step if it
+/// is behind a lock.
+pub(crate) trait Access {
+    /// Runs a step on the address space.
+    fn step<T>(&mut self, f: impl FnOnce(&mut AddrSpace) -> T) -> T;
+
+    /// Runs `f`, and then the I/O it leaves in the [`PendingIo`].
+    fn with_io<T>(
+        &mut self,
+        f: impl FnOnce(&mut AddrSpace, &mut PendingIo) -> AxResult<T>,
+    ) -> AxResult<T> {
+        let mut io = PendingIo::new();
+        let res = self.step(|aspace| f(aspace, &mut io));
+        let io_res = io.run();
+        if io.needs_finish() {
+            self.step(|aspace| {
+                aspace.finish_io(&mut io);
+            });
+        }
+        res.and_then(|value| io_res.map(|_| value))
+    }
+
+    /// See [`AddrSpace::handle_page_fault`].
+    fn page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        let mut fetched = None;
+        let (mut over_limit, mut reclaimed) = (false, false);
+        loop {
+            let failures = alloc_failures();
+            let fault =
+                self.step(|aspace| aspace.try_page_fault(vaddr, access_flags, &mut fetched));
+            // Not mapped, e.g. the page has been mapped by another CPU.
+            if let Some(page) = fetched.take() {
+                page.release();
+            }
+            let fault = match fault {
+                Fault::Fetch(req) => match req.fetch() {
+                    Some(page) => {
+                        fetched = Some(page);
+                        continue;
+                    }
+                    None => Fault::Done(false),
+                },
+                fault => fault,
+            };
+            match fault {
+                Fault::Done(true) => return true,
+                Fault::OverLimit if !over_limit => {
+                    over_limit = true;
+                    self.reclaim_pages(RECLAIM_BATCH, true);
+                }
+                Fault::OverLimit => {
+                    warn!("resident set size limit exceeded at {:#x}", vaddr);
+                    return false;
+                }
+                // Retry once if it failed for lack of memory.
+                _ if reclaimed || alloc_failures() == failures => return false,
+                _ => {
+                    reclaimed = true;
+                    let count = self.reclaim_pages(RECLAIM_BATCH, false)
+                        + crate::reclaim::reclaim_others(RECLAIM_BATCH);
+                    if count == 0 {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// See [`AddrSpace::reclaim`] and [`AddrSpace::reclaim_deferred`].
+    fn reclaim_pages(&mut self, count: usize, limited: bool) -> usize {
+        let mut io = PendingIo::new();
+        let freed = self.step(|aspace| aspace.reclaim_deferred(count, limited, &mut io));
+        let _ = io.run();
+        let swapped = if io.needs_finish() {
+            self.step(|aspace| aspace.finish_io(&mut io))
+        } else {
+            0
+        };
+        freed + swapped
+    }
+
+    /// See [`AddrSpace::protect`].
+    fn protect_pages(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        // Write back the dirty pages of the shared file mappings first if they
+        // become read-only, until none is written meanwhile.
+        while !self.with_io(|aspace, io| aspace.protect_deferred(start, size, flags, io))? {}
+        Ok(())
+    }
+
+    /// See [`AddrSpace::mlock`].
+    fn lock_pages(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        let mut next = start;
+        // Fault in the pages one by one, until all of them are resident and
+        // locked at once.
+        while let Some(vaddr) = self.step(|aspace| aspace.lock_resident(start, size, next))? {
+            if !self.page_fault(vaddr, MappingFlags::empty()) {
+                return ax_err!(NoMemory, "failed to populate locked pages");
+            }
+            next = vaddr + PAGE_SIZE_4K;
+        }
+        Ok(())
+    }
+}
+
+impl Access for AddrSpace {
+    fn step<T>(&mut self, f: impl FnOnce(&mut AddrSpace) -> T) -> T {
+        f(self)
+    }
+}
+
+struct Locked<'a, L: ?Sized>(&'a L);
+
+impl<L: AddrSpaceLock + ?Sized> Access for Locked<'_, L> {
+    fn step<T>(&mut self, f: impl FnOnce(&mut AddrSpace) -> T) -> T {
+        let mut f = Some(f);
+        let mut res = None;
+        self.0.with(&mut |aspace| res = f.take().map(|f| f(aspace)));
+        res.expect("the address space is not locked")
+    }
+}
@@ -0,0 +1,81 @@
+//! Page reclaim across the address spaces.
+//!
+//! When a frame cannot be allocated on a page fault, the faulting address space
+//! reclaims its own pages, and the pages of the kernel address space and the
+//! registered ones are reclaimed as well, see [`register_aspace`]. The kernel
+//! address space is registered when it is initialized.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+
+use crate::backend::PendingIo;
+use crate::AddrSpaceLock;
+
+/// The registered address spaces.
+static ASPACES: SpinNoIrq<Vec<Weak<dyn AddrSpaceLock>>> = SpinNoIrq::new(Vec::new());
+/// The kernel address space, which is never dropped.
+static KERNEL_ASPACE: LazyInit<&'static dyn AddrSpaceLock> = LazyInit::new();
+/// The address space to start from in the next reclaim, so that they are
+/// reclaimed in turn.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers an address space, whose pages are reclaimed when a page fault in
+/// any address space runs out of frames. It is unregistered after dropped.
+///
+/// The kernel address space is always registered.
+pub fn register_aspace<L: AddrSpaceLock + 'static>(aspace: &Arc<L>) {
+    let aspace: Arc<dyn AddrSpaceLock> = aspace.clone();
+    let mut aspaces = ASPACES.lock();
+    aspaces.retain(|aspace| aspace.strong_count() > 0);
+    aspaces.push(Arc::downgrade(&aspace));
+}
+
+/// Registers the kernel address space.
+pub(crate) fn register_kernel_aspace(aspace: &'static dyn AddrSpaceLock) {
+    KERNEL_ASPACE.init_once(aspace);
+}
+
+/// Reclaims at most `count` pages from the kernel address space and the
+/// registered ones, except the ones that are locked now, e.g. by the faulting
+/// one.
+///
+/// Returns the number of pages reclaimed.
+pub(crate) fn reclaim_others(count: usize) -> usize {
+    let aspaces = ASPACES
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+    let kernel = KERNEL_ASPACE.get().copied();
+    let start = NEXT.fetch_add(1, Ordering::Relaxed);
+    let total = aspaces.len() + 1;
+    let mut reclaimed = 0;
+    for i in 0..total {
+        if reclaimed >= count {
+            break;
+        }
+        reclaimed += match (start + i) % total {
+            0 => kernel.map_or(0, |aspace| try_reclaim(aspace, count - reclaimed)),
+            n => try_reclaim(aspaces[n - 1].as_ref(), count - reclaimed),
+        };
+    }
+    reclaimed
+}
+
+/// Reclaims at most `count` pages from `aspace` if it can be locked without
+/// waiting, with the pages swapped out without the lock.
+fn try_reclaim(aspace: &dyn AddrSpaceLock, count: usize) -> usize {
+    let mut io = PendingIo::new();
+    let mut reclaimed = 0;
+    aspace.try_with(&mut |aspace| reclaimed = aspace.reclaim_deferred(count, false, &mut io));
+    let _ = io.run();
+    if io.needs_finish() {
+        // The pending pages are kept if it is locked by others now.
+        aspace.try_with(&mut |aspace| reclaimed += aspace.finish_io(&mut io));
+    }
+    reclaimed
+}
@@ -0,0 +1,94 @@
+//! Kernel task stacks with guard pages.
+//!
+//! The stacks are mapped in a dedicated region at the top of the kernel
+//! address space, with an inaccessible guard page below each one, so that an
+//! overflowing stack faults instead of corrupting the memory below it.
+
+use alloc::vec::Vec;
+
+use axhal::paging::MappingFlags;
+use kspin::SpinNoIrq;
+use memory_addr::{align_up_4k, MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+/// The size of the guard page below each stack.
+pub const STACK_GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+/// The size of the region for the kernel stacks.
+const STACK_REGION_SIZE: usize = 0x4000_0000; // 1G
+
+/// The maximum number of the freed stacks kept for reuse.
+const MAX_FREE_STACKS: usize = 16;
+
+/// The freed stacks `(bottom, size)`, which are kept mapped and reused to save
+/// the TLB shootdowns of unmapping them. The ones beyond [`MAX_FREE_STACKS`]
+/// are unmapped.
+static FREE_STACKS: SpinNoIrq<Vec<(VirtAddr, usize)>> = SpinNoIrq::new(Vec::new());
+
+/// Returns the region for the kernel stacks.
+pub(crate) fn stack_region() -> VirtAddrRange {
+    let end = VirtAddr::from(axconfig::KERNEL_ASPACE_BASE + axconfig::KERNEL_ASPACE_SIZE)
+        .align_down(STACK_REGION_SIZE);
+    VirtAddrRange::from_start_size(end - STACK_REGION_SIZE, STACK_REGION_SIZE)
+}
+
+/// Creates the page tables of the stack region, so that they are shared by
+/// the user address spaces copying the kernel mappings.
+pub(crate) fn init_stack_region(aspace: &mut crate::AddrSpace) {
+    let start = stack_region().start;
+    aspace
+        .map_alloc(start, PAGE_SIZE_4K, MappingFlags::empty(), false)
+        .and_then(|_| aspace.unmap(start, PAGE_SIZE_4K))
+        .expect("failed to initialize the kernel stack region");
+}
+
+/// Allocates a kernel stack of `size` bytes (rounded up to 4K) with a guard
+/// page below it.
+///
+/// Returns the bottom (lowest address) of the stack, or `None` if the stack
+/// region or the memory is exhausted.
+pub fn alloc_kernel_stack(size: usize) -> Option<VirtAddr> {
+    let size = align_up_4k(size);
+    {
+        let mut free_stacks = FREE_STACKS.lock();
+        if let Some(idx) = free_stacks.iter().position(|&(_, s)| s == size) {
+            return Some(free_stacks.swap_remove(idx).0);
+        }
+    }
+
+    let mut aspace = crate::kernel_aspace().lock();
+    let region = stack_region();
+    let start = aspace.find_free_area(region.start, STACK_GUARD_SIZE + size, region)?;
+    let bottom = start + STACK_GUARD_SIZE;
+    // The guard page is also an area, so that it is not reused by others.
+    aspace
+        .map_alloc(start, STACK_GUARD_SIZE, MappingFlags::empty(), false)
+        .ok()?;
+    if aspace
+        .map_alloc(bottom, size, MappingFlags::READ | MappingFlags::WRITE, true)
+        .is_err()
+    {
+        aspace.unmap(start, STACK_GUARD_SIZE).ok();
+        return None;
+    }
+    Some(bottom)
+}
+
+/// Frees a kernel stack allocated by [`alloc_kernel_stack`].
+pub fn dealloc_kernel_stack(bottom: VirtAddr, size: usize) {
+    let size = align_up_4k(size);
+    {
+        let mut free_stacks = FREE_STACKS.lock();
+        if free_stacks.len() < MAX_FREE_STACKS {
+            free_stacks.push((bottom, size));
+            return;
+        }
+    }
+    // Unmap it with the guard page, and free the frames.
+    let start = bottom - STACK_GUARD_SIZE;
+    if let Err(e) = crate::kernel_aspace()
+        .lock()
+        .unmap(start, STACK_GUARD_SIZE + size)
+    {
+        warn!("failed to unmap the kernel stack at {:#x}: {:?}", bottom, e);
+    }
+}
@@ -0,0 +1,188 @@
+//! Swap space for the reclaimed pages.
+//!
+//! The pages of the allocation mappings (and the private file mappings) are
+//! written to the slots of a [`SwapDevice`] when they are reclaimed, and their
+//! page table entries are replaced by non-present entries holding the slot
+//! numbers. They are read back on the next page fault.
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::VfsNodeRef;
+use axhal::mem::phys_to_virt;
+use kspin::SpinNoIrq;
+use memory_addr::{PhysAddr, PAGE_SIZE_4K};
+
+/// A device to store the swapped out pages, e.g., a swap file or a block
+/// device.
+pub trait SwapDevice: Send + Sync {
+    /// Returns the number of pages the device can store.
+    fn capacity(&self) -> usize;
+
+    /// Reads the page at the slot `index` into `buf`.
+    fn read_page(&self, index: usize, buf: &mut [u8]) -> AxResult;
+
+    /// Writes `buf` to the page at the slot `index`.
+    fn write_page(&self, index: usize, buf: &[u8]) -> AxResult;
+}
+
+/// Uses a file (or a device file) as the swap device, the size of the file is
+/// not changed.
+///
+/// The file should bypass the page cache, otherwise the swapped out pages use
+/// the memory again.
+impl SwapDevice for VfsNodeRef {
+    fn capacity(&self) -> usize {
+        self.get_attr()
+            .map_or(0, |attr| attr.size() as usize / PAGE_SIZE_4K)
+    }
+
+    fn read_page(&self, index: usize, buf: &mut [u8]) -> AxResult {
+        let offset = (index * PAGE_SIZE_4K) as u64;
+        let mut read = 0;
+        while read < buf.len() {
+            match self.read_at(offset + read as u64, &mut buf[read..])? {
+                0 => return ax_err!(UnexpectedEof),
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, index: usize, buf: &[u8]) -> AxResult {
+        let offset = (index * PAGE_SIZE_4K) as u64;
+        let mut written = 0;
+        while written < buf.len() {
+            match self.write_at(offset + written as u64, &buf[written..])? {
+                0 => return ax_err!(WriteZero),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct SwapSpace {
+    device: Arc<dyn SwapDevice>,
+    /// The reference counts of the slots, 0 for the free ones. A slot is
+    /// shared if the address space is cloned after the page is swapped out.
+    refs: Vec<u16>,
+    /// Where to start searching for a free slot.
+    next: usize,
+}
+
+static SWAP_SPACE: SpinNoIrq<Option<SwapSpace>> = SpinNoIrq::new(None);
+
+/// Enables swapping to `device`.
+///
+/// Returns an error if a swap device is already enabled, or the device is too
+/// small.
+pub fn swap_on(device: Arc<dyn SwapDevice>) -> AxResult {
+    let capacity = device.capacity();
+    if capacity == 0 {
+        return ax_err!(InvalidInput, "swap device too small");
+    }
+    let mut space = SWAP_SPACE.lock();
+    if space.is_some() {
+        return ax_err!(AlreadyExists, "swap device already enabled");
+    }
+    info!("swap on: {} pages", capacity);
+    *space = Some(SwapSpace {
+        device,
+        refs: vec![0; capacity],
+        next: 0,
+    });
+    Ok(())
+}
+
+/// Disables swapping.
+///
+/// Returns an error if some pages are still swapped out.
+pub fn swap_off() -> AxResult {
+    let mut space = SWAP_SPACE.lock();
+    match space.as_ref() {
+        None => ax_err!(NotFound, "no swap device"),
+        Some(s) if s.refs.iter().any(|&r| r != 0) => ax_err!(ResourceBusy, "swap device in use"),
+        Some(_) => {
+            *space = None;
+            Ok(())
+        }
+    }
+}
+
+/// Returns the number of used and total slots of the swap device.
+pub fn swap_usage() -> (usize, usize) {
+    match SWAP_SPACE.lock().as_ref() {
+        Some(s) => (s.refs.iter().filter(|&&r| r != 0).count(), s.refs.len()),
+        None => (0, 0),
+    }
+}
+
+fn frame_bytes<'a>(frame: PhysAddr) -> &'a mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Allocates a slot, returns `None` if no swap device is enabled or it is full.
+pub(crate) fn alloc_slot() -> Option<usize> {
+    let mut space = SWAP_SPACE.lock();
+    let s = space.as_mut()?;
+    let len = s.refs.len();
+    let slot = (0..len)
+        .map(|i| (s.next + i) % len)
+        .find(|&i| s.refs[i] == 0)?;
+    s.refs[slot] = 1;
+    s.next = (slot + 1) % len;
+    Some(slot)
+}
+
+/// Adds a reference to the slot.
+pub(crate) fn dup_slot(slot: usize) {
+    if let Some(s) = SWAP_SPACE.lock().as_mut() {
+        debug_assert!(s.refs[slot] > 0, "swap slot {} is free", slot);
+        s.refs[slot] = s.refs[slot]
+            .checked_add(1)
+            .expect("too many swap slot references");
+    }
+}
+
+/// Drops a reference to the slot, and frees it if it is the last one.
+pub(crate) fn put_slot(slot: usize) {
+    if let Some(s) = SWAP_SPACE.lock().as_mut() {
+        debug_assert!(s.refs[slot] > 0, "swap slot {} is free", slot);
+        match s.refs[slot].checked_sub(1) {
+            Some(refs) => s.refs[slot] = refs,
+            None => warn!("put the free swap slot {}", slot),
+        }
+    }
+}
+
+fn device() -> Option<Arc<dyn SwapDevice>> {
+    SWAP_SPACE.lock().as_ref().map(|s| s.device.clone())
+}
+
+/// Writes the frame to the slot.
+pub(crate) fn write_slot(slot: usize, frame: PhysAddr) -> bool {
+    // Do the I/O without holding the lock.
+    device().is_some_and(|dev| dev.write_page(slot, frame_bytes(frame)).is_ok())
+}
+
+/// Reads the slot into the frame.
+pub(crate) fn read_slot(slot: usize, frame: PhysAddr) -> bool {
+    device().is_some_and(|dev| dev.read_page(slot, frame_bytes(frame)).is_ok())
+}
+
+/// Returns the non-present page table entry (as the target physical address)
+/// referring to the slot.
+///
+/// It starts from the second page, as the lazy mappings are mapped to the
+/// physical address 0.
+pub(crate) fn slot_entry(slot: usize) -> PhysAddr {
+    PhysAddr::from((slot + 1) * PAGE_SIZE_4K)
+}
+
+/// Returns the slot that the non-present page table entry refers to.
+pub(crate) fn entry_slot(entry: PhysAddr) -> Option<usize> {
+    (entry.as_usize() >= PAGE_SIZE_4K).then(|| entry.as_usize() / PAGE_SIZE_4K - 1)
+}
@@ -0,0 +1,306 @@
+use std::sync::{Arc, Mutex, Once};
+
+use axerrno::AxError;
+use axfs_vfs::{VfsNodeRef, VfsNodeType, VfsOps};
+use axhal::paging::{MappingFlags, PageSize};
+use kspin::SpinNoIrq;
+use memory_addr::{va, VirtAddr, PAGE_SIZE_4K};
+
+use crate::{AddrSpace, SwapDevice, KERNEL_ASPACE};
+
+/// The memory of the frames, aligned to the huge pages.
+const MEMORY_SIZE: usize = 0x400_0000;
+const HUGE_SIZE: usize = PageSize::Size2M as usize;
+
+const BASE: VirtAddr = va!(0x1000_0000);
+const RW: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::USER);
+
+static INIT: Once = Once::new();
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn init() {
+    INIT.call_once(|| {
+        let layout = std::alloc::Layout::from_size_align(MEMORY_SIZE, HUGE_SIZE).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) } as usize;
+        assert_ne!(start, 0);
+        axalloc::global_init(start, MEMORY_SIZE);
+        // Cloned address spaces copy the kernel mappings from it.
+        let kernel = AddrSpace::new_empty(va!(0xffff_8000_0000_0000), 0x1_0000_0000).unwrap();
+        KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel));
+    });
+}
+
+fn new_aspace() -> AddrSpace {
+    AddrSpace::new_empty(BASE, 0x1000_0000).unwrap()
+}
+
+/// Faults in the page at `vaddr` as the CPU would on the access.
+fn fault(aspace: &mut AddrSpace, vaddr: VirtAddr, write: bool) {
+    let access = if write {
+        MappingFlags::WRITE
+    } else {
+        MappingFlags::READ
+    };
+    assert!(aspace.handle_page_fault(vaddr, access));
+}
+
+fn read_bytes<const N: usize>(aspace: &AddrSpace, vaddr: VirtAddr) -> Result<[u8; N], AxError> {
+    let mut buf = [0; N];
+    aspace.read(vaddr, &mut buf).map(|_| buf)
+}
+
+/// A swap device in memory.
+struct MemSwap(Mutex<Vec<[u8; PAGE_SIZE_4K]>>);
+
+impl SwapDevice for MemSwap {
+    fn capacity(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    fn read_page(&self, index: usize, buf: &mut [u8]) -> axerrno::AxResult {
+        buf.copy_from_slice(&self.0.lock().unwrap()[index]);
+        Ok(())
+    }
+
+    fn write_page(&self, index: usize, buf: &[u8]) -> axerrno::AxResult {
+        self.0.lock().unwrap()[index].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cow_fork() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let mut parent = new_aspace();
+    parent.map_alloc(BASE, 2 * PAGE_SIZE_4K, RW, true).unwrap();
+    parent.write(BASE, b"parent").unwrap();
+    let mut child = parent.clone_cow().unwrap();
+    assert_eq!(child.rss(), parent.rss());
+    assert_eq!(&read_bytes(&child, BASE).unwrap(), b"parent");
+
+    // The first write copies the shared page.
+    fault(&mut child, BASE, true);
+    child.write(BASE, b"child!").unwrap();
+    assert_eq!(&read_bytes(&parent, BASE).unwrap(), b"parent");
+    assert_eq!(&read_bytes(&child, BASE).unwrap(), b"child!");
+
+    // The other one is still shared, until the parent writes it.
+    let page = BASE + PAGE_SIZE_4K;
+    fault(&mut parent, page, true);
+    parent.write(page, b"second").unwrap();
+    assert_eq!(read_bytes(&child, page).unwrap(), [0; 6]);
+
+    // The child is left alone when the parent is gone.
+    drop(parent);
+    fault(&mut child, page, true);
+    assert_eq!(read_bytes(&child, page).unwrap(), [0; 6]);
+}
+
+#[test]
+fn test_file_mmap() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let fs = axfs_ramfs::RamFileSystem::new();
+    fs.root_dir().create("file", VfsNodeType::File).unwrap();
+    let file: VfsNodeRef = fs.root_dir().lookup("file").unwrap();
+    file.write_at(0, &[0; 2 * PAGE_SIZE_4K]).unwrap();
+    file.write_at(10, b"old").unwrap();
+
+    let mut aspace = new_aspace();
+    let size = 2 * PAGE_SIZE_4K;
+    aspace
+        .map_file(BASE, size, RW, file.clone(), 0, true)
+        .unwrap();
+    let private = BASE + size;
+    aspace
+        .map_file(private, size, RW, file.clone(), 0, false)
+        .unwrap();
+
+    // The pages are read from the file on demand, and the shared ones are
+    // read-only until they are written.
+    fault(&mut aspace, BASE, false);
+    assert_eq!(&read_bytes(&aspace, BASE + 10).unwrap(), b"old");
+
+    fault(&mut aspace, BASE, true);
+    aspace.write(BASE + 10, b"new").unwrap();
+    fault(&mut aspace, private, true);
+    aspace.write(private + 20, b"private").unwrap();
+    let mut buf = [0; 3];
+    file.read_at(10, &mut buf).unwrap();
+    assert_eq!(&buf, b"old");
+
+    // Only the shared mapping is written back.
+    aspace.msync(BASE, 2 * size).unwrap();
+    file.read_at(10, &mut buf).unwrap();
+    assert_eq!(&buf, b"new");
+    file.read_at(20, &mut buf).unwrap();
+    assert_eq!(buf, [0; 3]);
+
+    // The dirty pages are written back on unmap as well.
+    fault(&mut aspace, BASE + PAGE_SIZE_4K, false);
+    fault(&mut aspace, BASE + PAGE_SIZE_4K, true);
+    aspace.write(BASE + PAGE_SIZE_4K, b"tail").unwrap();
+    aspace.unmap(BASE, size).unwrap();
+    let mut buf = [0; 4];
+    file.read_at(PAGE_SIZE_4K as u64, &mut buf).unwrap();
+    assert_eq!(&buf, b"tail");
+    assert_eq!(read_bytes::<1>(&aspace, BASE), Err(AxError::BadAddress));
+}
+
+#[test]
+fn test_mremap() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let mut aspace = new_aspace();
+    aspace.map_alloc(BASE, 2 * PAGE_SIZE_4K, RW, false).unwrap();
+    fault(&mut aspace, BASE, true);
+    aspace.write(BASE, b"data").unwrap();
+
+    // grow in place
+    let size = 4 * PAGE_SIZE_4K;
+    assert_eq!(aspace.mremap(BASE, 2 * PAGE_SIZE_4K, size, false), Ok(BASE));
+    assert_eq!(aspace.vm_size(), size);
+    fault(&mut aspace, BASE + 3 * PAGE_SIZE_4K, true);
+    assert_eq!(&read_bytes(&aspace, BASE).unwrap(), b"data");
+
+    // move, as the pages after it are mapped
+    aspace
+        .map_alloc(BASE + size, PAGE_SIZE_4K, RW, false)
+        .unwrap();
+    assert_eq!(
+        aspace.mremap(BASE, size, 2 * size, false),
+        Err(AxError::NoMemory)
+    );
+    let rss = aspace.rss();
+    let new_start = aspace.mremap(BASE, size, 2 * size, true).unwrap();
+    assert_ne!(new_start, BASE);
+    assert_eq!(aspace.rss(), rss);
+    assert_eq!(&read_bytes(&aspace, new_start).unwrap(), b"data");
+    assert_eq!(read_bytes::<4>(&aspace, BASE), Err(AxError::BadAddress));
+    assert!(!aspace.handle_page_fault(BASE, MappingFlags::READ));
+    fault(&mut aspace, new_start + size, true);
+
+    // shrink
+    assert_eq!(
+        aspace.mremap(new_start, 2 * size, PAGE_SIZE_4K, false),
+        Ok(new_start)
+    );
+    assert_eq!(aspace.vm_size(), 2 * PAGE_SIZE_4K);
+    assert_eq!(&read_bytes(&aspace, new_start).unwrap(), b"data");
+}
+
+#[test]
+fn test_madvise_mlock() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let mut aspace = new_aspace();
+    let size = 2 * PAGE_SIZE_4K;
+    aspace.map_alloc(BASE, size, RW, false).unwrap();
+    fault(&mut aspace, BASE, true);
+    aspace.write(BASE, b"data").unwrap();
+
+    // The discarded pages are zero-filled on the next access.
+    aspace.discard(BASE, size).unwrap();
+    assert_eq!(aspace.rss(), 0);
+    assert_eq!(read_bytes::<4>(&aspace, BASE), Err(AxError::BadAddress));
+    fault(&mut aspace, BASE, false);
+    assert_eq!(read_bytes(&aspace, BASE).unwrap(), [0; 4]);
+
+    // The locked pages are populated, and are neither discarded nor reclaimed.
+    aspace.mlock(BASE, size).unwrap();
+    assert_eq!(aspace.rss(), size);
+    assert_eq!(aspace.discard(BASE, size), Err(AxError::InvalidInput));
+    assert_eq!(aspace.reclaim(2), 0);
+    aspace.munlock(BASE, size).unwrap();
+    aspace.discard(BASE, size).unwrap();
+    assert_eq!(aspace.rss(), 0);
+}
+
+#[test]
+fn test_huge_page_split() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let mut aspace = new_aspace();
+    let size = 2 * HUGE_SIZE;
+    aspace
+        .map_alloc_huge(BASE, size, RW, false, PageSize::Size2M)
+        .unwrap();
+    fault(&mut aspace, BASE + 0x1234, true);
+    let (_, _, page_size) = aspace.page_table().query(BASE).unwrap();
+    assert_eq!(page_size, PageSize::Size2M);
+    assert_eq!(aspace.rss(), HUGE_SIZE);
+    aspace.write(BASE, b"first").unwrap();
+    aspace.write(BASE + 2 * PAGE_SIZE_4K, b"third").unwrap();
+
+    // Unmapping a page in the middle splits the huge page around it.
+    aspace.unmap(BASE + PAGE_SIZE_4K, PAGE_SIZE_4K).unwrap();
+    for vaddr in [BASE, BASE + 2 * PAGE_SIZE_4K] {
+        let (_, _, page_size) = aspace.page_table().query(vaddr).unwrap();
+        assert_eq!(page_size, PageSize::Size4K);
+    }
+    assert_eq!(aspace.rss(), HUGE_SIZE - PAGE_SIZE_4K);
+    assert_eq!(&read_bytes(&aspace, BASE).unwrap(), b"first");
+    assert_eq!(
+        &read_bytes(&aspace, BASE + 2 * PAGE_SIZE_4K).unwrap(),
+        b"third"
+    );
+    assert_eq!(
+        read_bytes::<1>(&aspace, BASE + PAGE_SIZE_4K),
+        Err(AxError::BadAddress)
+    );
+    assert!(!aspace.handle_page_fault(BASE + PAGE_SIZE_4K, MappingFlags::READ));
+
+    // The other huge page is untouched.
+    fault(&mut aspace, BASE + HUGE_SIZE, true);
+    let (_, _, page_size) = aspace.page_table().query(BASE + HUGE_SIZE).unwrap();
+    assert_eq!(page_size, PageSize::Size2M);
+}
+
+#[test]
+fn test_swap() {
+    let _lock = SERIAL.lock();
+    init();
+
+    let device = MemSwap(Mutex::new(vec![[0; PAGE_SIZE_4K]; 8]));
+    crate::swap_on(Arc::new(device)).unwrap();
+    assert_eq!(crate::swap_usage(), (0, 8));
+
+    let mut aspace = new_aspace();
+    let pages = 4;
+    aspace
+        .map_alloc(BASE, pages * PAGE_SIZE_4K, RW, false)
+        .unwrap();
+    for i in 0..pages {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        fault(&mut aspace, vaddr, true);
+        aspace.write(vaddr, &[i as u8 + 1; 8]).unwrap();
+    }
+
+    assert_eq!(aspace.reclaim(pages), pages);
+    assert_eq!(aspace.rss(), 0);
+    assert_eq!(crate::swap_usage(), (pages, 8));
+    assert_eq!(read_bytes::<8>(&aspace, BASE), Err(AxError::BadAddress));
+
+    // The pages are read back on the next access, and the slots are freed.
+    for i in 0..pages {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        fault(&mut aspace, vaddr, false);
+        assert_eq!(read_bytes(&aspace, vaddr).unwrap(), [i as u8 + 1; 8]);
+    }
+    assert_eq!(aspace.rss(), pages * PAGE_SIZE_4K);
+    assert_eq!(crate::swap_usage(), (0, 8));
+
+    // The swapped out pages are freed with the address space.
+    assert_eq!(aspace.reclaim(1), 1);
+    assert_eq!(crate::swap_off(), Err(AxError::ResourceBusy));
+    drop(aspace);
+    crate::swap_off().unwrap();
+}
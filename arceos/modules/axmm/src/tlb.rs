@@ -0,0 +1,345 @@
+//! Address space identifiers (ASIDs) and TLB shootdown.
+//!
+//! An address space gets an ASID (a PCID on x86_64) when it is activated, so
+//! that switching to it keeps the TLB entries of the others. The ASIDs are
+//! allocated in generations: when they run out, a new generation starts, and
+//! the address spaces get new ASIDs on their next activation. A CPU flushes
+//! the entries of an ASID when it uses the ASID for the first time, as they
+//! may be left by the previous owner. If ASIDs are not supported, switching
+//! the page table flushes the entire TLB.
+//!
+//! When the mappings of an address space change, the CPUs running it flush
+//! their TLBs on inter-processor interrupts (with the `smp` and `irq`
+//! features), and the changer waits for them for a while. The other CPUs that
+//! have run it flush the entries lazily on the next activation.
+
+use core::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+
+use axhal::arch::{
+    flush_tlb, flush_tlb_asid, max_asid, write_page_table_root, write_page_table_root_asid,
+};
+use axhal::cpu::this_cpu_id;
+use kspin::SpinNoIrq;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// The number of bits of the ASID in a context number.
+const ASID_BITS: u32 = 16;
+/// Flushes all the entries of the ASID if more pages are changed.
+const MAX_FLUSH_PAGES: usize = 32;
+/// The ID of the kernel address space.
+const KERNEL_ID: u64 = 0;
+/// No ASID is used by the CPU.
+const NO_ASID: usize = usize::MAX;
+/// The number of CPUs in the masks of CPUs.
+const MAX_CPUS: usize = usize::BITS as usize;
+
+/// The next context number, i.e., `generation << ASID_BITS | asid`. The ASID
+/// 0 is reserved for the untagged page tables.
+static NEXT_CONTEXT: SpinNoIrq<u64> = SpinNoIrq::new(1 << ASID_BITS | 1);
+static NEXT_ID: AtomicU64 = AtomicU64::new(KERNEL_ID + 1);
+
+/// The CPUs where the memory management is initialized.
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+/// The ID of the address space active on each CPU.
+static ACTIVE_ID: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(KERNEL_ID) }; MAX_CPUS];
+/// The ASID used by each CPU.
+static ACTIVE_ASID: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(NO_ASID) }; MAX_CPUS];
+
+/// The TLB state of an address space.
+pub(crate) struct TlbState {
+    /// The unique ID of the address space.
+    id: u64,
+    /// The context number of the ASID, 0 if not allocated.
+    context: AtomicU64,
+    /// The CPUs that have used the ASID.
+    cpus: AtomicUsize,
+    /// The CPUs that should flush the entries of the ASID on the next
+    /// activation.
+    stale: AtomicUsize,
+}
+
+impl TlbState {
+    /// Creates the TLB state of a new address space.
+    pub fn new() -> Self {
+        Self::with_id(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Creates the TLB state of the kernel address space, whose mappings are
+    /// also in the other address spaces.
+    pub fn kernel() -> Self {
+        Self::with_id(KERNEL_ID)
+    }
+
+    const fn with_id(id: u64) -> Self {
+        Self {
+            id,
+            context: AtomicU64::new(0),
+            cpus: AtomicUsize::new(0),
+            stale: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the ASID of the current generation, and allocates a new one if
+    /// it is outdated.
+    fn asid(&self, max_asid: usize) -> usize {
+        let mut next = NEXT_CONTEXT.lock();
+        let context = self.context.load(Ordering::Relaxed);
+        if context >> ASID_BITS == *next >> ASID_BITS {
+            return (context & ((1 << ASID_BITS) - 1)) as usize;
+        }
+        if (*next & ((1 << ASID_BITS) - 1)) as usize > max_asid {
+            // Start a new generation.
+            *next = ((*next >> ASID_BITS) + 1) << ASID_BITS | 1;
+        }
+        let context = *next;
+        *next += 1;
+        self.cpus.store(0, Ordering::Relaxed);
+        self.stale.store(0, Ordering::Relaxed);
+        self.context.store(context, Ordering::Relaxed);
+        (context & ((1 << ASID_BITS) - 1)) as usize
+    }
+
+    /// Switches the current CPU to the address space with the page table
+    /// `root`.
+    ///
+    /// # Safety
+    ///
+    /// The page table should map the kernel, and should not be freed while it
+    /// is active.
+    pub unsafe fn activate(&self, root: PhysAddr) {
+        let cpu = this_cpu_id();
+        ACTIVE_ID[cpu].store(self.id, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+
+        let max_asid = max_asid();
+        if self.id == KERNEL_ID || max_asid == 0 {
+            ACTIVE_ASID[cpu].store(NO_ASID, Ordering::Relaxed);
+            write_page_table_root(root);
+            return;
+        }
+        let asid = self.asid(max_asid);
+        let mask = 1 << cpu;
+        // Flush the entries of the previous owner, or of the changed mappings.
+        let first = self.cpus.fetch_or(mask, Ordering::AcqRel) & mask == 0;
+        let stale = self.stale.fetch_and(!mask, Ordering::AcqRel) & mask != 0;
+        ACTIVE_ASID[cpu].store(asid, Ordering::Relaxed);
+        write_page_table_root_asid(root, asid, first || stale);
+    }
+
+    /// Flushes the TLB entries of the pages in `[start, start + size)` on all
+    /// the CPUs, after the mappings are changed.
+    pub fn flush(&self, start: VirtAddr, size: usize) {
+        let pages = size.div_ceil(PAGE_SIZE_4K);
+        if pages == 0 {
+            return;
+        }
+        let cpu = this_cpu_id();
+        let online = ONLINE_CPUS.load(Ordering::Relaxed) | 1 << cpu;
+        let (local, targets) = self.flush_targets(cpu, online);
+        if local {
+            let asid = if self.id == KERNEL_ID {
+                None
+            } else {
+                local_asid(cpu)
+            };
+            flush_local(asid, start, pages);
+        }
+        if targets != 0 {
+            shootdown(targets, self.id == KERNEL_ID, start, pages);
+        }
+    }
+
+    /// Returns whether `cpu` should flush its TLB after the mappings are
+    /// changed on it, and the other CPUs in `online` to shoot down.
+    ///
+    /// The CPUs that have used the ASID but are not running the address space
+    /// are marked to flush on the next activation.
+    fn flush_targets(&self, cpu: usize, online: usize) -> (bool, usize) {
+        if self.id == KERNEL_ID {
+            // The kernel mappings are in all the address spaces.
+            return (true, online & !(1 << cpu));
+        }
+        // Let the inactive CPUs flush on the next activation.
+        self.stale.fetch_or(
+            self.cpus.load(Ordering::Relaxed) & !(1 << cpu),
+            Ordering::AcqRel,
+        );
+        fence(Ordering::SeqCst);
+        let mut local = false;
+        let mut targets = 0;
+        for i in (0..MAX_CPUS).filter(|&i| online & 1 << i != 0) {
+            if ACTIVE_ID[i].load(Ordering::Relaxed) != self.id {
+                if i == cpu {
+                    self.stale.fetch_or(1 << cpu, Ordering::AcqRel);
+                }
+            } else if i == cpu {
+                local = true;
+            } else {
+                targets |= 1 << i;
+            }
+        }
+        (local, targets)
+    }
+}
+
+/// Switches the current CPU to the kernel page table `root`.
+///
+/// # Safety
+///
+/// The page table should be the one of the kernel address space.
+pub(crate) unsafe fn activate_kernel(root: PhysAddr) {
+    TlbState::kernel().activate(root);
+}
+
+fn local_asid(cpu: usize) -> Option<usize> {
+    match ACTIVE_ASID[cpu].load(Ordering::Relaxed) {
+        NO_ASID => None,
+        asid => Some(asid),
+    }
+}
+
+/// Flushes the entries of `pages` pages from `start` tagged with `asid`, or of
+/// all ASIDs if it is `None`, on the current CPU.
+fn flush_local(asid: Option<usize>, start: VirtAddr, pages: usize) {
+    if pages > MAX_FLUSH_PAGES {
+        match asid {
+            Some(asid) => flush_tlb_asid(asid, None),
+            None => flush_tlb(None),
+        }
+        return;
+    }
+    for i in 0..pages {
+        let vaddr = start + i * PAGE_SIZE_4K;
+        match asid {
+            Some(asid) => flush_tlb_asid(asid, Some(vaddr)),
+            None => flush_tlb(Some(vaddr)),
+        }
+    }
+}
+
+/// Marks the current CPU as online, which receives the TLB shootdowns.
+pub(crate) fn cpu_online() {
+    ONLINE_CPUS.fetch_or(1 << this_cpu_id(), Ordering::Release);
+}
+
+#[cfg(all(feature = "smp", feature = "irq"))]
+mod ipi {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use kspin::SpinNoIrq;
+    use memory_addr::VirtAddr;
+
+    use super::{flush_local, flush_tlb, local_asid};
+
+    /// Serializes the shootdowns.
+    static SHOOTDOWN_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+    /// The CPUs that have not handled the current shootdown.
+    static PENDING: AtomicUsize = AtomicUsize::new(0);
+    /// The CPUs that should flush the entire TLB, as they did not handle a
+    /// shootdown in time.
+    static FLUSH_ALL: AtomicUsize = AtomicUsize::new(0);
+    static REQ_GLOBAL: AtomicBool = AtomicBool::new(false);
+    static REQ_START: AtomicUsize = AtomicUsize::new(0);
+    static REQ_PAGES: AtomicUsize = AtomicUsize::new(0);
+    /// How long to wait for the targets of a shootdown.
+    const SHOOTDOWN_TIMEOUT_NANOS: u64 = 10_000_000;
+
+    /// Sends the shootdown to `targets`, and waits for them to flush.
+    ///
+    /// A target may be waiting with IRQs disabled for a lock held by us, e.g.
+    /// of the address space, so the wait is bounded. The targets that do not
+    /// respond in time flush the entire TLB when they handle the IPI later.
+    pub(super) fn shootdown(targets: usize, global: bool, start: VirtAddr, pages: usize) {
+        let _guard = loop {
+            if let Some(guard) = SHOOTDOWN_LOCK.try_lock() {
+                break guard;
+            }
+            // The IRQs may be disabled, handle the shootdown to us here.
+            handle_shootdown();
+            core::hint::spin_loop();
+        };
+        REQ_GLOBAL.store(global, Ordering::Relaxed);
+        REQ_START.store(start.as_usize(), Ordering::Relaxed);
+        REQ_PAGES.store(pages, Ordering::Relaxed);
+        PENDING.store(targets, Ordering::Release);
+        for cpu in (0..axconfig::SMP).filter(|&cpu| targets & 1 << cpu != 0) {
+            axhal::irq::send_ipi(cpu);
+        }
+        let deadline = axhal::time::monotonic_time_nanos() + SHOOTDOWN_TIMEOUT_NANOS;
+        while PENDING.load(Ordering::Acquire) & targets != 0 {
+            if axhal::time::monotonic_time_nanos() > deadline {
+                // Mark them before dropping the request, see `handle_shootdown`.
+                let late = PENDING.load(Ordering::Acquire) & targets;
+                FLUSH_ALL.fetch_or(late, Ordering::AcqRel);
+                PENDING.fetch_and(!late, Ordering::AcqRel);
+                warn!("TLB shootdown to CPUs {:#x} timed out", late);
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Handles the pending TLB shootdown to the current CPU.
+    ///
+    /// It is called by the handler of the inter-processor interrupts.
+    pub fn handle_shootdown() {
+        let cpu = axhal::cpu::this_cpu_id();
+        let mask = 1 << cpu;
+        if PENDING.load(Ordering::Acquire) & mask != 0 {
+            let asid = if REQ_GLOBAL.load(Ordering::Relaxed) {
+                None
+            } else {
+                local_asid(cpu)
+            };
+            let start = VirtAddr::from(REQ_START.load(Ordering::Relaxed));
+            flush_local(asid, start, REQ_PAGES.load(Ordering::Relaxed));
+            PENDING.fetch_and(!mask, Ordering::AcqRel);
+        }
+        // Checked after the request, which may be dropped on the timeout and
+        // replaced by a new one while it is read.
+        if FLUSH_ALL.load(Ordering::Acquire) & mask != 0 {
+            FLUSH_ALL.fetch_and(!mask, Ordering::AcqRel);
+            flush_tlb(None);
+        }
+    }
+}
+
+#[cfg(all(feature = "smp", feature = "irq"))]
+pub use self::ipi::handle_shootdown;
+#[cfg(all(feature = "smp", feature = "irq"))]
+use self::ipi::shootdown;
+
+/// Without IPIs, the TLB entries on the other CPUs cannot be flushed.
+#[cfg(not(all(feature = "smp", feature = "irq")))]
+fn shootdown(targets: usize, _global: bool, _start: VirtAddr, _pages: usize) {
+    warn!("cannot shoot down the TLB entries on CPUs {:#x}", targets);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_flush() {
+        let tlb = TlbState::new();
+        // CPU 1 is running the address space, and CPU 0 has run it.
+        tlb.cpus.store(0b11, Ordering::Relaxed);
+        ACTIVE_ID[0].store(KERNEL_ID, Ordering::Relaxed);
+        ACTIVE_ID[1].store(tlb.id, Ordering::Relaxed);
+
+        // CPU 0 shoots down CPU 1, and flushes on the next activation.
+        assert_eq!(tlb.flush_targets(0, 0b11), (false, 0b10));
+        assert_ne!(tlb.stale.load(Ordering::Relaxed) & 0b01, 0);
+
+        // Both are running it now.
+        ACTIVE_ID[0].store(tlb.id, Ordering::Relaxed);
+        assert_eq!(tlb.flush_targets(0, 0b11), (true, 0b10));
+        assert_eq!(tlb.flush_targets(1, 0b11), (true, 0b01));
+
+        // An offline CPU is not shot down.
+        assert_eq!(tlb.flush_targets(0, 0b01), (true, 0));
+
+        // The kernel mappings are flushed on all the online CPUs.
+        assert_eq!(TlbState::kernel().flush_targets(1, 0b111), (true, 0b101));
+    }
+}
@@ -6,11 +6,14 @@
 //! # Cargo Features
 //!
 //! - `alloc`: Enable global memory allocator.
+//! - `alloc-debug`: Enable the heap debugging of the global memory allocator,
+//!   and report the live allocations when the application exits.
 //! - `paging`: Enable page table manipulation support.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
 //! - `fs`: Enable filesystem support.
+//! - `swap`: Swap to the file `/swapfile` if it exists.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
 //!
@@ -21,6 +24,8 @@
 
 #[macro_use]
 extern crate axlog;
+#[cfg(any(all(feature = "alloc", feature = "multitask"), feature = "swap"))]
+extern crate alloc;
 
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
@@ -83,6 +88,71 @@ impl axlog::LogIf for LogIfImpl {
     }
 }
 
+#[cfg(all(feature = "alloc", feature = "multitask"))]
+struct HeapAccountingImpl;
+
+/// Charges the heap memory to the task groups.
+#[cfg(all(feature = "alloc", feature = "multitask"))]
+impl axalloc::HeapAccounting for HeapAccountingImpl {
+    fn charge(&self, size: usize) -> bool {
+        axtask::current_may_uninit().map_or(true, |curr| curr.charge_mem(size))
+    }
+
+    fn uncharge(&self, size: usize) {
+        if let Some(curr) = axtask::current_may_uninit() {
+            curr.uncharge_mem(size);
+        }
+    }
+}
+
+/// The file to swap to, see the `swap` feature.
+#[cfg(feature = "swap")]
+const SWAP_FILE: &str = "/swapfile";
+
+#[cfg(feature = "swap")]
+fn init_swap() {
+    let mut opts = axfs::fops::OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    // The swapped out pages should not be cached in memory again.
+    opts.direct(true);
+    let Ok(file) = axfs::fops::File::open(SWAP_FILE, &opts) else {
+        return;
+    };
+    let device = match file.mmap_node(true) {
+        Ok(node) => alloc::sync::Arc::new(node),
+        Err(e) => {
+            warn!("failed to swap to {}: {:?}", SWAP_FILE, e);
+            return;
+        }
+    };
+    // The mapped node keeps the filesystem mounted after the file is closed.
+    if let Err(e) = axmm::swap_on(device) {
+        warn!("failed to swap to {}: {:?}", SWAP_FILE, e);
+    }
+}
+
+/// Records the call sites of the heap allocations by walking the stack.
+#[cfg(feature = "alloc-debug")]
+fn trace_call_site(call_site: &mut [usize]) -> usize {
+    // The tasks without their own stacks (e.g., the main task) run on the boot
+    // stacks.
+    #[cfg(feature = "multitask")]
+    let stack = axtask::current_may_uninit()
+        .and_then(|curr| curr.kernel_stack_range())
+        .unwrap_or_else(axhal::mem::boot_stack_range);
+    #[cfg(not(feature = "multitask"))]
+    let stack = axhal::mem::boot_stack_range();
+    let mut depth = 0;
+    axhal::backtrace::unwind(stack, |ret_addr| {
+        if depth < call_site.len() {
+            call_site[depth] = ret_addr;
+            depth += 1;
+        }
+    });
+    depth
+}
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
@@ -144,6 +214,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(any(feature = "alloc", feature = "alt_alloc"))]
     init_allocator();
 
+    #[cfg(feature = "alloc-debug")]
+    axalloc::set_call_site_tracer(trace_call_site);
+
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
 
@@ -153,6 +226,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
 
+    #[cfg(all(feature = "alloc", feature = "multitask"))]
+    axalloc::set_heap_accounting(&HeapAccountingImpl);
+
     #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
     {
         #[allow(unused_variables)]
@@ -161,6 +237,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         #[cfg(feature = "fs")]
         axfs::init_filesystems(all_devices.block);
 
+        #[cfg(feature = "swap")]
+        init_swap();
+
         #[cfg(feature = "net")]
         axnet::init_network(all_devices.net);
 
@@ -190,8 +269,16 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         core::hint::spin_loop();
     }
 
+    // Move the application to its own group, so that its limit does not fail
+    // the allocations of the kernel tasks.
+    #[cfg(all(feature = "alloc", feature = "multitask"))]
+    axtask::current().set_mem_account(alloc::sync::Arc::new(axtask::MemAccount::new()));
+
     unsafe { main() };
 
+    #[cfg(feature = "alloc-debug")]
+    axalloc::dump_allocations();
+
     #[cfg(feature = "multitask")]
     axtask::exit(0);
     #[cfg(not(feature = "multitask"))]
@@ -264,12 +351,15 @@ fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
 
     // Setup timer interrupt handler
+    #[cfg(not(feature = "multitask"))]
     const PERIODIC_INTERVAL_NANOS: u64 =
         axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
 
+    #[cfg(not(feature = "multitask"))]
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
 
+    #[cfg(not(feature = "multitask"))]
     fn update_timer() {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
@@ -281,12 +371,24 @@ fn init_interrupt() {
         axhal::time::set_oneshot_timer(deadline);
     }
 
+    // With `multitask`, the task manager programs the timer for both the
+    // periodic ticks and the timed events.
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
+        #[cfg(not(feature = "multitask"))]
         update_timer();
         #[cfg(feature = "multitask")]
         axtask::on_timer_tick();
     });
 
+    // Setup the handler of IPIs, for rescheduling and TLB shootdowns
+    #[cfg(feature = "smp")]
+    axhal::irq::register_handler(axhal::irq::IPI_IRQ_NUM, || {
+        #[cfg(feature = "paging")]
+        axmm::handle_shootdown();
+        #[cfg(feature = "multitask")]
+        axtask::on_reschedule_ipi();
+    });
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
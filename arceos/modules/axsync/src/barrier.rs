@@ -0,0 +1,117 @@
+//! A barrier to synchronize a group of tasks.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// A barrier enables multiple tasks to synchronize the beginning of some
+/// computation, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+pub struct Barrier {
+    wq: WaitQueue,
+    num_tasks: usize,
+    count: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+/// A [`BarrierWaitResult`] is returned by [`Barrier::wait()`] when all tasks
+/// in the [`Barrier`] have rendezvoused.
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` if this task is the "leader task" for the call to
+    /// [`Barrier::wait()`].
+    ///
+    /// Only one task will have `true` returned from their result, all other
+    /// tasks will have `false` returned.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a new barrier that can block `n` tasks.
+    ///
+    /// A barrier will block `n - 1` tasks which call [`wait()`] and then wake
+    /// up all tasks at once when the `n`th task calls [`wait()`]. A barrier
+    /// created with `n = 0` behaves the same as `n = 1`.
+    ///
+    /// [`wait()`]: Barrier::wait
+    pub const fn new(n: usize) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            num_tasks: n,
+            count: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks the current task until all tasks have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all tasks have rendezvoused once, and can
+    /// be used continuously. The last arrived task is the leader.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.count.fetch_add(1, Ordering::AcqRel) + 1 >= self.num_tasks {
+            self.count.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            self.wq.notify_all(true);
+            BarrierWaitResult(true)
+        } else {
+            self.wq
+                .wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+            BarrierWaitResult(false)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("num_tasks", &self.num_tasks)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Barrier;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::mutex::tests::{may_interrupt, INIT, SERIAL};
+
+    #[test]
+    fn rendezvous() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_ROUNDS: usize = 10;
+        static BARRIER: Barrier = Barrier::new(NUM_TASKS);
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+        let tasks: Vec<_> = (0..NUM_TASKS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for round in 0..NUM_ROUNDS {
+                        may_interrupt();
+                        ARRIVED.fetch_add(1, Ordering::Relaxed);
+                        if BARRIER.wait().is_leader() {
+                            LEADERS.fetch_add(1, Ordering::Relaxed);
+                        }
+                        assert!(ARRIVED.load(Ordering::Relaxed) >= (round + 1) * NUM_TASKS);
+                        BARRIER.wait();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.join();
+        }
+        assert_eq!(LEADERS.load(Ordering::Relaxed), NUM_ROUNDS);
+    }
+}
@@ -0,0 +1,152 @@
+//! A condition variable.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axtask::WaitQueue;
+
+use crate::MutexGuard;
+
+/// A type indicating whether a timed wait on a condition variable returned
+/// due to a time out or not.
+#[cfg(feature = "irq")]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+#[cfg(feature = "irq")]
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait was known to have timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// It is used along with a [`Mutex`](crate::Mutex) to block a task until
+/// some condition becomes true. Like other condition variables, the waiting
+/// task may be woken up spuriously, so the condition should be checked in a
+/// loop, or use [`Condvar::wait_while`] instead.
+pub struct Condvar {
+    wq: WaitQueue,
+    /// Increased on each notification, so that the notifications between
+    /// unlocking the mutex and blocking are not missed.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Blocks the current task until this condition variable receives a
+    /// notification.
+    ///
+    /// The mutex of `guard` is unlocked while blocking, and is locked again
+    /// before returning.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        self.wq
+            .wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+
+    /// Blocks the current task until the `condition` on the data protected by
+    /// the mutex becomes false.
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Waits on this condition variable for a notification, timing out after
+    /// the specified duration.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: core::time::Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        let timeout = self
+            .wq
+            .wait_timeout_until(dur, || self.seq.load(Ordering::Acquire) != seq);
+        (mutex.lock(), WaitTimeoutResult(timeout))
+    }
+
+    /// Wakes up one blocked task on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Wakes up all blocked tasks on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Condvar { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Condvar, Mutex};
+    use axtask as thread;
+
+    use crate::mutex::tests::{INIT, SERIAL};
+
+    #[test]
+    fn producer_consumer() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_ITEMS: usize = 100;
+        static QUEUE: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        static NOT_EMPTY: Condvar = Condvar::new();
+
+        let consumer = thread::spawn(|| {
+            let mut sum = 0;
+            for _ in 0..NUM_ITEMS {
+                let mut queue = NOT_EMPTY.wait_while(QUEUE.lock(), |q| q.is_empty());
+                sum += queue.pop().unwrap();
+            }
+            thread::exit(sum as _);
+        });
+        for i in 0..NUM_ITEMS {
+            QUEUE.lock().push(i);
+            NOT_EMPTY.notify_one();
+            if i % 7 == 0 {
+                thread::yield_now();
+            }
+        }
+        assert_eq!(consumer.join(), Some((0..NUM_ITEMS).sum::<usize>() as _));
+    }
+}
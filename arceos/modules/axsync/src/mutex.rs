@@ -118,6 +118,29 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Locks the [`Mutex`] like [`Mutex::lock`], but gives up after the given
+    /// duration.
+    #[cfg(feature = "irq")]
+    pub fn lock_timeout(&self, dur: core::time::Duration) -> Option<MutexGuard<T>> {
+        if let Some(guard) = self.try_lock() {
+            return Some(guard);
+        }
+        let current_id = current().id().as_u64();
+        let try_acquire = || {
+            self.owner_id
+                .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        };
+        if self.wq.wait_timeout_until(dur, try_acquire) {
+            None
+        } else {
+            Some(MutexGuard {
+                lock: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        }
+    }
+
     /// Force unlock the [`Mutex`].
     ///
     /// # Safety
@@ -167,6 +190,13 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the mutex this guard was created from.
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -197,15 +227,30 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     }
 }
 
+#[cfg(feature = "paging")]
+impl axmm::AddrSpaceLock for Mutex<axmm::AddrSpace> {
+    fn with(&self, f: &mut dyn FnMut(&mut axmm::AddrSpace)) {
+        f(&mut self.lock());
+    }
+
+    fn try_with(&self, f: &mut dyn FnMut(&mut axmm::AddrSpace)) {
+        if let Some(mut aspace) = self.try_lock() {
+            f(&mut aspace);
+        }
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::Mutex;
     use axtask as thread;
     use std::sync::Once;
 
-    static INIT: Once = Once::new();
+    pub(crate) static INIT: Once = Once::new();
+    /// The tests share the scheduler, so they must run one by one.
+    pub(crate) static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
-    fn may_interrupt() {
+    pub(crate) fn may_interrupt() {
         // simulate interrupts
         if rand::random::<u32>() % 3 == 0 {
             thread::yield_now();
@@ -214,6 +259,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -0,0 +1,259 @@
+//! A sleeping mutex with priority inheritance.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use axtask::RawPiMutex;
+
+/// A mutual exclusion primitive with priority inheritance.
+///
+/// It works like [`Mutex`](crate::Mutex), but a task blocked on it lends its
+/// scheduling policy to the owner if that is more urgent, until the owner
+/// unlocks it. So a low-priority owner can not be starved by medium-priority
+/// tasks while a high-priority task is waiting for it. The inheritance is
+/// transitive along the chain of owners blocked on other [`PiMutex`]es.
+///
+/// When the mutex is unlocked, it is handed over to the most urgent waiter.
+pub struct PiMutex<T: ?Sized> {
+    raw: RawPiMutex,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct PiMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a PiMutex<T>,
+    data: *mut T,
+}
+
+// Same unsafe impls as `std::sync::Mutex`
+unsafe impl<T: ?Sized + Send> Sync for PiMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for PiMutex<T> {}
+
+impl<T> PiMutex<T> {
+    /// Creates a new [`PiMutex`] wrapping the supplied data.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            raw: RawPiMutex::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`PiMutex`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let PiMutex { data, .. } = self;
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> PiMutex<T> {
+    /// Returns `true` if the lock is currently held.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
+    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    /// Locks the [`PiMutex`] and returns a guard that permits access to the inner data.
+    ///
+    /// While the current task is blocked, the owner inherits its scheduling
+    /// policy if that is more urgent.
+    pub fn lock(&self) -> PiMutexGuard<T> {
+        self.raw.lock();
+        PiMutexGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Try to lock this [`PiMutex`], returning a lock guard if successful.
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<PiMutexGuard<T>> {
+        if self.raw.try_lock() {
+            Some(PiMutexGuard {
+                lock: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Locks the [`PiMutex`] like [`PiMutex::lock`], but gives up after the
+    /// given duration.
+    #[cfg(feature = "irq")]
+    pub fn lock_timeout(&self, dur: core::time::Duration) -> Option<PiMutexGuard<T>> {
+        if self.raw.lock_timeout(dur) {
+            Some(PiMutexGuard {
+                lock: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Force unlock the [`PiMutex`].
+    ///
+    /// # Safety
+    ///
+    /// This is *extremely* unsafe if the lock is not held by the current
+    /// thread. However, this can be useful in some instances for exposing
+    /// the lock to FFI that doesn’t know how to deal with RAII.
+    pub unsafe fn force_unlock(&self) {
+        self.raw.unlock();
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`PiMutex`] mutably, and a mutable reference is guaranteed to be exclusive in
+    /// Rust, no actual locking needs to take place -- the mutable borrow statically guarantees no locks exist. As
+    /// such, this is a 'zero-cost' operation.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: Default> Default for PiMutex<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PiMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "PiMutex {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "PiMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for PiMutexGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        // We know statically that only we are referencing data
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for PiMutexGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        // We know statically that only we are referencing data
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for PiMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for PiMutexGuard<'a, T> {
+    /// The dropping of the [`PiMutexGuard`] will release the lock it was created from.
+    fn drop(&mut self) {
+        unsafe { self.lock.force_unlock() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PiMutex;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::mutex::tests::{may_interrupt, INIT, SERIAL};
+
+    #[test]
+    fn lots_and_lots() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: u32 = 10;
+        const NUM_ITERS: u32 = 10_000;
+        static M: PiMutex<u32> = PiMutex::new(0);
+
+        fn inc(delta: u32) {
+            for _ in 0..NUM_ITERS {
+                let mut val = M.lock();
+                *val += delta;
+                may_interrupt();
+                drop(val);
+                may_interrupt();
+            }
+        }
+
+        let tasks: Vec<_> = (0..NUM_TASKS)
+            .flat_map(|_| [thread::spawn(|| inc(1)), thread::spawn(|| inc(2))])
+            .collect();
+        for task in tasks {
+            task.join();
+        }
+
+        assert!(!M.is_locked());
+        assert_eq!(*M.lock(), NUM_ITERS * NUM_TASKS * 3);
+        println!("PiMutex test OK");
+    }
+
+    #[test]
+    fn transitive_boost() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static M1: PiMutex<()> = PiMutex::new(());
+        static M2: PiMutex<()> = PiMutex::new(());
+        static NORMAL_RAN: AtomicBool = AtomicBool::new(false);
+
+        let guard = M1.lock();
+        // a normal task holding `M2` is blocked on `M1`
+        let middle = thread::spawn(|| {
+            let _g2 = M2.lock();
+            drop(M1.lock());
+        });
+        thread::yield_now();
+        assert!(M2.is_locked());
+
+        // a real-time task is blocked on `M2`, boosting both owners
+        let high = thread::spawn(|| {
+            thread::set_current_sched_policy(thread::SchedPolicy::Fifo(10)).unwrap();
+            drop(M2.lock());
+        });
+        thread::yield_now();
+
+        let normal = thread::spawn(|| NORMAL_RAN.store(true, Ordering::Release));
+        for _ in 0..10 {
+            thread::yield_now();
+            assert!(!NORMAL_RAN.load(Ordering::Acquire));
+        }
+        assert_eq!(
+            thread::current().sched_policy(),
+            thread::SchedPolicy::Normal
+        );
+
+        drop(guard);
+        high.join();
+        middle.join();
+        normal.join();
+        assert!(NORMAL_RAN.load(Ordering::Acquire));
+        assert!(!M1.is_locked() && !M2.is_locked());
+    }
+}
@@ -0,0 +1,478 @@
+//! A sleeping readers-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+const WRITER: usize = 1;
+const UPGRADABLE: usize = 1 << 1;
+const READER: usize = 1 << 2;
+
+/// A readers-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// It allows a number of readers or at most one writer at any point in time.
+/// An additional upgradable reader can coexist with the readers, and later be
+/// upgraded to a writer without releasing the lock.
+///
+/// The lock prefers writers: when a writer (or an upgrading reader) is
+/// waiting, new readers block until it finishes, so writers can not be
+/// starved. As a result, a task must not acquire the read lock recursively,
+/// unless with [`RwLock::read_recursive`].
+pub struct RwLock<T: ?Sized> {
+    wq: WaitQueue,
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will decrement the read count.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides immutable data access, and can be upgraded to a
+/// [`RwLockWriteGuard`].
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct RwLockUpgradableGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockUpgradableGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new instance of an [`RwLock`] which is unlocked.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`], returning the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        let RwLock { data, .. } = self;
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn acquire_reader(&self, recursive: bool) -> bool {
+        if !recursive && self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        let state = self.state.fetch_add(READER, Ordering::Acquire);
+        if state & WRITER != 0 {
+            // a writer may have failed because of us
+            self.release(READER);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn acquire_upgradable(&self) -> bool {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        let state = self.state.fetch_or(UPGRADABLE, Ordering::Acquire);
+        if state & (WRITER | UPGRADABLE) == 0 {
+            return true;
+        }
+        if state & UPGRADABLE == 0 {
+            // we set the bit, but a writer holds the lock
+            self.release(UPGRADABLE);
+        }
+        false
+    }
+
+    fn acquire_writer(&self, from: usize) -> bool {
+        self.state
+            .compare_exchange(from, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Blocks until the state changes from `from` to [`WRITER`], blocking new
+    /// readers while waiting.
+    fn wait_writer(&self, from: usize) {
+        if self.acquire_writer(from) {
+            return;
+        }
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        self.wq.wait_until(|| self.acquire_writer(from));
+        self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        if !self.acquire_reader(false) {
+            self.wq.wait_until(|| self.acquire_reader(false));
+        }
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with shared read access.
+    #[inline(always)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if self.acquire_reader(false) {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with shared read access like [`RwLock::read`],
+    /// but does not block for the waiting writers.
+    ///
+    /// So it can be acquired recursively while a writer is waiting, at the
+    /// cost that the writers may be starved.
+    pub fn read_recursive(&self) -> RwLockReadGuard<T> {
+        if !self.acquire_reader(true) {
+            self.wq.wait_until(|| self.acquire_reader(true));
+        }
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with shared read access like
+    /// [`RwLock::try_read`], but succeeds even if writers are waiting.
+    #[inline(always)]
+    pub fn try_read_recursive(&self) -> Option<RwLockReadGuard<T>> {
+        if self.acquire_reader(true) {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with upgradable read access, blocking the current
+    /// task until it can be acquired.
+    ///
+    /// There can be at most one upgradable reader at a time, along with any
+    /// number of readers.
+    pub fn upgradeable_read(&self) -> RwLockUpgradableGuard<T> {
+        if !self.acquire_upgradable() {
+            self.wq.wait_until(|| self.acquire_upgradable());
+        }
+        RwLockUpgradableGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with upgradable read access.
+    #[inline(always)]
+    pub fn try_upgradeable_read(&self) -> Option<RwLockUpgradableGuard<T>> {
+        if self.acquire_upgradable() {
+            Some(RwLockUpgradableGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the current
+    /// task until it can be acquired.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.wait_writer(0);
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Attempts to lock this [`RwLock`] with exclusive write access.
+    #[inline(always)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self.acquire_writer(0) {
+            Some(RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if a writer holds the lock.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    #[inline(always)]
+    pub fn is_locked_exclusive(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    /// Returns the number of readers, excluding the upgradable one.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    #[inline(always)]
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    /// Force decrement the reader count.
+    ///
+    /// # Safety
+    ///
+    /// This is *extremely* unsafe if there are no outstanding readers. It is
+    /// useful for exposing the lock to FFI that doesn't know how to deal with
+    /// RAII.
+    pub unsafe fn force_read_decrement(&self) {
+        debug_assert!(self.reader_count() > 0);
+        self.release(READER);
+    }
+
+    /// Force unlock exclusive write access.
+    ///
+    /// # Safety
+    ///
+    /// This is *extremely* unsafe if there are outstanding readers or no
+    /// writer. It is useful for exposing the lock to FFI that doesn't know how
+    /// to deal with RAII.
+    pub unsafe fn force_write_unlock(&self) {
+        debug_assert!(self.is_locked_exclusive());
+        self.release(WRITER);
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking needs
+    /// to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn release(&self, bits: usize) {
+        let state = self.state.fetch_sub(bits, Ordering::Release) - bits;
+        // Wake up the waiters if it may be acquired by a writer, or if the
+        // writer or upgradable reader has left.
+        if state & !UPGRADABLE == 0 || bits != READER {
+            self.wq.notify_all(true);
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    /// Downgrades the writer to a reader, without allowing other writers to
+    /// acquire the lock in between.
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.state.fetch_and(!WRITER, Ordering::Release);
+        lock.wq.notify_all(true);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<'a, T: ?Sized> RwLockUpgradableGuard<'a, T> {
+    /// Upgrades to a writer, blocking the current task until all the other
+    /// readers have left.
+    pub fn upgrade(self) -> RwLockWriteGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.wait_writer(UPGRADABLE);
+        RwLockWriteGuard { lock }
+    }
+
+    /// Tries to upgrade to a writer, returns the guard back if there are
+    /// other readers.
+    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'a, T>, Self> {
+        if self.lock.acquire_writer(UPGRADABLE) {
+            let lock = ManuallyDrop::new(self).lock;
+            Ok(RwLockWriteGuard { lock })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Downgrades to a normal reader, so that another upgradable reader may
+    /// acquire the lock.
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.release(UPGRADABLE);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockUpgradableGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockUpgradableGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(READER);
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradableGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(UPGRADABLE);
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(WRITER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RwLock;
+    use axtask as thread;
+
+    use crate::mutex::tests::{may_interrupt, INIT, SERIAL};
+
+    #[test]
+    fn readers_and_writers() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_ITERS: usize = 1_000;
+        static L: RwLock<(usize, usize)> = RwLock::new((0, 0));
+
+        let mut tasks = Vec::new();
+        for i in 0..NUM_TASKS {
+            tasks.push(thread::spawn(move || {
+                for _ in 0..NUM_ITERS {
+                    match i % 3 {
+                        0 => {
+                            let mut val = L.write();
+                            val.0 += 1;
+                            may_interrupt();
+                            val.1 += 1;
+                        }
+                        1 => {
+                            let val = L.read();
+                            may_interrupt();
+                            assert_eq!(val.0, val.1);
+                        }
+                        _ => {
+                            let val = L.upgradeable_read();
+                            let old = *val;
+                            may_interrupt();
+                            let mut val = val.upgrade();
+                            assert_eq!(*val, old);
+                            val.0 += 1;
+                            val.1 += 1;
+                            let val = val.downgrade();
+                            may_interrupt();
+                            assert_eq!(*val, (old.0 + 1, old.1 + 1));
+                        }
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.join();
+        }
+
+        let val = L.read();
+        assert!(L.try_write().is_none());
+        let upgradable = L.try_upgradeable_read().unwrap();
+        let upgradable = upgradable.try_upgrade().unwrap_err();
+        drop(val);
+        let val = upgradable.try_upgrade().unwrap();
+        assert_eq!(*val, (7 * NUM_ITERS, 7 * NUM_ITERS));
+    }
+
+    #[test]
+    fn recursive_read() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static L: RwLock<usize> = RwLock::new(0);
+
+        let val = L.read();
+        let writer = thread::spawn(|| *L.write() += 1);
+        thread::yield_now(); // the writer is waiting now
+        assert!(L.try_read().is_none());
+        let val2 = L.read_recursive();
+        assert_eq!(*val + *val2, 0);
+        drop(val2);
+        drop(val);
+        writer.join();
+        assert_eq!(*L.try_read_recursive().unwrap(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! A counting semaphore.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// A counting semaphore.
+///
+/// It maintains a count of available resources. [`Semaphore::acquire`]
+/// blocks the current task until the count is positive and decrements it,
+/// while [`Semaphore::release`] increments it and wakes up a waiting task.
+pub struct Semaphore {
+    wq: WaitQueue,
+    count: AtomicUsize,
+}
+
+/// A guard that releases the acquired resource when dropped.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the initial count.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Acquires a resource of this semaphore, blocking the current task until
+    /// it can do so.
+    pub fn acquire(&self) {
+        if !self.try_acquire() {
+            self.wq.wait_until(|| self.try_acquire());
+        }
+    }
+
+    /// Tries to acquire a resource of this semaphore, returns `true` on
+    /// success.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Acquires a resource of this semaphore like [`Semaphore::acquire`], but
+    /// gives up after the given duration. Returns `true` on success.
+    #[cfg(feature = "irq")]
+    pub fn acquire_timeout(&self, dur: core::time::Duration) -> bool {
+        self.try_acquire() || !self.wq.wait_timeout_until(dur, || self.try_acquire())
+    }
+
+    /// Releases a resource to this semaphore, waking up a waiting task.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Acquires a resource of this semaphore, and returns a guard to release
+    /// it when dropped.
+    pub fn access(&self) -> SemaphoreGuard<'_> {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+
+    /// Returns the number of the available resources.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("count", &self.available())
+            .finish()
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Semaphore;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::mutex::tests::{may_interrupt, INIT, SERIAL};
+
+    #[test]
+    fn limits_concurrency() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        static SEM: Semaphore = Semaphore::new(3);
+        static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+        let tasks: Vec<_> = (0..NUM_TASKS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..100 {
+                        let _guard = SEM.access();
+                        assert!(ACTIVE.fetch_add(1, Ordering::Relaxed) < 3);
+                        may_interrupt();
+                        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.join();
+        }
+        assert_eq!(SEM.available(), 3);
+    }
+}
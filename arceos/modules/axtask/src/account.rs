@@ -0,0 +1,87 @@
+//! Memory accounting of task groups.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The memory usage of a group of tasks, with an optional hard limit.
+///
+/// A new task is in the same group as the task creating it, unless it is given
+/// another account by [`TaskInner::set_mem_account`]. The tasks created during
+/// the initialization (e.g., `gc`) are in the group of the kernel, and the
+/// application is moved to its own group before it starts. The heap memory is
+/// charged to the group of the allocating task, and is uncharged from the
+/// group of the freeing task, so the usage is approximate if memory is passed
+/// between groups.
+///
+/// [`TaskInner::set_mem_account`]: crate::TaskInner::set_mem_account
+pub struct MemAccount {
+    used: AtomicUsize,
+    peak: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl MemAccount {
+    /// Creates a new account without limit.
+    pub const fn new() -> Self {
+        Self {
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            limit: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Returns the number of bytes used by the group.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum number of bytes used by the group.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Returns the limit of the used bytes, `usize::MAX` if unlimited.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Sets the limit of the used bytes, `usize::MAX` for unlimited.
+    ///
+    /// It only fails the later allocations, the memory already used is kept.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Charges `size` bytes to the group.
+    ///
+    /// Returns `false` and charges nothing if it exceeds the limit.
+    pub fn charge(&self, size: usize) -> bool {
+        let limit = self.limit();
+        let res = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|&new| new <= limit)
+            });
+        match res {
+            Ok(used) => {
+                self.peak.fetch_max(used + size, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Uncharges `size` bytes from the group.
+    pub fn uncharge(&self, size: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+}
+
+impl Default for MemAccount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
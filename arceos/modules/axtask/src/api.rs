@@ -2,8 +2,17 @@
 
 use alloc::{string::String, sync::Arc};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::{current_run_queue, AxRunQueue};
+pub(crate) use crate::sched::ClassScheduler as Scheduler;
 
+#[doc(cfg(feature = "multitask"))]
+pub use crate::account::MemAccount;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::futex::{futex_requeue, futex_wait, futex_wake, FutexKey, FUTEX_BITSET_MATCH_ANY};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::pi_mutex::RawPiMutex;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::sched::{DeadlineParams, SchedPolicy, MAX_RT_PRIO, MIN_RT_PRIO};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task::{CurrentTask, TaskId, TaskInner};
 #[doc(cfg(feature = "multitask"))]
@@ -14,20 +23,12 @@ pub use crate::wait_queue::WaitQueue;
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
 
-cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
-        const MAX_TIME_SLICE: usize = 5;
-        pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
-    } else if #[cfg(feature = "sched_cfs")] {
-        pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::CFScheduler<TaskInner>;
-    } else {
-        // If no scheduler features are set, use FIFO as the default.
-        pub(crate) type AxTask = scheduler::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::FifoScheduler<TaskInner>;
-    }
-}
+/// The set of CPUs on which a task is allowed to run.
+pub type AxCpuMask = cpumask::CpuMask<{ axconfig::SMP }>;
+
+// Every task carries the states of the CFS scheduler, by which batch tasks are
+// scheduled.
+pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
 
 #[cfg(feature = "preempt")]
 struct KernelGuardIfImpl;
@@ -69,7 +70,10 @@ pub fn init_scheduler() {
 
     crate::run_queue::init();
     #[cfg(feature = "irq")]
-    crate::timers::init();
+    {
+        crate::timers::init();
+        crate::timers::init_deferred();
+    }
 
     info!("  use {} scheduler.", Scheduler::scheduler_name());
 }
@@ -77,33 +81,129 @@ pub fn init_scheduler() {
 /// Initializes the task scheduler for secondary CPUs.
 pub fn init_scheduler_secondary() {
     crate::run_queue::init_secondary();
+    #[cfg(feature = "irq")]
+    crate::timers::init();
 }
 
-/// Handles periodic timer ticks for the task manager.
+/// Handles timer interrupts for the task manager.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// For example, advance scheduler states, checks timed events, etc. It also
+/// programs the one-shot timer of the current CPU to fire at the next periodic
+/// tick or the next timed event, whichever comes first.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
-    crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    if crate::timers::on_timer_irq() {
+        current_run_queue().scheduler_timer_tick();
+    }
+}
+
+/// Handles the reschedule IPI, which is sent by other CPUs after they put
+/// some tasks into the run queue of the current CPU.
+#[cfg(all(feature = "smp", feature = "irq"))]
+#[doc(cfg(all(feature = "smp", feature = "irq")))]
+pub fn on_reschedule_ipi() {
+    current_run_queue().reschedule_ipi();
 }
 
 /// Adds the given task to the run queue, returns the task reference.
+///
+/// With the `smp` feature, the task is placed on the CPU with the fewest
+/// ready tasks.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    crate::run_queue::spawn_task(task_ref.clone());
     task_ref
 }
 
 /// Spawns a new task with the given parameters.
 ///
+/// The new task inherits the CPU affinity of the current task.
+///
 /// Returns the task reference.
 pub fn spawn_raw<F>(f: F, name: String, stack_size: usize) -> AxTaskRef
 where
     F: FnOnce() + Send + 'static,
 {
-    spawn_task(TaskInner::new(f, name, stack_size))
+    TaskBuilder::new()
+        .name(name)
+        .stack_size(stack_size)
+        .spawn(f)
+}
+
+/// Task factory, which can be used to configure the properties of a new task.
+///
+/// # Examples
+///
+/// ```
+/// # axtask::init_scheduler();
+/// let task = axtask::TaskBuilder::new()
+///     .name("pinned".into())
+///     .cpumask(axtask::AxCpuMask::one_shot(0))
+///     .spawn(|| axtask::exit(42));
+/// assert_eq!(task.join(), Some(42));
+/// ```
+pub struct TaskBuilder {
+    name: String,
+    stack_size: usize,
+    cpumask: Option<AxCpuMask>,
+}
+
+impl TaskBuilder {
+    /// Creates a builder with the default parameters: an empty name, a stack
+    /// of [`axconfig::TASK_STACK_SIZE`] bytes, and the CPU affinity of the
+    /// current task.
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            stack_size: axconfig::TASK_STACK_SIZE,
+            cpumask: None,
+        }
+    }
+
+    /// Sets the name of the new task.
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Sets the stack size of the new task.
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Sets the CPUs on which the new task is allowed to run.
+    ///
+    /// An empty mask is ignored, the new task then inherits the CPU affinity
+    /// of the current task as if it is not set.
+    pub fn cpumask(mut self, cpumask: AxCpuMask) -> Self {
+        self.cpumask = Some(cpumask);
+        self
+    }
+
+    /// Spawns a new task with the given entry function, returns the task
+    /// reference.
+    pub fn spawn<F>(self, f: F) -> AxTaskRef
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let task = TaskInner::new(f, self.name, self.stack_size);
+        let cpumask = self
+            .cpumask
+            .filter(|cpumask| !cpumask.is_empty())
+            .or_else(|| current_may_uninit().map(|curr| curr.cpumask()));
+        if let Some(cpumask) = cpumask {
+            task.set_cpumask(cpumask);
+        }
+        spawn_task(task)
+    }
+}
+
+impl Default for TaskBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Spawns a new task with the default parameters.
@@ -121,21 +221,81 @@ where
 
 /// Set the priority for current task.
 ///
-/// The range of the priority is dependent on the underlying scheduler. For
-/// example, in the [CFS] scheduler, the priority is the nice value, ranging from
-/// -20 to 19.
+/// The priority is the nice value of [normal](SchedPolicy::Normal) and
+/// [batch](SchedPolicy::Batch) tasks, ranging from -20 to 19, which weights
+/// the CPU time of batch tasks in the [CFS] scheduler. Real-time and EDF tasks
+/// have no such priority, see [`set_current_sched_policy`].
 ///
 /// Returns `true` if the priority is set successfully.
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Set the scheduling policy for current task.
+///
+/// Real-time and EDF tasks always run before normal tasks. An EDF task is
+/// only admitted if the total bandwidth (runtime / period) of all EDF tasks
+/// stays within 95% of the CPUs, otherwise [`AxError::ResourceBusy`] is
+/// returned. Invalid parameters are rejected with [`AxError::InvalidInput`].
+///
+/// [`AxError::ResourceBusy`]: axerrno::AxError::ResourceBusy
+/// [`AxError::InvalidInput`]: axerrno::AxError::InvalidInput
+pub fn set_current_sched_policy(policy: SchedPolicy) -> axerrno::AxResult {
+    current_run_queue().set_current_sched_policy(policy)
+}
+
+/// Set the scheduling policy for the given task.
+///
+/// It takes effect immediately even if the task is ready or running on other
+/// CPUs. See [`set_current_sched_policy`] for the errors.
+pub fn set_sched_policy(task: &AxTaskRef, policy: SchedPolicy) -> axerrno::AxResult {
+    if current().ptr_eq(task) {
+        set_current_sched_policy(policy)
+    } else {
+        crate::run_queue::update_sched_entity(task, |entity| entity.set_policy(policy))
+    }
+}
+
+/// Set the CPU affinity for current task.
+///
+/// If the current CPU is not in the mask, the current task is migrated to
+/// one of the allowed CPUs immediately.
+///
+/// Returns `false` if the mask is empty.
+pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
+    if !current().set_cpumask(cpumask) {
+        return false;
+    }
+    current_run_queue().migrate_current();
+    true
+}
+
+/// Set the CPU affinity for the given task.
+///
+/// If the task is running on a CPU that is not in the mask, that CPU is asked
+/// to migrate it by an IPI (only with the `preempt` feature, otherwise it is
+/// migrated the next time it yields or blocks). The ready and blocked tasks are
+/// moved the next time they are scheduled.
+///
+/// Returns `false` if the mask is empty.
+pub fn set_affinity(task: &AxTaskRef, cpumask: AxCpuMask) -> bool {
+    if current().ptr_eq(task) {
+        return set_current_affinity(cpumask);
+    }
+    if !task.set_cpumask(cpumask) {
+        return false;
+    }
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    crate::run_queue::kick_disallowed(task);
+    true
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
@@ -150,14 +310,14 @@ pub fn sleep(dur: core::time::Duration) {
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    current_run_queue().sleep_until(deadline);
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
 /// Exits the current task.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current_run_queue().exit_current(exit_code)
 }
 
 /// The idle task routine.
@@ -0,0 +1,249 @@
+//! Futex-style wait/wake keyed by address.
+//!
+//! Tasks wait on a 32-bit word identified by a [`FutexKey`], and are woken up
+//! by other tasks with the same key. There is no object to create beforehand,
+//! so user-space locks only need to enter the kernel when they are contended.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinRaw;
+
+use crate::{current_run_queue, AxTaskRef};
+
+/// The bitset that matches all waiters, used by the waits and wakes without a
+/// bitset.
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// The number of the hash buckets is `2 ^ FUTEX_HASH_BITS`.
+const FUTEX_HASH_BITS: u32 = 6;
+
+/// Identifies a futex by the virtual address of the word and the address space
+/// containing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FutexKey {
+    aspace: usize,
+    addr: usize,
+}
+
+impl FutexKey {
+    /// Creates a key from the address space identifier (e.g., the physical
+    /// address of its page table root, 0 for the kernel) and the virtual
+    /// address of the futex word.
+    pub const fn new(aspace: usize, addr: usize) -> Self {
+        Self { aspace, addr }
+    }
+
+    fn bucket(&self) -> &'static FutexBucket {
+        let hash = ((self.addr >> 2) ^ self.aspace.rotate_left(16))
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+        &FUTEX_TABLE[hash >> (usize::BITS - FUTEX_HASH_BITS)]
+    }
+}
+
+struct FutexWaiter {
+    task: AxTaskRef,
+    bitset: u32,
+    /// The key can be changed by [`futex_requeue`], under the locks of both
+    /// the old and new buckets.
+    aspace: AtomicUsize,
+    addr: AtomicUsize,
+}
+
+impl FutexWaiter {
+    fn key(&self) -> FutexKey {
+        FutexKey::new(
+            self.aspace.load(Ordering::Acquire),
+            self.addr.load(Ordering::Acquire),
+        )
+    }
+
+    fn set_key(&self, key: FutexKey) {
+        self.aspace.store(key.aspace, Ordering::Release);
+        self.addr.store(key.addr, Ordering::Release);
+    }
+
+    fn wake(&self, rq: &mut crate::AxRunQueue) {
+        self.task.set_in_wait_queue(false);
+        rq.unblock_task(self.task.clone(), true);
+    }
+}
+
+// we already disabled IRQs when lock the run queue
+type FutexBucket = SpinRaw<VecDeque<Arc<FutexWaiter>>>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_BUCKET: FutexBucket = SpinRaw::new(VecDeque::new());
+static FUTEX_TABLE: [FutexBucket; 1 << FUTEX_HASH_BITS] = [EMPTY_BUCKET; 1 << FUTEX_HASH_BITS];
+
+/// Blocks the current task on the futex `key` if the value of `futex` is
+/// `val`, until it is woken up by [`futex_wake`] with a bitset intersecting
+/// `bitset`, or the `timeout` has elapsed.
+///
+/// Returns [`AxError::WouldBlock`] if the value is not `val`, or
+/// [`AxError::TimedOut`] on timeout, at once if the `timeout` is zero. Like
+/// Linux, it may return `Ok` on a spurious wakeup, the caller should check the
+/// value again.
+///
+/// The `timeout` is ignored without the `irq` feature.
+pub fn futex_wait(
+    key: FutexKey,
+    futex: &AtomicU32,
+    val: u32,
+    bitset: u32,
+    timeout: Option<core::time::Duration>,
+) -> AxResult {
+    if bitset == 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let curr = crate::current();
+    let waiter = Arc::new(FutexWaiter {
+        task: curr.clone(),
+        bitset,
+        aspace: AtomicUsize::new(key.aspace),
+        addr: AtomicUsize::new(key.addr),
+    });
+
+    #[cfg(feature = "irq")]
+    let deadline = timeout.map(|dur| axhal::time::wall_time() + dur);
+    #[cfg(not(feature = "irq"))]
+    if timeout.is_some() {
+        warn!("futex_wait: the `timeout` argument is ignored without the `irq` feature");
+    }
+
+    let mut rq = current_run_queue();
+    let mut bucket = key.bucket().lock();
+    // Check the value under the bucket lock, so that the wake after changing
+    // the value is not missed.
+    if futex.load(Ordering::SeqCst) != val {
+        return Err(AxError::WouldBlock);
+    }
+    // Arm the alarm with the run queue locked (and IRQs disabled), so that it
+    // cannot fire before the task is blocked.
+    #[cfg(feature = "irq")]
+    if let Some(deadline) = deadline {
+        if axhal::time::wall_time() >= deadline {
+            return Err(AxError::TimedOut);
+        }
+        crate::timers::set_alarm_wakeup(deadline, curr.clone());
+    }
+    rq.block_current(|task| {
+        task.set_in_wait_queue(true);
+        bucket.push_back(waiter.clone());
+        drop(bucket);
+    });
+    drop(rq);
+
+    // Still in the bucket, must be woken up by the timer.
+    let woken_by_timer = curr.in_wait_queue() && {
+        let _guard = kernel_guard::IrqSave::new();
+        loop {
+            let key = waiter.key();
+            let mut bucket = key.bucket().lock();
+            if waiter.key() != key {
+                continue; // requeued to another bucket
+            }
+            if curr.in_wait_queue() {
+                bucket.retain(|w| !Arc::ptr_eq(w, &waiter));
+                curr.set_in_wait_queue(false);
+                break true;
+            }
+            break false;
+        }
+    };
+    #[cfg(feature = "irq")]
+    if curr.in_timer_list() {
+        crate::timers::cancel_alarm(curr.as_task_ref());
+    }
+
+    if woken_by_timer && timeout.is_some() {
+        Err(AxError::TimedOut)
+    } else {
+        Ok(())
+    }
+}
+
+/// Wakes up at most `count` tasks waiting on the futex `key` with a bitset
+/// intersecting `bitset`.
+///
+/// Returns the number of tasks woken up.
+pub fn futex_wake(key: FutexKey, count: usize, bitset: u32) -> usize {
+    let mut rq = current_run_queue();
+    let mut bucket = key.bucket().lock();
+    let mut woken = 0;
+    bucket.retain(|w| {
+        if woken < count && w.bitset & bitset != 0 && w.key() == key {
+            w.wake(&mut rq);
+            woken += 1;
+            false
+        } else {
+            true
+        }
+    });
+    woken
+}
+
+/// Wakes up at most `nr_wake` tasks waiting on the futex `key`, and moves at
+/// most `nr_requeue` of the remaining tasks to wait on the futex `new_key`.
+///
+/// If `cmp` is given, it is done only if the value of the futex is the
+/// expected one, otherwise [`AxError::WouldBlock`] is returned.
+///
+/// Returns the number of tasks woken up or requeued.
+pub fn futex_requeue(
+    key: FutexKey,
+    nr_wake: usize,
+    new_key: FutexKey,
+    nr_requeue: usize,
+    cmp: Option<(&AtomicU32, u32)>,
+) -> AxResult<usize> {
+    let mut rq = current_run_queue();
+    let (from, to) = (key.bucket(), new_key.bucket());
+    let same_bucket = core::ptr::eq(from, to);
+    // Lock the buckets in the order of their addresses to avoid deadlocks.
+    let (mut from_queue, mut to_queue) = if same_bucket {
+        (from.lock(), None)
+    } else if (from as *const FutexBucket) < (to as *const FutexBucket) {
+        let from_queue = from.lock();
+        (from_queue, Some(to.lock()))
+    } else {
+        let to_queue = to.lock();
+        (from.lock(), Some(to_queue))
+    };
+
+    if let Some((futex, val)) = cmp {
+        if futex.load(Ordering::SeqCst) != val {
+            return Err(AxError::WouldBlock);
+        }
+    }
+
+    let (mut woken, mut requeued) = (0, 0);
+    let mut moved = Vec::new();
+    from_queue.retain(|w| {
+        if w.key() != key {
+            true
+        } else if woken < nr_wake {
+            w.wake(&mut rq);
+            woken += 1;
+            false
+        } else if requeued < nr_requeue {
+            w.set_key(new_key);
+            requeued += 1;
+            if same_bucket {
+                true
+            } else {
+                moved.push(w.clone());
+                false
+            }
+        } else {
+            true
+        }
+    });
+    if let Some(to_queue) = to_queue.as_mut() {
+        to_queue.extend(moved);
+    }
+    Ok(woken + requeued)
+}
@@ -12,18 +12,32 @@
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
 //!    APIs can be used, such as [`sleep`], [`sleep_until`], and
 //!    [`WaitQueue::wait_timeout`].
+//! - `smp`: Enable SMP (symmetric multiprocessing) support. Each CPU has its
+//!   own run queue, new tasks are placed on the least loaded CPU, and idle CPUs
+//!   steal tasks from others.
 //! - `preempt`: Enable preemptive scheduling.
-//! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
+//! - `tickless`: Stop the periodic scheduler tick while a CPU is idle. Timed
+//!   events (e.g., [`sleep`]) are always served by one-shot timers.
+//! - `paging`: Allocate the task stacks in a dedicated kernel virtual region
+//!   with a guard page below each one, and report the stack overflows.
+//! - `sched_fifo`: New tasks are [normal](SchedPolicy::Normal) tasks, which
+//!   are scheduled in FIFO order cooperatively. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
-//! - `sched_rr`: Use the [Round-robin preemptive scheduler][2]. It also enables
-//!   the `multitask` and `preempt` features if it is enabled.
-//! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
+//! - `sched_rr`: New tasks are normal tasks, which are scheduled in
+//!   round-robin preemptively. It also enables the `multitask` and `preempt`
+//!   features if it is enabled.
+//! - `sched_cfs`: New tasks are [batch](SchedPolicy::Batch) tasks, which are
+//!   scheduled by the [Completely Fair Scheduler][1]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
 //!
-//! [1]: scheduler::FifoScheduler
-//! [2]: scheduler::RRScheduler
-//! [3]: scheduler::CFScheduler
+//! The features above only select the default policy. The scheduling policy
+//! of each task can be changed at runtime (see [`SchedPolicy`]), so tasks of
+//! all classes can run in one image: real-time tasks (FIFO, RR and EDF) always
+//! run before normal tasks, and batch tasks run when no normal tasks are
+//! ready.
+//!
+//! [1]: scheduler::CFScheduler
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
@@ -42,7 +56,11 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod account;
+        mod futex;
+        mod pi_mutex;
         mod run_queue;
+        mod sched;
         mod task;
         mod task_ext;
         mod api;
@@ -50,6 +68,9 @@ cfg_if::cfg_if! {
 
         #[cfg(feature = "irq")]
         mod timers;
+        #[cfg(feature = "irq")]
+        #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+        pub use self::timers::Timer;
 
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
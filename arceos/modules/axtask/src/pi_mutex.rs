@@ -0,0 +1,275 @@
+//! Priority inheritance.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use kspin::{SpinNoIrq, SpinRaw};
+
+use crate::{current_run_queue, AxTaskRef, SchedPolicy};
+
+/// Serializes the changes of all PI mutexes and the walks along the chains of
+/// blocked tasks, so that the owner and waiters of the PI mutexes in the
+/// chain can not change while we are walking.
+static PI_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+/// The PI mutexes related to a task, protected by [`PI_LOCK`].
+pub(crate) struct PiTaskState {
+    /// The PI mutex the task is blocked on.
+    blocked_on: Option<*const RawPiMutex>,
+    /// The PI mutexes the task holds.
+    held: Vec<*const RawPiMutex>,
+}
+
+impl PiTaskState {
+    pub const fn new() -> Self {
+        Self {
+            blocked_on: None,
+            held: Vec::new(),
+        }
+    }
+}
+
+struct PiMutexState {
+    owner: Option<AxTaskRef>,
+    waiters: VecDeque<AxTaskRef>,
+}
+
+/// The raw lock of a mutex with priority inheritance, which only maintains
+/// the owner and waiters.
+///
+/// When a task is blocked on it, the owner inherits the scheduling policy of
+/// the task if that is more urgent (see [`SchedPolicy`]), until the owner
+/// unlocks it. The inheritance is transitive: if the owner is blocked on
+/// another PI mutex, the owner of that mutex inherits the policy too.
+///
+/// On unlocking, the mutex is handed over to the most urgent waiter.
+pub struct RawPiMutex {
+    // IRQs are disabled when it is locked, as `PI_LOCK` or the run queue is
+    // locked first.
+    state: SpinRaw<PiMutexState>,
+}
+
+// The task states only point to the mutexes while holding or blocked on them,
+// during which the mutexes can not be moved.
+unsafe impl Send for RawPiMutex {}
+unsafe impl Sync for RawPiMutex {}
+
+/// Returns the policy a task inherits from the waiters of the PI mutexes it
+/// holds. `PI_LOCK` must be held.
+fn inherited_policy(task: &AxTaskRef) -> Option<SchedPolicy> {
+    let pi = task.pi_state().lock();
+    pi.held
+        .iter()
+        .flat_map(|&mutex| {
+            let state = unsafe { &*mutex }.state.lock();
+            state
+                .waiters
+                .iter()
+                .map(|t| t.sched_entity().effective_policy())
+                .collect::<Vec<_>>()
+        })
+        .max_by_key(SchedPolicy::urgency)
+}
+
+/// Updates the inherited policy of the task, it may be ready in a run queue.
+/// `PI_LOCK` must be held.
+fn update_boost(task: &AxTaskRef) -> bool {
+    let boost = inherited_policy(task);
+    if task.sched_entity().boost() == boost {
+        return false;
+    }
+    debug!("task boost: {}, {:?}", task.id_name(), boost);
+    crate::run_queue::update_sched_entity(task, |entity| entity.set_boost(boost));
+    true
+}
+
+/// Boosts the owners along the chain of blocked tasks, until the policy of
+/// some owner does not change. `PI_LOCK` must be held.
+fn boost_chain(mut owner: Option<AxTaskRef>) {
+    while let Some(task) = owner.take() {
+        if !update_boost(&task) {
+            break;
+        }
+        if let Some(mutex) = task.pi_state().lock().blocked_on {
+            owner = unsafe { &*mutex }.state.lock().owner.clone();
+        }
+    }
+}
+
+impl RawPiMutex {
+    /// Creates a new unlocked PI mutex.
+    pub const fn new() -> Self {
+        Self {
+            state: SpinRaw::new(PiMutexState {
+                owner: None,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns `true` if the mutex is currently held.
+    pub fn is_locked(&self) -> bool {
+        let _pi = PI_LOCK.lock();
+        self.state.lock().owner.is_some()
+    }
+
+    /// Tries to lock the mutex, returns `true` on success.
+    pub fn try_lock(&self) -> bool {
+        let curr = crate::current();
+        let _pi = PI_LOCK.lock();
+        let mut state = self.state.lock();
+        if state.owner.is_some() {
+            return false;
+        }
+        state.owner = Some(curr.clone());
+        curr.pi_state().lock().held.push(self);
+        true
+    }
+
+    /// Takes the mutex if it is free, otherwise registers the current task
+    /// as a waiter and boosts the owners. Returns `true` if it is taken.
+    fn lock_or_enqueue(&self, curr: &AxTaskRef) -> bool {
+        let _pi = PI_LOCK.lock();
+        let mut state = self.state.lock();
+        match &state.owner {
+            None => {
+                state.owner = Some(curr.clone());
+                curr.pi_state().lock().held.push(self);
+                return true;
+            }
+            Some(owner) => assert!(
+                !Arc::ptr_eq(curr, owner),
+                "{} tried to acquire mutex it already owns.",
+                curr.id_name()
+            ),
+        }
+        state.waiters.push_back(curr.clone());
+        curr.pi_state().lock().blocked_on = Some(self);
+        let owner = state.owner.clone();
+        drop(state);
+        boost_chain(owner);
+        false
+    }
+
+    /// Locks the mutex, blocks until it is handed over to the current task if
+    /// it is held by another task.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current task already holds the mutex.
+    pub fn lock(&self) {
+        let curr = crate::current();
+        if self.lock_or_enqueue(curr.as_task_ref()) {
+            return;
+        }
+        loop {
+            let mut rq = current_run_queue();
+            let state = self.state.lock();
+            if state.owner.as_ref().is_some_and(|owner| curr.ptr_eq(owner)) {
+                break;
+            }
+            // Mark the current task as blocked before unlocking the state, so
+            // that it can not miss the handover.
+            rq.block_current(|_| drop(state));
+        }
+    }
+
+    /// Locks the mutex like [`RawPiMutex::lock`], but gives up after the
+    /// given duration. Returns `true` if the mutex is locked.
+    ///
+    /// On timeout, the owners drop the policy inherited from the current task.
+    #[cfg(feature = "irq")]
+    pub fn lock_timeout(&self, dur: core::time::Duration) -> bool {
+        let curr = crate::current();
+        if self.lock_or_enqueue(curr.as_task_ref()) {
+            return true;
+        }
+        let deadline = axhal::time::wall_time() + dur;
+        loop {
+            let mut rq = current_run_queue();
+            let state = self.state.lock();
+            if state.owner.as_ref().is_some_and(|owner| curr.ptr_eq(owner))
+                || axhal::time::wall_time() >= deadline
+            {
+                break;
+            }
+            // Armed with the run queue locked (and IRQs disabled), so that the
+            // alarm cannot fire before the task is blocked.
+            if !curr.in_timer_list() {
+                crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            }
+            rq.block_current(|_| drop(state));
+        }
+        if curr.in_timer_list() {
+            crate::timers::cancel_alarm(curr.as_task_ref());
+        }
+
+        let _pi = PI_LOCK.lock();
+        let mut state = self.state.lock();
+        if state.owner.as_ref().is_some_and(|owner| curr.ptr_eq(owner)) {
+            return true;
+        }
+        state.waiters.retain(|t| !curr.ptr_eq(t));
+        curr.pi_state().lock().blocked_on = None;
+        let owner = state.owner.clone();
+        drop(state);
+        boost_chain(owner);
+        false
+    }
+
+    /// Unlocks the mutex, and hands it over to the most urgent waiter.
+    ///
+    /// The current task drops the policy inherited from the waiters of this
+    /// mutex, and may be preempted by the new owner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current task does not hold the mutex.
+    pub fn unlock(&self) {
+        let curr = crate::current();
+        let _pi = PI_LOCK.lock();
+        let mut state = self.state.lock();
+        assert!(
+            state.owner.as_ref().is_some_and(|owner| curr.ptr_eq(owner)),
+            "{} tried to release mutex it doesn't own",
+            curr.id_name()
+        );
+        curr.pi_state()
+            .lock()
+            .held
+            .retain(|&mutex| !core::ptr::eq(mutex, self));
+
+        let next = state
+            .waiters
+            .iter()
+            .enumerate()
+            .rev() // the first one wins the ties
+            .max_by_key(|(_, t)| t.sched_entity().effective_policy().urgency())
+            .map(|(idx, _)| idx)
+            .and_then(|idx| state.waiters.remove(idx));
+        state.owner = next.clone();
+        if let Some(next) = &next {
+            let mut pi = next.pi_state().lock();
+            pi.blocked_on = None;
+            pi.held.push(self);
+        }
+        drop(state);
+
+        let boost = inherited_policy(curr.as_task_ref());
+        if curr.sched_entity().set_boost(boost).is_err() {
+            unreachable!("the current task is not in any run queue");
+        }
+        // It may not be blocked yet, but then it will see the handover.
+        if let Some(next) = next {
+            // It may be ready if it was preempted before blocking, or after a
+            // timeout.
+            update_boost(&next);
+            current_run_queue().unblock_task(next, true);
+        }
+    }
+}
+
+impl Default for RawPiMutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,16 +1,30 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use kspin::SpinNoIrq;
+use axerrno::AxResult;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use kernel_guard::NoPreemptIrqSave;
+use kspin::{SpinNoIrq, SpinRaw, SpinRawGuard};
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
+#[cfg(feature = "smp")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sched::SchedEntity;
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
+#[cfg(feature = "smp")]
+use crate::AxCpuMask;
+use crate::{AxTaskRef, SchedPolicy, Scheduler, TaskInner, WaitQueue};
 
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+#[percpu::def_percpu]
+static RUN_QUEUE: LazyInit<SpinRaw<AxRunQueue>> = LazyInit::new();
 
-// TODO: per-CPU
+/// Exited tasks to be dropped by the `gc` task.
+///
+/// It is shared by all CPUs rather than per-CPU, since the only `gc` task lives
+/// on the primary CPU, and the lock is held only to push or pop a task. Tasks
+/// exiting on other CPUs wake it up as a remote wakeup.
 static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
 
 static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
@@ -18,22 +32,251 @@ static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
+/// Tasks spawned or woken up by other CPUs, they will be moved into the local
+/// run queue at the next rescheduling.
+///
+/// Other CPUs never lock our run queue while holding their own (which may lead
+/// to deadlocks), they put tasks here and send us an IPI instead.
+#[cfg(feature = "smp")]
+#[percpu::def_percpu]
+static REMOTE_WAKEUPS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
+
+/// Number of ready tasks of each CPU (including those in [`REMOTE_WAKEUPS`]),
+/// used to place new tasks.
+#[cfg(feature = "smp")]
+#[percpu::def_percpu]
+static NR_READY: AtomicUsize = AtomicUsize::new(0);
+
+/// The previous task which is not allowed to run on this CPU anymore, and the
+/// CPU it is going to be moved to.
+///
+/// It is still running when we decide to migrate it, so it can only be handed
+/// over to the target CPU after the context switch (see [`finish_task_switch`]).
+#[cfg(feature = "smp")]
+#[percpu::def_percpu]
+static MIGRATING_TASK: Option<(usize, AxTaskRef)> = None;
+
+/// Returns the run queue of the given CPU.
+///
+/// # Panics
+///
+/// Panics if the run queue of the given CPU is not initialized.
+#[inline]
+pub(crate) fn run_queue_of(cpu_id: usize) -> &'static SpinRaw<AxRunQueue> {
+    unsafe { RUN_QUEUE.remote_ref_raw(cpu_id) }
+}
+
+/// A locked run queue of the current CPU, with IRQs and preemption disabled.
+///
+/// The current task may be switched out while holding it, and then resumes on
+/// another CPU (e.g., stolen or migrated). In that case, the lock of the new
+/// CPU's run queue was acquired before switching to us, so that one is
+/// released on drop instead.
+pub(crate) struct CurrentRunQueueRef {
+    inner: ManuallyDrop<SpinRawGuard<'static, AxRunQueue>>,
+    _guard: NoPreemptIrqSave,
+}
+
+impl Deref for CurrentRunQueueRef {
+    type Target = AxRunQueue;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for CurrentRunQueueRef {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Drop for CurrentRunQueueRef {
+    fn drop(&mut self) {
+        let cpu_id = axhal::cpu::this_cpu_id();
+        if self.inner.cpu_id == cpu_id {
+            unsafe { ManuallyDrop::drop(&mut self.inner) };
+        } else {
+            unsafe { run_queue_of(cpu_id).force_unlock() };
+        }
+    }
+}
+
+/// Locks and returns the run queue of the current CPU.
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    // Disable preemption first, so that we cannot be migrated to another CPU
+    // before the lock is acquired.
+    let guard = NoPreemptIrqSave::new();
+    let inner = run_queue_of(axhal::cpu::this_cpu_id()).lock();
+    CurrentRunQueueRef {
+        inner: ManuallyDrop::new(inner),
+        _guard: guard,
+    }
+}
+
+/// Puts a newly spawned task into the run queue of the selected CPU.
+pub(crate) fn spawn_task(task: AxTaskRef) {
+    let mut rq = current_run_queue();
+    #[cfg(feature = "smp")]
+    {
+        let cpu_id = select_cpu(rq.cpu_id, &task.cpumask());
+        if cpu_id != rq.cpu_id {
+            debug!("task spawn: {} on CPU {}", task.id_name(), cpu_id);
+            assert!(task.is_ready());
+            drop(rq);
+            enqueue_remote(cpu_id, task);
+            return;
+        }
+    }
+    rq.add_task(task);
+}
+
+/// Changes the scheduling states of a task that is not the current task of
+/// this CPU by `update`.
+///
+/// If the task is ready, `update` fails with the CPU in whose run queue the
+/// task is, then the task is taken out of that run queue, updated and put
+/// back. It must not be called with the current run queue locked.
+pub(crate) fn update_sched_entity<R>(
+    task: &AxTaskRef,
+    update: impl Fn(&SchedEntity) -> Result<R, usize>,
+) -> R {
+    loop {
+        let cpu_id = match update(task.sched_entity()) {
+            Ok(res) => return res,
+            Err(cpu_id) => cpu_id,
+        };
+        let _guard = NoPreemptIrqSave::new();
+        let mut rq = run_queue_of(cpu_id).lock();
+        // It may be picked (or stolen) before we lock the run queue.
+        let Some(task) = rq.scheduler.remove_task(task) else {
+            continue;
+        };
+        let Ok(res) = update(task.sched_entity()) else {
+            unreachable!("task is not in any run queue");
+        };
+        #[cfg(feature = "smp")]
+        if cpu_id != axhal::cpu::this_cpu_id() {
+            // Hand it over again, so that CPU checks whether to preempt its
+            // current task.
+            nr_ready(cpu_id).fetch_sub(1, Ordering::Relaxed);
+            drop(rq);
+            enqueue_remote(cpu_id, task);
+            return res;
+        }
+        rq.scheduler.add_task(task);
+        rq.check_preempt_current();
+        return res;
+    }
+}
+
+/// Sends the reschedule IPI to the CPU that `task` is on if the task is not
+/// allowed to run there anymore, so that it is migrated at once if it is
+/// running there (see [`AxRunQueue::reschedule_ipi`]).
+///
+/// The ready tasks are checked when they are picked, so this CPU needs no IPI.
+#[cfg(all(feature = "smp", feature = "irq"))]
+pub(crate) fn kick_disallowed(task: &AxTaskRef) {
+    let cpu_id = task.cpu_id();
+    if cpu_id != axhal::cpu::this_cpu_id() && !task.cpumask().get(cpu_id) {
+        axhal::irq::send_ipi(cpu_id);
+    }
+}
+
+#[cfg(feature = "smp")]
+#[inline]
+fn nr_ready(cpu_id: usize) -> &'static AtomicUsize {
+    unsafe { NR_READY.remote_ref_raw(cpu_id) }
+}
+
+#[cfg(feature = "smp")]
+#[inline]
+fn cpu_is_online(cpu_id: usize) -> bool {
+    unsafe { RUN_QUEUE.remote_ref_raw(cpu_id) }.is_inited()
+}
+
+/// Puts the task into the wakeup list of the given CPU, and notifies that CPU
+/// by an IPI.
+#[cfg(feature = "smp")]
+fn enqueue_remote(cpu_id: usize, task: AxTaskRef) {
+    task.set_cpu_id(cpu_id);
+    nr_ready(cpu_id).fetch_add(1, Ordering::Relaxed);
+    unsafe { REMOTE_WAKEUPS.remote_ref_raw(cpu_id) }
+        .lock()
+        .push_back(task);
+    #[cfg(feature = "irq")]
+    axhal::irq::send_ipi(cpu_id);
+}
+
+/// Selects a CPU in `cpumask` to run a task, see [`select_cpu_by`].
+#[cfg(feature = "smp")]
+fn select_cpu(local: usize, cpumask: &AxCpuMask) -> usize {
+    select_cpu_by(local, cpumask, |cpu_id| {
+        (cpu_id == local || cpu_is_online(cpu_id)).then(|| nr_ready(cpu_id).load(Ordering::Relaxed))
+    })
+}
+
+/// Selects a CPU in `allowed` to run a task, the one with the fewest ready
+/// tasks is preferred, and `local` wins the ties. `nr_ready` returns the number
+/// of ready tasks of a CPU, or `None` if it is offline.
+///
+/// Returns `local` if none of the allowed CPUs is online.
+#[cfg(feature = "smp")]
+pub(crate) fn select_cpu_by(
+    local: usize,
+    allowed: impl IntoIterator<Item = usize>,
+    nr_ready: impl Fn(usize) -> Option<usize>,
+) -> usize {
+    let mut target = local;
+    let mut min_ready = usize::MAX;
+    for cpu_id in allowed {
+        let Some(n) = nr_ready(cpu_id) else {
+            continue;
+        };
+        if n < min_ready || (n == min_ready && cpu_id == local) {
+            target = cpu_id;
+            min_ready = n;
+        }
+    }
+    target
+}
+
+/// The other CPUs to steal tasks from, in the order they are tried: the ones
+/// after `local` first, so that the CPUs do not all steal from the same one.
+#[cfg(feature = "smp")]
+pub(crate) fn steal_order(local: usize, nr_cpus: usize) -> impl Iterator<Item = usize> {
+    (local + 1..nr_cpus).chain(0..local)
+}
+
+/// Hands over the previous task to its new CPU, if it is being migrated.
+///
+/// It must be called by the next task right after the context switch, with the
+/// run queue still locked.
+pub(crate) fn finish_task_switch() {
+    #[cfg(feature = "smp")]
+    if let Some((cpu_id, task)) = unsafe { MIGRATING_TASK.current_ref_mut_raw() }.take() {
+        debug!("task migrate: {} to CPU {}", task.id_name(), cpu_id);
+        enqueue_remote(cpu_id, task);
+    }
+}
+
 pub(crate) struct AxRunQueue {
+    cpu_id: usize,
     scheduler: Scheduler,
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    pub fn new(cpu_id: usize) -> SpinRaw<Self> {
+        SpinRaw::new(Self {
+            cpu_id,
+            scheduler: Scheduler::new(cpu_id),
+        })
     }
 
     pub fn add_task(&mut self, task: AxTaskRef) {
-        debug!("task spawn: {}", task.id_name());
+        debug!("task spawn: {} on CPU {}", task.id_name(), self.cpu_id);
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        self.enqueue_task(task);
+        self.check_preempt_current();
     }
 
     #[cfg(feature = "irq")]
@@ -45,6 +288,29 @@ impl AxRunQueue {
         }
     }
 
+    /// Moves the tasks woken up by other CPUs into the scheduler.
+    ///
+    /// It is called when this CPU receives the reschedule IPI. If the current
+    /// task is the idle task, or is not allowed to run on this CPU anymore
+    /// (see [`kick_disallowed`]), it will be preempted as soon as possible.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    pub fn reschedule_ipi(&mut self) {
+        #[cfg(feature = "preempt")]
+        {
+            let curr = crate::current();
+            if !curr.is_idle() && !curr.cpumask().get(self.cpu_id) {
+                curr.set_preempt_pending(true);
+            }
+        }
+        if self.take_remote_wakeups() {
+            #[cfg(feature = "preempt")]
+            if crate::current().is_idle() {
+                crate::current().set_preempt_pending(true);
+            }
+            self.check_preempt_current();
+        }
+    }
+
     pub fn yield_current(&mut self) {
         let curr = crate::current();
         trace!("task yield: {}", curr.id_name());
@@ -57,13 +323,40 @@ impl AxRunQueue {
             .set_priority(crate::current().as_task_ref(), prio)
     }
 
+    /// Changes the scheduling policy of the current task, and yields if a
+    /// ready task should run first now.
+    pub fn set_current_sched_policy(&mut self, policy: SchedPolicy) -> AxResult {
+        let curr = crate::current();
+        assert!(curr.is_running());
+        let Ok(res) = curr.sched_entity().set_policy(policy) else {
+            unreachable!("the current task is not in any run queue");
+        };
+        res?;
+        debug!("task sched policy: {}, {:?}", curr.id_name(), policy);
+        if self.scheduler.should_preempt(curr.as_task_ref()) {
+            self.resched(true);
+        }
+        Ok(())
+    }
+
+    /// Moves the current task to another CPU if it is not allowed to run on
+    /// this CPU.
+    pub fn migrate_current(&mut self) {
+        let curr = crate::current();
+        assert!(curr.is_running());
+        #[cfg(feature = "smp")]
+        if !curr.cpumask().get(self.cpu_id) {
+            self.resched(false);
+        }
+    }
+
     #[cfg(feature = "preempt")]
     pub fn preempt_resched(&mut self) {
         let curr = crate::current();
         assert!(curr.is_running());
 
         // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
+        // have held its lock with both IRQs and preemption
         // disabled. So we need to set `current_disable_count` to 1 in
         // `can_preempt()` to obtain the preemption permission before
         //  locking the run queue.
@@ -90,6 +383,8 @@ impl AxRunQueue {
             EXITED_TASKS.lock().clear();
             axhal::misc::terminate();
         } else {
+            // Release the reserved bandwidth of EDF tasks.
+            let _ = curr.sched_entity().set_policy(SchedPolicy::Normal);
             curr.set_state(TaskState::Exited);
             curr.notify_exit(exit_code, self);
             EXITED_TASKS.lock().push_back(curr.clone());
@@ -117,14 +412,27 @@ impl AxRunQueue {
         self.resched(false);
     }
 
+    /// Wakes up a blocked task.
+    ///
+    /// The task is put back to the CPU it last ran on. If that is not the
+    /// current CPU, the task is handed over to that CPU by an IPI. The CPU
+    /// affinity is checked when the task is picked there, as it may still be
+    /// switching out on that CPU now.
     pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
         debug!("task unblock: {}", task.id_name());
         if task.is_blocked() {
             task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
+            #[cfg(feature = "smp")]
+            if task.cpu_id() != self.cpu_id {
+                enqueue_remote(task.cpu_id(), task);
+                return;
+            }
+            self.enqueue_task(task);
             if resched {
                 #[cfg(feature = "preempt")]
                 crate::current().set_preempt_pending(true);
+            } else {
+                self.check_preempt_current();
             }
         }
     }
@@ -146,17 +454,125 @@ impl AxRunQueue {
 }
 
 impl AxRunQueue {
+    fn enqueue_task(&mut self, task: AxTaskRef) {
+        #[cfg(feature = "smp")]
+        {
+            task.set_cpu_id(self.cpu_id);
+            nr_ready(self.cpu_id).fetch_add(1, Ordering::Relaxed);
+        }
+        self.scheduler.add_task(task);
+    }
+
+    /// Preempts the current task if a ready task of a higher scheduling class
+    /// (or an earlier deadline) is in the run queue.
+    fn check_preempt_current(&self) {
+        #[cfg(feature = "preempt")]
+        {
+            let curr = crate::current();
+            if self.scheduler.should_preempt(curr.as_task_ref()) {
+                curr.set_preempt_pending(true);
+            }
+        }
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        #[cfg(feature = "smp")]
+        {
+            if !prev.cpumask().get(self.cpu_id) {
+                let cpu_id = select_cpu(self.cpu_id, &prev.cpumask());
+                if cpu_id != self.cpu_id {
+                    unsafe { *MIGRATING_TASK.current_ref_mut_raw() = Some((cpu_id, prev)) };
+                    return;
+                }
+            }
+            nr_ready(self.cpu_id).fetch_add(1, Ordering::Relaxed);
+        }
+        self.scheduler.put_prev_task(prev, preempt);
+    }
+
+    #[cfg(not(feature = "smp"))]
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.scheduler.pick_next_task()
+    }
+
+    #[cfg(feature = "smp")]
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.take_remote_wakeups();
+        while let Some(task) = self.scheduler.pick_next_task() {
+            nr_ready(self.cpu_id).fetch_sub(1, Ordering::Relaxed);
+            // The CPU affinity may be changed after the task was enqueued.
+            let cpu_id = if task.cpumask().get(self.cpu_id) {
+                self.cpu_id
+            } else {
+                select_cpu(self.cpu_id, &task.cpumask())
+            };
+            if cpu_id == self.cpu_id {
+                return Some(task);
+            }
+            debug!("task migrate: {} to CPU {}", task.id_name(), cpu_id);
+            enqueue_remote(cpu_id, task);
+        }
+        self.steal_task()
+    }
+
+    /// Moves the tasks in [`REMOTE_WAKEUPS`] into the scheduler, returns
+    /// `true` if there are any.
+    #[cfg(feature = "smp")]
+    fn take_remote_wakeups(&mut self) -> bool {
+        let mut wakeups = unsafe { REMOTE_WAKEUPS.remote_ref_raw(self.cpu_id) }.lock();
+        let has_wakeups = !wakeups.is_empty();
+        while let Some(task) = wakeups.pop_front() {
+            self.scheduler.add_task(task);
+        }
+        has_wakeups
+    }
+
+    /// Steals a ready task from other CPUs, used when the local scheduler
+    /// has nothing to run.
+    ///
+    /// We only try to lock other run queues, as other CPUs may be stealing
+    /// from us at the same time.
+    #[cfg(feature = "smp")]
+    fn steal_task(&mut self) -> Option<AxTaskRef> {
+        for cpu_id in steal_order(self.cpu_id, axconfig::SMP) {
+            if !cpu_is_online(cpu_id) || nr_ready(cpu_id).load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            if let Some(mut rq) = run_queue_of(cpu_id).try_lock() {
+                if let Some(task) = rq.scheduler.pick_next_task() {
+                    if !task.cpumask().get(self.cpu_id) {
+                        // Not allowed to run here, give it back.
+                        rq.scheduler.add_task(task);
+                        continue;
+                    }
+                    nr_ready(cpu_id).fetch_sub(1, Ordering::Relaxed);
+                    debug!(
+                        "task steal: {} from CPU {} to CPU {}",
+                        task.id_name(),
+                        cpu_id,
+                        self.cpu_id
+                    );
+                    return Some(task);
+                }
+            }
+        }
+        None
+    }
+
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
     fn resched(&mut self, preempt: bool) {
         let prev = crate::current();
+        if !prev.is_idle() {
+            self.scheduler.charge_prev(prev.as_task_ref());
+        }
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+                self.put_prev_task(prev.clone(), preempt);
             }
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
+        let next = self.pick_next_task().unwrap_or_else(|| unsafe {
             // Safety: IRQs must be disabled at this time.
             IDLE_TASK.current_ref_raw().get_unchecked().clone()
         });
@@ -172,9 +588,15 @@ impl AxRunQueue {
         #[cfg(feature = "preempt")]
         next_task.set_preempt_pending(false);
         next_task.set_state(TaskState::Running);
+        #[cfg(feature = "smp")]
+        next_task.set_cpu_id(self.cpu_id);
         if prev_task.ptr_eq(&next_task) {
             return;
         }
+        #[cfg(feature = "tickless")]
+        if prev_task.is_idle() {
+            crate::timers::restart_tick();
+        }
 
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
@@ -185,9 +607,13 @@ impl AxRunQueue {
             assert!(Arc::strong_count(prev_task.as_task_ref()) > 1);
             assert!(Arc::strong_count(&next_task) >= 1);
 
+            #[cfg(feature = "paging")]
+            prev_task.switch_page_table(&next_task);
+
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
         }
+        finish_task_switch();
     }
 }
 
@@ -214,6 +640,8 @@ fn gc_entry() {
 }
 
 pub(crate) fn init() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Create the `idle` task (not current task).
     const IDLE_TASK_STACK_SIZE: usize = 4096;
     let idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
@@ -224,12 +652,23 @@ pub(crate) fn init() {
     // Put the subsequent execution into the `main` task.
     let main_task = TaskInner::new_init("main".into()).into_arc();
     main_task.set_state(TaskState::Running);
+    #[cfg(feature = "smp")]
+    main_task.set_cpu_id(cpu_id);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    RUN_QUEUE.with_current(|rq| {
+        rq.init_once(AxRunQueue::new(cpu_id));
+    });
+
+    // The only `gc` task lives on the primary CPU, and is never stolen.
+    let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
+    gc_task.set_cpumask(crate::AxCpuMask::one_shot(cpu_id));
+    current_run_queue().add_task(gc_task);
 }
 
 pub(crate) fn init_secondary() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Put the subsequent execution into the `idle` task.
     let idle_task = TaskInner::new_init("idle".into()).into_arc();
     idle_task.set_state(TaskState::Running);
@@ -237,4 +676,8 @@ pub(crate) fn init_secondary() {
         i.init_once(idle_task.clone());
     });
     unsafe { CurrentTask::init_current(idle_task) }
+
+    RUN_QUEUE.with_current(|rq| {
+        rq.init_once(AxRunQueue::new(cpu_id));
+    });
 }
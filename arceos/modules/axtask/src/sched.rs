@@ -0,0 +1,565 @@
+//! Scheduling classes.
+//!
+//! Every task has its own scheduling policy, which can be changed at runtime.
+//! Ready tasks are picked by the class of their policies: earliest-deadline-
+//! first (EDF) tasks first, then real-time FIFO/RR tasks by priority, then
+//! normal tasks in round-robin, and at last batch tasks by the [CFS].
+//!
+//! [CFS]: scheduler::CFScheduler
+
+use alloc::collections::{BTreeMap, VecDeque};
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, AxResult};
+#[cfg(not(test))]
+use axhal::time::monotonic_time_nanos as now_nanos;
+use kspin::SpinNoIrq;
+use scheduler::{BaseScheduler, CFScheduler};
+
+use crate::{task::TaskInner, AxTaskRef};
+
+/// The lowest priority of real-time (FIFO or RR) tasks.
+pub const MIN_RT_PRIO: u8 = 1;
+/// The highest priority of real-time (FIFO or RR) tasks.
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Time slice of round-robin (real-time or normal) tasks (100ms), in ticks.
+const RR_TIME_SLICE: usize = if axconfig::TICKS_PER_SEC >= 10 {
+    axconfig::TICKS_PER_SEC / 10
+} else {
+    1
+};
+
+/// The policy of new tasks, which is [`SchedPolicy::Batch`] if the
+/// `sched_cfs` feature is enabled, or [`SchedPolicy::Normal`] otherwise.
+const DEFAULT_POLICY: SchedPolicy = if cfg!(feature = "sched_cfs") {
+    SchedPolicy::Batch
+} else {
+    SchedPolicy::Normal
+};
+
+/// Bandwidths (runtime / period) are fixed-point numbers, `BW_UNIT` is 100%.
+const BW_SHIFT: u32 = 20;
+const BW_UNIT: u64 = 1 << BW_SHIFT;
+/// The bandwidth of each CPU that EDF tasks can reserve in total, the rest is
+/// left for other tasks.
+const DL_BW_PER_CPU: u64 = BW_UNIT * 95 / 100;
+
+/// The total bandwidth reserved by EDF tasks.
+static DL_TOTAL_BW: SpinNoIrq<u64> = SpinNoIrq::new(0);
+
+/// The clock of the unit tests, advanced by hand, as the time does not go on
+/// with the dummy platform.
+#[cfg(test)]
+pub(crate) static TEST_CLOCK_NANOS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(test)]
+fn now_nanos() -> u64 {
+    TEST_CLOCK_NANOS.load(Ordering::Acquire)
+}
+
+/// Scheduling policy of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Normal (interactive) tasks, scheduled in round-robin. They are only
+    /// preempted at the end of the time slices if the `preempt` feature is
+    /// enabled, otherwise they run in FIFO order cooperatively.
+    Normal,
+    /// Batch tasks, which share the CPU time by their nice values (see
+    /// [`set_priority`](crate::set_priority)) under the [CFS]. They only run
+    /// when there are no normal tasks ready.
+    ///
+    /// [CFS]: scheduler::CFScheduler
+    Batch,
+    /// Real-time first-in first-out, with a priority in
+    /// [`MIN_RT_PRIO`]..=[`MAX_RT_PRIO`] (higher runs first).
+    Fifo(u8),
+    /// Real-time round-robin, with a priority in
+    /// [`MIN_RT_PRIO`]..=[`MAX_RT_PRIO`] (higher runs first).
+    RoundRobin(u8),
+    /// Earliest deadline first.
+    Deadline(DeadlineParams),
+}
+
+/// Parameters of an EDF task.
+///
+/// The task is guaranteed `runtime` of CPU time within `deadline` since the
+/// start of every `period`. A zero `period` means the same as `deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineParams {
+    /// CPU time reserved in each period.
+    pub runtime: Duration,
+    /// Relative deadline.
+    pub deadline: Duration,
+    /// Length of each period.
+    pub period: Duration,
+}
+
+impl DeadlineParams {
+    fn period(&self) -> Duration {
+        if self.period.is_zero() {
+            self.deadline
+        } else {
+            self.period
+        }
+    }
+
+    fn bandwidth(&self) -> u64 {
+        ((self.runtime.as_nanos() << BW_SHIFT) / self.period().as_nanos()) as u64
+    }
+}
+
+impl SchedPolicy {
+    fn validate(&self) -> AxResult {
+        match self {
+            Self::Normal | Self::Batch => Ok(()),
+            Self::Fifo(prio) | Self::RoundRobin(prio) => {
+                if (MIN_RT_PRIO..=MAX_RT_PRIO).contains(prio) {
+                    Ok(())
+                } else {
+                    ax_err!(InvalidInput, "invalid real-time priority")
+                }
+            }
+            Self::Deadline(params) => {
+                if params.runtime.is_zero()
+                    || params.runtime > params.deadline
+                    || params.deadline > params.period()
+                {
+                    ax_err!(InvalidInput, "invalid deadline parameters")
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn bandwidth(&self) -> u64 {
+        match self {
+            Self::Deadline(params) => params.bandwidth(),
+            _ => 0,
+        }
+    }
+
+    /// Returns a key to compare how urgent the tasks with the policies are,
+    /// the larger the more urgent.
+    pub(crate) fn urgency(&self) -> (u8, u128) {
+        match self {
+            Self::Batch => (0, 0),
+            Self::Normal => (1, 0),
+            Self::Fifo(prio) | Self::RoundRobin(prio) => (2, *prio as u128),
+            Self::Deadline(params) => (3, u128::MAX - params.deadline.as_nanos()),
+        }
+    }
+}
+
+struct EntityState {
+    policy: SchedPolicy,
+    /// The policy inherited from the tasks blocked on the PI mutexes that the
+    /// task holds.
+    boost: Option<SchedPolicy>,
+    /// The CPU in whose run queue the task is, or [`None`] if it is not in
+    /// any run queue (e.g., running or blocked).
+    queued_on: Option<usize>,
+}
+
+impl EntityState {
+    /// The policy the task is scheduled by, the more urgent one of its own
+    /// policy and the inherited one.
+    fn effective_policy(&self) -> SchedPolicy {
+        match self.boost {
+            Some(boost) if boost.urgency() > self.policy.urgency() => boost,
+            _ => self.policy,
+        }
+    }
+}
+
+/// Per-task scheduling state.
+pub(crate) struct SchedEntity {
+    state: SpinNoIrq<EntityState>,
+    /// Remaining time slice of a round-robin (real-time or normal) task, in
+    /// ticks.
+    rr_slice: AtomicUsize,
+    /// Whether the task just becomes a batch task, it should start from the
+    /// minimum virtual runtime of the run queue rather than its stale one.
+    cfs_renew: AtomicBool,
+    /// Absolute deadline of the current period of an EDF task, in monotonic
+    /// nanoseconds.
+    dl_deadline: AtomicU64,
+    /// Remaining runtime in the current period of an EDF task, in nanoseconds.
+    dl_runtime: AtomicI64,
+    /// When the runtime of an EDF task was last charged.
+    dl_exec_start: AtomicU64,
+}
+
+impl SchedEntity {
+    pub const fn new() -> Self {
+        Self {
+            state: SpinNoIrq::new(EntityState {
+                policy: DEFAULT_POLICY,
+                boost: None,
+                queued_on: None,
+            }),
+            rr_slice: AtomicUsize::new(RR_TIME_SLICE),
+            cfs_renew: AtomicBool::new(true),
+            dl_deadline: AtomicU64::new(0),
+            dl_runtime: AtomicI64::new(0),
+            dl_exec_start: AtomicU64::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> SchedPolicy {
+        self.state.lock().policy
+    }
+
+    pub fn effective_policy(&self) -> SchedPolicy {
+        self.state.lock().effective_policy()
+    }
+
+    pub fn boost(&self) -> Option<SchedPolicy> {
+        self.state.lock().boost
+    }
+
+    /// Changes the scheduling policy if the task is not in any run queue.
+    ///
+    /// Otherwise, the policy is not changed and `Err` with the CPU of the run
+    /// queue that the task is in is returned, the caller should remove the
+    /// task from that run queue and try again.
+    ///
+    /// Returns `Ok(Err(ResourceBusy))` if an EDF task can not be admitted as
+    /// the total reserved bandwidth would exceed the limit.
+    pub fn set_policy(&self, policy: SchedPolicy) -> Result<AxResult, usize> {
+        if let Err(err) = policy.validate() {
+            return Ok(Err(err));
+        }
+        let mut state = self.state.lock();
+        if let Some(cpu_id) = state.queued_on {
+            return Err(cpu_id);
+        }
+        let (old_bw, new_bw) = (state.policy.bandwidth(), policy.bandwidth());
+        if old_bw != new_bw {
+            let mut total_bw = DL_TOTAL_BW.lock();
+            let new_total_bw = *total_bw - old_bw + new_bw;
+            if new_bw > DL_BW_PER_CPU || new_total_bw > DL_BW_PER_CPU * axconfig::SMP as u64 {
+                return Ok(ax_err!(ResourceBusy, "EDF bandwidth overcommitted"));
+            }
+            *total_bw = new_total_bw;
+        }
+        let old_effective = state.effective_policy();
+        state.policy = policy;
+        self.reset(old_effective, state.effective_policy());
+        Ok(Ok(()))
+    }
+
+    /// Changes the inherited policy if the task is not in any run queue,
+    /// otherwise returns `Err` like [`SchedEntity::set_policy`].
+    pub fn set_boost(&self, boost: Option<SchedPolicy>) -> Result<(), usize> {
+        let mut state = self.state.lock();
+        if let Some(cpu_id) = state.queued_on {
+            return Err(cpu_id);
+        }
+        let old_effective = state.effective_policy();
+        state.boost = boost;
+        self.reset(old_effective, state.effective_policy());
+        Ok(())
+    }
+
+    /// Resets the class-specific states if the task is going to be scheduled
+    /// by another policy.
+    fn reset(&self, old: SchedPolicy, new: SchedPolicy) {
+        if old == new {
+            return;
+        }
+        self.rr_slice.store(RR_TIME_SLICE, Ordering::Release);
+        if new == SchedPolicy::Batch {
+            self.cfs_renew.store(true, Ordering::Release);
+        }
+        if let SchedPolicy::Deadline(params) = new {
+            let now = now_nanos();
+            self.dl_deadline
+                .store(now + params.deadline.as_nanos() as u64, Ordering::Release);
+            self.dl_runtime
+                .store(params.runtime.as_nanos() as i64, Ordering::Release);
+            self.dl_exec_start.store(now, Ordering::Release);
+        }
+    }
+
+    /// Returns the absolute deadline and the remaining runtime of an EDF task.
+    #[cfg(test)]
+    pub(crate) fn dl_state(&self) -> (u64, i64) {
+        (
+            self.dl_deadline.load(Ordering::Acquire),
+            self.dl_runtime.load(Ordering::Acquire),
+        )
+    }
+
+    /// Marks the task in the run queue of the given CPU (or not in any run
+    /// queue if [`None`]), returns the policy it is scheduled by.
+    fn set_queued_on(&self, cpu_id: Option<usize>) -> SchedPolicy {
+        let mut state = self.state.lock();
+        state.queued_on = cpu_id;
+        state.effective_policy()
+    }
+
+    /// Starts a new period if the EDF task can not use its remaining runtime
+    /// before the current deadline without exceeding its bandwidth, called
+    /// when the task becomes ready (the wakeup rule of CBS).
+    fn dl_wakeup(&self, params: &DeadlineParams) {
+        let now = now_nanos();
+        let deadline = self.dl_deadline.load(Ordering::Acquire);
+        let runtime = self.dl_runtime.load(Ordering::Acquire);
+        if deadline <= now
+            || runtime as u128 * params.deadline.as_nanos()
+                > (deadline - now) as u128 * params.runtime.as_nanos()
+        {
+            self.dl_deadline
+                .store(now + params.deadline.as_nanos() as u64, Ordering::Release);
+            self.dl_runtime
+                .store(params.runtime.as_nanos() as i64, Ordering::Release);
+        }
+    }
+
+    /// Charges the runtime used by the running EDF task since the last charge.
+    ///
+    /// When the runtime of the current period is used up, the deadline is
+    /// postponed by a period with the runtime replenished (a soft CBS), so
+    /// the task can not steal the bandwidth reserved by others.
+    fn dl_charge(&self, params: &DeadlineParams) {
+        let now = now_nanos();
+        let used = now.saturating_sub(self.dl_exec_start.swap(now, Ordering::AcqRel));
+        let mut runtime = self.dl_runtime.load(Ordering::Acquire) - used as i64;
+        if runtime <= 0 {
+            let periods = -runtime as u64 / params.runtime.as_nanos() as u64 + 1;
+            runtime += (periods * params.runtime.as_nanos() as u64) as i64;
+            let postpone = periods * params.period().as_nanos() as u64;
+            self.dl_deadline.fetch_add(postpone, Ordering::AcqRel);
+        }
+        self.dl_runtime.store(runtime, Ordering::Release);
+    }
+}
+
+/// The scheduler of a run queue, which dispatches tasks to the scheduler of
+/// their classes.
+pub(crate) struct ClassScheduler {
+    cpu_id: usize,
+    /// EDF tasks, ordered by the absolute deadline (and then the task ID).
+    dl: BTreeMap<(u64, u64), AxTaskRef>,
+    /// Real-time tasks, ordered by the priority from high to low.
+    rt: BTreeMap<Reverse<u8>, VecDeque<AxTaskRef>>,
+    /// Normal tasks, in round-robin order.
+    normal: VecDeque<AxTaskRef>,
+    batch: CFScheduler<TaskInner>,
+}
+
+impl ClassScheduler {
+    pub fn new(cpu_id: usize) -> Self {
+        Self {
+            cpu_id,
+            dl: BTreeMap::new(),
+            rt: BTreeMap::new(),
+            normal: VecDeque::new(),
+            batch: CFScheduler::new(),
+        }
+    }
+
+    pub fn scheduler_name() -> &'static str {
+        "Multi-class"
+    }
+
+    /// Charges the runtime used by the task `prev` when it is switched out,
+    /// whether it is still ready, blocked or exited, as an EDF task may run
+    /// for less than a tick and then block.
+    pub fn charge_prev(&self, prev: &AxTaskRef) {
+        let entity = prev.sched_entity();
+        if let SchedPolicy::Deadline(params) = entity.effective_policy() {
+            entity.dl_charge(&params);
+        }
+    }
+
+    /// Returns whether a ready task should preempt the running task `curr`.
+    pub fn should_preempt(&self, curr: &AxTaskRef) -> bool {
+        match curr.sched_entity().effective_policy() {
+            // Batch tasks are not preempted by each other until the ticks
+            // (like `SCHED_BATCH` in Linux).
+            SchedPolicy::Batch => {
+                !self.normal.is_empty() || !self.dl.is_empty() || !self.rt.is_empty()
+            }
+            SchedPolicy::Normal => !self.dl.is_empty() || !self.rt.is_empty(),
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                !self.dl.is_empty() || self.rt.keys().next().is_some_and(|p| p.0 > prio)
+            }
+            SchedPolicy::Deadline(_) => self.dl.keys().next().is_some_and(|(deadline, _)| {
+                *deadline < curr.sched_entity().dl_deadline.load(Ordering::Acquire)
+            }),
+        }
+    }
+
+    fn dl_key(task: &AxTaskRef) -> (u64, u64) {
+        let deadline = task.sched_entity().dl_deadline.load(Ordering::Acquire);
+        (deadline, task.id().as_u64())
+    }
+
+    /// Puts a round-robin task back to `queue`, at the front if it is
+    /// preempted before its time slice is used up.
+    fn rr_push(queue: &mut VecDeque<AxTaskRef>, task: AxTaskRef, preempt: bool) {
+        let entity = task.sched_entity();
+        if entity.rr_slice.load(Ordering::Acquire) == 0 {
+            entity.rr_slice.store(RR_TIME_SLICE, Ordering::Release);
+            queue.push_back(task);
+        } else if preempt {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+    }
+
+    fn rt_push(&mut self, prio: u8, task: AxTaskRef, front: bool) {
+        let queue = self.rt.entry(Reverse(prio)).or_default();
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+    }
+
+    /// Decreases the time slice of the running round-robin task, returns
+    /// `true` if it is used up.
+    fn rr_tick(task: &AxTaskRef) -> bool {
+        let entity = task.sched_entity();
+        let slice = entity.rr_slice.load(Ordering::Acquire).saturating_sub(1);
+        entity.rr_slice.store(slice, Ordering::Release);
+        slice == 0
+    }
+
+    fn pick_next(&mut self) -> Option<AxTaskRef> {
+        if let Some((_, task)) = self.dl.pop_first() {
+            let now = now_nanos();
+            task.sched_entity()
+                .dl_exec_start
+                .store(now, Ordering::Release);
+            return Some(task);
+        }
+        if let Some(mut queue) = self.rt.first_entry() {
+            let task = queue.get_mut().pop_front();
+            if queue.get().is_empty() {
+                queue.remove();
+            }
+            return task;
+        }
+        if let Some(task) = self.normal.pop_front() {
+            return Some(task);
+        }
+        self.batch.pick_next_task()
+    }
+}
+
+impl BaseScheduler for ClassScheduler {
+    type SchedItem = AxTaskRef;
+
+    fn init(&mut self) {
+        self.batch.init();
+    }
+
+    fn add_task(&mut self, task: AxTaskRef) {
+        let entity = task.sched_entity();
+        match entity.set_queued_on(Some(self.cpu_id)) {
+            SchedPolicy::Normal => self.normal.push_back(task),
+            SchedPolicy::Batch => {
+                entity.cfs_renew.store(false, Ordering::Release);
+                self.batch.add_task(task);
+            }
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                self.rt_push(prio, task, false)
+            }
+            SchedPolicy::Deadline(params) => {
+                entity.dl_wakeup(&params);
+                self.dl.insert(Self::dl_key(&task), task);
+            }
+        }
+    }
+
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let entity = task.sched_entity();
+        let state = entity.state.lock();
+        if state.queued_on != Some(self.cpu_id) {
+            return None;
+        }
+        let policy = state.effective_policy();
+        drop(state);
+        let task = match policy {
+            SchedPolicy::Normal => {
+                let idx = self
+                    .normal
+                    .iter()
+                    .position(|t| AxTaskRef::ptr_eq(t, task))?;
+                self.normal.remove(idx)
+            }
+            SchedPolicy::Batch => self.batch.remove_task(task),
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                let queue = self.rt.get_mut(&Reverse(prio))?;
+                let idx = queue.iter().position(|t| AxTaskRef::ptr_eq(t, task))?;
+                let task = queue.remove(idx);
+                if queue.is_empty() {
+                    self.rt.remove(&Reverse(prio));
+                }
+                task
+            }
+            SchedPolicy::Deadline(_) => self.dl.remove(&Self::dl_key(task)),
+        };
+        entity.set_queued_on(None);
+        task
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        let task = self.pick_next()?;
+        task.sched_entity().set_queued_on(None);
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        let entity = prev.sched_entity();
+        match entity.set_queued_on(Some(self.cpu_id)) {
+            SchedPolicy::Normal => Self::rr_push(&mut self.normal, prev, preempt),
+            SchedPolicy::Batch => {
+                if entity.cfs_renew.swap(false, Ordering::AcqRel) {
+                    self.batch.add_task(prev);
+                } else {
+                    self.batch.put_prev_task(prev, preempt);
+                }
+            }
+            SchedPolicy::Fifo(prio) => self.rt_push(prio, prev, preempt),
+            SchedPolicy::RoundRobin(prio) => {
+                let queue = self.rt.entry(Reverse(prio)).or_default();
+                Self::rr_push(queue, prev, preempt);
+            }
+            SchedPolicy::Deadline(_) => {
+                self.dl.insert(Self::dl_key(&prev), prev);
+            }
+        }
+    }
+
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let entity = current.sched_entity();
+        match entity.effective_policy() {
+            SchedPolicy::Normal | SchedPolicy::RoundRobin(_) => {
+                Self::rr_tick(current) || self.should_preempt(current)
+            }
+            SchedPolicy::Batch => self.batch.task_tick(current) || self.should_preempt(current),
+            SchedPolicy::Fifo(_) => self.should_preempt(current),
+            SchedPolicy::Deadline(params) => {
+                entity.dl_charge(&params);
+                self.should_preempt(current)
+            }
+        }
+    }
+
+    fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        match task.sched_entity().policy() {
+            // Also kept for normal tasks, which takes effect when they become
+            // batch tasks.
+            SchedPolicy::Normal | SchedPolicy::Batch => self.batch.set_priority(task, prio),
+            _ => false,
+        }
+    }
+}
@@ -3,17 +3,21 @@ use core::ops::Deref;
 use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
+#[cfg(any(feature = "preempt", feature = "smp"))]
 use core::sync::atomic::AtomicUsize;
 
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
+use crate::account::MemAccount;
+use crate::pi_mutex::PiTaskState;
+use crate::sched::{SchedEntity, SchedPolicy};
 use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::{AxCpuMask, AxRunQueue, AxTask, AxTaskRef, WaitQueue};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -43,6 +47,16 @@ pub struct TaskInner {
     #[cfg(feature = "irq")]
     in_timer_list: AtomicBool,
 
+    /// CPUs on which the task is allowed to run.
+    cpumask: SpinNoIrq<AxCpuMask>,
+    /// The CPU which the task last ran on (or is placed on).
+    #[cfg(feature = "smp")]
+    cpu_id: AtomicUsize,
+
+    /// Scheduling policy and class-specific states.
+    sched: SchedEntity,
+    pi_state: SpinNoIrq<PiTaskState>,
+
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
     #[cfg(feature = "preempt")]
@@ -51,9 +65,16 @@ pub struct TaskInner {
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
+    /// The memory account of the task group.
+    mem_account: SpinNoIrq<Arc<MemAccount>>,
+
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
     task_ext: AxTaskExt,
+    /// The address space to switch to when the task runs, or the kernel one
+    /// if `None`.
+    #[cfg(feature = "paging")]
+    page_table: Option<axmm::PageTableHandle>,
 
     #[cfg(feature = "tls")]
     tls: TlsArea,
@@ -126,6 +147,61 @@ impl TaskInner {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name)
     }
 
+    /// Gets the CPU affinity mask of the task.
+    pub fn cpumask(&self) -> AxCpuMask {
+        *self.cpumask.lock()
+    }
+
+    /// Sets the CPU affinity mask of the task.
+    ///
+    /// It takes effect the next time the task is scheduled. Use
+    /// [`set_affinity`](crate::set_affinity) to move the task immediately,
+    /// even if it is running on another CPU.
+    ///
+    /// Returns `false` if the mask is empty.
+    pub fn set_cpumask(&self, cpumask: AxCpuMask) -> bool {
+        if cpumask.is_empty() {
+            return false;
+        }
+        *self.cpumask.lock() = cpumask;
+        true
+    }
+
+    /// Gets the scheduling policy of the task.
+    pub fn sched_policy(&self) -> SchedPolicy {
+        self.sched.policy()
+    }
+
+    /// Gets the memory account of the task group.
+    pub fn mem_account(&self) -> Arc<MemAccount> {
+        self.mem_account.lock().clone()
+    }
+
+    /// Moves the task to the group with the memory account `account`.
+    ///
+    /// The memory used by the task before is still charged to the previous
+    /// group.
+    pub fn set_mem_account(&self, account: Arc<MemAccount>) {
+        let old = core::mem::replace(&mut *self.mem_account.lock(), account);
+        // Dropping it may free memory, which is uncharged from the new group.
+        drop(old);
+    }
+
+    /// Charges `size` bytes to the group of the task, see
+    /// [`MemAccount::charge`].
+    ///
+    /// Unlike charging the account from [`TaskInner::mem_account`], it never
+    /// drops the account, so it can be called in the allocator.
+    pub fn charge_mem(&self, size: usize) -> bool {
+        self.mem_account.lock().charge(size)
+    }
+
+    /// Uncharges `size` bytes from the group of the task, see
+    /// [`MemAccount::uncharge`].
+    pub fn uncharge_mem(&self, size: usize) {
+        self.mem_account.lock().uncharge(size);
+    }
+
     /// Wait for the task to exit, and return the exit code.
     ///
     /// It will return immediately if the task has already exited (but not dropped).
@@ -174,15 +250,26 @@ impl TaskInner {
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             in_timer_list: AtomicBool::new(false),
+            cpumask: SpinNoIrq::new(AxCpuMask::full()),
+            #[cfg(feature = "smp")]
+            cpu_id: AtomicUsize::new(0),
+            sched: SchedEntity::new(),
+            pi_state: SpinNoIrq::new(PiTaskState::new()),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
+            mem_account: SpinNoIrq::new(
+                crate::current_may_uninit()
+                    .map_or_else(|| Arc::new(MemAccount::new()), |curr| curr.mem_account()),
+            ),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             task_ext: AxTaskExt::empty(),
+            #[cfg(feature = "paging")]
+            page_table: None,
             #[cfg(feature = "tls")]
             tls: TlsArea::alloc(),
         }
@@ -205,6 +292,16 @@ impl TaskInner {
         t
     }
 
+    #[inline]
+    pub(crate) const fn sched_entity(&self) -> &SchedEntity {
+        &self.sched
+    }
+
+    #[inline]
+    pub(crate) const fn pi_state(&self) -> &SpinNoIrq<PiTaskState> {
+        &self.pi_state
+    }
+
     pub(crate) fn into_arc(self) -> AxTaskRef {
         Arc::new(AxTask::new(self))
     }
@@ -266,6 +363,18 @@ impl TaskInner {
         self.in_timer_list.store(in_timer_list, Ordering::Release);
     }
 
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn set_cpu_id(&self, cpu_id: usize) {
+        self.cpu_id.store(cpu_id, Ordering::Release);
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
@@ -297,7 +406,7 @@ impl TaskInner {
     fn current_check_preempt_pending() {
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let mut rq = crate::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
@@ -320,6 +429,31 @@ impl TaskInner {
         self.ctx.get_mut()
     }
 
+    /// Sets the address space to switch to when the task runs.
+    ///
+    /// The address space should map the kernel, and should not be dropped
+    /// before the task exits.
+    #[cfg(feature = "paging")]
+    pub fn set_page_table(&mut self, page_table: axmm::PageTableHandle) {
+        self.page_table = Some(page_table);
+    }
+
+    /// Switches from the address space of the task to the one of `next`.
+    ///
+    /// # Safety
+    ///
+    /// It should be called on the context switch to `next` with the IRQs
+    /// disabled.
+    #[cfg(feature = "paging")]
+    pub(crate) unsafe fn switch_page_table(&self, next: &Self) {
+        match (&self.page_table, &next.page_table) {
+            (Some(prev), Some(next)) if prev.ptr_eq(next) => {}
+            (_, Some(next)) => next.activate(),
+            (Some(_), None) => axmm::activate_kernel(),
+            (None, None) => {}
+        }
+    }
+
     /// Returns the top address of the kernel stack.
     #[inline]
     pub const fn kernel_stack_top(&self) -> Option<VirtAddr> {
@@ -328,6 +462,21 @@ impl TaskInner {
             None => None,
         }
     }
+
+    /// Returns the address range of the kernel stack, [`None`] for the tasks
+    /// running on the boot stacks.
+    pub fn kernel_stack_range(&self) -> Option<core::ops::Range<usize>> {
+        self.kstack.as_ref().map(TaskStack::range)
+    }
+
+    /// Returns the guard page below the kernel stack, or 0 if there is none.
+    #[cfg(feature = "paging")]
+    pub(crate) fn stack_guard_page(&self) -> usize {
+        self.kstack
+            .as_ref()
+            .and_then(TaskStack::guard_page)
+            .unwrap_or(0)
+    }
 }
 
 impl fmt::Debug for TaskInner {
@@ -349,28 +498,97 @@ impl Drop for TaskInner {
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
+    /// Whether the stack is allocated with a guard page by [`axmm`].
+    #[cfg(feature = "paging")]
+    guarded: bool,
 }
 
 impl TaskStack {
     pub fn alloc(size: usize) -> Self {
         let layout = Layout::from_size_align(size, 16).unwrap();
+        #[cfg(feature = "paging")]
+        if let Some(bottom) = axmm::alloc_kernel_stack(size) {
+            return Self {
+                ptr: NonNull::new(bottom.as_mut_ptr()).unwrap(),
+                layout,
+                guarded: true,
+            };
+        }
         Self {
             ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
             layout,
+            #[cfg(feature = "paging")]
+            guarded: false,
         }
     }
 
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
+
+    /// Returns the address range of the stack.
+    pub fn range(&self) -> core::ops::Range<usize> {
+        let bottom = self.ptr.as_ptr() as usize;
+        bottom..bottom + self.layout.size()
+    }
+
+    /// Whether `vaddr` is in the guard page below the stack.
+    #[cfg(feature = "paging")]
+    pub fn guard_contains(&self, vaddr: VirtAddr) -> bool {
+        self.guard_page().is_some_and(|guard| {
+            (guard..guard + axmm::STACK_GUARD_SIZE).contains(&vaddr.as_usize())
+        })
+    }
+
+    /// Returns the guard page below the stack, if any.
+    #[cfg(feature = "paging")]
+    pub fn guard_page(&self) -> Option<usize> {
+        let bottom = self.ptr.as_ptr() as usize;
+        self.guarded.then(|| bottom - axmm::STACK_GUARD_SIZE)
+    }
 }
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
+        #[cfg(feature = "paging")]
+        if self.guarded {
+            axmm::dealloc_kernel_stack(
+                VirtAddr::from_mut_ptr_of(self.ptr.as_ptr()),
+                self.layout.size(),
+            );
+            return;
+        }
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
     }
 }
 
+/// Reports the stack overflow of the current task if `vaddr` is in the guard
+/// page of its stack.
+#[cfg(feature = "paging")]
+#[axhal::trap::register_trap_handler(axhal::trap::STACK_GUARD)]
+fn handle_stack_guard(vaddr: VirtAddr) {
+    let Some(curr) = crate::current_may_uninit() else {
+        return;
+    };
+    if let Some(kstack) = &curr.kstack {
+        if kstack.guard_contains(vaddr) {
+            // Do not allocate, the overflow may happen in the allocator.
+            error!(
+                "stack overflow in Task({}, {:?}), fault_vaddr={:#x}",
+                curr.id().as_u64(),
+                curr.name(),
+                vaddr,
+            );
+            axhal::backtrace::dump(kstack.range());
+            panic!(
+                "stack overflow in Task({}, {:?})",
+                curr.id().as_u64(),
+                curr.name()
+            );
+        }
+    }
+}
+
 use core::mem::ManuallyDrop;
 
 /// A wrapper of [`AxTaskRef`] as the current task.
@@ -409,6 +627,8 @@ impl CurrentTask {
         assert!(init_task.is_init());
         #[cfg(feature = "tls")]
         axhal::arch::write_thread_pointer(init_task.tls.tls_ptr() as usize);
+        #[cfg(feature = "paging")]
+        axhal::trap::set_stack_guard_page(init_task.stack_guard_page());
         let ptr = Arc::into_raw(init_task);
         axhal::cpu::set_current_task_ptr(ptr);
     }
@@ -416,6 +636,8 @@ impl CurrentTask {
     pub(crate) unsafe fn set_current(prev: Self, next: AxTaskRef) {
         let Self(arc) = prev;
         ManuallyDrop::into_inner(arc); // `call Arc::drop()` to decrease prev task reference count.
+        #[cfg(feature = "paging")]
+        axhal::trap::set_stack_guard_page(next.stack_guard_page());
         let ptr = Arc::into_raw(next);
         axhal::cpu::set_current_task_ptr(ptr);
     }
@@ -429,8 +651,9 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
+    crate::run_queue::finish_task_switch();
     // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    unsafe { crate::run_queue::run_queue_of(axhal::cpu::this_cpu_id()).force_unlock() };
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
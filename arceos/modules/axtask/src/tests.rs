@@ -1,5 +1,5 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Mutex, Once};
+use std::sync::{Arc, Mutex, Once};
 
 use crate::{api as axtask, current, WaitQueue};
 
@@ -128,3 +128,336 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_cpu_affinity() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    assert!(!axtask::set_current_affinity(axtask::AxCpuMask::new()));
+    assert!(axtask::set_current_affinity(axtask::AxCpuMask::one_shot(0)));
+    assert_eq!(current().cpumask(), axtask::AxCpuMask::one_shot(0));
+
+    // inherited by the spawned task
+    let task = axtask::spawn(|| axtask::exit(current().cpumask().len() as _));
+    assert_eq!(task.join(), Some(1));
+    // an empty mask is ignored
+    let task = axtask::TaskBuilder::new()
+        .cpumask(axtask::AxCpuMask::new())
+        .spawn(|| axtask::exit(current().cpumask().len() as _));
+    assert_eq!(task.join(), Some(1));
+
+    let task = axtask::TaskBuilder::new()
+        .name("affinity".into())
+        .cpumask(axtask::AxCpuMask::full())
+        .spawn(|| axtask::exit(current().cpumask().is_full() as _));
+    assert_eq!(task.join(), Some(1));
+
+    // set by another task before it runs
+    let task = axtask::spawn(|| axtask::exit(current().cpumask().len() as _));
+    assert!(!axtask::set_affinity(&task, axtask::AxCpuMask::new()));
+    assert!(axtask::set_affinity(&task, axtask::AxCpuMask::full()));
+    assert_eq!(task.join(), Some(axconfig::SMP as _));
+    assert!(axtask::set_affinity(
+        current().as_task_ref(),
+        axtask::AxCpuMask::full()
+    ));
+
+    assert!(axtask::set_current_affinity(axtask::AxCpuMask::full()));
+}
+
+#[cfg(feature = "smp")]
+#[test]
+fn test_smp_placement() {
+    use crate::run_queue::{select_cpu_by, steal_order};
+
+    // Ready tasks on CPUs 0..4, CPU 3 is offline.
+    let load = [2, 1, 1, 0];
+    let nr_ready = |cpu_id: usize| (cpu_id != 3).then(|| load[cpu_id]);
+
+    // the least loaded one, the first of them if the local one is not loaded least
+    assert_eq!(select_cpu_by(0, 0..4, nr_ready), 1);
+    // the local one wins the ties
+    assert_eq!(select_cpu_by(2, 0..4, nr_ready), 2);
+    assert_eq!(select_cpu_by(1, 0..4, |_| Some(0)), 1);
+    // only the allowed CPUs are selected
+    assert_eq!(select_cpu_by(1, [0], nr_ready), 0);
+    assert_eq!(select_cpu_by(3, [0, 2], nr_ready), 2);
+    // the local one if no allowed CPU is online
+    assert_eq!(select_cpu_by(1, [3], nr_ready), 1);
+
+    // every other CPU is tried once, starting from the next one
+    assert_eq!(steal_order(2, 4).collect::<Vec<_>>(), [3, 0, 1]);
+    assert_eq!(steal_order(0, 4).collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(steal_order(0, 1).count(), 0);
+}
+
+#[test]
+fn test_sched_policy() {
+    use core::time::Duration;
+    use std::sync::atomic::AtomicBool;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let dl = |runtime_ms, period_ms| {
+        axtask::SchedPolicy::Deadline(axtask::DeadlineParams {
+            runtime: Duration::from_millis(runtime_ms),
+            deadline: Duration::from_millis(period_ms),
+            period: Duration::ZERO,
+        })
+    };
+    assert!(axtask::set_current_sched_policy(axtask::SchedPolicy::Fifo(0)).is_err());
+    assert!(axtask::set_current_sched_policy(dl(20, 10)).is_err());
+
+    // admission control (a single CPU in tests)
+    assert!(axtask::set_current_sched_policy(dl(1000, 1000)).is_err());
+    axtask::set_current_sched_policy(dl(10, 100)).unwrap();
+    assert_eq!(current().sched_policy(), dl(10, 100));
+    let task = axtask::spawn(move || {
+        let res = axtask::set_current_sched_policy(dl(90, 100));
+        axtask::exit(res.is_ok() as _);
+    });
+    assert_eq!(task.join(), Some(0));
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Normal).unwrap();
+    let task = axtask::spawn(move || {
+        let res = axtask::set_current_sched_policy(dl(90, 100));
+        axtask::exit(res.is_ok() as _);
+    });
+    assert_eq!(task.join(), Some(1));
+    // the bandwidth is released on exit
+    axtask::set_current_sched_policy(dl(90, 100)).unwrap();
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Normal).unwrap();
+
+    // real-time tasks run before normal tasks
+    static NORMAL_RAN: AtomicBool = AtomicBool::new(false);
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Fifo(10)).unwrap();
+    let task = axtask::spawn(|| NORMAL_RAN.store(true, Ordering::Release));
+    for _ in 0..10 {
+        axtask::yield_now();
+        assert!(!NORMAL_RAN.load(Ordering::Acquire));
+    }
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Normal).unwrap();
+    task.join();
+    assert!(NORMAL_RAN.load(Ordering::Acquire));
+}
+
+#[test]
+fn test_sched_deadline_block() {
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const RUNS: usize = 15;
+    const MS: u64 = 1_000_000;
+    static WQ: WaitQueue = WaitQueue::new();
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+
+    // An EDF task with 10ms in every 100ms runs for 1ms (less than a tick)
+    // and then blocks, again and again.
+    let task = axtask::spawn(|| {
+        let policy = axtask::SchedPolicy::Deadline(axtask::DeadlineParams {
+            runtime: Duration::from_millis(10),
+            deadline: Duration::from_millis(100),
+            period: Duration::ZERO,
+        });
+        axtask::set_current_sched_policy(policy).unwrap();
+        let (deadline, _) = current().sched_entity().dl_state();
+        for _ in 0..RUNS {
+            crate::sched::TEST_CLOCK_NANOS.fetch_add(MS, Ordering::AcqRel);
+            WQ.wait();
+        }
+        // 15ms used: the runtime is used up once, and the deadline is
+        // postponed by a period with 5ms left.
+        let charged = current().sched_entity().dl_state() == (deadline + 100 * MS, 5 * MS as i64);
+        axtask::set_current_sched_policy(axtask::SchedPolicy::Normal).unwrap();
+        DONE.store(1, Ordering::Release);
+        axtask::exit(charged as _);
+    });
+    while DONE.load(Ordering::Acquire) == 0 {
+        WQ.notify_one(true);
+        axtask::yield_now();
+    }
+    assert_eq!(task.join(), Some(1));
+}
+
+#[test]
+fn test_sched_batch() {
+    use std::sync::atomic::AtomicBool;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static BATCH_RAN: AtomicBool = AtomicBool::new(false);
+    let task = axtask::spawn(|| BATCH_RAN.store(true, Ordering::Release));
+    axtask::set_sched_policy(&task, axtask::SchedPolicy::Batch).unwrap();
+    assert_eq!(task.sched_policy(), axtask::SchedPolicy::Batch);
+
+    // batch tasks only run when no normal tasks are ready
+    let normal = axtask::spawn(|| assert!(!BATCH_RAN.load(Ordering::Acquire)));
+    normal.join();
+    assert!(!BATCH_RAN.load(Ordering::Acquire));
+
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Batch).unwrap();
+    assert!(axtask::set_priority(5));
+    assert!(!axtask::set_priority(20));
+    task.join();
+    assert!(BATCH_RAN.load(Ordering::Acquire));
+    axtask::set_current_sched_policy(axtask::SchedPolicy::Normal).unwrap();
+}
+
+#[test]
+fn test_futex() {
+    use axerrno::AxError;
+    use core::sync::atomic::AtomicU32;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    const ANY: u32 = axtask::FUTEX_BITSET_MATCH_ANY;
+    static FUTEX1: AtomicU32 = AtomicU32::new(0);
+    static FUTEX2: AtomicU32 = AtomicU32::new(0);
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    let key = |futex: &AtomicU32| axtask::FutexKey::new(0, futex as *const _ as usize);
+
+    assert_eq!(
+        axtask::futex_wait(key(&FUTEX1), &FUTEX1, 1, ANY, None),
+        Err(AxError::WouldBlock)
+    );
+
+    for _ in 0..NUM_TASKS {
+        axtask::spawn(move || {
+            STARTED.fetch_add(1, Ordering::Relaxed);
+            while FUTEX1.load(Ordering::Acquire) == 0 {
+                let _ = axtask::futex_wait(key(&FUTEX1), &FUTEX1, 0, ANY, None);
+            }
+            FINISHED.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    while STARTED.load(Ordering::Relaxed) < NUM_TASKS {
+        axtask::yield_now();
+    }
+    axtask::yield_now(); // all tasks are blocked now
+
+    FUTEX1.store(1, Ordering::Release);
+    let cmp = Some((&FUTEX1, 0));
+    assert_eq!(
+        axtask::futex_requeue(key(&FUTEX1), 1, key(&FUTEX2), usize::MAX, cmp),
+        Err(AxError::WouldBlock)
+    );
+    let cmp = Some((&FUTEX1, 1));
+    assert_eq!(
+        axtask::futex_requeue(key(&FUTEX1), 1, key(&FUTEX2), usize::MAX, cmp),
+        Ok(NUM_TASKS)
+    );
+    assert_eq!(axtask::futex_wake(key(&FUTEX1), usize::MAX, ANY), 0);
+    assert_eq!(axtask::futex_wake(key(&FUTEX2), usize::MAX, 0), 0);
+    assert_eq!(
+        axtask::futex_wake(key(&FUTEX2), usize::MAX, ANY),
+        NUM_TASKS - 1
+    );
+    while FINISHED.load(Ordering::Relaxed) < NUM_TASKS {
+        axtask::yield_now();
+    }
+    assert!(!current().in_wait_queue());
+}
+
+#[cfg(feature = "irq")]
+#[test]
+fn test_futex_timeout() {
+    use axerrno::AxError;
+    use core::sync::atomic::AtomicU32;
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const ANY: u32 = axtask::FUTEX_BITSET_MATCH_ANY;
+    static FUTEX: AtomicU32 = AtomicU32::new(0);
+    let key = axtask::FutexKey::new(0, &FUTEX as *const _ as usize);
+
+    // The value is checked first.
+    assert_eq!(
+        axtask::futex_wait(key, &FUTEX, 1, ANY, Some(Duration::ZERO)),
+        Err(AxError::WouldBlock)
+    );
+    // A zero relative timeout.
+    assert_eq!(
+        axtask::futex_wait(key, &FUTEX, 0, ANY, Some(Duration::ZERO)),
+        Err(AxError::TimedOut)
+    );
+    // An absolute timeout in the past, converted to the duration from now.
+    let abstime = axhal::time::wall_time().saturating_sub(Duration::from_secs(1));
+    let timeout = abstime.saturating_sub(axhal::time::wall_time());
+    assert_eq!(
+        axtask::futex_wait(key, &FUTEX, 0, ANY, Some(timeout)),
+        Err(AxError::TimedOut)
+    );
+    assert!(!current().in_wait_queue());
+    assert_eq!(axtask::futex_wake(key, usize::MAX, ANY), 0);
+}
+
+#[test]
+fn test_mem_account() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    // A new task is in the group of its creator.
+    let task = axtask::spawn(|| {});
+    assert!(Arc::ptr_eq(&task.mem_account(), &current().mem_account()));
+    task.join();
+
+    let account = Arc::new(axtask::MemAccount::new());
+    account.set_limit(0x1000);
+    let task = axtask::TaskInner::new(|| {}, "group".into(), 0x1000);
+    task.set_mem_account(account.clone());
+    let task = axtask::spawn_task(task);
+    task.join();
+
+    assert!(account.charge(0x800));
+    assert!(account.charge(0x800));
+    assert!(!account.charge(1));
+    assert_eq!(account.used(), 0x1000);
+    account.uncharge(0xc00);
+    assert_eq!(account.used(), 0x400);
+    assert_eq!(account.peak(), 0x1000);
+    assert_eq!(current().mem_account().limit(), usize::MAX);
+
+    // The running task can be moved to another group, like the application.
+    let old = current().mem_account();
+    let app = Arc::new(axtask::MemAccount::new());
+    current().set_mem_account(app.clone());
+    assert!(current().charge_mem(0x100));
+    assert_eq!(app.used(), 0x100);
+    current().set_mem_account(old);
+    assert!(!Arc::ptr_eq(&current().mem_account(), &app));
+}
+
+#[cfg(feature = "irq")]
+#[test]
+fn test_deferred_timer() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+    let timer = crate::Timer::new_deferred(|| {
+        assert_eq!(current().name(), "timer");
+        FIRED.fetch_add(1, Ordering::Relaxed);
+    });
+    timer.start(axhal::time::wall_time(), None);
+    axtask::on_timer_tick(); // as if in the timer interrupt handler
+    while FIRED.load(Ordering::Relaxed) == 0 {
+        axtask::yield_now();
+    }
+    assert_eq!(timer.deadline(), None);
+
+    // cancelled before the `timer` task runs it
+    timer.start(axhal::time::wall_time(), None);
+    axtask::on_timer_tick();
+    assert!(!timer.cancel());
+    axtask::yield_now();
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+}
@@ -1,48 +1,401 @@
-use alloc::sync::Arc;
-use axhal::time::wall_time;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use core::time::Duration;
+
+use axhal::time::{epochoffset_nanos, monotonic_time_nanos, wall_time, NANOS_PER_SEC};
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use timer_list::{TimeValue, TimerEvent, TimerList};
 
-use crate::{AxTaskRef, RUN_QUEUE};
+use crate::{current_run_queue, AxTaskRef, WaitQueue};
+
+/// Interval of the periodic scheduler tick, in nanoseconds.
+const PERIODIC_INTERVAL_NANOS: u64 = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+
+/// The farthest we program the timer hardware ahead, as some timers (e.g., the
+/// x86 local APIC timer) cannot count for too long.
+const MAX_TIMER_INTERVAL_NANOS: u64 = NANOS_PER_SEC;
+
+#[percpu::def_percpu]
+static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<TimerListEvent>>> = LazyInit::new();
+
+/// Monotonic time of the next periodic tick, in nanoseconds. `u64::MAX` means
+/// the tick is stopped (only with the `tickless` feature).
+#[percpu::def_percpu]
+static NEXT_TICK_NANOS: u64 = 0;
 
-// TODO: per-CPU
-static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<TaskWakeupEvent>>> = LazyInit::new();
+/// The deadline currently programmed into the timer hardware, in nanoseconds.
+#[percpu::def_percpu]
+static TIMER_DEADLINE_NANOS: u64 = u64::MAX;
 
-struct TaskWakeupEvent(AxTaskRef);
+/// The expired deferred timers, linked by [`TimerInner::next`], the most
+/// recently expired first.
+///
+/// It is an intrusive list, so that no memory is allocated in the timer
+/// interrupt handler.
+static DEFERRED_TIMERS: AtomicPtr<TimerInner> = AtomicPtr::new(null_mut());
+
+/// The `timer` task waits here for the deferred timers to expire.
+static DEFERRED_WQ: WaitQueue = WaitQueue::new();
+
+enum TimerListEvent {
+    TaskWakeup(AxTaskRef),
+    /// A [`Timer`] expires, with the generation it was started in.
+    Callback(Arc<TimerInner>, u64),
+}
+
+impl TimerEvent for TimerListEvent {
+    fn callback(self, now: TimeValue) {
+        match self {
+            Self::TaskWakeup(task) => {
+                let mut rq = current_run_queue();
+                task.set_in_timer_list(false);
+                rq.unblock_task(task, true);
+            }
+            Self::Callback(timer, generation) => timer.fire(generation, now),
+        }
+    }
+}
+
+/// Returns the timer list of the current CPU, preemption must be disabled.
+fn timer_list() -> &'static SpinNoIrq<TimerList<TimerListEvent>> {
+    unsafe { TIMER_LIST.current_ref_raw() }
+}
+
+/// Adds an event to the timer list of the current CPU.
+///
+/// Preemption and IRQs must be disabled.
+fn add_event(deadline: TimeValue, event: TimerListEvent) {
+    timer_list().lock().set(deadline, event);
+    program_timer(Some(deadline), false);
+}
+
+/// Removes the events that match the condition from the timer lists of all
+/// CPUs.
+fn remove_events(condition: impl Fn(&TimerListEvent) -> bool) {
+    for cpu_id in 0..axconfig::SMP {
+        if let Some(timers) = unsafe { TIMER_LIST.remote_ref_raw(cpu_id) }.get() {
+            timers.lock().cancel(&condition);
+        }
+    }
+}
 
-impl TimerEvent for TaskWakeupEvent {
-    fn callback(self, _now: TimeValue) {
-        let mut rq = RUN_QUEUE.lock();
-        self.0.set_in_timer_list(false);
-        rq.unblock_task(self.0, true);
+/// Programs the timer of the current CPU to fire at the next tick or at the
+/// given timer event deadline (in wall time), whichever comes first.
+///
+/// If `force` is `false`, the timer is only moved to an earlier deadline.
+/// IRQs must be disabled.
+fn program_timer(event_deadline: Option<TimeValue>, force: bool) {
+    let now_ns = monotonic_time_nanos();
+    let mut deadline = unsafe { NEXT_TICK_NANOS.read_current_raw() };
+    if let Some(event_deadline) = event_deadline {
+        let event_ns = (event_deadline.as_nanos() as u64).saturating_sub(epochoffset_nanos());
+        deadline = deadline.min(event_ns);
+    }
+    deadline = deadline.min(now_ns + MAX_TIMER_INTERVAL_NANOS);
+    if force || deadline < unsafe { TIMER_DEADLINE_NANOS.read_current_raw() } {
+        unsafe { TIMER_DEADLINE_NANOS.write_current_raw(deadline) };
+        axhal::time::set_oneshot_timer(deadline);
     }
 }
 
+/// Wakes up the blocked `task` at `deadline`.
+///
+/// The timer is programmed at once, so it is called with the run queue locked
+/// before the task is blocked, otherwise an early deadline may fire while the
+/// task is still running, and the task would never be woken up.
 pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
-    let mut timers = TIMER_LIST.lock();
+    // Stay on this CPU until the timer is programmed.
+    let _guard = NoPreemptIrqSave::new();
     task.set_in_timer_list(true);
-    timers.set(deadline, TaskWakeupEvent(task));
+    add_event(deadline, TimerListEvent::TaskWakeup(task));
 }
 
 pub fn cancel_alarm(task: &AxTaskRef) {
-    let mut timers = TIMER_LIST.lock();
     task.set_in_timer_list(false);
-    timers.cancel(|t| Arc::ptr_eq(&t.0, task));
+    // The alarm may be set on another CPU, before the task is migrated here.
+    remove_events(|e| matches!(e, TimerListEvent::TaskWakeup(t) if Arc::ptr_eq(t, task)));
+}
+
+/// A kernel timer that runs a callback when it expires.
+///
+/// The callback of a timer created by [`Timer::new`] runs in the timer
+/// interrupt handler with IRQs disabled, so it must not block or allocate
+/// memory. It may start or cancel timers (including its own) and wake up tasks.
+///
+/// The callback of a timer created by [`Timer::new_deferred`] runs later in
+/// the `timer` kernel task instead (like the softirqs in Linux), where it may
+/// block, allocate memory or spawn new tasks to do heavier work.
+///
+/// A timer expires on the CPU that started it. Dropping a timer cancels it.
+pub struct Timer {
+    inner: Arc<TimerInner>,
+}
+
+struct TimerInner {
+    callback: Box<dyn Fn() + Send + Sync>,
+    state: SpinNoIrq<TimerState>,
+    /// Whether the callback runs in the `timer` task.
+    deferred: bool,
+    /// Whether it is in [`DEFERRED_TIMERS`].
+    pending: AtomicBool,
+    /// The generation it expired in, when it is pending.
+    pending_generation: AtomicU64,
+    /// The next timer in [`DEFERRED_TIMERS`].
+    next: AtomicPtr<TimerInner>,
+}
+
+struct TimerState {
+    /// Bumped every time the timer is started or cancelled, so that the
+    /// events left in the timer lists by earlier settings are ignored.
+    generation: u64,
+    deadline: Option<TimeValue>,
+    interval: Option<Duration>,
+}
+
+impl TimerInner {
+    fn fire(self: Arc<Self>, generation: u64, now: TimeValue) {
+        let mut state = self.state.lock();
+        if state.generation != generation {
+            return;
+        }
+        match (state.deadline, state.interval) {
+            (Some(deadline), Some(interval)) => {
+                let mut next = deadline + interval;
+                if next <= now {
+                    // Skip the periods we have missed.
+                    let missed = (now - next).as_nanos() / interval.as_nanos() + 1;
+                    next += Duration::from_nanos((missed * interval.as_nanos()) as u64);
+                }
+                state.deadline = Some(next);
+                // The timer is reprogrammed after all expired events are handled.
+                timer_list()
+                    .lock()
+                    .set(next, TimerListEvent::Callback(self.clone(), generation));
+            }
+            _ => state.deadline = None,
+        }
+        drop(state);
+        if self.deferred {
+            self.defer(generation);
+        } else {
+            (self.callback)();
+        }
+    }
+
+    /// Puts the expired timer into [`DEFERRED_TIMERS`], and wakes up the
+    /// `timer` task to run the callback.
+    ///
+    /// If the timer is still pending from the last expiration, the two are
+    /// merged into one callback.
+    fn defer(self: Arc<Self>, generation: u64) {
+        self.pending_generation.store(generation, Ordering::Relaxed);
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let ptr = Arc::into_raw(self) as *mut TimerInner;
+        let mut head = DEFERRED_TIMERS.load(Ordering::Relaxed);
+        loop {
+            unsafe { &*ptr }.next.store(head, Ordering::Relaxed);
+            match DEFERRED_TIMERS.compare_exchange_weak(
+                head,
+                ptr,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(new_head) => head = new_head,
+            }
+        }
+        DEFERRED_WQ.notify_one(true);
+    }
 }
 
-pub fn check_events() {
+/// The entry of the `timer` task, which runs the callbacks of the expired
+/// deferred timers.
+fn deferred_timer_entry() {
+    loop {
+        DEFERRED_WQ.wait_until(|| !DEFERRED_TIMERS.load(Ordering::Acquire).is_null());
+        let mut expired = Vec::new();
+        let mut ptr = DEFERRED_TIMERS.swap(null_mut(), Ordering::AcqRel);
+        while !ptr.is_null() {
+            let timer = unsafe { Arc::from_raw(ptr) };
+            ptr = timer.next.swap(null_mut(), Ordering::Relaxed);
+            // It may expire and be pending again from now on.
+            timer.pending.store(false, Ordering::Release);
+            let generation = timer.pending_generation.load(Ordering::Relaxed);
+            expired.push((timer, generation));
+        }
+        // Run them in the order they expired.
+        for (timer, generation) in expired.into_iter().rev() {
+            // Skip it if it is cancelled or restarted after the expiration.
+            if timer.state.lock().generation == generation {
+                (timer.callback)();
+            }
+        }
+    }
+}
+
+impl Timer {
+    /// Creates a new timer that runs `callback` in the timer interrupt handler
+    /// when it expires.
+    ///
+    /// The timer is not started.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self::new_inner(Box::new(callback), false)
+    }
+
+    /// Creates a new timer that runs `callback` in the `timer` kernel task
+    /// when it expires.
+    ///
+    /// The timer is not started.
+    pub fn new_deferred<F>(callback: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self::new_inner(Box::new(callback), true)
+    }
+
+    fn new_inner(callback: Box<dyn Fn() + Send + Sync>, deferred: bool) -> Self {
+        Self {
+            inner: Arc::new(TimerInner {
+                callback,
+                state: SpinNoIrq::new(TimerState {
+                    generation: 0,
+                    deadline: None,
+                    interval: None,
+                }),
+                deferred,
+                pending: AtomicBool::new(false),
+                pending_generation: AtomicU64::new(0),
+                next: AtomicPtr::new(null_mut()),
+            }),
+        }
+    }
+
+    /// Starts the timer to expire at the given `deadline`, and then every
+    /// `interval` if it is not [`None`].
+    ///
+    /// The deadline is in wall time, the same as [`sleep_until`]. Restarting
+    /// an active timer replaces its previous setting.
+    ///
+    /// [`sleep_until`]: crate::sleep_until
+    pub fn start(&self, deadline: TimeValue, interval: Option<Duration>) {
+        let _guard = NoPreemptIrqSave::new();
+        let mut state = self.inner.state.lock();
+        if state.deadline.is_some() {
+            self.remove_events();
+        }
+        state.generation += 1;
+        state.deadline = Some(deadline);
+        state.interval = interval.filter(|i| !i.is_zero());
+        add_event(
+            deadline,
+            TimerListEvent::Callback(self.inner.clone(), state.generation),
+        );
+    }
+
+    /// Cancels the timer.
+    ///
+    /// Returns `true` if the timer was active. The callback may still be
+    /// running on another CPU (or in the `timer` task) when it returns.
+    pub fn cancel(&self) -> bool {
+        let mut state = self.inner.state.lock();
+        state.generation += 1;
+        let active = state.deadline.take().is_some();
+        if active {
+            self.remove_events();
+        }
+        active
+    }
+
+    /// Returns the time the timer will expire next, or [`None`] if it is not
+    /// active.
+    pub fn deadline(&self) -> Option<TimeValue> {
+        self.inner.state.lock().deadline
+    }
+
+    /// Returns the interval of the timer, or [`None`] if it is a one-shot
+    /// timer.
+    pub fn interval(&self) -> Option<Duration> {
+        self.inner.state.lock().interval
+    }
+
+    fn remove_events(&self) {
+        remove_events(
+            |e| matches!(e, TimerListEvent::Callback(t, _) if Arc::ptr_eq(t, &self.inner)),
+        );
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Handles the timer interrupt of the current CPU: fires the expired timer
+/// events, and programs the timer for the next one.
+///
+/// Returns `true` if it is time for a periodic tick.
+pub fn on_timer_irq() -> bool {
+    let now_ns = monotonic_time_nanos();
+    let next_tick = unsafe { NEXT_TICK_NANOS.read_current_raw() };
+    let is_tick = now_ns >= next_tick;
+    if is_tick {
+        let next_tick = if cfg!(feature = "tickless") && crate::current().is_idle() {
+            // Stop the tick while idle, see `restart_tick()`.
+            u64::MAX
+        } else if next_tick + PERIODIC_INTERVAL_NANOS > now_ns {
+            next_tick + PERIODIC_INTERVAL_NANOS
+        } else {
+            now_ns + PERIODIC_INTERVAL_NANOS
+        };
+        unsafe { NEXT_TICK_NANOS.write_current_raw(next_tick) };
+    }
+
     loop {
         let now = wall_time();
-        let event = TIMER_LIST.lock().expire_one(now);
+        let event = timer_list().lock().expire_one(now);
         if let Some((_deadline, event)) = event {
             event.callback(now);
         } else {
             break;
         }
     }
+
+    let event_deadline = timer_list().lock().next_deadline();
+    program_timer(event_deadline, true);
+    is_tick
+}
+
+/// Restarts the periodic tick of the current CPU if it was stopped, called
+/// when the CPU leaves the idle task.
+#[cfg(feature = "tickless")]
+pub fn restart_tick() {
+    if unsafe { NEXT_TICK_NANOS.read_current_raw() } == u64::MAX {
+        let next_tick = monotonic_time_nanos() + PERIODIC_INTERVAL_NANOS;
+        unsafe { NEXT_TICK_NANOS.write_current_raw(next_tick) };
+        program_timer(None, false);
+    }
 }
 
 pub fn init() {
-    TIMER_LIST.init_once(SpinNoIrq::new(TimerList::new()));
+    TIMER_LIST.with_current(|timers| {
+        timers.init_once(SpinNoIrq::new(TimerList::new()));
+    });
+}
+
+/// Spawns the `timer` task, only on the primary CPU.
+pub fn init_deferred() {
+    crate::spawn_raw(
+        deferred_timer_entry,
+        "timer".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
 }
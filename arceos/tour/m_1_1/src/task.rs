@@ -43,8 +43,7 @@ pub fn spawn_user_task(aspace: Arc<Mutex<AddrSpace>>, uctx: UspaceContext) -> Ax
         "userboot".into(),
         crate::KERNEL_STACK_SIZE,
     );
-    task.ctx_mut()
-        .set_page_table_root(aspace.lock().page_table_root());
+    task.set_page_table(aspace.lock().page_table_handle());
     task.init_task_ext(TaskExt::new(uctx, aspace));
     axtask::spawn_task(task)
 }
@@ -17,6 +17,9 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_FUTEX: usize = 98;
+
+const FUTEX_WAKE: i32 = 1;
 
 const AT_FDCWD: i32 = -100;
 
@@ -26,6 +29,7 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
     let ret = match syscall_num {
          SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         SYS_SET_TID_ADDRESS => sys_set_tid_address(tf.arg0() as _),
+        SYS_FUTEX => sys_futex(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, tf.arg3() as _, tf.arg4() as _, tf.arg5() as _),
         SYS_OPENAT => sys_openat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, tf.arg3() as _),
         SYS_CLOSE => sys_close(tf.arg0() as _),
         SYS_READ => sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
@@ -37,6 +41,7 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
         },
         SYS_EXIT => {
             ax_println!("[SYS_EXIT]: system is exiting ..");
+            clear_child_tid();
             axtask::exit(tf.arg0() as _)
         },
         _ => {
@@ -74,6 +79,22 @@ fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     curr.id().as_u64() as isize
 }
 
+fn sys_futex(uaddr: *mut u32, op: i32, val: u32, timeout: *const api::ctypes::timespec, uaddr2: *mut u32, val3: u32) -> isize {
+    unsafe { api::sys_futex(uaddr, op, val, timeout, uaddr2, val3) as isize }
+}
+
+/// Clears the word at the `clear_child_tid` address and wakes up a waiter on
+/// it, which is how `pthread_join` waits for the thread.
+fn clear_child_tid() {
+    let tid_ptr = current().task_ext().clear_child_tid() as *mut u32;
+    if !tid_ptr.is_null() {
+        unsafe {
+            tid_ptr.write(0);
+            api::sys_futex(tid_ptr, FUTEX_WAKE, 1, core::ptr::null(), core::ptr::null_mut(), 0);
+        }
+    }
+}
+
 fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
     ax_println!("Ignore SYS_IOCTL");
     0
@@ -2,6 +2,8 @@
 #include <stdio.h>
 #include <sys/mman.h>
 
+#ifndef AX_CONFIG_PAGING
+
 // TODO:
 void *mmap(void *addr, size_t len, int prot, int flags, int fildes, off_t off)
 {
@@ -37,3 +39,5 @@ int madvise(void *addr, size_t len, int advice)
     unimplemented();
     return 0;
 }
+
+#endif // AX_CONFIG_PAGING
@@ -33,48 +33,46 @@ int pthread_cancel(pthread_t t)
 }
 
 // TODO
-int pthread_mutex_trylock(pthread_mutex_t *m)
+int pthread_setname_np(pthread_t thread, const char *name)
 {
     unimplemented();
     return 0;
 }
 
-// TODO
-int pthread_setname_np(pthread_t thread, const char *name)
+int pthread_condattr_init(pthread_condattr_t *a)
 {
-    unimplemented();
+    *a = (pthread_condattr_t){0};
     return 0;
 }
 
-int pthread_cond_init(pthread_cond_t *restrict c, const pthread_condattr_t *restrict a)
+int pthread_condattr_destroy(pthread_condattr_t *a)
 {
-    *c = (pthread_cond_t){0};
-    if (a) {
-        c->_c_clock = a->__attr & 0x7fffffff;
-        if (a->__attr >> 31)
-            c->_c_shared = (void *)-1;
-    }
     return 0;
 }
 
-// TODO
-int pthread_cond_signal(pthread_cond_t *__cond)
+int pthread_condattr_setclock(pthread_condattr_t *a, clockid_t clk)
 {
-    unimplemented();
+    if (clk < 0 || clk - 2U < 2)
+        return EINVAL;
+    a->__attr &= 0x80000000;
+    a->__attr |= clk;
     return 0;
 }
 
-// TODO
-int pthread_cond_wait(pthread_cond_t *__restrict__ __cond, pthread_mutex_t *__restrict__ __mutex)
+int pthread_condattr_getclock(const pthread_condattr_t *restrict a, clockid_t *restrict clk)
 {
-    unimplemented();
+    *clk = a->__attr & 0x7fffffff;
     return 0;
 }
 
-// TODO
-int pthread_cond_broadcast(pthread_cond_t *c)
+int pthread_barrierattr_init(pthread_barrierattr_t *a)
+{
+    *a = (pthread_barrierattr_t){0};
+    return 0;
+}
+
+int pthread_barrierattr_destroy(pthread_barrierattr_t *a)
 {
-    unimplemented();
     return 0;
 }
 
@@ -92,6 +90,11 @@ int pthread_attr_init(pthread_attr_t *a)
     return 0;
 }
 
+int pthread_attr_destroy(pthread_attr_t *a)
+{
+    return 0;
+}
+
 int pthread_attr_getstacksize(const pthread_attr_t *restrict a, size_t *restrict size)
 {
     *size = a->_a_stacksize;
@@ -107,4 +110,18 @@ int pthread_attr_setstacksize(pthread_attr_t *a, size_t size)
     return 0;
 }
 
+int pthread_attr_getdetachstate(const pthread_attr_t *a, int *state)
+{
+    *state = a->_a_detach;
+    return 0;
+}
+
+int pthread_attr_setdetachstate(pthread_attr_t *a, int state)
+{
+    if (state > 1U)
+        return EINVAL;
+    a->_a_detach = state;
+    return 0;
+}
+
 #endif // AX_CONFIG_MULTITASK
@@ -16,13 +16,6 @@ int mkdir(const char *path, mode_t mode)
     return 0;
 }
 
-// TODO
-int chmod(const char *path, mode_t mode)
-{
-    unimplemented();
-    return 0;
-}
-
 // TODO
 mode_t umask(mode_t mask)
 {
@@ -2,6 +2,18 @@
 #define _SCHED_H
 
 #include <stddef.h>
+#include <sys/types.h>
+
+#define SCHED_OTHER    0
+#define SCHED_FIFO     1
+#define SCHED_RR       2
+#define SCHED_BATCH    3
+#define SCHED_IDLE     5
+#define SCHED_DEADLINE 6
+
+struct sched_param {
+    int sched_priority;
+};
 
 typedef struct cpu_set_t {
     unsigned long __bits[128 / sizeof(long)];
@@ -20,4 +32,11 @@ typedef struct cpu_set_t {
 
 int sched_setaffinity(pid_t, size_t, const cpu_set_t *);
 
+int sched_get_priority_max(int);
+int sched_get_priority_min(int);
+int sched_getparam(pid_t, struct sched_param *);
+int sched_getscheduler(pid_t);
+int sched_setparam(pid_t, const struct sched_param *);
+int sched_setscheduler(pid_t, int, const struct sched_param *);
+
 #endif // _SCHED_H
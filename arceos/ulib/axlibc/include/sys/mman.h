@@ -30,7 +30,9 @@
 #else
 #define MAP_ANONYMOUS 0x20 /* Don't use a file.  */
 #endif
-#define MAP_ANON MAP_ANONYMOUS
+#define MAP_ANON     MAP_ANONYMOUS
+#define MAP_POPULATE 0x08000 /* Populate (prefault) pagetables.  */
+#define MAP_HUGETLB  0x40000 /* Create huge page mapping.  */
 /* When MAP_HUGETLB is set bits [26:31] encode the log2 of the huge page size.  */
 #define MAP_HUGE_SHIFT 26
 #define MAP_HUGE_MASK  0x3f
@@ -42,11 +44,26 @@
 #define MREMAP_FIXED     2
 #define MREMAP_DONTUNMAP 4
 
+/* Advice to madvise.  */
+#define MADV_NORMAL     0 /* No further special treatment.  */
+#define MADV_RANDOM     1 /* Expect random page references.  */
+#define MADV_SEQUENTIAL 2 /* Expect sequential page references.  */
+#define MADV_WILLNEED   3 /* Will need these pages.  */
+#define MADV_DONTNEED   4 /* Don't need these pages.  */
+
+/* Flags for msync.  */
+#define MS_ASYNC      1 /* Sync memory asynchronously.  */
+#define MS_INVALIDATE 2 /* Invalidate the caches.  */
+#define MS_SYNC       4 /* Synchronous memory sync.  */
+
 void *mmap(void *addr, size_t len, int prot, int flags, int fildes, off_t off);
 int munmap(void *addr, size_t length);
 void *mremap(void *old_address, size_t old_size, size_t new_size, int flags,
              ... /* void *new_address */);
 int mprotect(void *addr, size_t len, int prot);
 int madvise(void *addr, size_t length, int advice);
+int mlock(const void *addr, size_t len);
+int munlock(const void *addr, size_t len);
+int msync(void *addr, size_t length, int flags);
 
 #endif
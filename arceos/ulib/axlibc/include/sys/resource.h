@@ -30,6 +30,8 @@ struct rlimit {
 #define RLIMIT_RTTIME     15
 #define RLIMIT_NLIMITS    16
 
+#define RLIM_INFINITY (~0ULL)
+
 #define RUSAGE_SELF     0
 #define RUSAGE_CHILDREN -1
 
@@ -57,12 +57,18 @@ mod fs;
 mod io_mpx;
 #[cfg(feature = "alloc")]
 mod malloc;
+#[cfg(feature = "paging")]
+mod mman;
 #[cfg(feature = "net")]
 mod net;
 #[cfg(feature = "pipe")]
 mod pipe;
 #[cfg(feature = "multitask")]
 mod pthread;
+#[cfg(feature = "multitask")]
+mod sched;
+#[cfg(feature = "multitask")]
+mod semaphore;
 #[cfg(feature = "alloc")]
 mod strftime;
 #[cfg(feature = "fp_simd")]
@@ -74,6 +80,7 @@ mod mktime;
 mod rand;
 mod resource;
 mod setjmp;
+mod signal;
 mod sys;
 mod time;
 mod unistd;
@@ -85,22 +92,36 @@ pub use self::io::{read, writev};
 pub use self::errno::strerror;
 pub use self::mktime::mktime;
 pub use self::rand::{rand, random, srand};
-pub use self::resource::{getrlimit, setrlimit};
+pub use self::resource::{getrlimit, getrusage, setrlimit};
 pub use self::setjmp::{longjmp, setjmp};
+pub use self::signal::ax_sigaction;
 pub use self::sys::sysconf;
 pub use self::time::{clock_gettime, nanosleep};
 pub use self::unistd::{abort, exit, getpid};
 
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use self::time::{
+    getitimer, setitimer, timer_create, timer_delete, timer_gettime, timer_settime,
+};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use self::unistd::alarm;
+
 #[cfg(feature = "alloc")]
 pub use self::malloc::{free, malloc};
 #[cfg(feature = "alloc")]
 pub use self::strftime::strftime;
 
+#[cfg(feature = "paging")]
+pub use self::mman::{madvise, mlock, mmap, mprotect, mremap, msync, munlock, munmap};
+
 #[cfg(feature = "fd")]
 pub use self::fd_ops::{ax_fcntl, close, dup, dup2, dup3};
 
 #[cfg(feature = "fs")]
-pub use self::fs::{ax_open, fstat, getcwd, lseek, lstat, rename, stat};
+pub use self::fs::{
+    ax_open, chmod, chown, fstat, getcwd, link, lseek, lstat, mount, readlink, rename, stat,
+    symlink, umount, umount2, utimensat,
+};
 
 #[cfg(feature = "net")]
 pub use self::net::{
@@ -109,9 +130,45 @@ pub use self::net::{
 };
 
 #[cfg(feature = "multitask")]
-pub use self::pthread::{pthread_create, pthread_exit, pthread_join, pthread_self};
+pub use self::pthread::{
+    pthread_barrier_destroy, pthread_barrier_init, pthread_barrier_wait, pthread_once,
+};
+#[cfg(feature = "multitask")]
+pub use self::pthread::{
+    pthread_cond_broadcast, pthread_cond_destroy, pthread_cond_init, pthread_cond_signal,
+    pthread_cond_wait,
+};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use self::pthread::{pthread_cond_timedwait, pthread_mutex_timedlock};
+#[cfg(feature = "multitask")]
+pub use self::pthread::{
+    pthread_create, pthread_detach, pthread_exit, pthread_join, pthread_self,
+    pthread_setaffinity_np,
+};
+#[cfg(feature = "multitask")]
+pub use self::pthread::{
+    pthread_getspecific, pthread_key_create, pthread_key_delete, pthread_setspecific,
+};
+#[cfg(feature = "multitask")]
+pub use self::pthread::{
+    pthread_mutex_destroy, pthread_mutex_init, pthread_mutex_lock, pthread_mutex_trylock,
+    pthread_mutex_unlock, pthread_mutexattr_destroy, pthread_mutexattr_getprotocol,
+    pthread_mutexattr_init, pthread_mutexattr_setprotocol,
+};
+#[cfg(feature = "multitask")]
+pub use self::pthread::{
+    pthread_rwlock_destroy, pthread_rwlock_init, pthread_rwlock_rdlock, pthread_rwlock_tryrdlock,
+    pthread_rwlock_trywrlock, pthread_rwlock_unlock, pthread_rwlock_wrlock,
+};
+#[cfg(feature = "multitask")]
+pub use self::sched::{
+    sched_get_priority_max, sched_get_priority_min, sched_getparam, sched_getscheduler,
+    sched_setaffinity, sched_setparam, sched_setscheduler,
+};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+pub use self::semaphore::sem_timedwait;
 #[cfg(feature = "multitask")]
-pub use self::pthread::{pthread_mutex_init, pthread_mutex_lock, pthread_mutex_unlock};
+pub use self::semaphore::{sem_destroy, sem_getvalue, sem_init, sem_post, sem_trywait, sem_wait};
 
 #[cfg(feature = "pipe")]
 pub use self::pipe::pipe;
@@ -0,0 +1,81 @@
+use core::ffi::{c_int, c_void};
+
+use arceos_posix_api::{
+    sys_madvise, sys_mlock, sys_mmap, sys_mprotect, sys_mremap, sys_msync, sys_munlock, sys_munmap,
+};
+
+use crate::{ctypes, utils::e};
+
+/// Converts the returned address or the negative error code, and sets `errno`
+/// on error.
+fn map_result(ret: isize) -> *mut c_void {
+    if (-4095..0).contains(&ret) {
+        crate::errno::set_errno(-ret as c_int);
+        usize::MAX as *mut c_void // MAP_FAILED
+    } else {
+        ret as *mut c_void
+    }
+}
+
+/// Map files or anonymous memory into memory.
+#[no_mangle]
+pub unsafe extern "C" fn mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fildes: c_int,
+    off: ctypes::off_t,
+) -> *mut c_void {
+    map_result(sys_mmap(addr, len, prot, flags, fildes, off))
+}
+
+/// Unmap pages of memory.
+#[no_mangle]
+pub unsafe extern "C" fn munmap(addr: *mut c_void, len: usize) -> c_int {
+    e(sys_munmap(addr, len))
+}
+
+/// Remap pages of memory.
+///
+/// The optional `new_address` argument is ignored, as `MREMAP_FIXED` is not
+/// supported.
+#[no_mangle]
+pub unsafe extern "C" fn mremap(
+    old_address: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    flags: c_int,
+) -> *mut c_void {
+    map_result(sys_mremap(old_address, old_size, new_size, flags))
+}
+
+/// Set protection of memory mapping.
+#[no_mangle]
+pub unsafe extern "C" fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int {
+    e(sys_mprotect(addr, len, prot))
+}
+
+/// Give advice about use of memory.
+#[no_mangle]
+pub unsafe extern "C" fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int {
+    e(sys_madvise(addr, len, advice))
+}
+
+/// Lock a range of memory.
+#[no_mangle]
+pub unsafe extern "C" fn mlock(addr: *const c_void, len: usize) -> c_int {
+    e(sys_mlock(addr, len))
+}
+
+/// Unlock a range of memory.
+#[no_mangle]
+pub unsafe extern "C" fn munlock(addr: *const c_void, len: usize) -> c_int {
+    e(sys_munlock(addr, len))
+}
+
+/// Synchronize a file with a memory map.
+#[no_mangle]
+pub unsafe extern "C" fn msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int {
+    e(sys_msync(addr, len, flags))
+}
@@ -0,0 +1,58 @@
+use core::ffi::c_int;
+
+use arceos_posix_api::{
+    sys_sched_get_priority_max, sys_sched_get_priority_min, sys_sched_getparam,
+    sys_sched_getscheduler, sys_sched_setaffinity, sys_sched_setparam, sys_sched_setscheduler,
+};
+
+use crate::{ctypes, utils::e};
+
+/// Set the CPU affinity mask of a thread (0 for the calling thread).
+#[no_mangle]
+pub unsafe extern "C" fn sched_setaffinity(
+    pid: c_int,
+    cpusetsize: ctypes::size_t,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_setaffinity(pid, cpusetsize as _, cpuset))
+}
+
+/// Set the scheduling policy and priority of a thread (0 for the calling thread).
+#[no_mangle]
+pub unsafe extern "C" fn sched_setscheduler(
+    pid: c_int,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    e(sys_sched_setscheduler(pid, policy, param))
+}
+
+/// Get the scheduling policy of a thread (0 for the calling thread).
+#[no_mangle]
+pub extern "C" fn sched_getscheduler(pid: c_int) -> c_int {
+    e(sys_sched_getscheduler(pid))
+}
+
+/// Set the scheduling priority of a thread (0 for the calling thread).
+#[no_mangle]
+pub unsafe extern "C" fn sched_setparam(pid: c_int, param: *const ctypes::sched_param) -> c_int {
+    e(sys_sched_setparam(pid, param))
+}
+
+/// Get the scheduling priority of a thread (0 for the calling thread).
+#[no_mangle]
+pub unsafe extern "C" fn sched_getparam(pid: c_int, param: *mut ctypes::sched_param) -> c_int {
+    e(sys_sched_getparam(pid, param))
+}
+
+/// Get the maximum scheduling priority of a policy.
+#[no_mangle]
+pub extern "C" fn sched_get_priority_max(policy: c_int) -> c_int {
+    e(sys_sched_get_priority_max(policy))
+}
+
+/// Get the minimum scheduling priority of a policy.
+#[no_mangle]
+pub extern "C" fn sched_get_priority_min(policy: c_int) -> c_int {
+    e(sys_sched_get_priority_min(policy))
+}
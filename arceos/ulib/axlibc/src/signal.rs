@@ -0,0 +1,15 @@
+use core::ffi::c_int;
+
+use arceos_posix_api::sys_sigaction;
+
+use crate::{ctypes, utils::e};
+
+/// Examine and change a signal action, called by `sigaction()` in C.
+#[no_mangle]
+pub unsafe extern "C" fn ax_sigaction(
+    signum: c_int,
+    act: *const ctypes::sigaction,
+    oldact: *mut ctypes::sigaction,
+) -> c_int {
+    e(sys_sigaction(signum, act, oldact))
+}
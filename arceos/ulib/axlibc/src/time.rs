@@ -1,4 +1,9 @@
 use arceos_posix_api::{sys_clock_gettime, sys_nanosleep};
+#[cfg(all(feature = "multitask", feature = "irq"))]
+use arceos_posix_api::{
+    sys_getitimer, sys_setitimer, sys_timer_create, sys_timer_delete, sys_timer_gettime,
+    sys_timer_settime,
+};
 use core::ffi::c_int;
 
 use crate::{ctypes, utils::e};
@@ -19,3 +24,61 @@ pub unsafe extern "C" fn nanosleep(
 ) -> c_int {
     e(sys_nanosleep(req, rem))
 }
+
+/// Create a POSIX per-process timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn timer_create(
+    clockid: ctypes::clockid_t,
+    sevp: *mut ctypes::sigevent,
+    timerid: *mut ctypes::timer_t,
+) -> c_int {
+    e(sys_timer_create(clockid, sevp, timerid))
+}
+
+/// Delete a POSIX per-process timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn timer_delete(timerid: ctypes::timer_t) -> c_int {
+    e(sys_timer_delete(timerid))
+}
+
+/// Arm or disarm a POSIX per-process timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn timer_settime(
+    timerid: ctypes::timer_t,
+    flags: c_int,
+    new_value: *const ctypes::itimerspec,
+    old_value: *mut ctypes::itimerspec,
+) -> c_int {
+    e(sys_timer_settime(timerid, flags, new_value, old_value))
+}
+
+/// Fetch the state of a POSIX per-process timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn timer_gettime(
+    timerid: ctypes::timer_t,
+    curr_value: *mut ctypes::itimerspec,
+) -> c_int {
+    e(sys_timer_gettime(timerid, curr_value))
+}
+
+/// Set the value of an interval timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn setitimer(
+    which: c_int,
+    new_value: *const ctypes::itimerval,
+    old_value: *mut ctypes::itimerval,
+) -> c_int {
+    e(sys_setitimer(which, new_value, old_value))
+}
+
+/// Get the value of an interval timer
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[no_mangle]
+pub unsafe extern "C" fn getitimer(which: c_int, curr_value: *mut ctypes::itimerval) -> c_int {
+    e(sys_getitimer(which, curr_value))
+}
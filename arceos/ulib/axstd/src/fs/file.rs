@@ -1,4 +1,5 @@
 use crate::io::{prelude::*, Result, SeekFrom};
+use crate::time::{Duration, SystemTime, UNIX_EPOCH};
 use core::fmt;
 
 use arceos_api::fs as api;
@@ -16,7 +17,7 @@ pub struct File {
 }
 
 /// Metadata information about a file.
-pub struct Metadata(api::AxFileAttr);
+pub struct Metadata(pub(super) api::AxFileAttr);
 
 /// Options and flags which can be used to configure how a file is opened.
 #[derive(Clone, Debug)]
@@ -110,6 +111,58 @@ impl Metadata {
     pub const fn blocks(&self) -> u64 {
         self.0.blocks()
     }
+
+    /// Returns `true` if this metadata is for a symbolic link, which is only
+    /// possible from [`symlink_metadata`](super::symlink_metadata).
+    pub const fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    /// Returns the user ID of the owner of the file.
+    pub const fn uid(&self) -> u32 {
+        self.0.uid()
+    }
+
+    /// Returns the group ID of the owner of the file.
+    pub const fn gid(&self) -> u32 {
+        self.0.gid()
+    }
+
+    /// Returns the number of hard links to the file.
+    pub const fn nlink(&self) -> u32 {
+        self.0.nlink()
+    }
+
+    /// Returns the last access time of this metadata.
+    pub fn accessed(&self) -> Result<SystemTime> {
+        Ok(UNIX_EPOCH + self.atime())
+    }
+
+    /// Returns the last modification time of the content of this metadata.
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(UNIX_EPOCH + self.mtime())
+    }
+
+    /// Returns the last status change time of this metadata.
+    pub fn changed(&self) -> Result<SystemTime> {
+        Ok(UNIX_EPOCH + self.ctime())
+    }
+
+    /// Returns the last access time, since the Unix epoch.
+    pub const fn atime(&self) -> Duration {
+        self.0.atime()
+    }
+
+    /// Returns the last modification time of the content, since the Unix
+    /// epoch.
+    pub const fn mtime(&self) -> Duration {
+        self.0.mtime()
+    }
+
+    /// Returns the last status change time, since the Unix epoch.
+    pub const fn ctime(&self) -> Duration {
+        self.0.ctime()
+    }
 }
 
 impl fmt::Debug for Metadata {
@@ -119,6 +172,7 @@ impl fmt::Debug for Metadata {
             .field("is_dir", &self.is_dir())
             .field("is_file", &self.is_file())
             .field("permissions", &self.permissions())
+            .field("modified", &self.modified())
             .finish_non_exhaustive()
     }
 }
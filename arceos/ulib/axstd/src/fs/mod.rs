@@ -4,6 +4,7 @@ mod dir;
 mod file;
 
 use crate::io::{self, prelude::*};
+use crate::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "alloc")]
 use alloc::{string::String, vec::Vec};
@@ -42,6 +43,33 @@ pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Query the metadata about a file without following symlinks.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    arceos_api::fs::ax_symlink_attr(path).map(Metadata)
+}
+
+/// Changes the permissions found on a file or a directory.
+pub fn set_permissions(path: &str, perm: Permissions) -> io::Result<()> {
+    arceos_api::fs::ax_set_perm(path, perm)
+}
+
+/// Changes the owner and group of a file or a directory. `None` leaves it
+/// unchanged.
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    arceos_api::fs::ax_chown(path, uid, gid)
+}
+
+/// Changes the last access and modification times of a file or a directory.
+/// `None` leaves it unchanged.
+pub fn set_times(
+    path: &str,
+    accessed: Option<SystemTime>,
+    modified: Option<SystemTime>,
+) -> io::Result<()> {
+    let since_epoch = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    arceos_api::fs::ax_set_times(path, accessed.map(since_epoch), modified.map(since_epoch))
+}
+
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
     ReadDir::new(path)
@@ -75,3 +103,21 @@ pub fn remove_file(path: &str) -> io::Result<()> {
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     arceos_api::fs::ax_rename(old, new)
 }
+
+/// Creates a new symbolic link `link` pointing to `original`.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_symlink(original, link)
+}
+
+/// Reads a symbolic link, returning the path it points to.
+#[cfg(feature = "alloc")]
+pub fn read_link(path: &str) -> io::Result<String> {
+    arceos_api::fs::ax_read_link(path)
+}
+
+/// Creates a new hard link `link` to the file `original`.
+///
+/// This only works when both paths are in the same mounted fs.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_hard_link(original, link)
+}
@@ -0,0 +1,79 @@
+//! A barrier to synchronize a group of tasks.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+/// A barrier enables multiple tasks to synchronize the beginning of some
+/// computation, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+pub struct Barrier {
+    wq: AxWaitQueueHandle,
+    num_tasks: usize,
+    count: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+/// A [`BarrierWaitResult`] is returned by [`Barrier::wait()`] when all tasks
+/// in the [`Barrier`] have rendezvoused.
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` if this task is the "leader task" for the call to
+    /// [`Barrier::wait()`].
+    ///
+    /// Only one task will have `true` returned from their result, all other
+    /// tasks will have `false` returned.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a new barrier that can block `n` tasks.
+    ///
+    /// A barrier will block `n - 1` tasks which call [`wait()`] and then wake
+    /// up all tasks at once when the `n`th task calls [`wait()`]. A barrier
+    /// created with `n = 0` behaves the same as `n = 1`.
+    ///
+    /// [`wait()`]: Barrier::wait
+    pub const fn new(n: usize) -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            num_tasks: n,
+            count: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks the current task until all tasks have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all tasks have rendezvoused once, and can
+    /// be used continuously. The last arrived task is the leader.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.count.fetch_add(1, Ordering::AcqRel) + 1 >= self.num_tasks {
+            self.count.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            api::ax_wait_queue_wake(&self.wq, u32::MAX);
+            BarrierWaitResult(true)
+        } else {
+            api::ax_wait_queue_wait(
+                &self.wq,
+                || self.generation.load(Ordering::Acquire) != generation,
+                None,
+            );
+            BarrierWaitResult(false)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("num_tasks", &self.num_tasks)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,349 @@
+//! A sleeping readers-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+const WRITER: usize = 1;
+const UPGRADABLE: usize = 1 << 1;
+const READER: usize = 1 << 2;
+
+/// A readers-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// It allows a number of readers or at most one writer at any point in time.
+/// An additional upgradable reader can coexist with the readers, and later be
+/// upgraded to a writer without releasing the lock.
+///
+/// The lock prefers writers: when a writer (or an upgrading reader) is
+/// waiting, new readers block until it finishes, so writers can not be
+/// starved. As a result, a task must not acquire the read lock recursively.
+pub struct RwLock<T: ?Sized> {
+    wq: AxWaitQueueHandle,
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will decrement the read count.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides immutable data access, and can be upgraded to a
+/// [`RwLockWriteGuard`].
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct RwLockUpgradableGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockUpgradableGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new instance of an [`RwLock`] which is unlocked.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`], returning the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        let RwLock { data, .. } = self;
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn acquire_reader(&self) -> bool {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        let state = self.state.fetch_add(READER, Ordering::Acquire);
+        if state & WRITER != 0 {
+            // a writer may have failed because of us
+            self.release(READER);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn acquire_upgradable(&self) -> bool {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        let state = self.state.fetch_or(UPGRADABLE, Ordering::Acquire);
+        if state & (WRITER | UPGRADABLE) == 0 {
+            return true;
+        }
+        if state & UPGRADABLE == 0 {
+            // we set the bit, but a writer holds the lock
+            self.release(UPGRADABLE);
+        }
+        false
+    }
+
+    fn acquire_writer(&self, from: usize) -> bool {
+        self.state
+            .compare_exchange(from, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Blocks until the state changes from `from` to [`WRITER`], blocking new
+    /// readers while waiting.
+    fn wait_writer(&self, from: usize) {
+        if self.acquire_writer(from) {
+            return;
+        }
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        api::ax_wait_queue_wait(&self.wq, || self.acquire_writer(from), None);
+        self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        if !self.acquire_reader() {
+            api::ax_wait_queue_wait(&self.wq, || self.acquire_reader(), None);
+        }
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with shared read access.
+    #[inline(always)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if self.acquire_reader() {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with upgradable read access, blocking the current
+    /// task until it can be acquired.
+    ///
+    /// There can be at most one upgradable reader at a time, along with any
+    /// number of readers.
+    pub fn upgradeable_read(&self) -> RwLockUpgradableGuard<T> {
+        if !self.acquire_upgradable() {
+            api::ax_wait_queue_wait(&self.wq, || self.acquire_upgradable(), None);
+        }
+        RwLockUpgradableGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with upgradable read access.
+    #[inline(always)]
+    pub fn try_upgradeable_read(&self) -> Option<RwLockUpgradableGuard<T>> {
+        if self.acquire_upgradable() {
+            Some(RwLockUpgradableGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the current
+    /// task until it can be acquired.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.wait_writer(0);
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Attempts to lock this [`RwLock`] with exclusive write access.
+    #[inline(always)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self.acquire_writer(0) {
+            Some(RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if a writer holds the lock.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    #[inline(always)]
+    pub fn is_locked_exclusive(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    /// Returns the number of readers, excluding the upgradable one.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    #[inline(always)]
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking needs
+    /// to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn release(&self, bits: usize) {
+        let state = self.state.fetch_sub(bits, Ordering::Release) - bits;
+        // Wake up the waiters if it may be acquired by a writer, or if the
+        // writer or upgradable reader has left.
+        if state & !UPGRADABLE == 0 || bits != READER {
+            api::ax_wait_queue_wake(&self.wq, u32::MAX);
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    /// Downgrades the writer to a reader, without allowing other writers to
+    /// acquire the lock in between.
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.state.fetch_and(!WRITER, Ordering::Release);
+        api::ax_wait_queue_wake(&lock.wq, u32::MAX);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<'a, T: ?Sized> RwLockUpgradableGuard<'a, T> {
+    /// Upgrades to a writer, blocking the current task until all the other
+    /// readers have left.
+    pub fn upgrade(self) -> RwLockWriteGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.wait_writer(UPGRADABLE);
+        RwLockWriteGuard { lock }
+    }
+
+    /// Tries to upgrade to a writer, returns the guard back if there are
+    /// other readers.
+    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'a, T>, Self> {
+        if self.lock.acquire_writer(UPGRADABLE) {
+            let lock = ManuallyDrop::new(self).lock;
+            Ok(RwLockWriteGuard { lock })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Downgrades to a normal reader, so that another upgradable reader may
+    /// acquire the lock.
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = ManuallyDrop::new(self).lock;
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.release(UPGRADABLE);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockUpgradableGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockUpgradableGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(READER);
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradableGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(UPGRADABLE);
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(WRITER);
+    }
+}
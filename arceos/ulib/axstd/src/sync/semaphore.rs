@@ -0,0 +1,84 @@
+//! A counting semaphore.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+/// A counting semaphore.
+///
+/// It maintains a count of available resources. [`Semaphore::acquire`]
+/// blocks the current task until the count is positive and decrements it,
+/// while [`Semaphore::release`] increments it and wakes up a waiting task.
+pub struct Semaphore {
+    wq: AxWaitQueueHandle,
+    count: AtomicUsize,
+}
+
+/// A guard that releases the acquired resource when dropped.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the initial count.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Acquires a resource of this semaphore, blocking the current task until
+    /// it can do so.
+    pub fn acquire(&self) {
+        if !self.try_acquire() {
+            api::ax_wait_queue_wait(&self.wq, || self.try_acquire(), None);
+        }
+    }
+
+    /// Tries to acquire a resource of this semaphore, returns `true` on
+    /// success.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Releases a resource to this semaphore, waking up a waiting task.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        api::ax_wait_queue_wake(&self.wq, 1);
+    }
+
+    /// Acquires a resource of this semaphore, and returns a guard to release
+    /// it when dropped.
+    pub fn access(&self) -> SemaphoreGuard<'_> {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+
+    /// Returns the number of the available resources.
+    ///
+    /// Do not use it for synchronization purposes, as the result may be out
+    /// of date the instant it is called.
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("count", &self.available())
+            .finish()
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
@@ -9,6 +9,9 @@ use core::{cell::UnsafeCell, num::NonZeroU64};
 use arceos_api::task::{self as api, AxTaskHandle};
 use axerrno::ax_err_type;
 
+/// A set of CPUs, used to restrict the CPUs on which a thread can run.
+pub use arceos_api::task::AxCpuMask as CpuMask;
+
 /// A unique identifier for a running thread.
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct ThreadId(NonZeroU64);
@@ -49,6 +52,8 @@ pub struct Builder {
     name: Option<String>,
     // The size of the stack for the spawned thread in bytes
     stack_size: Option<usize>,
+    // The CPUs on which the spawned thread is allowed to run
+    cpumask: Option<CpuMask>,
 }
 
 impl Builder {
@@ -58,6 +63,7 @@ impl Builder {
         Builder {
             name: None,
             stack_size: None,
+            cpumask: None,
         }
     }
 
@@ -73,6 +79,14 @@ impl Builder {
         self
     }
 
+    /// Sets the CPUs on which the new thread is allowed to run.
+    ///
+    /// By default, the new thread inherits the CPU affinity of the caller.
+    pub fn cpumask(mut self, cpumask: CpuMask) -> Builder {
+        self.cpumask = Some(cpumask);
+        self
+    }
+
     /// Spawns a new thread by taking ownership of the `Builder`, and returns an
     /// [`io::Result`] to its [`JoinHandle`].
     ///
@@ -115,7 +129,7 @@ impl Builder {
             drop(their_packet);
         };
 
-        let task = api::ax_spawn(main, name, stack_size);
+        let task = api::ax_spawn(main, name, stack_size, self.cpumask);
         Ok(JoinHandle {
             thread: Thread::from_id(task.id()),
             native: task,